@@ -0,0 +1,99 @@
+//! Compares the lookup-table hex scanner with `from_str_radix` and measures
+//! end-to-end parsing. Set `MEMTRACK_BENCH_TRACE` to a real (multi-GB) trace
+//! to include it in the parser benchmark.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use memtrace_utils::numparse::Fields;
+use memtrace_utils::parser::Parser;
+use std::fmt::Write;
+use std::hint::black_box;
+use std::path::PathBuf;
+
+fn lines(count: usize) -> String {
+    let mut out = String::with_capacity(count * 24);
+    for i in 0..count as u64 {
+        match i % 4 {
+            0 => writeln!(out, "+ {:x} {:x}", i % 4096, 0x17d8e9a0 + i),
+            1 => writeln!(out, "- {:x} {:x}", i % 4096, 0x17d8e9a0 + i),
+            2 => writeln!(out, "t {:x} {:x}", i % 65536, i / 2),
+            _ => writeln!(
+                out,
+                "i {:x} 1 {:x} {:x} {:x}",
+                0x100004000 + i,
+                i,
+                i + 1,
+                i % 1000
+            ),
+        }
+        .unwrap();
+    }
+    out
+}
+
+fn bench_fields(c: &mut Criterion) {
+    let input = lines(100_000);
+    let mut group = c.benchmark_group("fields");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+
+    group.bench_function("from_str_radix", |b| {
+        b.iter(|| {
+            let mut sum = 0u64;
+            for line in input.lines() {
+                for field in line.split_whitespace().skip(1) {
+                    sum = sum.wrapping_add(u64::from_str_radix(field, 16).unwrap());
+                }
+            }
+            black_box(sum)
+        })
+    });
+
+    group.bench_function("numparse", |b| {
+        b.iter(|| {
+            let mut sum = 0u64;
+            for line in input.lines() {
+                let mut fields = Fields::new(line);
+                fields.next();
+                while let Some(value) = fields.next_hex() {
+                    sum = sum.wrapping_add(value);
+                }
+            }
+            black_box(sum)
+        })
+    });
+
+    group.finish();
+}
+
+fn bench_parser(c: &mut Criterion) {
+    let path = match std::env::var_os("MEMTRACK_BENCH_TRACE") {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let path = std::env::temp_dir().join("memtrack-bench.trace");
+            let mut trace = String::from("v 1 3\ns 4 main\ni 10 1 1\nt 1 0\n");
+            for size in 0..4096u64 {
+                writeln!(trace, "a {:x} 1", size + 1).unwrap();
+            }
+            for i in 0..500_000u64 {
+                writeln!(trace, "+ {:x} {:x}", i % 4096, i).unwrap();
+                writeln!(trace, "- {:x} {:x}", i % 4096, i).unwrap();
+            }
+            std::fs::write(&path, trace).unwrap();
+            path
+        }
+    };
+
+    let size = std::fs::metadata(&path).unwrap().len();
+    let mut group = c.benchmark_group("parser");
+    group.throughput(Throughput::Bytes(size)).sample_size(10);
+    group.bench_function("parse_file", |b| {
+        b.iter_batched(
+            Parser::new,
+            |parser| black_box(parser.parse_file(&path).unwrap()),
+            BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_fields, bench_parser);
+criterion_main!(benches);
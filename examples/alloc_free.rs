@@ -0,0 +1,17 @@
+//! Allocates and frees `COUNT` blocks of `SIZE` bytes, given as arguments,
+//! for the `alloc-free` conformance scenario.
+
+use std::env;
+use std::hint::black_box;
+
+fn main() {
+    let mut args = env::args().skip(1).map(|a| a.parse::<usize>());
+    let (Some(Ok(size)), Some(Ok(count))) = (args.next(), args.next()) else {
+        eprintln!("usage: alloc_free SIZE COUNT");
+        std::process::exit(2);
+    };
+
+    for _ in 0..count {
+        drop(black_box(Vec::<u8>::with_capacity(size)));
+    }
+}
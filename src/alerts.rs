@@ -0,0 +1,177 @@
+//! Rate-of-change alerts evaluated while a program is being traced.
+//!
+//! Rates are measured between consecutive clock records of the trace, so
+//! their resolution is the clock interval of the preloaded library.
+
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AlertRule {
+    /// The heap grew faster than `bytes_per_minute` for at least `sustained`.
+    HeapGrowth {
+        bytes_per_minute: u64,
+        sustained: Duration,
+    },
+    /// More than `per_second` temporary allocations per second.
+    TemporaryRate { per_second: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alert {
+    /// Index of the rule in the list given to [`Alerts::new`].
+    pub rule: usize,
+    /// Time since the start of the traced program.
+    pub timestamp: Duration,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    timestamp: Duration,
+    heap: u64,
+    temporary: u64,
+}
+
+#[derive(Debug)]
+struct RuleState {
+    rule: AlertRule,
+    since: Option<Duration>,
+    firing: bool,
+}
+
+/// Evaluates [`AlertRule`]s against samples of the heap state. A rule fires
+/// once when its condition starts to hold and is re-armed when it stops.
+#[derive(Debug)]
+pub struct Alerts {
+    rules: Vec<RuleState>,
+    last: Option<Sample>,
+}
+
+impl Alerts {
+    pub fn new(rules: Vec<AlertRule>) -> Self {
+        Self {
+            rules: rules
+                .into_iter()
+                .map(|rule| RuleState {
+                    rule,
+                    since: None,
+                    firing: false,
+                })
+                .collect(),
+            last: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Feeds the heap size and temporary allocation count at `timestamp` and
+    /// returns the alerts that fired.
+    pub fn update(&mut self, timestamp: Duration, heap: u64, temporary: u64) -> Vec<Alert> {
+        let sample = Sample {
+            timestamp,
+            heap,
+            temporary,
+        };
+        let Some(last) = self.last.replace(sample) else {
+            return Vec::new();
+        };
+
+        let elapsed = timestamp.saturating_sub(last.timestamp).as_secs_f64();
+        if elapsed == 0.0 {
+            self.last = Some(last);
+            return Vec::new();
+        }
+
+        let mut alerts = Vec::new();
+        for (idx, state) in self.rules.iter_mut().enumerate() {
+            let message = match state.rule {
+                AlertRule::HeapGrowth {
+                    bytes_per_minute,
+                    sustained,
+                } => {
+                    let rate = heap.saturating_sub(last.heap) as f64 * 60.0 / elapsed;
+                    if rate <= bytes_per_minute as f64 {
+                        state.since = None;
+                        state.firing = false;
+                        continue;
+                    }
+
+                    let since = *state.since.get_or_insert(last.timestamp);
+                    if timestamp - since < sustained {
+                        continue;
+                    }
+                    format!(
+                        "heap growth above {} bytes/min for {}s",
+                        bytes_per_minute,
+                        (timestamp - since).as_secs()
+                    )
+                }
+                AlertRule::TemporaryRate { per_second } => {
+                    let rate = temporary.saturating_sub(last.temporary) as f64 / elapsed;
+                    if rate <= per_second as f64 {
+                        state.firing = false;
+                        continue;
+                    }
+                    format!(
+                        "{:.0} temporary allocations/s, above {}/s",
+                        rate, per_second
+                    )
+                }
+            };
+
+            if !state.firing {
+                state.firing = true;
+                alerts.push(Alert {
+                    rule: idx,
+                    timestamp,
+                    message,
+                });
+            }
+        }
+
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::alerts::{AlertRule, Alerts};
+    use std::time::Duration;
+
+    #[test]
+    fn test_heap_growth_sustained() {
+        let mut alerts = Alerts::new(vec![AlertRule::HeapGrowth {
+            bytes_per_minute: 1000,
+            sustained: Duration::from_secs(120),
+        }]);
+
+        let minute = |m: u64| Duration::from_secs(60 * m);
+        assert!(alerts.update(minute(0), 0, 0).is_empty());
+        assert!(alerts.update(minute(1), 2000, 0).is_empty());
+        let fired = alerts.update(minute(2), 4000, 0);
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].timestamp, minute(2));
+        // fires once per stretch
+        assert!(alerts.update(minute(3), 6000, 0).is_empty());
+
+        // flat heap re-arms the rule and restarts the stretch
+        assert!(alerts.update(minute(4), 6000, 0).is_empty());
+        assert!(alerts.update(minute(5), 8000, 0).is_empty());
+        assert_eq!(alerts.update(minute(6), 10000, 0).len(), 1);
+    }
+
+    #[test]
+    fn test_temporary_rate() {
+        let mut alerts = Alerts::new(vec![AlertRule::TemporaryRate { per_second: 100 }]);
+
+        let second = Duration::from_secs;
+        assert!(alerts.update(second(0), 0, 0).is_empty());
+        assert!(alerts.update(second(1), 0, 50).is_empty());
+        assert_eq!(alerts.update(second(2), 0, 500).len(), 1);
+        assert!(alerts.update(second(3), 0, 1000).is_empty());
+        assert!(alerts.update(second(4), 0, 1010).is_empty());
+        assert_eq!(alerts.update(second(5), 0, 2000).len(), 1);
+    }
+}
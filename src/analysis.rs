@@ -0,0 +1,19 @@
+//! Analyses computed on top of parsed traces and [`Profile`](crate::model::Profile)s.
+
+pub mod address_map;
+pub mod allocators;
+pub mod churn;
+pub mod crates;
+pub mod leaf;
+pub mod leaks;
+pub mod ownership;
+pub mod phases;
+pub mod pools;
+pub mod rate;
+pub mod registry;
+pub mod rss;
+pub mod sampling;
+pub mod size_class;
+pub mod stack_depth;
+pub mod transactions;
+pub mod waste;
@@ -0,0 +1,119 @@
+//! Address-space map of live allocations.
+//!
+//! Live allocations are binned into fixed-size, aligned regions. Each region
+//! reports the bytes it holds, the site contributing most of them and how
+//! densely it is used, which helps correlating with `vmmap` output and
+//! spotting fragmentation.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+pub const DEFAULT_REGION_SIZE: u64 = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LiveAllocation {
+    pub ptr: u64,
+    pub size: u64,
+    pub trace_idx: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Region {
+    pub start: u64,
+    pub end: u64,
+    pub bytes: u64,
+    pub allocations: u64,
+    /// Trace index of the site holding the most bytes in the region.
+    pub dominant_trace_idx: u64,
+    pub dominant_bytes: u64,
+    /// Share of the region covered by live allocations. Allocations are
+    /// accounted to the region they start in, so this may exceed 1.
+    pub density: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct AddressMap {
+    pub region_size: u64,
+    /// Live bytes when the map was taken.
+    pub heap: u64,
+    /// Regions holding at least one allocation, ordered by address.
+    pub regions: Vec<Region>,
+}
+
+impl AddressMap {
+    pub fn build(live: impl IntoIterator<Item = LiveAllocation>, region_size: u64) -> Self {
+        let region_size = region_size.max(1);
+        let mut regions: BTreeMap<u64, (u64, u64, HashMap<u64, u64>)> = BTreeMap::new();
+        let mut heap = 0;
+
+        for allocation in live {
+            heap += allocation.size;
+
+            let (bytes, allocations, sites) =
+                regions.entry(allocation.ptr / region_size).or_default();
+            *bytes += allocation.size;
+            *allocations += 1;
+            *sites.entry(allocation.trace_idx).or_default() += allocation.size;
+        }
+
+        let regions = regions
+            .into_iter()
+            .map(|(idx, (bytes, allocations, sites))| {
+                let (dominant_trace_idx, dominant_bytes) = sites
+                    .into_iter()
+                    .max_by_key(|&(trace_idx, bytes)| (bytes, std::cmp::Reverse(trace_idx)))
+                    .unwrap_or_default();
+
+                Region {
+                    start: idx * region_size,
+                    end: idx * region_size + region_size,
+                    bytes,
+                    allocations,
+                    dominant_trace_idx,
+                    dominant_bytes,
+                    density: bytes as f64 / region_size as f64,
+                }
+            })
+            .collect();
+
+        Self {
+            region_size,
+            heap,
+            regions,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::address_map::{AddressMap, LiveAllocation};
+
+    #[test]
+    fn test_build() {
+        let live = [
+            LiveAllocation {
+                ptr: 0x1000,
+                size: 0x100,
+                trace_idx: 1,
+            },
+            LiveAllocation {
+                ptr: 0x1800,
+                size: 0x400,
+                trace_idx: 2,
+            },
+            LiveAllocation {
+                ptr: 0x5000,
+                size: 0x10,
+                trace_idx: 1,
+            },
+        ];
+
+        let map = AddressMap::build(live, 0x1000);
+        assert_eq!(map.heap, 0x510);
+        assert_eq!(map.regions.len(), 2);
+        assert_eq!(map.regions[0].start, 0x1000);
+        assert_eq!(map.regions[0].bytes, 0x500);
+        assert_eq!(map.regions[0].dominant_trace_idx, 2);
+        assert_eq!(map.regions[1].allocations, 1);
+    }
+}
@@ -0,0 +1,97 @@
+//! Detection of custom allocator wrappers.
+//!
+//! Programs routing every allocation through their own `my_malloc` make that
+//! wrapper the allocating frame of nearly every site. Such a function hides
+//! the real callers and is better treated as part of the allocator.
+
+use crate::model::Site;
+use std::collections::HashMap;
+
+/// Share of allocations a single leaf function must account for to be
+/// considered an allocator wrapper.
+pub const DOMINANCE_THRESHOLD: f64 = 0.95;
+
+/// Returns the functions dominating the allocating frame of `sites`, outermost
+/// last. Wrappers calling further wrappers are peeled off one level at a time.
+pub fn detect(sites: &[Site], threshold: f64) -> Vec<String> {
+    let mut wrappers: Vec<String> = Vec::new();
+
+    loop {
+        let mut leaves: HashMap<&str, u64> = HashMap::new();
+        let mut total = 0;
+
+        for site in sites {
+            let leaf = site.stack.iter().find(|f| !wrappers.contains(&f.function));
+            if let Some(leaf) = leaf {
+                *leaves.entry(&leaf.function).or_default() += site.cost.allocations;
+                total += site.cost.allocations;
+            }
+        }
+
+        let Some((function, count)) = leaves.into_iter().max_by_key(|(_, count)| *count) else {
+            break;
+        };
+
+        // a single site trivially dominates itself
+        let distinct_callers = sites
+            .iter()
+            .filter(|s| s.stack.iter().any(|f| f.function == function))
+            .count();
+
+        if total == 0 || distinct_callers < 2 || (count as f64) < total as f64 * threshold {
+            break;
+        }
+
+        wrappers.push(function.to_string());
+    }
+
+    wrappers
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::allocators::{detect, DOMINANCE_THRESHOLD};
+    use crate::model::tests::parse;
+    use crate::model::{Profile, ProfileOptions};
+
+    const TRACE: &str = "\
+s 4 /bin
+s 4 main
+s 1 a
+s 1 b
+s 9 my_malloc
+i 10 1 2
+i 20 1 3
+i 30 1 4
+i 40 1 5
+t 1 0
+t 2 1
+t 4 2
+t 3 1
+t 4 4
+a 10 3
+a 20 5
++ 0
++ 1
+";
+
+    #[test]
+    fn test_detect_wrapper() {
+        let data = parse(TRACE);
+        let profile = Profile::new(&data).unwrap();
+        assert_eq!(detect(&profile.sites, DOMINANCE_THRESHOLD), ["my_malloc"]);
+
+        let options = ProfileOptions {
+            detect_allocators: true,
+            ..Default::default()
+        };
+        let profile = Profile::with_options(&data, &options).unwrap();
+        let leaves: Vec<&str> = profile
+            .sites
+            .iter()
+            .map(|s| s.leaf().unwrap().function.as_str())
+            .collect();
+        assert_eq!(leaves, ["a", "b"]);
+        assert!(profile.function("my_malloc").is_none());
+    }
+}
@@ -0,0 +1,130 @@
+//! Estimate of the CPU time a run spent in the allocator.
+//!
+//! Every allocation and free is charged a fixed cost plus a cost per
+//! allocated byte. The defaults are rough figures for the system allocator
+//! of the platform; measure them on the target machine for better
+//! estimates.
+
+use crate::parser::AccumulatedData;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+/// Cost of allocator calls in nanoseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AllocatorCosts {
+    pub alloc_ns: f64,
+    pub free_ns: f64,
+    /// Cost per allocated byte, e.g. for touching fresh pages.
+    pub byte_ns: f64,
+}
+
+impl AllocatorCosts {
+    /// Defaults for the system allocator of the current platform.
+    pub fn for_platform() -> Self {
+        if cfg!(target_os = "macos") {
+            Self {
+                alloc_ns: 40.0,
+                free_ns: 30.0,
+                byte_ns: 0.02,
+            }
+        } else {
+            Self {
+                alloc_ns: 25.0,
+                free_ns: 15.0,
+                byte_ns: 0.02,
+            }
+        }
+    }
+}
+
+impl Default for AllocatorCosts {
+    fn default() -> Self {
+        Self::for_platform()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct ChurnEstimate {
+    pub allocations: u64,
+    pub frees: u64,
+    pub bytes: u64,
+    pub allocator_time: Duration,
+    /// Part of the allocator time spent on temporary allocations, which are
+    /// freed right after being allocated.
+    pub temporary_time: Duration,
+    pub run_time: Duration,
+}
+
+impl ChurnEstimate {
+    pub fn new(data: &AccumulatedData, costs: &AllocatorCosts) -> Self {
+        let bytes = data
+            .allocation_infos
+            .iter()
+            .map(|info| info.size * info.allocations)
+            .sum();
+        let total = &data.total;
+
+        let nanos = total.allocations as f64 * costs.alloc_ns
+            + total.frees as f64 * costs.free_ns
+            + bytes as f64 * costs.byte_ns;
+        let temporary = total.temporary as f64 * (costs.alloc_ns + costs.free_ns);
+
+        Self {
+            allocations: total.allocations,
+            frees: total.frees,
+            bytes,
+            allocator_time: Duration::from_nanos(nanos as u64),
+            temporary_time: Duration::from_nanos(temporary as u64),
+            run_time: data.duration,
+        }
+    }
+
+    /// Share of the run spent in the allocator, 0 for traces without a
+    /// duration.
+    pub fn share(&self) -> f64 {
+        if self.run_time.is_zero() {
+            return 0.0;
+        }
+        self.allocator_time.as_secs_f64() / self.run_time.as_secs_f64()
+    }
+}
+
+impl fmt::Display for ChurnEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "≈{:.1} s of this {:.1} s run was malloc/free ({:.1} s on temporary allocations)",
+            self.allocator_time.as_secs_f64(),
+            self.run_time.as_secs_f64(),
+            self.temporary_time.as_secs_f64()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::churn::{AllocatorCosts, ChurnEstimate};
+    use crate::model::tests::data;
+    use std::time::Duration;
+
+    #[test]
+    fn test_estimate() {
+        let costs = AllocatorCosts {
+            alloc_ns: 100.0,
+            free_ns: 50.0,
+            byte_ns: 1.0,
+        };
+        let estimate = ChurnEstimate::new(&data(), &costs);
+
+        assert_eq!(estimate.allocations, 3);
+        assert_eq!(estimate.frees, 1);
+        assert_eq!(estimate.bytes, 0x40);
+        assert_eq!(
+            estimate.allocator_time,
+            Duration::from_nanos(3 * 100 + 50 + 0x40)
+        );
+        assert_eq!(estimate.run_time, Duration::from_millis(100));
+        assert!(estimate.to_string().starts_with("≈0.0 s of this 0.1 s run"));
+    }
+}
@@ -0,0 +1,285 @@
+//! Aggregation of sites by the Rust crate of their frames.
+//!
+//! The crate of a frame is the first segment of its demangled path, e.g.
+//! `serde_json` for `serde_json::de::from_str` or `alloc` for
+//! `<alloc::vec::Vec<T> as Clone>::clone`. Each site is attributed to one
+//! crate: by default the crate of its innermost frame outside the standard
+//! library. With the workspace crates set, a site is instead attributed to
+//! the dependency the workspace code called into, answering which dependency
+//! the memory was spent in.
+
+use crate::model::{Cost, Frame, Metric, Site};
+use indexmap::IndexMap;
+use serde::Serialize;
+use std::fmt;
+
+/// Crates whose frames are skipped when attributing a site.
+pub const DEFAULT_IGNORED: [&str; 3] = ["std", "core", "alloc"];
+
+/// Crate of a demangled Rust function name, `None` for other symbols.
+pub fn crate_name(function: &str) -> Option<&str> {
+    // `<T as Trait>::f` and `<&T>::f` belong to the crate of `T`
+    let path = function.trim_start_matches(['<', '&']);
+    let path = path.strip_prefix("dyn ").unwrap_or(path);
+    let end = path.find("::")?;
+    let name = &path[..end];
+
+    let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    valid.then_some(name)
+}
+
+#[derive(Debug, Clone)]
+pub struct CrateOptions {
+    pub ignored: Vec<String>,
+    /// Crates of the traced workspace. Sites are attributed to the
+    /// dependency called from them, see the [module docs](self).
+    pub workspace: Vec<String>,
+    pub top_functions: usize,
+    /// Metric to rank crates and their functions by.
+    pub metric: Metric,
+}
+
+impl Default for CrateOptions {
+    fn default() -> Self {
+        Self {
+            ignored: DEFAULT_IGNORED.map(str::to_string).to_vec(),
+            workspace: Vec::new(),
+            top_functions: 5,
+            metric: Metric::Peak,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CrateFunction {
+    pub name: String,
+    pub cost: Cost,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CrateCost {
+    pub name: String,
+    pub workspace: bool,
+    /// Cost of the sites attributed to the crate.
+    pub cost: Cost,
+    /// Cost of all sites with a frame of the crate, counted once per site.
+    pub inclusive: Cost,
+    /// Functions of the crate the attributed sites went through, highest
+    /// metric first.
+    pub top_functions: Vec<CrateFunction>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CrateReport {
+    /// Crates ordered by the attributed cost, highest first.
+    pub crates: Vec<CrateCost>,
+    /// Cost of the sites without a frame of a crate that is not ignored,
+    /// e.g. allocations made by C libraries.
+    pub unattributed: Cost,
+}
+
+struct Entry<'a> {
+    cost: Cost,
+    inclusive: Cost,
+    functions: IndexMap<&'a str, Cost>,
+}
+
+impl CrateReport {
+    pub fn new<'a>(sites: impl IntoIterator<Item = &'a Site>, options: &CrateOptions) -> Self {
+        let mut entries: IndexMap<&str, Entry> = IndexMap::new();
+        let mut unattributed = Cost::default();
+
+        for site in sites {
+            let frames: Vec<(&str, &Frame)> = site
+                .stack
+                .iter()
+                .filter_map(|frame| Some((crate_name(&frame.function)?, frame)))
+                .filter(|(name, _)| !options.ignored.iter().any(|i| i == name))
+                .collect();
+
+            let mut seen: Vec<&str> = Vec::new();
+            for &(name, _) in &frames {
+                if !seen.contains(&name) {
+                    seen.push(name);
+                    entry(&mut entries, name).inclusive.add(&site.cost);
+                }
+            }
+
+            let Some(&(name, frame)) = attribute(&frames, &options.workspace) else {
+                unattributed.add(&site.cost);
+                continue;
+            };
+            let entry = entry(&mut entries, name);
+            entry.cost.add(&site.cost);
+            entry
+                .functions
+                .entry(&frame.function)
+                .or_default()
+                .add(&site.cost);
+        }
+
+        let metric = options.metric;
+        let mut crates: Vec<CrateCost> = entries
+            .into_iter()
+            .map(|(name, entry)| {
+                let mut functions: Vec<CrateFunction> = entry
+                    .functions
+                    .into_iter()
+                    .map(|(name, cost)| CrateFunction {
+                        name: name.to_string(),
+                        cost,
+                    })
+                    .collect();
+                functions.sort_by_key(|f| std::cmp::Reverse(f.cost.get(metric)));
+                functions.truncate(options.top_functions);
+
+                CrateCost {
+                    name: name.to_string(),
+                    workspace: options.workspace.iter().any(|w| w == name),
+                    cost: entry.cost,
+                    inclusive: entry.inclusive,
+                    top_functions: functions,
+                }
+            })
+            .collect();
+        crates.sort_by_key(|c| std::cmp::Reverse((c.cost.get(metric), c.inclusive.get(metric))));
+
+        Self {
+            crates,
+            unattributed,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&CrateCost> {
+        self.crates.iter().find(|c| c.name == name)
+    }
+
+    /// Crates outside the workspace.
+    pub fn dependencies(&self) -> impl Iterator<Item = &CrateCost> {
+        self.crates.iter().filter(|c| !c.workspace)
+    }
+}
+
+fn entry<'a, 'e>(
+    entries: &'e mut IndexMap<&'a str, Entry<'a>>,
+    name: &'a str,
+) -> &'e mut Entry<'a> {
+    entries.entry(name).or_insert_with(|| Entry {
+        cost: Cost::default(),
+        inclusive: Cost::default(),
+        functions: IndexMap::new(),
+    })
+}
+
+/// Picks the frame a site is attributed to from its frames of crates that
+/// are not ignored, innermost first.
+fn attribute<'f, 'a>(
+    frames: &'f [(&'a str, &'a Frame)],
+    workspace: &[String],
+) -> Option<&'f (&'a str, &'a Frame)> {
+    let called_from_workspace = frames
+        .iter()
+        .position(|(name, _)| workspace.iter().any(|w| w == name))
+        .filter(|&pos| pos > 0);
+
+    match called_from_workspace {
+        // the outermost dependency frame below the workspace code
+        Some(pos) => frames.get(pos - 1),
+        None => frames.first(),
+    }
+}
+
+impl fmt::Display for CrateReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for krate in &self.crates {
+            writeln!(
+                f,
+                "{}{}: allocations {}, temporary {}, leaked {}, peak {}",
+                krate.name,
+                if krate.workspace { " (workspace)" } else { "" },
+                krate.cost.allocations,
+                krate.cost.temporary,
+                krate.cost.leaked,
+                krate.cost.peak
+            )?;
+            for function in &krate.top_functions {
+                writeln!(f, "  {}: peak {}", function.name, function.cost.peak)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::crates::{crate_name, CrateOptions, CrateReport};
+    use crate::model::{Cost, Frame, Site};
+
+    fn site(functions: &[&str], peak: u64) -> Site {
+        Site {
+            stack: functions
+                .iter()
+                .map(|function| Frame {
+                    ip: 0,
+                    module: None,
+                    function: function.to_string(),
+                    file: None,
+                    line: None,
+                    inlined: false,
+                })
+                .collect(),
+            cost: Cost {
+                allocations: 1,
+                peak,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_crate_name() {
+        assert_eq!(crate_name("serde_json::de::from_str"), Some("serde_json"));
+        assert_eq!(
+            crate_name("<alloc::vec::Vec<T> as core::clone::Clone>::clone"),
+            Some("alloc")
+        );
+        assert_eq!(crate_name("malloc"), None);
+        assert_eq!(crate_name("operator new(unsigned long)"), None);
+    }
+
+    #[test]
+    fn test_crate_report() {
+        let sites = [
+            site(
+                &[
+                    "alloc::raw_vec::finish_grow",
+                    "serde_json::read::parse_str",
+                    "serde::de::Visitor::visit_map",
+                    "app::config::load",
+                    "app::main",
+                ],
+                0x100,
+            ),
+            site(&["app::cache::insert", "app::main"], 0x20),
+            site(&["malloc", "libc_start_main"], 0x10),
+        ];
+
+        let report = CrateReport::new(&sites, &CrateOptions::default());
+        assert_eq!(report.crates[0].name, "serde_json");
+        assert_eq!(report.crates[0].cost.peak, 0x100);
+        assert_eq!(report.get("app").unwrap().inclusive.peak, 0x120);
+        assert_eq!(report.unattributed.peak, 0x10);
+
+        let options = CrateOptions {
+            workspace: vec!["app".into()],
+            ..Default::default()
+        };
+        let report = CrateReport::new(&sites, &options);
+        let dependencies: Vec<_> = report.dependencies().map(|c| c.name.as_str()).collect();
+        assert_eq!(dependencies, ["serde", "serde_json"]);
+        let serde = report.get("serde").unwrap();
+        assert_eq!(serde.cost.peak, 0x100);
+        assert_eq!(serde.top_functions[0].name, "serde::de::Visitor::visit_map");
+        assert!(report.get("app").unwrap().workspace);
+    }
+}
@@ -0,0 +1,141 @@
+//! Costs by allocating function: the leaf frame that called the allocator.
+//!
+//! Unlike a [`Profile`](crate::model::Profile), only the innermost frame of
+//! every site is resolved, so the report is cheap even for traces with deep
+//! stacks and is often enough to spot the culprits.
+
+use crate::model::{Cost, Metric};
+use crate::parser::AccumulatedData;
+use indexmap::IndexMap;
+use serde::Serialize;
+use std::fmt;
+
+/// Name of the function of sites whose leaf cannot be resolved.
+pub const UNKNOWN_FUNCTION: &str = "??";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LeafFunction {
+    pub function: String,
+    pub module: Option<String>,
+    /// Sites allocating directly from the function.
+    pub sites: usize,
+    pub cost: Cost,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LeafReport {
+    pub metric: Metric,
+    /// Functions ordered by descending `metric`.
+    pub functions: Vec<LeafFunction>,
+}
+
+impl LeafReport {
+    pub fn new(data: &AccumulatedData, metric: Metric) -> Self {
+        let mut by_function: IndexMap<(&str, Option<&str>), LeafFunction> = IndexMap::new();
+        for allocation in &data.allocations {
+            let (function, module) = leaf(data, allocation.trace_idx);
+            let entry = by_function
+                .entry((function, module))
+                .or_insert_with(|| LeafFunction {
+                    function: function.to_string(),
+                    module: module.map(str::to_string),
+                    sites: 0,
+                    cost: Cost::default(),
+                });
+            entry.sites += 1;
+            entry.cost.add(&Cost::from(&allocation.data));
+        }
+
+        let mut functions: Vec<LeafFunction> = by_function.into_values().collect();
+        functions.sort_by_key(|f| std::cmp::Reverse(f.cost.get(metric)));
+
+        Self { metric, functions }
+    }
+
+    /// Returns up to `limit` functions, skipping those where the metric is
+    /// zero.
+    pub fn top(&self, limit: usize) -> &[LeafFunction] {
+        let nonzero = self
+            .functions
+            .iter()
+            .take_while(|f| f.cost.get(self.metric) > 0)
+            .count();
+        &self.functions[..nonzero.min(limit)]
+    }
+}
+
+/// Function and module of the innermost frame of the trace at `trace_idx`.
+fn leaf(data: &AccumulatedData, trace_idx: u64) -> (&str, Option<&str>) {
+    let string = |idx: usize| idx.checked_sub(1).and_then(|i| data.strings.get(i));
+
+    let ip = trace_idx
+        .checked_sub(1)
+        .and_then(|i| data.traces.get(i as usize))
+        .and_then(|trace| trace.ip_idx.checked_sub(1))
+        .and_then(|i| data.instruction_pointers.get(i as usize));
+    let Some(ip) = ip else {
+        return (UNKNOWN_FUNCTION, None);
+    };
+
+    let function = string(ip.frame.function_idx()).map_or(UNKNOWN_FUNCTION, String::as_str);
+    (function, string(ip.module_idx).map(String::as_str))
+}
+
+impl fmt::Display for LeafReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "allocating functions by {}:", self.metric)?;
+        for function in &self.functions {
+            writeln!(
+                f,
+                "  {}: {} ({} sites)",
+                function.function,
+                function.cost.get(self.metric),
+                function.sites
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::leaf::LeafReport;
+    use crate::model::tests::{data, parse, TRACE};
+    use crate::model::{CostKind, Metric, Profile};
+
+    #[test]
+    fn test_leaf_report() {
+        let data = data();
+        let report = LeafReport::new(&data, Metric::Allocations);
+
+        let names: Vec<&str> = report
+            .functions
+            .iter()
+            .map(|f| f.function.as_str())
+            .collect();
+        assert_eq!(names, ["malloc_a", "b"]);
+        assert_eq!(report.functions[0].module.as_deref(), Some("/bin"));
+
+        // matches the exclusive costs of the full profile
+        let profile = Profile::new(&data).unwrap();
+        for function in &report.functions {
+            let exclusive = profile
+                .function(&function.function)
+                .unwrap()
+                .cost(CostKind::Exclusive);
+            assert_eq!(&function.cost, exclusive);
+        }
+
+        // a second site allocating from b
+        let data = parse(
+            &TRACE
+                .replace("a 20 4\n", "a 20 4\nt 3 2\na 8 5\n")
+                .replace("- 0\n", "- 0\n+ 2\n"),
+        );
+        let report = LeafReport::new(&data, Metric::Leaked);
+        assert_eq!(report.functions[0].function, "b");
+        assert_eq!(report.functions[0].sites, 2);
+        assert_eq!(report.functions[0].cost.leaked, 0x28);
+        assert_eq!(report.top(1)[0].function, "b");
+    }
+}
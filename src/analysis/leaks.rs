@@ -0,0 +1,95 @@
+//! Classification of leaked bytes by their reachability at exit.
+//!
+//! When the injected library scans the globals and thread-local storage of
+//! the program at exit, every allocation still referenced from them is
+//! marked with an `r` record. Leaks are then split like valgrind does:
+//! memory still reachable at exit was merely not freed before the program
+//! ended, while unreachable memory can no longer be freed at all.
+
+use crate::parser::{AccumulatedData, AllocationData};
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct LeakClassification {
+    /// Leaked bytes still referenced from a root at exit.
+    pub reachable: u64,
+    /// Leaked bytes no root referred to.
+    pub unreachable: u64,
+}
+
+impl LeakClassification {
+    fn new(data: &AllocationData) -> Self {
+        // a block can be reported reachable while it is freed by a late
+        // destructor, so `reachable` may exceed `leaked`
+        let reachable = data.reachable.min(data.leaked);
+        Self {
+            reachable,
+            unreachable: data.leaked - reachable,
+        }
+    }
+
+    pub fn leaked(&self) -> u64 {
+        self.reachable + self.unreachable
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct LeakReport {
+    /// Number of roots the library scanned.
+    pub roots: u64,
+    pub total: LeakClassification,
+    /// Classification per allocation, in the order of
+    /// [`AccumulatedData::allocations`].
+    pub sites: Vec<LeakClassification>,
+}
+
+impl LeakReport {
+    /// `None` for traces recorded without a root scan, whose leaks cannot
+    /// be classified.
+    pub fn new(data: &AccumulatedData) -> Option<Self> {
+        let roots = data.scanned_roots()?;
+
+        Some(Self {
+            roots,
+            total: LeakClassification::new(&data.total),
+            sites: data
+                .allocations
+                .iter()
+                .map(|allocation| LeakClassification::new(&allocation.data))
+                .collect(),
+        })
+    }
+}
+
+impl fmt::Display for LeakReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "roots scanned: {}", self.roots)?;
+        writeln!(f, "still reachable: {} bytes", self.total.reachable)?;
+        writeln!(f, "unreachable: {} bytes", self.total.unreachable)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::leaks::{LeakClassification, LeakReport};
+    use crate::model::tests::{parse, TRACE};
+
+    #[test]
+    fn test_leak_report() {
+        assert_eq!(LeakReport::new(&parse(TRACE)), None);
+
+        let trace = format!("{}M reachability.roots 1 3\nr 0\n", TRACE);
+        let report = LeakReport::new(&parse(&trace)).unwrap();
+        assert_eq!(report.roots, 3);
+        assert_eq!(
+            report.total,
+            LeakClassification {
+                reachable: 0x10,
+                unreachable: 0x20,
+            }
+        );
+        assert_eq!(report.sites[0].reachable, 0x10);
+        assert_eq!(report.sites[1].unreachable, 0x20);
+    }
+}
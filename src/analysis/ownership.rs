@@ -0,0 +1,104 @@
+//! Leaks and frees by the component owning the allocations.
+//!
+//! In handoff-heavy programs memory is often allocated by one subsystem,
+//! e.g. a network layer filling buffers, and released by another. Blaming
+//! the allocating stack then points at the wrong code. Allocations the
+//! program transfers with the ownership records are attributed to their
+//! last owner instead.
+
+use crate::parser::{AccumulatedData, Ownership};
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Owner {
+    pub name: String,
+    #[serde(flatten)]
+    pub ownership: Ownership,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct OwnershipReport {
+    /// Bytes transferred to any component.
+    pub transferred: u64,
+    /// Bytes still owned by a component at the end of the trace.
+    pub leaked: u64,
+    /// Owners ordered by descending leaked bytes.
+    pub owners: Vec<Owner>,
+}
+
+impl OwnershipReport {
+    pub fn new(data: &AccumulatedData) -> Self {
+        let mut owners: Vec<Owner> = data
+            .owners
+            .iter()
+            .map(|(&idx, ownership)| Owner {
+                name: idx
+                    .checked_sub(1)
+                    .and_then(|idx| data.strings.get(idx))
+                    .cloned()
+                    .unwrap_or_else(|| format!("<string {:#x}>", idx)),
+                ownership: *ownership,
+            })
+            .collect();
+        owners.sort_by_key(|owner| std::cmp::Reverse(owner.ownership.leaked));
+
+        Self {
+            transferred: owners.iter().map(|o| o.ownership.transferred).sum(),
+            leaked: owners.iter().map(|o| o.ownership.leaked).sum(),
+            owners,
+        }
+    }
+}
+
+impl fmt::Display for OwnershipReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "owners: {} bytes transferred, {} bytes leaked",
+            self.transferred, self.leaked
+        )?;
+        for owner in &self.owners {
+            let ownership = &owner.ownership;
+            writeln!(
+                f,
+                "  {}: {} bytes leaked, {} of {} transfers freed, {} handed over",
+                owner.name,
+                ownership.leaked,
+                ownership.frees,
+                ownership.transfers,
+                ownership.handed_over
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::ownership::OwnershipReport;
+    use crate::model::tests::{parse, TRACE};
+
+    #[test]
+    fn test_ownership_report() {
+        // the first allocation of info 1 goes to `cache`, then to `queue`
+        // which frees it; the second stays with `cache`
+        let trace = format!(
+            "{}s 5 cache\ns 5 queue\n+ 1\no 1 6\n+ 1\no 1 6\no 1 7 6\n- 1 0 7\n",
+            TRACE
+        );
+        let data = parse(&trace);
+        assert_eq!(data.owners[&6].transfers, 2);
+        assert_eq!(data.owners[&6].handed_over, 1);
+        assert_eq!(data.owners[&7].freed, 0x20);
+
+        let report = OwnershipReport::new(&data);
+        assert_eq!(report.transferred, 0x60);
+        assert_eq!(report.leaked, 0x20);
+        assert_eq!(report.owners[0].name, "cache");
+        assert_eq!(report.owners[1].ownership.leaked, 0);
+        assert!(report
+            .to_string()
+            .contains("  cache: 32 bytes leaked, 0 of 2 transfers freed, 1 handed over"));
+    }
+}
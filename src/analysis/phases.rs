@@ -0,0 +1,162 @@
+//! Segmentation of a trace into activity phases separated by idle periods.
+//!
+//! Request-driven servers allocate in bursts. Splitting the run at periods
+//! without allocations gives the cost of each burst instead of one total
+//! smeared over the whole run. Activity is derived from the `c` timeline
+//! samples, so phase boundaries have the resolution of the sampling interval.
+
+use crate::parser::{AccumulatedData, TimelinePoint};
+use serde::Serialize;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct IdlePeriod {
+    pub start: Duration,
+    pub end: Duration,
+}
+
+impl IdlePeriod {
+    pub fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Phase {
+    pub start: Duration,
+    pub end: Duration,
+    pub allocations: u64,
+    /// Change of the leaked bytes over the phase; negative if the phase freed
+    /// more than it allocated.
+    pub leaked_delta: i64,
+    /// Highest sampled heap size within the phase.
+    pub peak_leaked: u64,
+    pub peak_rss: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Phases {
+    pub phases: Vec<Phase>,
+    /// Idle periods of at least the requested length, including those before
+    /// the first and after the last phase.
+    pub idle: Vec<IdlePeriod>,
+}
+
+fn phase(samples: &[TimelinePoint]) -> Phase {
+    let (first, last) = (samples[0], samples[samples.len() - 1]);
+
+    Phase {
+        start: first.timestamp,
+        end: last.timestamp,
+        allocations: last.allocations - first.allocations,
+        leaked_delta: last.leaked as i64 - first.leaked as i64,
+        peak_leaked: samples.iter().map(|s| s.leaked).max().unwrap_or_default(),
+        peak_rss: samples.iter().map(|s| s.rss).max().unwrap_or_default(),
+    }
+}
+
+impl Phases {
+    /// Splits the trace at every stretch of at least `min_idle` without
+    /// allocations.
+    pub fn new(data: &AccumulatedData, min_idle: Duration) -> Self {
+        // the run starts with an empty heap
+        let samples: Vec<TimelinePoint> = std::iter::once(TimelinePoint::default())
+            .chain(data.timeline.iter().copied())
+            .collect();
+
+        let mut result = Phases::default();
+        // sample indices of the current phase
+        let mut current: Option<(usize, usize)> = None;
+        let mut last_active = 0;
+
+        for i in 1..samples.len() {
+            if samples[i].allocations == samples[i - 1].allocations {
+                continue;
+            }
+
+            let gap = IdlePeriod {
+                start: samples[last_active].timestamp,
+                end: samples[i - 1].timestamp,
+            };
+            let idle = gap.duration() >= min_idle && gap.duration() > Duration::ZERO;
+
+            current = match current {
+                Some((start, end)) if idle => {
+                    result.phases.push(phase(&samples[start..=end]));
+                    result.idle.push(gap);
+                    Some((i - 1, i))
+                }
+                Some((start, _)) => Some((start, i)),
+                None => {
+                    if idle {
+                        result.idle.push(gap);
+                    }
+                    Some((i - 1, i))
+                }
+            };
+            last_active = i;
+        }
+
+        if let Some((start, end)) = current {
+            result.phases.push(phase(&samples[start..=end]));
+        }
+
+        let trailing = IdlePeriod {
+            start: samples[last_active].timestamp,
+            end: samples[samples.len() - 1].timestamp,
+        };
+        if trailing.duration() >= min_idle && trailing.duration() > Duration::ZERO {
+            result.idle.push(trailing);
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::phases::Phases;
+    use crate::parser::{AccumulatedData, TimelinePoint};
+    use std::time::Duration;
+
+    #[test]
+    fn test_phases() {
+        let mut data = AccumulatedData::new();
+        // two bursts separated by 5s of silence, then 3s idle at the end
+        let samples = [
+            (1, 10, 100),
+            (2, 20, 300),
+            (3, 20, 100),
+            (8, 20, 100),
+            (9, 25, 400),
+            (12, 25, 0),
+        ];
+        data.timeline = samples
+            .iter()
+            .map(|&(secs, allocations, leaked)| TimelinePoint {
+                timestamp: Duration::from_secs(secs),
+                allocations,
+                leaked,
+                rss: 0,
+            })
+            .collect();
+
+        let result = Phases::new(&data, Duration::from_secs(2));
+
+        assert_eq!(result.phases.len(), 2);
+        assert_eq!(result.phases[0].start, Duration::ZERO);
+        assert_eq!(result.phases[0].end, Duration::from_secs(2));
+        assert_eq!(result.phases[0].allocations, 20);
+        assert_eq!(result.phases[0].peak_leaked, 300);
+        assert_eq!(result.phases[1].start, Duration::from_secs(8));
+        assert_eq!(result.phases[1].allocations, 5);
+        assert_eq!(result.phases[1].leaked_delta, 300);
+
+        let idle: Vec<_> = result.idle.iter().map(|p| p.duration()).collect();
+        assert_eq!(idle, [Duration::from_secs(6), Duration::from_secs(3)]);
+
+        let merged = Phases::new(&data, Duration::from_secs(10));
+        assert_eq!(merged.phases.len(), 1);
+        assert_eq!(merged.phases[0].allocations, 25);
+    }
+}
@@ -0,0 +1,139 @@
+//! Utilization of the pools and arenas declared by the traced program.
+//!
+//! A program serving allocations from pools of its own shows up in the
+//! trace as a few large allocations, hiding how much of them is used.
+//! Declaring the pools and the allocations they serve with the pool records
+//! lets the bytes a pool holds be compared with the bytes requested from it.
+
+use crate::parser::{AccumulatedData, Pool};
+use indexmap::IndexMap;
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct PoolUtilization {
+    pub name: String,
+    /// Pools created under the name, e.g. an arena per request.
+    pub instances: u64,
+    /// Capacity of all instances.
+    pub capacity: u64,
+    pub allocations: u64,
+    /// Peak bytes requested from every instance, summed.
+    pub peak_requested: u64,
+    /// Bytes still requested from instances live at the end of the trace.
+    pub requested: u64,
+}
+
+impl PoolUtilization {
+    /// Share of the capacity in use at the peak of every instance.
+    pub fn utilization(&self) -> f64 {
+        if self.capacity == 0 {
+            return 0.0;
+        }
+        self.peak_requested as f64 / self.capacity as f64
+    }
+
+    /// Capacity never requested, even at the peaks.
+    pub fn unused(&self) -> u64 {
+        self.capacity.saturating_sub(self.peak_requested)
+    }
+
+    fn add(&mut self, pool: &Pool) {
+        self.instances += 1;
+        self.capacity += pool.capacity;
+        self.allocations += pool.allocations;
+        self.peak_requested += pool.peak_requested;
+        self.requested += pool.requested;
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct PoolReport {
+    pub capacity: u64,
+    pub peak_requested: u64,
+    /// Pools merged by name, ordered by descending unused capacity.
+    pub pools: Vec<PoolUtilization>,
+}
+
+impl PoolReport {
+    pub fn new(data: &AccumulatedData) -> Self {
+        let mut by_name: IndexMap<&str, PoolUtilization> = IndexMap::new();
+        for pool in &data.pools {
+            by_name
+                .entry(&pool.name)
+                .or_insert_with(|| PoolUtilization {
+                    name: pool.name.clone(),
+                    ..Default::default()
+                })
+                .add(pool);
+        }
+
+        let mut pools: Vec<PoolUtilization> = by_name.into_values().collect();
+        pools.sort_by_key(|pool| std::cmp::Reverse(pool.unused()));
+
+        Self {
+            capacity: pools.iter().map(|pool| pool.capacity).sum(),
+            peak_requested: pools.iter().map(|pool| pool.peak_requested).sum(),
+            pools,
+        }
+    }
+}
+
+impl fmt::Display for PoolReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "pools: {} bytes of capacity, {} bytes requested at peak",
+            self.capacity, self.peak_requested
+        )?;
+        for pool in &self.pools {
+            writeln!(
+                f,
+                "  {} ({} instances): {:.1}% used, {} bytes unused, {} allocations",
+                pool.name,
+                pool.instances,
+                pool.utilization() * 100.0,
+                pool.unused(),
+                pool.allocations
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::pools::PoolReport;
+    use crate::model::tests::{parse, TRACE};
+    use crate::parser::AnomalyKind;
+
+    #[test]
+    fn test_pool_report() {
+        let trace = format!(
+            "{}P 100 5 frame\np 0 40\np 0 20\nq 0 40\nP 1000 5 arena\np 1 80\n\
+             D 0\nP 100 5 frame\np 2 10\nq 3 8\n",
+            TRACE
+        );
+        let data = parse(&trace);
+        assert_eq!(data.pools.len(), 3);
+        assert!(data.pools[0].destroyed);
+        assert_eq!(data.pools[0].requested, 0);
+        assert_eq!(
+            data.anomalies[0].kind,
+            AnomalyKind::UnknownPool {
+                record: 'q',
+                idx: 3
+            }
+        );
+
+        let report = PoolReport::new(&data);
+        assert_eq!(report.capacity, 0x1200);
+        assert_eq!(report.pools[0].name, "arena");
+        assert_eq!(report.pools[0].unused(), 0xf80);
+
+        let frame = &report.pools[1];
+        assert_eq!(frame.instances, 2);
+        assert_eq!(frame.peak_requested, 0x60 + 0x10);
+        assert_eq!(frame.requested, 0x10);
+    }
+}
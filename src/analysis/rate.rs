@@ -0,0 +1,210 @@
+//! Spikes of the allocation rate.
+//!
+//! The allocation rate of every interval between two timeline samples is
+//! compared with a high percentile of the rates of the whole run. Intervals
+//! above it are spikes, blamed on the sites that allocated the most within
+//! them. Bursts of allocations cost allocator time and fragment the heap
+//! even when they are freed right away, so they do not show up in the
+//! leaked or peak figures.
+
+use crate::model::Profile;
+use crate::numparse::parse_hex;
+use crate::parser;
+use crate::parser::{read_line, AccumulatedData};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateSpikeOptions {
+    /// Percentile of the interval rates above which an interval is a
+    /// spike, between 0 and 1.
+    pub percentile: f64,
+    pub top_sites: usize,
+}
+
+impl Default for RateSpikeOptions {
+    fn default() -> Self {
+        Self {
+            percentile: 0.99,
+            top_sites: 3,
+        }
+    }
+}
+
+/// Allocations of a site within a spike.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RateSite {
+    /// Index of the site in [`AccumulatedData::allocations`] and
+    /// [`Profile::sites`].
+    pub site: usize,
+    pub allocations: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RateSpike {
+    /// Times of the samples around the interval.
+    pub start: Duration,
+    pub end: Duration,
+    pub allocations: u64,
+    /// Allocations per second.
+    pub rate: f64,
+    /// Sites with the most allocations within the interval, most first.
+    pub sites: Vec<RateSite>,
+}
+
+impl RateSpike {
+    /// Leaf function of every site, `??` for unresolved ones.
+    pub fn functions<'a>(&self, profile: &'a Profile) -> Vec<&'a str> {
+        self.sites
+            .iter()
+            .map(|site| {
+                profile
+                    .sites
+                    .get(site.site)
+                    .and_then(|site| site.leaf())
+                    .map_or("??", |frame| frame.function.as_str())
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RateSpikes {
+    /// Allocation rate at the percentile, in allocations per second.
+    pub threshold: f64,
+    pub spikes: Vec<RateSpike>,
+}
+
+/// Finds the intervals of `data` allocating faster than the percentile of
+/// `options`. `input` is the trace `data` was parsed from, read again to
+/// attribute the allocations of the spikes to their sites.
+pub fn rate_spikes(
+    data: &AccumulatedData,
+    mut input: impl BufRead,
+    options: &RateSpikeOptions,
+) -> Result<RateSpikes, parser::Error> {
+    let mut spikes: Vec<RateSpike> = data
+        .timeline
+        .windows(2)
+        .filter(|pair| pair[1].timestamp > pair[0].timestamp)
+        .map(|pair| {
+            let allocations = pair[1].allocations.saturating_sub(pair[0].allocations);
+            RateSpike {
+                start: pair[0].timestamp,
+                end: pair[1].timestamp,
+                allocations,
+                rate: allocations as f64 / (pair[1].timestamp - pair[0].timestamp).as_secs_f64(),
+                sites: Vec::new(),
+            }
+        })
+        .collect();
+    if spikes.is_empty() {
+        return Ok(RateSpikes::default());
+    }
+
+    let mut rates: Vec<f64> = spikes.iter().map(|spike| spike.rate).collect();
+    rates.sort_by(f64::total_cmp);
+    let rank = (options.percentile.clamp(0.0, 1.0) * rates.len() as f64).ceil() as usize;
+    let threshold = rates[rank.clamp(1, rates.len()) - 1];
+
+    spikes.retain(|spike| spike.rate > threshold);
+    if spikes.is_empty() {
+        return Ok(RateSpikes { threshold, spikes });
+    }
+
+    let mut counts: Vec<HashMap<usize, u64>> = vec![HashMap::new(); spikes.len()];
+    let mut now = Duration::ZERO;
+    let mut line = Vec::new();
+    while read_line(&mut input, &mut line)? {
+        let mut fields = line.split(|&b| b == b' ').filter(|f| !f.is_empty());
+        match fields.next() {
+            Some(b"c") => {
+                let ms = fields.next().and_then(parse_hex);
+                now = Duration::from_millis(ms.ok_or(parser::Error::InvalidFormat)?);
+            }
+            Some(b"+") => {
+                let Some(info) = fields
+                    .next()
+                    .and_then(parse_hex)
+                    .and_then(|idx| data.allocation_infos.get(idx as usize))
+                else {
+                    continue;
+                };
+                // events after a sample happen before the next one
+                let idx = spikes.partition_point(|spike| spike.end <= now);
+                if let Some(spike) = spikes.get(idx)
+                    && spike.start <= now
+                {
+                    *counts[idx].entry(info.allocation_idx as usize).or_default() += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for (spike, counts) in spikes.iter_mut().zip(counts) {
+        let mut sites: Vec<_> = counts
+            .into_iter()
+            .map(|(site, allocations)| RateSite { site, allocations })
+            .collect();
+        sites.sort_by_key(|s| (std::cmp::Reverse(s.allocations), s.site));
+        sites.truncate(options.top_sites);
+        spike.sites = sites;
+    }
+
+    Ok(RateSpikes { threshold, spikes })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::rate::{rate_spikes, RateSite, RateSpikeOptions};
+    use crate::model::tests::{parse, TRACE};
+    use crate::model::Profile;
+    use crate::report::{Report, ReportOptions};
+    use std::time::Duration;
+
+    #[test]
+    fn test_rate_spikes() {
+        let trace = format!(
+            "{}+ 1\nc c8\n+ 0\nc 12c\n+ 1\n+ 1\n+ 1\n+ 0\nc 190\n+ 1\nc 1f4\n",
+            TRACE
+        );
+        let data = parse(&trace);
+        let options = RateSpikeOptions {
+            percentile: 0.5,
+            top_sites: 1,
+        };
+
+        let spikes = rate_spikes(&data, trace.as_bytes(), &options).unwrap();
+
+        // intervals of 1, 1, 4 and 1 allocations per 100ms
+        assert_eq!(spikes.threshold, 10.0);
+        assert_eq!(spikes.spikes.len(), 1);
+        let spike = &spikes.spikes[0];
+        assert_eq!(spike.start, Duration::from_millis(300));
+        assert_eq!(spike.end, Duration::from_millis(400));
+        assert_eq!(spike.allocations, 4);
+        assert_eq!(
+            spike.sites,
+            [RateSite {
+                site: 1,
+                allocations: 3
+            }]
+        );
+
+        let profile = Profile::new(&data).unwrap();
+        assert_eq!(spike.functions(&profile), ["b"]);
+
+        let report =
+            Report::new(&data, &profile, &ReportOptions::default()).with_spikes(&spikes, &profile);
+        let mut json = Vec::new();
+        report.write_json(&mut json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["spikes"][0]["sites"][0][0], "b");
+        let mut html = Vec::new();
+        report.write_html(&mut html).unwrap();
+        assert!(String::from_utf8(html).unwrap().contains("<h1>Spikes</h1>"));
+    }
+}
@@ -0,0 +1,154 @@
+//! Registry of analysis passes provided by other crates.
+//!
+//! Organizations often need checks of their own, e.g. the memory held by a
+//! subsystem or a house rule on allocation sizes. A crate implements them as
+//! an [`AnalysisPass`] and [`register`]s it once at startup; every later
+//! [`Report`](crate::report::Report) runs the registered passes selected by
+//! [`ReportOptions::analyses`](crate::report::ReportOptions::analyses) and
+//! shows their results next to the built-in sections.
+
+use crate::parser::AccumulatedData;
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+
+/// Result of an [`AnalysisPass`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct AnalysisOutput {
+    /// One-line summary shown in the HTML report.
+    pub summary: String,
+    /// Structured results, kept in the JSON report.
+    pub details: serde_json::Value,
+}
+
+/// A named analysis over a parsed trace.
+pub trait AnalysisPass: Send + Sync {
+    /// Unique name of the pass, used to select it, e.g. `acme-cache-budget`.
+    fn name(&self) -> &str;
+
+    fn description(&self) -> &str {
+        ""
+    }
+
+    fn run(&self, data: &AccumulatedData) -> AnalysisOutput;
+}
+
+/// Output of a pass run for a report.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AnalysisResult {
+    pub name: String,
+    #[serde(flatten)]
+    pub output: AnalysisOutput,
+}
+
+static PASSES: RwLock<Vec<Arc<dyn AnalysisPass>>> = RwLock::new(Vec::new());
+
+/// Adds `pass` to the registry, replacing a pass of the same name, which is
+/// returned.
+pub fn register(pass: impl AnalysisPass + 'static) -> Option<Arc<dyn AnalysisPass>> {
+    let pass: Arc<dyn AnalysisPass> = Arc::new(pass);
+    let mut passes = PASSES.write().unwrap();
+    match passes.iter_mut().find(|p| p.name() == pass.name()) {
+        Some(existing) => Some(std::mem::replace(existing, pass)),
+        None => {
+            passes.push(pass);
+            None
+        }
+    }
+}
+
+/// Removes the pass named `name`.
+pub fn unregister(name: &str) -> Option<Arc<dyn AnalysisPass>> {
+    let mut passes = PASSES.write().unwrap();
+    let idx = passes.iter().position(|p| p.name() == name)?;
+    Some(passes.remove(idx))
+}
+
+/// Registered passes in the order they were registered.
+pub fn passes() -> Vec<Arc<dyn AnalysisPass>> {
+    PASSES.read().unwrap().clone()
+}
+
+pub fn find(name: &str) -> Option<Arc<dyn AnalysisPass>> {
+    PASSES
+        .read()
+        .unwrap()
+        .iter()
+        .find(|p| p.name() == name)
+        .cloned()
+}
+
+/// Runs the passes named in `names`, in that order, or every registered
+/// pass if `None`. Names without a registered pass are skipped.
+pub fn run(data: &AccumulatedData, names: Option<&[String]>) -> Vec<AnalysisResult> {
+    let selected = match names {
+        Some(names) => names.iter().filter_map(|name| find(name)).collect(),
+        None => passes(),
+    };
+
+    selected
+        .iter()
+        .map(|pass| AnalysisResult {
+            name: pass.name().to_string(),
+            output: pass.run(data),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::registry::{
+        find, register, run, unregister, AnalysisOutput, AnalysisPass,
+    };
+    use crate::model::tests::data;
+    use crate::model::Profile;
+    use crate::parser::AccumulatedData;
+    use crate::report::{Report, ReportOptions};
+
+    struct LargeSites(u64);
+
+    impl AnalysisPass for LargeSites {
+        fn name(&self) -> &str {
+            "test-large-sites"
+        }
+
+        fn run(&self, data: &AccumulatedData) -> AnalysisOutput {
+            let large = data
+                .allocation_infos
+                .iter()
+                .filter(|info| info.size >= self.0)
+                .count();
+            AnalysisOutput {
+                summary: format!("{} allocation infos of {} bytes or more", large, self.0),
+                details: serde_json::json!({ "large": large }),
+            }
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        assert!(register(LargeSites(0x10)).is_none());
+        assert!(register(LargeSites(0x20)).is_some());
+
+        let data = data();
+        let names = ["test-large-sites".to_string(), "unknown".to_string()];
+        let results = run(&data, Some(&names));
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].output.details["large"], 1);
+
+        let profile = Profile::new(&data).unwrap();
+        let options = ReportOptions {
+            analyses: Some(names.to_vec()),
+            ..Default::default()
+        };
+        let report = Report::new(&data, &profile, &options);
+        assert_eq!(report.analyses, results);
+        let mut html = Vec::new();
+        report.write_html(&mut html).unwrap();
+        assert!(String::from_utf8(html)
+            .unwrap()
+            .contains("1 allocation infos of 32 bytes or more"));
+
+        assert!(unregister("test-large-sites").is_some());
+        assert!(find("test-large-sites").is_none());
+    }
+}
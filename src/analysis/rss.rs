@@ -0,0 +1,291 @@
+//! Composition of the peak RSS and the allocation sites behind RSS growth.
+//!
+//! At the timeline sample with the highest RSS, the resident memory is split
+//! into the live heap, the mappings present before the program allocated
+//! (code, libraries, stacks), estimated from the first sample, and the rest.
+//! The rest is mostly allocator overhead and freed memory not yet returned
+//! to the system.
+//!
+//! [`rss_spikes`] finds steps of the RSS between two timeline samples and
+//! blames them on the sites that allocated the most bytes in the window
+//! before the step.
+
+use crate::model::Profile;
+use crate::numparse::parse_hex;
+use crate::parser;
+use crate::parser::{read_line, AccumulatedData, TimelinePoint};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::BufRead;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct RssBreakdown {
+    pub peak_rss: u64,
+    /// Time of the sample with the peak RSS.
+    pub timestamp: Duration,
+    /// Live heap bytes at the peak RSS.
+    pub heap: u64,
+    /// Highest live heap of the run, which may lie elsewhere.
+    pub peak_heap: u64,
+    /// Resident memory not owned by the heap at the first sample.
+    pub mappings: u64,
+    pub unexplained: u64,
+    /// Physical memory of the traced host, 0 if unknown.
+    pub physical_memory: u64,
+}
+
+impl RssBreakdown {
+    /// `None` for traces without RSS samples.
+    pub fn new(data: &AccumulatedData) -> Option<Self> {
+        Self::from_samples(&data.timeline, data.total.peak, data.page_size * data.pages)
+    }
+
+    /// Breakdown of timeline samples of a run with the given heap peak and
+    /// physical memory.
+    pub fn from_samples(
+        timeline: &[TimelinePoint],
+        peak_heap: u64,
+        physical_memory: u64,
+    ) -> Option<Self> {
+        let samples = || timeline.iter().filter(|p| p.rss > 0);
+        let first = samples().next()?;
+        let peak = samples().max_by_key(|p| p.rss)?;
+
+        let heap = peak.leaked.min(peak.rss);
+        let mappings = first.rss.saturating_sub(first.leaked).min(peak.rss - heap);
+
+        Some(Self {
+            peak_rss: peak.rss,
+            timestamp: peak.timestamp,
+            heap,
+            peak_heap,
+            mappings,
+            unexplained: peak.rss - heap - mappings,
+            physical_memory,
+        })
+    }
+
+    /// Share of the peak RSS held by `bytes`.
+    pub fn share(&self, bytes: u64) -> f64 {
+        if self.peak_rss == 0 {
+            return 0.0;
+        }
+        bytes as f64 / self.peak_rss as f64
+    }
+}
+
+impl fmt::Display for RssBreakdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "peak RSS {} bytes at {:.1} s: {} heap ({:.0}%), {} mappings ({:.0}%), {} unexplained ({:.0}%)",
+            self.peak_rss,
+            self.timestamp.as_secs_f64(),
+            self.heap,
+            self.share(self.heap) * 100.0,
+            self.mappings,
+            self.share(self.mappings) * 100.0,
+            self.unexplained,
+            self.share(self.unexplained) * 100.0
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpikeOptions {
+    /// Smallest RSS increase between two samples reported as a spike.
+    pub min_growth: u64,
+    /// Length of the window before a spike whose allocations are blamed.
+    pub window: Duration,
+    pub top_sites: usize,
+}
+
+impl Default for SpikeOptions {
+    fn default() -> Self {
+        Self {
+            min_growth: 64 << 20,
+            window: Duration::from_secs(1),
+            top_sites: 3,
+        }
+    }
+}
+
+/// Bytes allocated by a site within the window of a spike.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SpikeSite {
+    /// Index of the site in [`AccumulatedData::allocations`] and
+    /// [`Profile::sites`].
+    pub site: usize,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RssSpike {
+    /// Time of the sample after the step.
+    pub timestamp: Duration,
+    pub rss: u64,
+    pub growth: u64,
+    /// Bytes allocated within the window.
+    pub allocated: u64,
+    /// Sites that allocated the most bytes within the window, most first.
+    pub sites: Vec<SpikeSite>,
+}
+
+impl RssSpike {
+    /// Share of the bytes allocated within the window held by `bytes`.
+    pub fn share(&self, bytes: u64) -> f64 {
+        if self.allocated == 0 {
+            return 0.0;
+        }
+        bytes as f64 / self.allocated as f64
+    }
+
+    /// One-line summary naming the top site by its allocating function.
+    pub fn describe(&self, profile: &Profile) -> String {
+        let mut text = format!(
+            "RSS grew {} bytes at t={:.1}s",
+            self.growth,
+            self.timestamp.as_secs_f64()
+        );
+        if let Some(top) = self.sites.first() {
+            let function = profile
+                .sites
+                .get(top.site)
+                .and_then(|site| site.leaf())
+                .map_or("??", |frame| frame.function.as_str());
+            text += &format!(
+                "; {:.0}% of bytes allocated in that window came from {}",
+                self.share(top.bytes) * 100.0,
+                function
+            );
+        }
+        text
+    }
+}
+
+/// Finds the RSS spikes of `data` and blames them on the sites allocating
+/// within their windows. `input` is the trace `data` was parsed from, read
+/// again for the allocation times; these have the resolution of the timeline
+/// samples.
+pub fn rss_spikes(
+    data: &AccumulatedData,
+    mut input: impl BufRead,
+    options: &SpikeOptions,
+) -> Result<Vec<RssSpike>, parser::Error> {
+    let samples: Vec<_> = data.timeline.iter().filter(|p| p.rss > 0).collect();
+    let mut spikes: Vec<RssSpike> = samples
+        .windows(2)
+        .filter(|pair| pair[1].rss >= pair[0].rss + options.min_growth.max(1))
+        .map(|pair| RssSpike {
+            timestamp: pair[1].timestamp,
+            rss: pair[1].rss,
+            growth: pair[1].rss - pair[0].rss,
+            allocated: 0,
+            sites: Vec::new(),
+        })
+        .collect();
+    if spikes.is_empty() {
+        return Ok(spikes);
+    }
+
+    let mut bytes: Vec<HashMap<usize, u64>> = vec![HashMap::new(); spikes.len()];
+    let mut now = Duration::ZERO;
+    let mut line = Vec::new();
+    while read_line(&mut input, &mut line)? {
+        let mut fields = line.split(|&b| b == b' ').filter(|f| !f.is_empty());
+        match fields.next() {
+            Some(b"c") => {
+                let ms = fields.next().and_then(parse_hex);
+                now = Duration::from_millis(ms.ok_or(parser::Error::InvalidFormat)?);
+            }
+            Some(b"+") => {
+                let Some(info) = fields
+                    .next()
+                    .and_then(parse_hex)
+                    .and_then(|idx| data.allocation_infos.get(idx as usize))
+                else {
+                    continue;
+                };
+                // events after a sample happen before the next one
+                for (spike, bytes) in spikes.iter_mut().zip(&mut bytes) {
+                    if now < spike.timestamp && now + options.window >= spike.timestamp {
+                        spike.allocated += info.size;
+                        *bytes.entry(info.allocation_idx as usize).or_default() += info.size;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for (spike, bytes) in spikes.iter_mut().zip(bytes) {
+        let mut sites: Vec<_> = bytes
+            .into_iter()
+            .map(|(site, bytes)| SpikeSite { site, bytes })
+            .collect();
+        sites.sort_by_key(|s| (std::cmp::Reverse(s.bytes), s.site));
+        sites.truncate(options.top_sites);
+        spike.sites = sites;
+    }
+
+    Ok(spikes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::rss::{rss_spikes, RssBreakdown, SpikeOptions, SpikeSite};
+    use crate::model::tests::{parse, TRACE};
+    use crate::model::Profile;
+    use std::time::Duration;
+
+    #[test]
+    fn test_breakdown() {
+        assert_eq!(RssBreakdown::new(&parse("v 3 3\n")), None);
+
+        let data = parse(&format!(
+            "{}c c8\nR 1800\n+ 1\nc 12c\nR 1400\n",
+            TRACE.replace("c 64\nR 1000", "c 32\nR 1000\nc 64\nR 1000")
+        ));
+        let breakdown = RssBreakdown::new(&data).unwrap();
+
+        assert_eq!(breakdown.peak_rss, 0x1800);
+        assert_eq!(breakdown.timestamp.as_millis(), 300);
+        assert_eq!(breakdown.heap, 0x50);
+        // the first sample holds 0x30 heap bytes
+        assert_eq!(breakdown.mappings, 0x1000 - 0x30);
+        assert_eq!(breakdown.unexplained, 0x1800 - 0x50 - 0xfd0);
+        assert_eq!(breakdown.physical_memory, 0x4000 * 0x100);
+    }
+
+    #[test]
+    fn test_rss_spikes() {
+        let trace = format!("{}c c8\n+ 1\n+ 1\n+ 0\nc 12c\nR 3000\nc 190\n", TRACE);
+        let data = parse(&trace);
+        let options = SpikeOptions {
+            min_growth: 0x1000,
+            window: Duration::from_millis(200),
+            top_sites: 1,
+        };
+
+        let spikes = rss_spikes(&data, trace.as_bytes(), &options).unwrap();
+
+        assert_eq!(spikes.len(), 1);
+        let spike = &spikes[0];
+        assert_eq!(spike.timestamp, Duration::from_millis(400));
+        assert_eq!(spike.growth, 0x2000);
+        assert_eq!(spike.allocated, 0x50);
+        assert_eq!(
+            spike.sites,
+            [SpikeSite {
+                site: 1,
+                bytes: 0x40
+            }]
+        );
+        assert_eq!(
+            spike.describe(&Profile::new(&data).unwrap()),
+            "RSS grew 8192 bytes at t=0.4s; 80% of bytes allocated in that window came from b"
+        );
+    }
+}
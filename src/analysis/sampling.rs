@@ -0,0 +1,174 @@
+//! Scaling of sampled traces back to full-run estimates.
+//!
+//! With sampling each allocation is recorded independently with probability
+//! `rate`, so an observed count `n` estimates `n / rate` events with a
+//! variance of `n * (1 - rate) / rate²`. Byte figures use the same model with
+//! the mean allocation size of the sample.
+
+use crate::model::{Cost, Profile};
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Estimate {
+    pub value: f64,
+    pub lower: f64,
+    pub upper: f64,
+}
+
+impl Estimate {
+    fn exact(value: f64) -> Self {
+        Self {
+            value,
+            lower: value,
+            upper: value,
+        }
+    }
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.lower == self.upper {
+            write!(f, "{:.0}", self.value)
+        } else {
+            write!(
+                f,
+                "{:.0} [{:.0}, {:.0}]",
+                self.value, self.lower, self.upper
+            )
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct EstimatedCost {
+    pub allocations: Estimate,
+    pub temporary: Estimate,
+    pub leaked: Estimate,
+    pub peak: Estimate,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EstimatedProfile {
+    pub rate: f64,
+    pub confidence: f64,
+    pub total: EstimatedCost,
+    /// Estimates in the same order as [`Profile::sites`].
+    pub sites: Vec<EstimatedCost>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Sampling {
+    rate: f64,
+    confidence: f64,
+    z: f64,
+}
+
+impl Sampling {
+    /// Creates a model for a trace recorded with the given sampling `rate`
+    /// in `(0, 1]` and a 95% confidence level.
+    pub fn new(rate: f64) -> Self {
+        let rate = rate.clamp(f64::MIN_POSITIVE, 1.0);
+        Self {
+            rate,
+            confidence: 0.95,
+            z: z_score(0.95),
+        }
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    pub fn confidence(&self) -> f64 {
+        self.confidence
+    }
+
+    /// Sets the two-sided confidence level of the intervals, e.g. `0.99`.
+    pub fn with_confidence(mut self, confidence: f64) -> Self {
+        self.confidence = confidence.clamp(0.5, 0.9999);
+        self.z = z_score(self.confidence);
+        self
+    }
+
+    pub fn count(&self, observed: u64) -> Estimate {
+        if self.rate >= 1.0 {
+            return Estimate::exact(observed as f64);
+        }
+
+        let n = observed as f64;
+        let value = n / self.rate;
+        let margin = self.z * (n * (1.0 - self.rate)).sqrt() / self.rate;
+
+        Estimate {
+            value,
+            lower: (value - margin).max(n),
+            upper: value + margin,
+        }
+    }
+
+    pub fn bytes(&self, observed: u64, samples: u64) -> Estimate {
+        if self.rate >= 1.0 || samples == 0 {
+            return Estimate::exact(observed as f64 / self.rate.min(1.0));
+        }
+
+        let count = self.count(samples);
+        let mean = observed as f64 / samples as f64;
+
+        Estimate {
+            value: count.value * mean,
+            lower: count.lower * mean,
+            upper: count.upper * mean,
+        }
+    }
+
+    pub fn cost(&self, cost: &Cost) -> EstimatedCost {
+        EstimatedCost {
+            allocations: self.count(cost.allocations),
+            temporary: self.count(cost.temporary),
+            leaked: self.bytes(cost.leaked, cost.allocations),
+            peak: self.bytes(cost.peak, cost.allocations),
+        }
+    }
+
+    pub fn expand(&self, profile: &Profile) -> EstimatedProfile {
+        EstimatedProfile {
+            rate: self.rate,
+            confidence: self.confidence,
+            total: self.cost(&profile.total),
+            sites: profile.sites.iter().map(|s| self.cost(&s.cost)).collect(),
+        }
+    }
+}
+
+/// Two-sided standard normal quantile for `confidence`, using the rational
+/// approximation 26.2.23 from Abramowitz and Stegun.
+fn z_score(confidence: f64) -> f64 {
+    let p = (1.0 - confidence) / 2.0;
+    let t = (-2.0 * p.ln()).sqrt();
+
+    t - (2.515517 + 0.802853 * t + 0.010328 * t * t)
+        / (1.0 + 1.432788 * t + 0.189269 * t * t + 0.001308 * t * t * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::sampling::{z_score, Sampling};
+
+    #[test]
+    fn test_z_score() {
+        assert!((z_score(0.95) - 1.96).abs() < 0.01);
+        assert!((z_score(0.99) - 2.576).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_count_estimate() {
+        let estimate = Sampling::new(0.01).count(100);
+        assert_eq!(estimate.value, 10000.0);
+        assert!(estimate.lower < 10000.0 && estimate.lower >= 100.0);
+        assert!(estimate.upper > 10000.0);
+
+        let exact = Sampling::new(1.0).count(100);
+        assert_eq!(exact.lower, exact.upper);
+    }
+}
@@ -0,0 +1,81 @@
+//! Allocator size-class models.
+//!
+//! Allocators round requests up to a fixed set of size classes. A model of
+//! those classes tells how many bytes a request actually occupies.
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SizeClasses {
+    /// Ascending class sizes.
+    pub classes: Vec<u64>,
+    /// Requests above the largest class are rounded up to a multiple of this.
+    pub large_quantum: u64,
+}
+
+impl SizeClasses {
+    pub fn new(mut classes: Vec<u64>, large_quantum: u64) -> Self {
+        classes.sort_unstable();
+        classes.dedup();
+        Self {
+            classes,
+            large_quantum: large_quantum.max(1),
+        }
+    }
+
+    /// Approximation of the macOS default zone: 16-byte quanta up to 1 KiB,
+    /// 512-byte quanta up to 128 KiB and 16 KiB pages beyond.
+    pub fn macos() -> Self {
+        let tiny = (1..=64).map(|i| i * 16);
+        let small = (3..=256).map(|i| i * 512);
+        Self::new(tiny.chain(small).collect(), 16384)
+    }
+
+    /// Approximation of jemalloc: four classes per power of two up to
+    /// 14 KiB and 4 KiB pages beyond.
+    pub fn jemalloc() -> Self {
+        let mut classes = vec![8, 16, 32, 48, 64, 80, 96, 112, 128];
+        let mut base = 128;
+        while base < 8192 {
+            let step = base / 4;
+            classes.extend((1..=4).map(|i| base + i * step));
+            base *= 2;
+        }
+        Self::new(classes, 4096)
+    }
+
+    /// Bytes occupied by a request of `size` bytes.
+    pub fn usable(&self, size: u64) -> u64 {
+        match self.classes.binary_search(&size) {
+            Ok(idx) => self.classes[idx],
+            Err(idx) if idx < self.classes.len() => self.classes[idx],
+            Err(_) => size.div_ceil(self.large_quantum) * self.large_quantum,
+        }
+    }
+
+    /// The largest class below `size`, used to tell how far a request
+    /// overshoots the previous class.
+    pub fn previous(&self, size: u64) -> u64 {
+        match self.classes.binary_search(&size) {
+            Ok(0) | Err(0) => 0,
+            Ok(idx) | Err(idx) if idx <= self.classes.len() => self.classes[idx - 1],
+            _ => self.classes.last().copied().unwrap_or(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::size_class::SizeClasses;
+
+    #[test]
+    fn test_usable() {
+        let classes = SizeClasses::jemalloc();
+        assert_eq!(classes.usable(1), 8);
+        assert_eq!(classes.usable(129), 160);
+        assert_eq!(classes.usable(8192), 8192);
+        assert_eq!(classes.usable(20000), 20480);
+        assert_eq!(classes.previous(129), 128);
+        assert_eq!(classes.previous(8), 0);
+    }
+}
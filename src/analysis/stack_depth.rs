@@ -0,0 +1,77 @@
+//! Distribution of stack depths over allocation sites.
+//!
+//! A pile-up of sites at the capture depth limit points at truncated stacks,
+//! while many very shallow stacks hint at unwinding failures.
+
+use crate::model::{Cost, Profile};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DepthBucket {
+    pub depth: usize,
+    pub sites: u64,
+    pub cost: Cost,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DepthHistogram {
+    /// One bucket per depth from zero to the deepest stack.
+    pub buckets: Vec<DepthBucket>,
+}
+
+impl DepthHistogram {
+    /// Builds the histogram over resolved frames, so inlined functions count
+    /// as separate frames.
+    pub fn new(profile: &Profile) -> Self {
+        let mut buckets: Vec<DepthBucket> = Vec::new();
+
+        for site in &profile.sites {
+            let depth = site.stack.len();
+            if buckets.len() <= depth {
+                buckets.extend((buckets.len()..=depth).map(|depth| DepthBucket {
+                    depth,
+                    ..Default::default()
+                }));
+            }
+
+            let bucket = &mut buckets[depth];
+            bucket.sites += 1;
+            bucket.cost.add(&site.cost);
+        }
+
+        Self { buckets }
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.buckets.len().saturating_sub(1)
+    }
+
+    /// Share of sites whose stack reached at least `limit` frames.
+    pub fn truncated_share(&self, limit: usize) -> f64 {
+        let total: u64 = self.buckets.iter().map(|b| b.sites).sum();
+        if total == 0 {
+            return 0.0;
+        }
+
+        let truncated: u64 = self.buckets.iter().skip(limit).map(|b| b.sites).sum();
+        truncated as f64 / total as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::stack_depth::DepthHistogram;
+    use crate::model::tests::data;
+    use crate::model::Profile;
+
+    #[test]
+    fn test_histogram() {
+        let profile = Profile::new(&data()).unwrap();
+        let histogram = DepthHistogram::new(&profile);
+
+        assert_eq!(histogram.max_depth(), 3);
+        assert_eq!(histogram.buckets[2].sites, 1);
+        assert_eq!(histogram.buckets[3].cost.allocations, 2);
+        assert_eq!(histogram.truncated_share(3), 0.5);
+    }
+}
@@ -0,0 +1,108 @@
+//! Distribution of memory costs across transactions.
+//!
+//! Transactions are delimited by marker records sent by the traced program,
+//! see [`Transaction`]. A server handling many similar requests shows its
+//! typical cost in the median and its outliers in the high percentiles.
+
+use crate::parser::{AccumulatedData, Transaction};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Distribution {
+    pub min: u64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub max: u64,
+    pub mean: f64,
+}
+
+impl Distribution {
+    pub fn new(mut values: Vec<u64>) -> Self {
+        if values.is_empty() {
+            return Self::default();
+        }
+        values.sort_unstable();
+
+        // nearest-rank percentile
+        let percentile = |p: usize| values[(values.len() * p).div_ceil(100).max(1) - 1];
+
+        Self {
+            min: values[0],
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: values[values.len() - 1],
+            mean: values.iter().sum::<u64>() as f64 / values.len() as f64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TransactionStats {
+    pub count: usize,
+    pub allocations: Distribution,
+    pub allocated: Distribution,
+    pub peak: Distribution,
+    /// Bytes left allocated at the end, transactions freeing more than they
+    /// allocated count as zero.
+    pub retained: Distribution,
+    /// Labels of the transactions with the highest peak, highest first.
+    pub top: Vec<String>,
+}
+
+impl TransactionStats {
+    pub fn new(data: &AccumulatedData, top: usize) -> Self {
+        Self::from_transactions(&data.transactions, top)
+    }
+
+    pub fn from_transactions(transactions: &[Transaction], top: usize) -> Self {
+        let collect = |f: fn(&Transaction) -> u64| transactions.iter().map(f).collect();
+
+        let mut ranked: Vec<&Transaction> = transactions.iter().collect();
+        ranked.sort_by_key(|t| std::cmp::Reverse(t.peak));
+
+        Self {
+            count: transactions.len(),
+            allocations: Distribution::new(collect(|t| t.allocations)),
+            allocated: Distribution::new(collect(|t| t.allocated)),
+            peak: Distribution::new(collect(|t| t.peak)),
+            retained: Distribution::new(collect(|t| t.retained().max(0) as u64)),
+            top: ranked.iter().take(top).map(|t| t.label.clone()).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::transactions::{Distribution, TransactionStats};
+    use crate::parser::Transaction;
+
+    #[test]
+    fn test_distribution() {
+        let distribution = Distribution::new((1..=100).collect());
+        assert_eq!(distribution.min, 1);
+        assert_eq!(distribution.p50, 50);
+        assert_eq!(distribution.p99, 99);
+        assert_eq!(distribution.max, 100);
+        assert_eq!(distribution.mean, 50.5);
+
+        let transactions: Vec<_> = [(10, 5), (30, 40), (20, 20)]
+            .iter()
+            .enumerate()
+            .map(|(i, &(allocated, freed))| Transaction {
+                label: format!("req-{}", i),
+                allocated,
+                freed,
+                peak: allocated,
+                ..Default::default()
+            })
+            .collect();
+
+        let stats = TransactionStats::from_transactions(&transactions, 2);
+        assert_eq!(stats.count, 3);
+        assert_eq!(stats.peak.p50, 20);
+        assert_eq!(stats.retained.max, 5);
+        assert_eq!(stats.top, ["req-1", "req-2"]);
+    }
+}
@@ -0,0 +1,106 @@
+//! Waste analysis: bytes lost to rounding requests up to size classes.
+
+use crate::analysis::size_class::SizeClasses;
+use crate::model::Profile;
+use crate::parser::AccumulatedData;
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SiteWaste {
+    /// Index into [`Profile::sites`].
+    pub site_idx: usize,
+    pub function: Option<String>,
+    pub allocations: u64,
+    pub requested: u64,
+    /// Usable minus requested bytes over all allocations of the site.
+    pub slack: u64,
+    /// Requested sizes just above a class boundary, where a slightly smaller
+    /// request would halve the slack or better.
+    pub straddling_sizes: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct WasteReport {
+    pub requested: u64,
+    pub slack: u64,
+    /// Sites ordered by descending slack.
+    pub sites: Vec<SiteWaste>,
+}
+
+impl WasteReport {
+    /// Computes the slack of every allocation in `data`. `profile` must be
+    /// built from the same data.
+    pub fn new(data: &AccumulatedData, profile: &Profile, classes: &SizeClasses) -> Self {
+        let mut sites: Vec<SiteWaste> = profile
+            .sites
+            .iter()
+            .enumerate()
+            .map(|(site_idx, site)| SiteWaste {
+                site_idx,
+                function: site.leaf().map(|f| f.function.clone()),
+                allocations: 0,
+                requested: 0,
+                slack: 0,
+                straddling_sizes: Vec::new(),
+            })
+            .collect();
+
+        let mut report = WasteReport::default();
+
+        for info in &data.allocation_infos {
+            let Some(site) = sites.get_mut(info.allocation_idx as usize) else {
+                continue;
+            };
+            if info.allocations == 0 {
+                continue;
+            }
+
+            let usable = classes.usable(info.size);
+            let slack = (usable - info.size) * info.allocations;
+            site.allocations += info.allocations;
+            site.requested += info.size * info.allocations;
+            site.slack += slack;
+
+            report.requested += info.size * info.allocations;
+            report.slack += slack;
+
+            let previous = classes.previous(info.size);
+            let overshoot = info.size - previous;
+            if previous > 0 && overshoot * 2 <= usable - previous && usable > info.size {
+                site.straddling_sizes.push(info.size);
+            }
+        }
+
+        sites.retain(|s| s.slack > 0);
+        sites.sort_by_key(|s| std::cmp::Reverse(s.slack));
+        for site in &mut sites {
+            site.straddling_sizes.sort_unstable();
+        }
+        report.sites = sites;
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::size_class::SizeClasses;
+    use crate::analysis::waste::WasteReport;
+    use crate::model::tests::data;
+    use crate::model::Profile;
+
+    #[test]
+    fn test_waste() {
+        let data = data();
+        let profile = Profile::new(&data).unwrap();
+        let classes = SizeClasses::new(vec![8, 12, 48], 64);
+
+        let report = WasteReport::new(&data, &profile, &classes);
+        assert_eq!(report.requested, 0x40);
+        // two 16-byte requests in 48-byte classes and one 32-byte request
+        assert_eq!(report.slack, 2 * 32 + 16);
+        assert_eq!(report.sites[0].function.as_deref(), Some("malloc_a"));
+        assert_eq!(report.sites[0].straddling_sizes, [16]);
+        assert!(report.sites[1].straddling_sizes.is_empty());
+    }
+}
@@ -0,0 +1,118 @@
+//! Investigation notes attached to allocation sites after the fact.
+//!
+//! Notes like "known cache, bounded" are stored in a sidecar file next to
+//! the trace, `<trace>.annotations.json`, so they travel with it between
+//! teammates without rewriting the trace. [`Parser::parse_file`] loads the
+//! sidecar of the file it parses and the exporters render the notes of
+//! every site they show.
+//!
+//! [`Parser::parse_file`]: crate::parser::Parser::parse_file
+
+use crate::model::Site;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const EXTENSION: &str = "annotations.json";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Annotations {
+    /// Notes by [site signature](Site::signature), in the order they were
+    /// added.
+    pub notes: BTreeMap<String, Vec<String>>,
+}
+
+impl Annotations {
+    /// Path of the sidecar file of the trace at `trace_path`.
+    pub fn sidecar_path(trace_path: impl AsRef<Path>) -> PathBuf {
+        let mut path = OsString::from(trace_path.as_ref());
+        path.push(".");
+        path.push(EXTENSION);
+        PathBuf::from(path)
+    }
+
+    /// Reads the sidecar file of the trace at `trace_path`. A trace without
+    /// one has no annotations.
+    pub fn load(trace_path: impl AsRef<Path>) -> io::Result<Self> {
+        match fs::read(Self::sidecar_path(trace_path)) {
+            Ok(content) => Ok(serde_json::from_slice(&content)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes the sidecar file of the trace at `trace_path`.
+    pub fn save(&self, trace_path: impl AsRef<Path>) -> io::Result<()> {
+        let json = serde_json::to_vec_pretty(self)?;
+        fs::write(Self::sidecar_path(trace_path), json)
+    }
+
+    pub fn add(&mut self, site_signature: &str, note: &str) {
+        self.notes
+            .entry(site_signature.to_string())
+            .or_default()
+            .push(note.to_string());
+    }
+
+    pub fn get(&self, site_signature: &str) -> &[String] {
+        self.notes.get(site_signature).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn for_site(&self, site: &Site) -> &[String] {
+        if self.notes.is_empty() {
+            return &[];
+        }
+        self.get(&site.signature())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::annotations::Annotations;
+    use crate::model::tests::data;
+    use crate::model::Profile;
+    use crate::parser::Parser;
+    use crate::report::{Report, ReportOptions};
+
+    #[test]
+    fn test_annotations() {
+        let path =
+            std::env::temp_dir().join(format!("memtrack-notes-{}.trace", std::process::id()));
+        std::fs::write(&path, crate::model::tests::TRACE).unwrap();
+
+        let mut data = data();
+        let profile = Profile::new(&data).unwrap();
+        let signature = profile.sites[0].signature();
+        assert_eq!(signature, "main;a;malloc_a");
+        data.annotate(&signature, "known cache, bounded");
+        data.annotations.save(&path).unwrap();
+
+        let parsed = Parser::new().parse_file(&path);
+        _ = std::fs::remove_file(&path);
+        _ = std::fs::remove_file(Annotations::sidecar_path(&path));
+
+        let data = parsed.unwrap();
+        assert_eq!(data.annotations.get(&signature), ["known cache, bounded"]);
+
+        let report = Report::new(&data, &profile, &ReportOptions::default());
+        let site = report
+            .sites
+            .iter()
+            .find(|site| site.stack[0].function == "malloc_a")
+            .unwrap();
+        assert_eq!(site.notes, ["known cache, bounded"]);
+
+        let mut html = Vec::new();
+        report.write_html(&mut html).unwrap();
+        assert!(String::from_utf8(html)
+            .unwrap()
+            .contains("title=\"known cache, bounded\""));
+    }
+}
@@ -0,0 +1,282 @@
+//! Allocation budgets declared in a TOML manifest and checked against a
+//! trace.
+//!
+//! ```toml
+//! [total]
+//! peak = "512 MB"
+//!
+//! [[budget]]
+//! name = "serde"
+//! functions = ["serde::", "serde_json::"]
+//! peak = "50 MB"
+//!
+//! [[budget]]
+//! name = "networking"
+//! modules = ["libssl"]
+//! temporary = "10k"
+//! ```
+//!
+//! A site counts towards a budget when any frame of its stack contains one
+//! of the `functions` patterns or lies in a module whose path contains one
+//! of the `modules` patterns. Byte limits accept the suffixes `KB`, `MB`,
+//! `GB` and `KiB`, `MiB`, `GiB`, counts `k`, `M` and `G`.
+
+use crate::model::{Cost, Metric, Profile, Site};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("IO error")]
+    Io(#[from] io::Error),
+    #[error("invalid manifest: {0}")]
+    Toml(#[from] toml::de::Error),
+}
+
+/// Parses a quantity like `50 MB`, `1.5GiB` or `10k`.
+pub fn parse_quantity(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let factor: u64 = match unit.trim() {
+        "" | "B" => 1,
+        "k" | "K" | "KB" | "kB" => 1_000,
+        "M" | "MB" => 1_000_000,
+        "G" | "GB" => 1_000_000_000,
+        "KiB" => 1 << 10,
+        "MiB" => 1 << 20,
+        "GiB" => 1 << 30,
+        _ => return None,
+    };
+
+    if let Ok(number) = number.parse::<u64>() {
+        return number.checked_mul(factor);
+    }
+    let number: f64 = number.parse().ok()?;
+    Some((number * factor as f64) as u64)
+}
+
+fn quantity<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Quantity {
+        Number(u64),
+        Text(String),
+    }
+
+    match Option::<Quantity>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Quantity::Number(number)) => Ok(Some(number)),
+        Some(Quantity::Text(text)) => parse_quantity(&text)
+            .map(Some)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid quantity `{}`", text))),
+    }
+}
+
+/// Upper bounds of the [`Cost`] metrics. Unset metrics are not checked.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    #[serde(default, deserialize_with = "quantity")]
+    pub allocations: Option<u64>,
+    #[serde(default, deserialize_with = "quantity")]
+    pub temporary: Option<u64>,
+    #[serde(default, deserialize_with = "quantity")]
+    pub leaked: Option<u64>,
+    #[serde(default, deserialize_with = "quantity")]
+    pub peak: Option<u64>,
+}
+
+impl Limits {
+    fn iter(&self) -> impl Iterator<Item = (Metric, u64)> {
+        [
+            (Metric::Allocations, self.allocations),
+            (Metric::Temporary, self.temporary),
+            (Metric::Leaked, self.leaked),
+            (Metric::Peak, self.peak),
+        ]
+        .into_iter()
+        .filter_map(|(metric, limit)| Some((metric, limit?)))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct Budget {
+    pub name: String,
+    #[serde(default)]
+    pub functions: Vec<String>,
+    #[serde(default)]
+    pub modules: Vec<String>,
+    #[serde(flatten)]
+    pub limits: Limits,
+}
+
+impl Budget {
+    pub fn matches(&self, site: &Site) -> bool {
+        site.stack.iter().any(|frame| {
+            self.functions
+                .iter()
+                .any(|p| frame.function.contains(p.as_str()))
+                || frame
+                    .module
+                    .as_deref()
+                    .is_some_and(|m| self.modules.iter().any(|p| m.contains(p.as_str())))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// Limits of the whole run.
+    #[serde(default)]
+    pub total: Limits,
+    #[serde(default, rename = "budget")]
+    pub budgets: Vec<Budget>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BudgetCheck {
+    /// Budget name, `total` for the limits of the whole run.
+    pub budget: String,
+    pub metric: Metric,
+    pub limit: u64,
+    pub actual: u64,
+}
+
+impl BudgetCheck {
+    pub fn passed(&self) -> bool {
+        self.actual <= self.limit
+    }
+}
+
+impl fmt::Display for BudgetCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}: {} of {}",
+            if self.passed() { "PASS" } else { "FAIL" },
+            self.budget,
+            self.metric,
+            self.actual,
+            self.limit
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct BudgetReport {
+    pub checks: Vec<BudgetCheck>,
+}
+
+impl BudgetReport {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|c| c.passed())
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &BudgetCheck> {
+        self.checks.iter().filter(|c| !c.passed())
+    }
+}
+
+impl fmt::Display for BudgetReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            writeln!(f, "{}", check)?;
+        }
+        Ok(())
+    }
+}
+
+impl Manifest {
+    pub fn parse(content: &str) -> Result<Self, Error> {
+        Ok(toml::from_str(content)?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Checks the budgets against a profile. The peak of a budget is the sum
+    /// of the peaks of its sites, an upper bound of its actual peak.
+    pub fn evaluate(&self, profile: &Profile) -> BudgetReport {
+        let mut checks = Vec::new();
+
+        let mut push = |name: &str, limits: &Limits, cost: &Cost| {
+            checks.extend(limits.iter().map(|(metric, limit)| BudgetCheck {
+                budget: name.to_string(),
+                metric,
+                limit,
+                actual: cost.get(metric),
+            }));
+        };
+
+        push("total", &self.total, &profile.total);
+
+        for budget in &self.budgets {
+            let mut cost = Cost::default();
+            for site in profile.sites.iter().filter(|s| budget.matches(s)) {
+                cost.add(&site.cost);
+            }
+            push(&budget.name, &budget.limits, &cost);
+        }
+
+        BudgetReport { checks }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::budget::{parse_quantity, Manifest};
+    use crate::model::tests::data;
+    use crate::model::{Metric, Profile};
+
+    #[test]
+    fn test_parse_quantity() {
+        assert_eq!(parse_quantity("50 MB"), Some(50_000_000));
+        assert_eq!(parse_quantity("1.5KiB"), Some(1536));
+        assert_eq!(parse_quantity("10k"), Some(10_000));
+        assert_eq!(parse_quantity("12"), Some(12));
+        assert_eq!(parse_quantity("5 parsecs"), None);
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let manifest = Manifest::parse(
+            r#"
+            [total]
+            allocations = 3
+
+            [[budget]]
+            name = "a"
+            functions = ["malloc_a"]
+            leaked = "16 B"
+
+            [[budget]]
+            name = "binary"
+            modules = ["/bin"]
+            peak = 16
+            "#,
+        )
+        .unwrap();
+        assert!(Manifest::parse("[[budget]]\nname = \"x\"\npeak = \"lots\"").is_err());
+        assert!(Manifest::parse("[total]\npeek = 1").is_err());
+
+        let profile = Profile::new(&data()).unwrap();
+        let report = manifest.evaluate(&profile);
+
+        assert_eq!(report.checks.len(), 3);
+        assert!(!report.passed());
+        let failures: Vec<_> = report.failures().collect();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].budget, "binary");
+        assert_eq!(failures[0].metric, Metric::Peak);
+    }
+}
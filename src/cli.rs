@@ -0,0 +1,437 @@
+//! `clap` argument structs shared by command line front-ends.
+//!
+//! Each struct can be flattened into a front-end's own parser and converted
+//! into the options of the corresponding API.
+
+use crate::analysis::crates::CrateOptions;
+use crate::budget::parse_quantity;
+use crate::diff::DiffOptions;
+use crate::export::preview::PreviewOptions;
+use crate::interpret::{ChildStdio, StackThreshold, StdioMode};
+use crate::model::{CostKind, InlineAttribution, Metric, ProfileOptions};
+use crate::parser::{ParsePolicy, Parser, SteadyState, STDIN_PATH};
+use crate::redact::Redaction;
+use crate::rules;
+use crate::rules::RulesHandle;
+use crate::runtime::RuntimeDirs;
+use crate::session::Session;
+use crate::soak::SoakOptions;
+use clap::error::ErrorKind;
+use clap::{Args, ValueEnum};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MetricArg {
+    Allocations,
+    Temporary,
+    Leaked,
+    Peak,
+}
+
+impl From<MetricArg> for Metric {
+    fn from(metric: MetricArg) -> Self {
+        match metric {
+            MetricArg::Allocations => Metric::Allocations,
+            MetricArg::Temporary => Metric::Temporary,
+            MetricArg::Leaked => Metric::Leaked,
+            MetricArg::Peak => Metric::Peak,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ParsePolicyArg {
+    Standard,
+    Strict,
+    Lenient,
+    CollectWarnings,
+}
+
+impl From<ParsePolicyArg> for ParsePolicy {
+    fn from(policy: ParsePolicyArg) -> Self {
+        match policy {
+            ParsePolicyArg::Standard => ParsePolicy::Standard,
+            ParsePolicyArg::Strict => ParsePolicy::Strict,
+            ParsePolicyArg::Lenient => ParsePolicy::Lenient,
+            ParsePolicyArg::CollectWarnings => ParsePolicy::CollectWarnings,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum InlineAttributionArg {
+    Inlinee,
+    Caller,
+    Both,
+}
+
+impl From<InlineAttributionArg> for InlineAttribution {
+    fn from(inlining: InlineAttributionArg) -> Self {
+        match inlining {
+            InlineAttributionArg::Inlinee => InlineAttribution::Inlinee,
+            InlineAttributionArg::Caller => InlineAttribution::Caller,
+            InlineAttributionArg::Both => InlineAttribution::Both,
+        }
+    }
+}
+
+fn parse_bytes(value: &str) -> Result<u64, String> {
+    parse_quantity(value).ok_or_else(|| format!("invalid size `{}`", value))
+}
+
+/// Arguments for recording a trace, see [`Session`].
+#[derive(Debug, Clone, Args)]
+pub struct TraceArgs {
+    /// Path to the injected tracing library
+    #[arg(long, env = "MEMTRACK_LIB")]
+    pub lib: PathBuf,
+    /// Output trace file
+    #[arg(short, long, default_value = "memtrack.trace")]
+    pub output: PathBuf,
+    /// Write a .memtrack.json preview next to the trace
+    #[arg(long)]
+    pub preview: bool,
+    /// Suppression and categorization rules file, reloaded on SIGHUP
+    #[arg(long)]
+    pub rules: Option<PathBuf>,
+    /// Keep mangled symbol names
+    #[arg(long)]
+    pub raw_symbols: bool,
+    /// Stop recording after this many seconds, leaving the program running
+    #[arg(long, value_name = "SECONDS")]
+    pub max_duration: Option<u64>,
+    /// Stop recording after this many allocations and frees
+    #[arg(long)]
+    pub max_events: Option<u64>,
+    /// Capture stacks only for allocations of at least this size, e.g.
+    /// `4KiB`; smaller ones are counted per size class and module
+    #[arg(long, value_name = "BYTES", value_parser = parse_bytes)]
+    pub stack_threshold: Option<u64>,
+    /// Report frees of untracked pointers and double frees as anomalies
+    #[arg(long)]
+    pub strict_frees: bool,
+    /// Wait for the library to reopen its pipe when it is closed while the
+    /// program still runs, instead of ending the trace
+    #[arg(long)]
+    pub reconnect: bool,
+    /// Redact secret options and variables from the recorded command line
+    /// and environment
+    #[arg(long)]
+    pub redact_secrets: bool,
+    /// Value to redact from the command line and environment, implies
+    /// --redact-secrets
+    #[arg(long = "redact", value_name = "VALUE")]
+    pub redact: Vec<String>,
+    /// Rewrite a JSON summary of the run to this file while tracing, for
+    /// monitoring soak tests
+    #[arg(long, value_name = "PATH")]
+    pub soak_summary: Option<PathBuf>,
+    /// Minutes of run time between two soak summaries
+    #[arg(long, value_name = "MINUTES", default_value_t = 5)]
+    pub soak_interval: u64,
+    /// Directory of the record fifos, instead of the one picked from
+    /// MEMTRACK_RUNTIME_DIR, XDG_RUNTIME_DIR or the temporary directory
+    #[arg(long, value_name = "PATH")]
+    pub runtime_dir: Option<PathBuf>,
+    /// Read the input of the traced program from this file
+    #[arg(long, value_name = "PATH")]
+    pub stdin: Option<PathBuf>,
+    /// Write the output of the traced program to this file
+    #[arg(long, value_name = "PATH")]
+    pub stdout: Option<PathBuf>,
+    /// Write the error output of the traced program to this file
+    #[arg(long, value_name = "PATH")]
+    pub stderr: Option<PathBuf>,
+    /// Discard the output of the traced program not redirected to a file
+    #[arg(long)]
+    pub quiet: bool,
+    /// Working directory of the traced program
+    #[arg(long, default_value = ".")]
+    pub cwd: PathBuf,
+    /// Program to trace
+    pub program: OsString,
+    /// Arguments of the traced program
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    pub args: Vec<OsString>,
+}
+
+impl TraceArgs {
+    pub fn session(&self) -> Result<Session, rules::Error> {
+        let mut session = Session::new(&self.lib, &self.output)
+            .with_demangle(!self.raw_symbols)
+            .with_strict_frees(self.strict_frees)
+            .with_reconnect(self.reconnect);
+
+        if self.preview {
+            session = session.with_preview(PreviewOptions::default());
+        }
+        if self.redact_secrets || !self.redact.is_empty() {
+            let redaction = self
+                .redact
+                .iter()
+                .fold(Redaction::default(), |r, value| r.with_pattern(value));
+            session = session.with_redaction(redaction);
+        }
+
+        if let Some(seconds) = self.max_duration {
+            session = session.with_max_duration(Duration::from_secs(seconds));
+        }
+        if let Some(events) = self.max_events {
+            session = session.with_max_events(events);
+        }
+        if let Some(path) = &self.soak_summary {
+            session = session.with_soak_summary(SoakOptions {
+                interval: Duration::from_secs(self.soak_interval * 60),
+                ..SoakOptions::new(path)
+            });
+        }
+        if let Some(dir) = &self.runtime_dir {
+            session = session.with_runtime_dirs(RuntimeDirs::new(dir));
+        }
+        if self.quiet || self.stdin.is_some() || self.stdout.is_some() || self.stderr.is_some() {
+            session = session.with_stdio(self.stdio());
+        }
+        if let Some(threshold) = self.stack_threshold {
+            session = session.with_stack_threshold(StackThreshold::new(threshold));
+        }
+
+        if let Some(path) = &self.rules {
+            let rules = RulesHandle::load(path)?;
+            rules.reload_on_sighup()?;
+            session = session.with_rules(rules);
+        }
+
+        Ok(session)
+    }
+
+    pub fn stdio(&self) -> ChildStdio {
+        let mode = |path: &Option<PathBuf>| match path {
+            Some(path) => StdioMode::File(path.clone()),
+            None if self.quiet => StdioMode::Null,
+            None => StdioMode::Inherit,
+        };
+        ChildStdio {
+            stdin: self
+                .stdin
+                .clone()
+                .map_or(StdioMode::Inherit, StdioMode::File),
+            stdout: mode(&self.stdout),
+            stderr: mode(&self.stderr),
+        }
+    }
+}
+
+/// Arguments for analyzing a recorded trace.
+#[derive(Debug, Clone, Args)]
+pub struct AnalyzeArgs {
+    /// Trace file to analyze, `-` for the standard input
+    pub trace: PathBuf,
+    /// Number of entries to show
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+    /// Metric to rank by
+    #[arg(long, value_enum, default_value_t = MetricArg::Peak)]
+    pub metric: MetricArg,
+    /// Rank functions by their own allocations only
+    #[arg(long)]
+    pub exclusive: bool,
+    /// Treat the given function as part of the allocator
+    #[arg(long = "allocator")]
+    pub allocators: Vec<String>,
+    /// Detect custom allocator wrappers
+    #[arg(long)]
+    pub detect_allocators: bool,
+    /// Function the allocations of inlined code are attributed to
+    #[arg(long, value_enum, default_value_t = InlineAttributionArg::Inlinee)]
+    pub inline_attribution: InlineAttributionArg,
+    /// Group costs by the crate of the allocating functions
+    #[arg(long)]
+    pub by_crate: bool,
+    /// Crate of the traced workspace, attributing costs to the dependencies
+    /// it calls; implies --by-crate
+    #[arg(long = "workspace")]
+    pub workspace: Vec<String>,
+    /// Leave out the first seconds of the run, e.g. its startup
+    #[arg(long, value_name = "SECONDS")]
+    pub skip_first: Option<u64>,
+    /// Leave out everything before the first marker or transaction with
+    /// this label
+    #[arg(long, value_name = "LABEL")]
+    pub start_marker: Option<String>,
+    /// Leave out the last seconds of the run, e.g. the frees at exit
+    #[arg(long, value_name = "SECONDS")]
+    pub skip_last: Option<u64>,
+    /// How to treat malformed lines and records with unknown tags
+    #[arg(long, value_enum, default_value_t = ParsePolicyArg::Standard)]
+    pub parse_policy: ParsePolicyArg,
+    /// Run only the given registered analysis pass instead of all of them
+    #[arg(long = "analysis", value_name = "NAME")]
+    pub analyses: Vec<String>,
+}
+
+impl AnalyzeArgs {
+    pub fn profile_options(&self) -> ProfileOptions {
+        ProfileOptions {
+            allocators: self.allocators.clone(),
+            detect_allocators: self.detect_allocators,
+            inlining: self.inline_attribution.into(),
+        }
+    }
+
+    pub fn cost_kind(&self) -> CostKind {
+        if self.exclusive {
+            CostKind::Exclusive
+        } else {
+            CostKind::Inclusive
+        }
+    }
+
+    pub fn metric(&self) -> Metric {
+        self.metric.into()
+    }
+
+    /// Parser of the trace, aggregating only the steady state if the
+    /// startup or teardown is left out.
+    pub fn parser(&self) -> Parser {
+        let parser = Parser::new().with_policy(self.parse_policy.into());
+        if self.skip_first.is_none() && self.start_marker.is_none() && self.skip_last.is_none() {
+            return parser;
+        }
+        parser.with_steady_state(SteadyState {
+            skip_first: Duration::from_secs(self.skip_first.unwrap_or(0)),
+            start_marker: self.start_marker.clone(),
+            skip_last: Duration::from_secs(self.skip_last.unwrap_or(0)),
+        })
+    }
+
+    /// Registered analysis passes to run, see
+    /// [`ReportOptions::analyses`](crate::report::ReportOptions::analyses).
+    pub fn analyses(&self) -> Option<Vec<String>> {
+        (!self.analyses.is_empty()).then(|| self.analyses.clone())
+    }
+
+    pub fn crate_options(&self) -> Option<CrateOptions> {
+        if !self.by_crate && self.workspace.is_empty() {
+            return None;
+        }
+        Some(CrateOptions {
+            workspace: self.workspace.clone(),
+            top_functions: self.top,
+            metric: self.metric(),
+            ..Default::default()
+        })
+    }
+}
+
+/// Arguments for comparing two traces.
+#[derive(Debug, Clone, Args)]
+pub struct DiffArgs {
+    /// Trace of the baseline run, `-` for the standard input
+    pub before: PathBuf,
+    /// Trace of the run to compare, `-` for the standard input
+    pub after: PathBuf,
+    /// Only match identical stacks
+    #[arg(long)]
+    pub exact: bool,
+    /// Minimum confidence of fuzzy matches
+    #[arg(long, default_value_t = DiffOptions::default().min_confidence)]
+    pub min_confidence: f64,
+}
+
+impl DiffArgs {
+    /// The traces to compare. Only one of them can be read from the
+    /// standard input.
+    pub fn traces(&self) -> Result<(&Path, &Path), clap::Error> {
+        let stdin = Path::new(STDIN_PATH);
+        if self.before == stdin && self.after == stdin {
+            return Err(clap::Error::raw(
+                ErrorKind::ArgumentConflict,
+                "only one of the traces can be read from the standard input\n",
+            ));
+        }
+        Ok((&self.before, &self.after))
+    }
+
+    pub fn options(&self) -> DiffOptions {
+        DiffOptions {
+            fuzzy: !self.exact,
+            min_confidence: self.min_confidence,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli::{AnalyzeArgs, DiffArgs, TraceArgs};
+    use crate::interpret::{ChildStdio, StdioMode};
+    use clap::Parser;
+    use std::time::Duration;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        trace: TraceArgs,
+    }
+
+    #[derive(Parser)]
+    struct Analyze {
+        #[command(flatten)]
+        analyze: AnalyzeArgs,
+    }
+
+    #[derive(Parser)]
+    struct Diff {
+        #[command(flatten)]
+        diff: DiffArgs,
+    }
+
+    #[test]
+    fn test_trace_args() {
+        let cli = Cli::parse_from(["memtrack", "--lib", "lib.dylib", "ls", "-la"]);
+        assert_eq!(cli.trace.program, "ls");
+        assert_eq!(cli.trace.args, ["-la"]);
+        assert!(cli.trace.session().is_ok());
+        assert_eq!(cli.trace.stdio(), ChildStdio::default());
+
+        let cli = Cli::parse_from(["memtrack", "--lib", "l", "--quiet", "--stderr", "e", "ls"]);
+        let stdio = cli.trace.stdio();
+        assert_eq!(stdio.stdin, StdioMode::Inherit);
+        assert_eq!(stdio.stdout, StdioMode::Null);
+        assert_eq!(stdio.stderr, StdioMode::File("e".into()));
+
+        let analyze = Analyze::parse_from(["memtrack", "t.trace", "--exclusive"]);
+        assert_eq!(analyze.analyze.top, 20);
+        assert!(analyze.analyze.crate_options().is_none());
+        assert!(analyze.analyze.analyses().is_none());
+
+        let analyze = Analyze::parse_from(["memtrack", "t.trace", "--analysis", "budget"]);
+        assert_eq!(analyze.analyze.analyses().unwrap(), ["budget"]);
+
+        let analyze = Analyze::parse_from(["memtrack", "t.trace", "--workspace", "app"]);
+        assert_eq!(analyze.analyze.crate_options().unwrap().workspace, ["app"]);
+
+        let analyze = Analyze::parse_from(["memtrack", "t.trace", "--skip-last", "5"]);
+        let data = analyze
+            .analyze
+            .parser()
+            .parse_reader("+ 0\nc 1388\n".as_bytes())
+            .unwrap();
+        assert_eq!(data.duration, Duration::ZERO);
+    }
+
+    #[test]
+    fn test_diff_stdin() {
+        let diff = Diff::parse_from(["memtrack", "-", "after.trace"]).diff;
+        let (before, after) = diff.traces().unwrap();
+        assert_eq!(
+            (before.to_str(), after.to_str()),
+            (Some("-"), Some("after.trace"))
+        );
+
+        let diff = Diff::parse_from(["memtrack", "-", "-"]).diff;
+        let err = diff.traces().unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+    }
+}
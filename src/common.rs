@@ -1,20 +1,67 @@
-use std::{fs, io};
+use std::{env, fs, io};
 use std::fs::File;
 use std::io::BufWriter;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use anyhow::Context;
 
-pub fn download_lib_if_needed(lib_dir: impl AsRef<Path>, lib_version: &str) -> anyhow::Result<String> {
+/// Extension of the injected library on the current platform.
+const LIB_EXTENSION: &str = if cfg!(target_os = "macos") { "dylib" } else { "so" };
+
+/// Target triple of the released library matching this build, `None` for
+/// platforms without a release.
+const LIB_TARGET: Option<&str> = if cfg!(all(target_os = "macos", target_arch = "aarch64")) {
+    Some("aarch64-apple-darwin")
+} else if cfg!(all(target_os = "macos", target_arch = "x86_64")) {
+    Some("x86_64-apple-darwin")
+} else if cfg!(all(target_os = "linux", target_env = "gnu", target_arch = "x86_64")) {
+    Some("x86_64-unknown-linux-gnu")
+} else if cfg!(all(target_os = "linux", target_env = "gnu", target_arch = "aarch64")) {
+    Some("aarch64-unknown-linux-gnu")
+} else {
+    None
+};
+
+/// Overrides the directory chosen by [`default_lib_dir`].
+pub const LIB_DIR_ENV: &str = "MEMTRACK_LIB_DIR";
+
+fn non_empty_var(name: &str) -> Option<PathBuf> {
+    env::var_os(name).filter(|v| !v.is_empty()).map(PathBuf::from)
+}
+
+/// Picks the directory of the downloaded libraries from, in order,
+/// `MEMTRACK_LIB_DIR`, `$XDG_CACHE_HOME/memtrack`, the user cache directory
+/// of the platform (`~/Library/Caches` on macOS, `~/.cache` elsewhere) and
+/// `memtrack-cache` in the temporary directory, for sandboxes without a home.
+pub fn default_lib_dir() -> PathBuf {
+    if let Some(dir) = non_empty_var(LIB_DIR_ENV) {
+        return dir;
+    }
+    if let Some(dir) = non_empty_var("XDG_CACHE_HOME") {
+        return dir.join("memtrack");
+    }
+    match non_empty_var("HOME") {
+        Some(home) if cfg!(target_os = "macos") => home.join("Library/Caches/memtrack"),
+        Some(home) => home.join(".cache/memtrack"),
+        None => env::temp_dir().join("memtrack-cache"),
+    }
+}
+
+/// Downloads the library of `lib_version` built for the target of this
+/// build into `lib_dir` unless it is already there, and returns its path.
+pub fn download_lib_if_needed(lib_dir: impl AsRef<Path>, lib_version: &str) -> anyhow::Result<PathBuf> {
     if lib_dir.as_ref().is_file() {
         anyhow::bail!("lib_dir is not a directory");
     }
+    let Some(target) = LIB_TARGET else {
+        anyhow::bail!("no released libmemtrace for this platform");
+    };
 
     let lib_file = lib_dir
         .as_ref()
-        .join(format!("libmemtrace_{}.dylib", lib_version));
+        .join(format!("libmemtrace_{}-{}.{}", lib_version, target, LIB_EXTENSION));
 
     if lib_file.exists() {
-        return Ok(lib_file.to_str().unwrap().to_string());
+        return Ok(lib_file);
     }
 
     println!("Loading libmemtrace version {}", lib_version);
@@ -22,14 +69,15 @@ pub fn download_lib_if_needed(lib_dir: impl AsRef<Path>, lib_version: &str) -> a
     fs::create_dir_all(lib_dir).context("failed to create dirs")?;
 
     let mut response = reqwest::blocking::get(format!(
-        "https://github.com/blkmlk/memtrace-lib/releases/download/{}/libmemtrace_lib.dylib",
-        lib_version
+        "https://github.com/blkmlk/memtrace-lib/releases/download/{}/libmemtrace_lib-{}.{}",
+        lib_version, target, LIB_EXTENSION
     ))
-        .context("failed to download libmemtrace.dylib")?;
+        .with_context(|| format!("failed to download libmemtrace.{} for {}", LIB_EXTENSION, target))?;
 
     if !response.status().is_success() {
         anyhow::bail!(
-            "failed to download libmemtrace.dylib. status: {}",
+            "failed to download libmemtrace.{}. status: {}",
+            LIB_EXTENSION,
             response.status()
         );
     }
@@ -40,9 +88,9 @@ pub fn download_lib_if_needed(lib_dir: impl AsRef<Path>, lib_version: &str) -> a
     io::copy(&mut response, &mut out_file).context("failed to write output file")?;
 
     println!(
-        "Successfully loaded libmemtrace.dylib version {}",
-        lib_version
+        "Successfully loaded libmemtrace.{} version {}",
+        LIB_EXTENSION, lib_version
     );
 
-    Ok(lib_file.to_str().unwrap().to_string())
+    Ok(lib_file)
 }
\ No newline at end of file
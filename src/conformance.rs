@@ -0,0 +1,424 @@
+use crate::executor;
+use crate::executor::ChildStdio;
+use crate::pipe_io::Record;
+use crate::runtime::RuntimeDirs;
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Execution failed")]
+    Exec(#[from] executor::Error),
+}
+
+/// Block size of the builtin `alloc-free` scenario, unusual enough not to be
+/// allocated by the runtime of the program itself.
+pub const ALLOC_FREE_SIZE: u64 = 12345;
+pub const ALLOC_FREE_COUNT: usize = 16;
+
+/// A single property the record stream of a scenario must satisfy.
+#[derive(Debug, Clone)]
+pub enum Expectation {
+    /// The first record is `Version`.
+    VersionFirst,
+    /// At least one `Exec` record is sent.
+    ExecReported,
+    /// At least one `PageInfo` record is sent.
+    PageInfoReported,
+    /// At least `min_count` allocations of exactly `size` bytes are reported.
+    AllocOfSize { size: u64, min_count: usize },
+    /// Every reported allocation of `size` bytes is freed before the end of the stream.
+    FreedOfSize { size: u64 },
+    /// Every `Trace` and `Alloc` refers to a trace index that was already sent.
+    ParentsKnown,
+    /// `Duration` records never go backwards.
+    DurationMonotonic,
+    /// Timestamps of `Alloc`, `Free` and `RSS` records never go backwards.
+    TimestampsMonotonic,
+}
+
+impl fmt::Display for Expectation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expectation::VersionFirst => write!(f, "version record first"),
+            Expectation::ExecReported => write!(f, "exec record reported"),
+            Expectation::PageInfoReported => write!(f, "page info reported"),
+            Expectation::AllocOfSize { size, min_count } => {
+                write!(f, "at least {} allocations of {} bytes", min_count, size)
+            }
+            Expectation::FreedOfSize { size } => {
+                write!(f, "all allocations of {} bytes freed", size)
+            }
+            Expectation::ParentsKnown => write!(f, "parent indices known"),
+            Expectation::DurationMonotonic => write!(f, "monotonic durations"),
+            Expectation::TimestampsMonotonic => write!(f, "monotonic timestamps"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Failure {
+    pub expectation: Expectation,
+    pub reason: String,
+}
+
+/// A scripted program run under the injected library together with the
+/// expectations on the produced record stream.
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    pub name: String,
+    pub program: OsString,
+    pub args: Vec<OsString>,
+    pub expectations: Vec<Expectation>,
+}
+
+impl Scenario {
+    pub fn new(name: &str, program: impl AsRef<OsStr>) -> Self {
+        Self {
+            name: name.to_string(),
+            program: program.as_ref().to_os_string(),
+            args: Vec::new(),
+            expectations: Vec::new(),
+        }
+    }
+
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Self {
+        self.args.push(arg.as_ref().to_os_string());
+        self
+    }
+
+    pub fn expect(mut self, expectation: Expectation) -> Self {
+        self.expectations.push(expectation);
+        self
+    }
+
+    /// Runs `program` with a block size and a count as arguments and expects
+    /// that many allocations of the size, all of them freed. `program` must
+    /// allocate exactly `size` bytes per block, like `examples/alloc_free.rs`.
+    pub fn alloc_free(program: impl AsRef<OsStr>, size: u64, count: usize) -> Self {
+        Scenario::new("alloc-free", program)
+            .arg(size.to_string())
+            .arg(count.to_string())
+            .expect(Expectation::AllocOfSize {
+                size,
+                min_count: count,
+            })
+            .expect(Expectation::FreedOfSize { size })
+    }
+
+    /// Scenarios every library implementation is expected to pass.
+    /// `alloc_program` is the program of [`Scenario::alloc_free`].
+    pub fn builtin(alloc_program: impl AsRef<OsStr>) -> Vec<Scenario> {
+        let protocol = [
+            Expectation::VersionFirst,
+            Expectation::ExecReported,
+            Expectation::PageInfoReported,
+            Expectation::ParentsKnown,
+            Expectation::DurationMonotonic,
+            Expectation::TimestampsMonotonic,
+        ];
+
+        let mut smoke = Scenario::new("smoke", "/bin/echo").arg("conformance");
+        smoke.expectations.extend(protocol.iter().cloned());
+
+        let mut alloc_free = Scenario::alloc_free(alloc_program, ALLOC_FREE_SIZE, ALLOC_FREE_COUNT);
+        alloc_free.expectations.extend(protocol.iter().cloned());
+
+        vec![smoke, alloc_free]
+    }
+}
+
+#[derive(Debug)]
+pub struct ScenarioReport {
+    pub scenario: String,
+    pub records: usize,
+    pub failures: Vec<Failure>,
+}
+
+impl ScenarioReport {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Runs `scenario` with the library at `lib_path` injected and checks the
+/// received records against its expectations.
+pub fn run(
+    scenario: &Scenario,
+    cwd: impl AsRef<Path>,
+    lib_path: impl AsRef<Path>,
+) -> Result<ScenarioReport, Error> {
+    let mut exec = executor::exec_cmd(
+        &scenario.program,
+        &scenario.args,
+        cwd,
+        lib_path,
+        &RuntimeDirs::default(),
+        &ChildStdio::default(),
+    )?;
+
+    let mut records = Vec::new();
+    while let Some(item) = exec.next() {
+        records.push(item?);
+    }
+
+    Ok(ScenarioReport {
+        scenario: scenario.name.clone(),
+        records: records.len(),
+        failures: check(&records, &scenario.expectations),
+    })
+}
+
+/// Checks an already captured record stream against `expectations`.
+pub fn check(records: &[Record], expectations: &[Expectation]) -> Vec<Failure> {
+    let mut failures = Vec::new();
+
+    for expectation in expectations {
+        if let Err(reason) = check_one(records, expectation) {
+            failures.push(Failure {
+                expectation: expectation.clone(),
+                reason,
+            });
+        }
+    }
+
+    failures
+}
+
+fn check_one(records: &[Record], expectation: &Expectation) -> Result<(), String> {
+    match expectation {
+        Expectation::VersionFirst => match records.first() {
+            Some(Record::Version(_)) => Ok(()),
+            Some(other) => Err(format!("first record is {:?}", other)),
+            None => Err("no records".into()),
+        },
+        Expectation::ExecReported => records
+            .iter()
+            .any(|r| matches!(r, Record::Exec(_)))
+            .then_some(())
+            .ok_or_else(|| "no exec record".into()),
+        Expectation::PageInfoReported => records
+            .iter()
+            .any(|r| matches!(r, Record::PageInfo { .. }))
+            .then_some(())
+            .ok_or_else(|| "no page info record".into()),
+        Expectation::AllocOfSize { size, min_count } => {
+            let count = records
+                .iter()
+                .filter(|r| matches!(r, Record::Alloc { size: s, .. } if s == size))
+                .count();
+            if count >= *min_count {
+                Ok(())
+            } else {
+                Err(format!("found {} allocations", count))
+            }
+        }
+        Expectation::FreedOfSize { size } => {
+            let mut live = HashSet::new();
+            for record in records {
+                match record {
+                    Record::Alloc { ptr, size: s, .. } if s == size => {
+                        live.insert(*ptr);
+                    }
+                    Record::Free { ptr, .. } => {
+                        live.remove(ptr);
+                    }
+                    _ => {}
+                }
+            }
+            if live.is_empty() {
+                Ok(())
+            } else {
+                Err(format!("{} allocations not freed", live.len()))
+            }
+        }
+        Expectation::ParentsKnown => {
+            let mut traces = 0;
+            for (idx, record) in records.iter().enumerate() {
+                let parent_idx = match record {
+                    Record::Trace { parent_idx, .. } => *parent_idx,
+                    Record::Alloc { parent_idx, .. } => *parent_idx,
+                    _ => continue,
+                };
+                if parent_idx > traces {
+                    return Err(format!(
+                        "record {} refers to trace {} of {}",
+                        idx, parent_idx, traces
+                    ));
+                }
+                if matches!(record, Record::Trace { .. }) {
+                    traces += 1;
+                }
+            }
+            Ok(())
+        }
+        Expectation::DurationMonotonic => {
+            let mut last = 0;
+            for record in records {
+                if let Record::Duration(duration) = record {
+                    if *duration < last {
+                        return Err(format!("duration {} after {}", duration, last));
+                    }
+                    last = *duration;
+                }
+            }
+            Ok(())
+        }
+        Expectation::TimestampsMonotonic => {
+            let mut last = 0;
+            for record in records {
+                let timestamp = match record {
+                    Record::Alloc { timestamp, .. }
+                    | Record::Free { timestamp, .. }
+                    | Record::RSS { timestamp, .. } => *timestamp,
+                    _ => continue,
+                };
+                if timestamp < last {
+                    return Err(format!("timestamp {} after {}", timestamp, last));
+                }
+                last = timestamp;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conformance::{
+        check, run, Expectation, Scenario, ALLOC_FREE_COUNT, ALLOC_FREE_SIZE,
+    };
+    use crate::pipe_io::Record;
+
+    fn records() -> Vec<Record> {
+        vec![
+            Record::Version(1),
+            Record::Exec("/bin/echo".into()),
+            Record::PageInfo {
+                size: 16384,
+                pages: 1024,
+            },
+            Record::Trace {
+                ip: 0x1000,
+                parent_idx: 0,
+            },
+            Record::Alloc {
+                ptr: 0xa0,
+                size: 32,
+                parent_idx: 1,
+                timestamp: 100,
+            },
+            Record::Duration(5),
+            Record::Free {
+                ptr: 0xa0,
+                timestamp: 200,
+            },
+            Record::Duration(10),
+        ]
+    }
+
+    #[test]
+    fn test_check_passes() {
+        let expectations = [
+            Expectation::VersionFirst,
+            Expectation::ExecReported,
+            Expectation::PageInfoReported,
+            Expectation::AllocOfSize {
+                size: 32,
+                min_count: 1,
+            },
+            Expectation::FreedOfSize { size: 32 },
+            Expectation::ParentsKnown,
+            Expectation::DurationMonotonic,
+            Expectation::TimestampsMonotonic,
+        ];
+
+        let failures = check(&records(), &expectations);
+        assert!(failures.is_empty(), "{:?}", failures);
+    }
+
+    #[test]
+    fn test_check_fails() {
+        let mut records = records();
+        records.remove(0);
+        records.push(Record::Alloc {
+            ptr: 0xb0,
+            size: 32,
+            parent_idx: 7,
+            timestamp: 50,
+        });
+        records.push(Record::Duration(1));
+
+        let expectations = [
+            Expectation::VersionFirst,
+            Expectation::FreedOfSize { size: 32 },
+            Expectation::ParentsKnown,
+            Expectation::DurationMonotonic,
+            Expectation::TimestampsMonotonic,
+        ];
+
+        let failures = check(&records, &expectations);
+        assert_eq!(failures.len(), 5);
+    }
+
+    #[test]
+    fn test_builtin_alloc_free() {
+        let scenarios = Scenario::builtin("alloc_free");
+        let alloc_free = scenarios.iter().find(|s| s.name == "alloc-free").unwrap();
+        assert_eq!(alloc_free.args, ["12345", "16"]);
+
+        let mut records = records();
+        for i in 0..ALLOC_FREE_COUNT as u64 {
+            records.push(Record::Alloc {
+                ptr: 0x100 + i,
+                size: ALLOC_FREE_SIZE,
+                parent_idx: 1,
+                timestamp: 300 + i,
+            });
+            records.push(Record::Free {
+                ptr: 0x100 + i,
+                timestamp: 300 + i,
+            });
+        }
+        let failures = check(&records, &alloc_free.expectations);
+        assert!(failures.is_empty(), "{:?}", failures);
+
+        records.pop();
+        let failures = check(&records, &alloc_free.expectations);
+        assert_eq!(failures.len(), 1);
+        assert!(matches!(
+            failures[0].expectation,
+            Expectation::FreedOfSize { .. }
+        ));
+    }
+
+    /// Runs the builtin scenarios with the library injected through
+    /// `LD_PRELOAD`:
+    ///
+    /// ```sh
+    /// cargo build --example alloc_free
+    /// MEMTRACK_TEST_LIB=/path/to/libmemtrace_lib.so cargo test -- --ignored test_ld_preload
+    /// ```
+    #[test]
+    #[cfg(target_os = "linux")]
+    #[ignore = "requires the tracing library in MEMTRACK_TEST_LIB and the alloc_free example"]
+    fn test_ld_preload() {
+        let lib = std::env::var_os("MEMTRACK_TEST_LIB").expect("MEMTRACK_TEST_LIB is not set");
+        let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
+        let alloc_program = root.join("target/debug/examples/alloc_free");
+
+        for scenario in Scenario::builtin(alloc_program) {
+            let report = run(&scenario, root, &lib).unwrap();
+            assert!(report.records > 0, "{}: no records", report.scenario);
+            assert!(
+                report.passed(),
+                "{}: {:?}",
+                report.scenario,
+                report.failures
+            );
+        }
+    }
+}
@@ -0,0 +1,338 @@
+//! Comparison of allocation sites between two runs.
+//!
+//! Sites are matched by their symbolized stacks. Stacks that differ only in
+//! inlining, e.g. because a later build inlined a helper, are matched with a
+//! fuzzy alignment and carry a confidence below 1.
+
+use crate::model;
+use crate::model::{Cost, Frame, Profile, Site};
+use crate::parser::AccumulatedData;
+use indexmap::IndexMap;
+use serde::Serialize;
+
+/// Weight of an inlined frame relative to a regular one when aligning
+/// stacks: inlining decisions change between builds far more often than the
+/// actual call structure.
+const INLINED_WEIGHT: f64 = 0.25;
+
+#[derive(Debug, Clone)]
+pub struct DiffOptions {
+    /// Match stacks that are not identical using [`align`].
+    pub fuzzy: bool,
+    /// Minimum alignment score for a fuzzy match.
+    pub min_confidence: f64,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            fuzzy: true,
+            min_confidence: 0.75,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SiteDiff {
+    /// Normalized function names of the stack, taken from the newer run when
+    /// the site exists there.
+    pub stack: Vec<String>,
+    pub before: Option<Cost>,
+    pub after: Option<Cost>,
+    /// 1 for identical stacks, lower for fuzzy matches and 0 for sites found
+    /// in one run only.
+    pub confidence: f64,
+}
+
+/// Change of the figures of a site, positive when the newer run allocates
+/// more. A site missing from a run counts as zero there.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct SiteDelta {
+    pub allocations: i64,
+    pub leaked: i64,
+    pub peak: i64,
+}
+
+impl SiteDiff {
+    pub fn delta(&self) -> SiteDelta {
+        let before = self.before.unwrap_or_default();
+        let after = self.after.unwrap_or_default();
+        let delta = |before: u64, after: u64| after as i64 - before as i64;
+        SiteDelta {
+            allocations: delta(before.allocations, after.allocations),
+            leaked: delta(before.leaked, after.leaked),
+            peak: delta(before.peak, after.peak),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Diff {
+    pub sites: Vec<SiteDiff>,
+}
+
+/// Normalizes a function name for comparison across builds by removing the
+/// Rust symbol hash.
+pub fn normalize(function: &str) -> &str {
+    match function.rsplit_once("::h") {
+        Some((prefix, hash)) if hash.len() == 16 && hash.chars().all(|c| c.is_ascii_hexdigit()) => {
+            prefix
+        }
+        _ => function,
+    }
+}
+
+fn weights(stack: &[Frame]) -> Vec<f64> {
+    stack
+        .iter()
+        .enumerate()
+        .map(|(depth, frame)| {
+            let weight = if frame.inlined { INLINED_WEIGHT } else { 1.0 };
+            weight / (1 + depth) as f64
+        })
+        .collect()
+}
+
+/// Scores how well two stacks align, from 0 (nothing in common) to 1
+/// (identical after normalization).
+///
+/// Frames are aligned with a weighted longest common subsequence. Frames
+/// close to the allocating end and regular frames weigh more than outer and
+/// inlined ones, so an inserted or removed inline frame costs little while a
+/// different allocating function costs a lot.
+pub fn align(a: &[Frame], b: &[Frame]) -> f64 {
+    let wa = weights(a);
+    let wb = weights(b);
+    let total: f64 = wa.iter().sum::<f64>() + wb.iter().sum::<f64>();
+    if total == 0.0 {
+        return 1.0;
+    }
+
+    let mut dp = vec![vec![0.0f64; b.len() + 1]; a.len() + 1];
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let mut best = dp[i - 1][j].max(dp[i][j - 1]);
+            if normalize(&a[i - 1].function) == normalize(&b[j - 1].function) {
+                best = best.max(dp[i - 1][j - 1] + wa[i - 1] + wb[j - 1]);
+            }
+            dp[i][j] = best;
+        }
+    }
+
+    dp[a.len()][b.len()] / total
+}
+
+fn names(site: &Site) -> Vec<String> {
+    site.stack
+        .iter()
+        .map(|f| normalize(&f.function).to_string())
+        .collect()
+}
+
+pub fn diff_profiles(before: &Profile, after: &Profile, options: &DiffOptions) -> Diff {
+    let before_names: Vec<Vec<String>> = before.sites.iter().map(names).collect();
+    let mut matched = vec![false; before.sites.len()];
+    let mut pending = Vec::new();
+    let mut sites = Vec::new();
+
+    for site in &after.sites {
+        let stack = names(site);
+        let exact = before_names
+            .iter()
+            .enumerate()
+            .position(|(idx, n)| !matched[idx] && *n == stack);
+
+        match exact {
+            Some(idx) => {
+                matched[idx] = true;
+                sites.push(SiteDiff {
+                    stack,
+                    before: Some(before.sites[idx].cost),
+                    after: Some(site.cost),
+                    confidence: 1.0,
+                });
+            }
+            None => pending.push(site),
+        }
+    }
+
+    if options.fuzzy {
+        let mut candidates = Vec::new();
+        for (after_idx, site) in pending.iter().enumerate() {
+            for (before_idx, other) in before.sites.iter().enumerate() {
+                if matched[before_idx] {
+                    continue;
+                }
+                let score = align(&other.stack, &site.stack);
+                if score >= options.min_confidence {
+                    candidates.push((score, after_idx, before_idx));
+                }
+            }
+        }
+        candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let mut taken = vec![false; pending.len()];
+        for (score, after_idx, before_idx) in candidates {
+            if taken[after_idx] || matched[before_idx] {
+                continue;
+            }
+            taken[after_idx] = true;
+            matched[before_idx] = true;
+
+            let site = pending[after_idx];
+            sites.push(SiteDiff {
+                stack: names(site),
+                before: Some(before.sites[before_idx].cost),
+                after: Some(site.cost),
+                confidence: score,
+            });
+        }
+
+        pending = pending
+            .into_iter()
+            .zip(taken)
+            .filter_map(|(site, taken)| (!taken).then_some(site))
+            .collect();
+    }
+
+    for site in pending {
+        sites.push(SiteDiff {
+            stack: names(site),
+            before: None,
+            after: Some(site.cost),
+            confidence: 0.0,
+        });
+    }
+
+    for (idx, site) in before.sites.iter().enumerate() {
+        if !matched[idx] {
+            sites.push(SiteDiff {
+                stack: before_names[idx].clone(),
+                before: Some(site.cost),
+                after: None,
+                confidence: 0.0,
+            });
+        }
+    }
+
+    Diff { sites }
+}
+
+/// Costs of the sites of `data` by normalized stack. Sites whose stacks
+/// only differ in instruction pointers are merged.
+fn sites_by_stack(data: &AccumulatedData) -> Result<IndexMap<Vec<String>, Cost>, model::Error> {
+    let profile = Profile::new(data)?;
+    let mut sites: IndexMap<Vec<String>, Cost> = IndexMap::new();
+    for site in &profile.sites {
+        sites.entry(names(site)).or_default().add(&site.cost);
+    }
+    Ok(sites)
+}
+
+/// Compares the sites of two parsed traces, `a` the baseline and `b` the
+/// run to compare, matching them by their symbolized stacks. Sites are
+/// listed in the order of `b`, followed by those found in `a` only. See
+/// [`diff_profiles`] to also match stacks that differ in inlining.
+pub fn diff(a: &AccumulatedData, b: &AccumulatedData) -> Result<Diff, model::Error> {
+    let mut before = sites_by_stack(a)?;
+    let after = sites_by_stack(b)?;
+
+    let mut sites: Vec<SiteDiff> = after
+        .into_iter()
+        .map(|(stack, cost)| {
+            let before = before.shift_remove(&stack);
+            SiteDiff {
+                confidence: if before.is_some() { 1.0 } else { 0.0 },
+                stack,
+                before,
+                after: Some(cost),
+            }
+        })
+        .collect();
+    sites.extend(before.into_iter().map(|(stack, cost)| SiteDiff {
+        stack,
+        before: Some(cost),
+        after: None,
+        confidence: 0.0,
+    }));
+
+    Ok(Diff { sites })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff::{align, diff, diff_profiles, normalize, DiffOptions, SiteDelta};
+    use crate::model::tests::{data, parse, TRACE};
+    use crate::model::{Frame, Profile};
+
+    fn frame(function: &str, inlined: bool) -> Frame {
+        Frame {
+            ip: 0,
+            module: None,
+            function: function.to_string(),
+            file: None,
+            line: None,
+            inlined,
+        }
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("app::run::h0123456789abcdef"), "app::run");
+        assert_eq!(normalize("app::hash"), "app::hash");
+    }
+
+    #[test]
+    fn test_align() {
+        let a = [frame("f", true), frame("g", false), frame("main", false)];
+        let b = [frame("g", false), frame("main", false)];
+        let c = [frame("x", false), frame("main", false)];
+
+        assert_eq!(align(&a, &a), 1.0);
+        assert!(align(&a, &b) > 0.85);
+        assert!(align(&b, &c) < 0.75);
+    }
+
+    #[test]
+    fn test_diff_profiles() {
+        let before = Profile::new(&data()).unwrap();
+        // the same run where `a` was inlined into `main` and lost its frame
+        let after = Profile::new(&parse(&TRACE.replace("t 4 2", "t 4 1"))).unwrap();
+
+        let diff = diff_profiles(&before, &after, &DiffOptions::default());
+        assert_eq!(diff.sites.len(), 2);
+        assert!(diff
+            .sites
+            .iter()
+            .all(|s| s.before.is_some() && s.after.is_some()));
+        assert!(diff.sites.iter().any(|s| s.confidence < 1.0));
+    }
+
+    #[test]
+    fn test_diff() {
+        let before = data();
+        // an allocation of malloc_a is no longer freed and a allocates directly
+        let after = parse(
+            &TRACE
+                .replace("a 20 4\n", "a 20 4\na 8 2\n")
+                .replace("- 0\n", "+ 2\n"),
+        );
+
+        let diff = diff(&before, &after).unwrap();
+        let stacks: Vec<String> = diff.sites.iter().map(|s| s.stack.join(";")).collect();
+        assert_eq!(stacks, ["malloc_a;a;main", "b;main", "a;main"]);
+        assert!(diff.sites[..2].iter().all(|s| s.confidence == 1.0));
+
+        assert_eq!(
+            diff.sites[0].delta(),
+            SiteDelta {
+                allocations: 0,
+                leaked: 0x10,
+                peak: 0,
+            }
+        );
+        assert_eq!(diff.sites[1].delta(), SiteDelta::default());
+        assert_eq!(diff.sites[2].before, None);
+        assert_eq!(diff.sites[2].delta().leaked, 8);
+    }
+}
@@ -0,0 +1,117 @@
+//! Capture of the environment a trace was recorded in.
+//!
+//! Memory behavior often depends on allocator tuning variables, resource
+//! limits and the machine itself. The captured values are written as
+//! metadata records at the start of a trace so runs can be compared later.
+
+use nix::sys::resource::{getrlimit, Resource, RLIM_INFINITY};
+
+/// Variables captured by default, mostly allocator and runtime tuning knobs.
+pub const DEFAULT_VARIABLES: &[&str] = &[
+    "MALLOC_ARENA_MAX",
+    "MALLOC_CONF",
+    "MALLOC_TOP_PAD_",
+    "MALLOC_TRIM_THRESHOLD_",
+    "MALLOC_MMAP_THRESHOLD_",
+    "MallocNanoZone",
+    "MallocStackLogging",
+    "GLIBC_TUNABLES",
+    "RUST_MIN_STACK",
+    "TOKIO_WORKER_THREADS",
+    "RAYON_NUM_THREADS",
+];
+
+const LIMITS: &[(&str, Resource)] = &[
+    ("as", Resource::RLIMIT_AS),
+    ("data", Resource::RLIMIT_DATA),
+    ("stack", Resource::RLIMIT_STACK),
+    ("nofile", Resource::RLIMIT_NOFILE),
+    ("core", Resource::RLIMIT_CORE),
+];
+
+#[derive(Debug, Clone)]
+pub struct EnvCapture {
+    /// Names of the environment variables to record when set.
+    pub variables: Vec<String>,
+}
+
+impl Default for EnvCapture {
+    fn default() -> Self {
+        Self {
+            variables: DEFAULT_VARIABLES.iter().map(|v| v.to_string()).collect(),
+        }
+    }
+}
+
+fn limit_value(limit: u64) -> String {
+    if limit == RLIM_INFINITY {
+        "unlimited".to_string()
+    } else {
+        limit.to_string()
+    }
+}
+
+fn physical_memory() -> Option<u64> {
+    // SAFETY: sysconf has no preconditions and only reads system settings
+    let (pages, page_size) = unsafe {
+        (
+            nix::libc::sysconf(nix::libc::_SC_PHYS_PAGES),
+            nix::libc::sysconf(nix::libc::_SC_PAGESIZE),
+        )
+    };
+
+    if pages <= 0 || page_size <= 0 {
+        return None;
+    }
+
+    Some(pages as u64 * page_size as u64)
+}
+
+impl EnvCapture {
+    /// Returns the captured `key`/`value` pairs. Keys are prefixed with
+    /// `env.`, `rlimit.` or `system.`.
+    pub fn capture(&self) -> Vec<(String, String)> {
+        let mut values = Vec::new();
+
+        for name in &self.variables {
+            if let Some(value) = std::env::var_os(name) {
+                values.push((format!("env.{}", name), value.to_string_lossy().to_string()));
+            }
+        }
+
+        for (name, resource) in LIMITS {
+            if let Ok((soft, hard)) = getrlimit(*resource) {
+                values.push((
+                    format!("rlimit.{}", name),
+                    format!("{} {}", limit_value(soft), limit_value(hard)),
+                ));
+            }
+        }
+
+        if let Some(memory) = physical_memory() {
+            values.push(("system.memory".to_string(), memory.to_string()));
+        }
+        if let Ok(cpus) = std::thread::available_parallelism() {
+            values.push(("system.cpus".to_string(), cpus.to_string()));
+        }
+        values.push(("system.os".to_string(), std::env::consts::OS.to_string()));
+        values.push((
+            "system.arch".to_string(),
+            std::env::consts::ARCH.to_string(),
+        ));
+
+        values
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::environment::EnvCapture;
+
+    #[test]
+    fn test_capture() {
+        let values = EnvCapture::default().capture();
+        assert!(values.iter().any(|(key, _)| key == "system.os"));
+        assert!(values.iter().any(|(key, _)| key == "rlimit.stack"));
+    }
+}
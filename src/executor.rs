@@ -1,12 +1,24 @@
 use crate::pipe_io;
-use crate::pipe_io::{PipeReader, Record};
+use crate::pipe_io::{
+    Command as LibCommand, CommandWriter, PipeReader, Record, RecordRef, StreamStats,
+};
+use crate::runtime::RuntimeDirs;
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
 use nix::sys::stat::Mode;
 use nix::unistd::mkfifo;
-use std::ffi::OsStr;
-use std::fs::{remove_file, OpenOptions};
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs::{remove_file, File, OpenOptions};
 use std::io;
-use std::path::Path;
-use std::process::{Child, Command, ExitStatus};
+use std::io::Read;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -14,89 +26,562 @@ pub enum Error {
     #[error("failed to execute command")]
     CmdFailed(ExitStatus),
     #[error("IO error")]
-    CmdError(#[from] io::Error),
+    Io(#[from] io::Error),
     #[error("pipe error")]
-    PipeError(#[from] pipe_io::Error),
+    Pipe(#[from] pipe_io::Error),
+    #[error("reconnected stream does not start with a version record")]
+    Handshake,
 }
 
+/// How long [`ExecResult::reconnect`] waits between checks for a writer.
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Variable of the dynamic loader injecting libraries into the traced
+/// program: `DYLD_INSERT_LIBRARIES` on macOS, `LD_PRELOAD` on Linux.
+pub const PRELOAD_ENV: &str = if cfg!(target_os = "macos") {
+    "DYLD_INSERT_LIBRARIES"
+} else {
+    "LD_PRELOAD"
+};
+
+/// Capacity requested for the record pipe on Linux, the default
+/// `/proc/sys/fs/pipe-max-size`. The default of 64 KiB stalls the writing
+/// threads of allocation-heavy programs.
+#[cfg(target_os = "linux")]
+const PIPE_CAPACITY: i32 = 1 << 20;
+
+/// Handling of a standard stream of the traced program.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum StdioMode {
+    /// Shared with the current process.
+    #[default]
+    Inherit,
+    /// Output read into memory while the program runs. As stdin, an input
+    /// closed right away.
+    Pipe,
+    /// Output discarded, or an empty input.
+    Null,
+    /// Output written to the file, truncated first, or input read from it.
+    File(PathBuf),
+}
+
+impl StdioMode {
+    fn input(&self) -> io::Result<Stdio> {
+        Ok(match self {
+            Self::Inherit => Stdio::inherit(),
+            Self::Pipe => Stdio::piped(),
+            Self::Null => Stdio::null(),
+            Self::File(path) => File::open(path)?.into(),
+        })
+    }
+
+    fn output(&self) -> io::Result<Stdio> {
+        Ok(match self {
+            Self::Inherit => Stdio::inherit(),
+            Self::Pipe => Stdio::piped(),
+            Self::Null => Stdio::null(),
+            Self::File(path) => File::create(path)?.into(),
+        })
+    }
+}
+
+/// Standard streams of the traced program, all inherited by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChildStdio {
+    pub stdin: StdioMode,
+    pub stdout: StdioMode,
+    pub stderr: StdioMode,
+}
+
+impl ChildStdio {
+    /// Discards the output, with an empty input.
+    pub fn null() -> Self {
+        Self {
+            stdin: StdioMode::Null,
+            stdout: StdioMode::Null,
+            stderr: StdioMode::Null,
+        }
+    }
+
+    /// Reads the output into memory, with an empty input.
+    pub fn captured() -> Self {
+        Self {
+            stdin: StdioMode::Null,
+            stdout: StdioMode::Pipe,
+            stderr: StdioMode::Pipe,
+        }
+    }
+}
+
+/// Output of the traced program read with [`StdioMode::Pipe`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CapturedOutput {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// Reads a piped stream of the program on a thread of its own, so a
+/// program filling the pipe does not block while records are read.
+struct Capture {
+    buffer: Arc<Mutex<Vec<u8>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Capture {
+    fn spawn(mut source: impl Read + Send + 'static) -> Self {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let output = buffer.clone();
+        let thread = thread::spawn(move || {
+            let mut chunk = [0; 8192];
+            // a read error ends the capture like the end of the stream
+            while let Ok(n @ 1..) = source.read(&mut chunk) {
+                output.lock().unwrap().extend_from_slice(&chunk[..n]);
+            }
+        });
+
+        Self {
+            buffer,
+            thread: Some(thread),
+        }
+    }
+
+    /// Output read since the previous call, waiting for the end of the
+    /// stream if `wait`.
+    fn take(&mut self, wait: bool) -> Vec<u8> {
+        if let Some(thread) = self.thread.take_if(|_| wait) {
+            _ = thread.join();
+        }
+        std::mem::take(&mut *self.buffer.lock().unwrap())
+    }
+}
+
+/// Libraries to preload: those already requested by the environment, e.g.
+/// a sanitizer runtime that must be loaded first, then `lib_path`.
+fn preload_value(lib_path: &OsStr, existing: Option<&OsStr>) -> OsString {
+    let mut value = OsString::new();
+    if let Some(existing) = existing.filter(|e| !e.is_empty()) {
+        value.push(existing);
+        value.push(":");
+    }
+    value.push(lib_path);
+    value
+}
+
+/// Grows the buffer of the record pipe. The writer only blocks more often
+/// if this fails.
+#[cfg(target_os = "linux")]
+fn grow_pipe(pipe_file: &File) {
+    _ = fcntl(pipe_file, FcntlArg::F_SETPIPE_SZ(PIPE_CAPACITY));
+}
+
+#[cfg(not(target_os = "linux"))]
+fn grow_pipe(_pipe_file: &File) {}
+
+/// Starts `program` with the library at `lib_path` injected, reading its
+/// records from a fifo created in `dirs`. Paths need not be valid UTF-8.
 pub fn exec_cmd<S, P>(
     program: S,
     args: impl IntoIterator<Item = S>,
     cwd: P,
-    lib_path: &str,
-) -> ExecResult
+    lib_path: impl AsRef<Path>,
+    dirs: &RuntimeDirs,
+    stdio: &ChildStdio,
+) -> Result<ExecResult, Error>
 where
     S: AsRef<OsStr>,
     P: AsRef<Path>,
 {
-    let pid = std::process::id();
-    let pipe_file_path = format!("/tmp/{}.pipe", pid);
+    let stdin = stdio.stdin.input()?;
+    let stdout = stdio.stdout.output()?;
+    let stderr = stdio.stderr.output()?;
 
-    mkfifo(pipe_file_path.as_str(), Mode::S_IRUSR | Mode::S_IWUSR).unwrap();
+    dirs.create()?;
+    let pipe_file_path = dirs.unique_fifo_path();
+    let control_file_path = pipe_file_path.with_extension("control");
 
-    let envs = [
-        ("PIPE_FILEPATH", pipe_file_path.as_str()),
-        ("DYLD_INSERT_LIBRARIES", lib_path),
-    ];
+    mkfifo(&pipe_file_path, Mode::S_IRUSR | Mode::S_IWUSR).map_err(io::Error::from)?;
+    // opened for reading too, so writes neither block nor fail while the
+    // library has not opened its end yet
+    let control = mkfifo(&control_file_path, Mode::S_IRUSR | Mode::S_IWUSR)
+        .ok()
+        .and_then(|_| {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&control_file_path)
+                .ok()
+        })
+        .map(CommandWriter::new);
 
     let mut cmd = Command::new(program);
     cmd.args(args);
-    cmd.envs(envs);
+    cmd.env("PIPE_FILEPATH", &pipe_file_path);
+    cmd.env("CONTROL_FILEPATH", &control_file_path);
+    cmd.env(
+        PRELOAD_ENV,
+        preload_value(
+            lib_path.as_ref().as_os_str(),
+            env::var_os(PRELOAD_ENV).as_deref(),
+        ),
+    );
     cmd.current_dir(cwd);
+    cmd.stdin(stdin).stdout(stdout).stderr(stderr);
 
-    let child = cmd.spawn().unwrap();
+    let mut child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            _ = remove_file(&pipe_file_path);
+            _ = remove_file(&control_file_path);
+            return Err(e.into());
+        }
+    };
 
-    ExecResult::new(child, pipe_file_path)
+    // closed for an empty input
+    drop(child.stdin.take());
+    let stdout = child.stdout.take().map(Capture::spawn);
+    let stderr = child.stderr.take().map(Capture::spawn);
+
+    let mut result = ExecResult::new(child, pipe_file_path);
+    result.stdout = stdout;
+    result.stderr = stderr;
+    if let Some(writer) = control {
+        result.control.connect(writer);
+    }
+    result.control_filepath = Some(control_file_path);
+    Ok(result)
+}
+
+#[derive(Default)]
+struct ControlState {
+    writer: Option<CommandWriter>,
+    /// Set once [`LibCommand::Stop`] was sent.
+    detached: bool,
+    /// Commands sent since the last [`ControlHandle::take_sent`].
+    sent: Vec<LibCommand>,
+}
+
+/// Sends commands to the injected library of a running program. Clones
+/// share the channel, so a handle taken before the run can drive it from
+/// another thread.
+#[derive(Clone, Default)]
+pub struct ControlHandle {
+    state: Arc<Mutex<ControlState>>,
+    /// Whether `sent` is non-empty, checked without locking.
+    pending: Arc<AtomicBool>,
+}
+
+impl ControlHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether a program is running and accepts commands.
+    pub fn is_connected(&self) -> bool {
+        self.state.lock().unwrap().writer.is_some()
+    }
+
+    pub fn send(&self, command: LibCommand) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let writer = state
+            .writer
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "no control channel"))?;
+        writer.write_command(&command)?;
+
+        state.detached |= command == LibCommand::Stop;
+        state.sent.push(command);
+        self.pending.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    pub fn pause(&self) -> io::Result<()> {
+        self.send(LibCommand::Pause)
+    }
+
+    pub fn resume(&self) -> io::Result<()> {
+        self.send(LibCommand::Resume)
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        self.send(LibCommand::Flush)
+    }
+
+    /// Records each later allocation with probability `rate`.
+    pub fn set_sampling(&self, rate: f64) -> io::Result<()> {
+        self.send(LibCommand::SetSampling(rate.clamp(0.0, 1.0)))
+    }
+
+    /// Stops recording, the program keeps running untraced.
+    pub fn stop(&self) -> io::Result<()> {
+        self.send(LibCommand::Stop)
+    }
+
+    fn connect(&self, writer: CommandWriter) {
+        let mut state = self.state.lock().unwrap();
+        *state = ControlState {
+            writer: Some(writer),
+            ..ControlState::default()
+        };
+    }
+
+    fn disconnect(&self) {
+        self.state.lock().unwrap().writer = None;
+    }
+
+    fn is_detached(&self) -> bool {
+        self.state.lock().unwrap().detached
+    }
+
+    pub(crate) fn take_sent(&self) -> Vec<LibCommand> {
+        if !self.pending.swap(false, Ordering::AcqRel) {
+            return Vec::new();
+        }
+        std::mem::take(&mut self.state.lock().unwrap().sent)
+    }
 }
 
 pub struct ExecResult {
     child: Child,
-    pipe_filepath: String,
+    pipe_filepath: PathBuf,
     reader: Option<PipeReader>,
+    done: bool,
+    control: ControlHandle,
+    control_filepath: Option<PathBuf>,
+    reconnect: bool,
+    /// Set when the pipe was closed while the program kept running.
+    lost: bool,
+    /// Counters of the previous connections.
+    previous_stats: StreamStats,
+    stdout: Option<Capture>,
+    stderr: Option<Capture>,
 }
 
 impl ExecResult {
-    pub fn new(child: Child, pipe_filepath: String) -> Self {
+    pub fn new(child: Child, pipe_filepath: impl Into<PathBuf>) -> Self {
         Self {
             child,
-            pipe_filepath,
+            pipe_filepath: pipe_filepath.into(),
             reader: None,
+            done: false,
+            control: ControlHandle::default(),
+            control_filepath: None,
+            reconnect: false,
+            lost: false,
+            previous_stats: StreamStats::default(),
+            stdout: None,
+            stderr: None,
         }
     }
 
-    pub fn next(&mut self) -> Option<Result<Record, Error>> {
+    /// Ends the stream instead of the session when the pipe is closed while
+    /// the program keeps running, so [`reconnect`](Self::reconnect) can wait
+    /// for the library to open it again.
+    pub fn with_reconnect(mut self, reconnect: bool) -> Self {
+        self.reconnect = reconnect;
+        self
+    }
+
+    /// Moves the control channel of the program to `control`, so commands
+    /// can be sent through existing clones of it.
+    pub fn with_control(mut self, control: ControlHandle) -> Self {
+        match self.control.state.lock().unwrap().writer.take() {
+            Some(writer) => control.connect(writer),
+            None => control.disconnect(),
+        }
+        self.control = control;
+        self
+    }
+
+    /// Asks the library to stop recording. The remaining records are still
+    /// read, but the end of the stream no longer waits for the program to
+    /// exit, it keeps running untraced.
+    pub fn stop(&mut self) -> Result<(), Error> {
+        Ok(self.control.stop()?)
+    }
+
+    /// Output of the streams in [`StdioMode::Pipe`] read since the previous
+    /// call. All of it once the program exited; a program left running by
+    /// [`stop`](Self::stop) keeps writing to the pipes.
+    pub fn captured(&mut self) -> CapturedOutput {
+        let exited = matches!(self.child.try_wait(), Ok(Some(_)));
+        let take = |capture: &mut Option<Capture>| {
+            capture
+                .as_mut()
+                .map_or_else(Vec::new, |capture| capture.take(exited))
+        };
+        CapturedOutput {
+            stdout: take(&mut self.stdout),
+            stderr: take(&mut self.stderr),
+        }
+    }
+
+    /// Sequencing counters of the records read so far, see [`StreamStats`].
+    /// Counters of earlier connections are included.
+    pub fn stream_stats(&self) -> StreamStats {
+        let mut stats = self.previous_stats;
+        if let Some(reader) = &self.reader {
+            stats.add(&reader.stats());
+        }
+        stats
+    }
+
+    /// Waits for the library to open the pipe again after the stream ended
+    /// with the program still running, and checks that the new stream
+    /// starts with the [`RecordRef::Version`] handshake. Returns whether
+    /// the stream can be read again with [`next_ref`](Self::next_ref), false
+    /// if it was not lost or the program exited in the meantime.
+    pub fn reconnect(&mut self) -> Result<bool, Error> {
+        if !self.lost {
+            return Ok(false);
+        }
+
+        // a blocking open would wait forever for a program that exited
+        let mut pipe_file = OpenOptions::new()
+            .read(true)
+            .custom_flags(OFlag::O_NONBLOCK.bits())
+            .open(&self.pipe_filepath)?;
+        let mut prefix = vec![0; 4096];
         loop {
-            match &mut self.reader {
-                None => {
-                    let pipe_file = OpenOptions::new()
-                        .read(true)
-                        .open(&self.pipe_filepath)
-                        .unwrap();
-
-                    let reader = PipeReader::new(pipe_file);
-                    self.reader = Some(reader);
+            let exited = self.child.try_wait()?;
+            match pipe_file.read(&mut prefix) {
+                Ok(0) => {}
+                Ok(n) => {
+                    prefix.truncate(n);
+                    break;
+                }
+                // a writer without pending data
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    prefix.clear();
+                    break;
                 }
-                Some(reader) => {
-                    return match self.child.try_wait() {
-                        Ok(result) => {
-                            if let Some(exit) = result {
-                                if !exit.success() {
-                                    return Some(Err(Error::CmdFailed(exit)));
-                                }
-                            }
-
-                            Some(reader.read_record()?.map_err(Error::from))
+                Err(e) => return Err(e.into()),
+            }
+
+            if let Some(exit) = exited {
+                self.lost = false;
+                self.done = true;
+                self.control.disconnect();
+                return if exit.success() {
+                    Ok(false)
+                } else {
+                    Err(Error::CmdFailed(exit))
+                };
+            }
+            thread::sleep(RECONNECT_INTERVAL);
+        }
+        fcntl(&pipe_file, FcntlArg::F_SETFL(OFlag::empty())).map_err(io::Error::from)?;
+        grow_pipe(&pipe_file);
+
+        let mut reader = PipeReader::with_prefix(pipe_file, prefix);
+        match reader.read_record_ref() {
+            Some(Ok(RecordRef::Version(_))) => {}
+            Some(Err(e)) => return Err(e.into()),
+            _ => return Err(Error::Handshake),
+        }
+        self.reader = Some(reader);
+        self.lost = false;
+        Ok(true)
+    }
+
+    pub fn next(&mut self) -> Option<Result<Record, Error>> {
+        Some(self.next_ref()?.map(|record| record.to_owned()))
+    }
+
+    /// Reads the next record without allocating, see [`RecordRef`].
+    pub fn next_ref(&mut self) -> Option<Result<RecordRef<'_>, Error>> {
+        if self.done || self.lost {
+            return None;
+        }
+        if self.reader.is_none() {
+            let pipe_file = match OpenOptions::new().read(true).open(&self.pipe_filepath) {
+                Ok(pipe_file) => pipe_file,
+                Err(e) => return Some(Err(e.into())),
+            };
+            grow_pipe(&pipe_file);
+
+            self.reader = Some(PipeReader::new(pipe_file));
+        }
+
+        // records written before an abnormal exit are still in the pipe, so
+        // the exit status is only checked once they are consumed
+        match self.reader.as_mut()?.is_eof() {
+            Ok(false) => {}
+            Ok(true) => {
+                if self.reconnect && !self.control.is_detached() {
+                    match self.child.try_wait() {
+                        Ok(None) => {
+                            let stats = self.reader.take().map(|r| r.stats());
+                            self.previous_stats.add(&stats.unwrap_or_default());
+                            self.lost = true;
+                            return None;
                         }
-                        Err(e) => Some(Err(e.into())),
+                        Ok(Some(_)) => {}
+                        Err(e) => return Some(Err(e.into())),
                     }
                 }
+                self.done = true;
+                self.control.disconnect();
+                if self.control.is_detached() {
+                    return None;
+                }
+                return match self.child.wait() {
+                    Ok(exit) if !exit.success() => Some(Err(Error::CmdFailed(exit))),
+                    Ok(_) => None,
+                    Err(e) => Some(Err(e.into())),
+                };
             }
+            Err(e) => return Some(Err(e.into())),
         }
+
+        let reader = self.reader.as_mut()?;
+        Some(reader.read_record_ref()?.map_err(Error::from))
     }
 }
 
 impl Drop for ExecResult {
     fn drop(&mut self) {
+        self.control.disconnect();
         _ = remove_file(&self.pipe_filepath);
+        if let Some(path) = &self.control_filepath {
+            _ = remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::executor::{preload_value, Capture, StdioMode};
+    use std::ffi::OsStr;
+    use std::process::Command;
+
+    #[test]
+    fn test_preload_value() {
+        let lib = OsStr::new("/lib/memtrace.so");
+        assert_eq!(preload_value(lib, None), lib);
+        assert_eq!(preload_value(lib, Some(OsStr::new(""))), lib);
+        assert_eq!(
+            preload_value(lib, Some(OsStr::new("/lib/libasan.so"))),
+            "/lib/libasan.so:/lib/memtrace.so"
+        );
+    }
+
+    #[test]
+    fn test_stdio_modes() {
+        let path = std::env::temp_dir().join(format!("memtrack-stderr-{}", std::process::id()));
+        let mut child = Command::new("sh")
+            .args(["-c", "cat; echo out; echo err >&2"])
+            .stdin(StdioMode::Null.input().unwrap())
+            .stdout(StdioMode::Pipe.output().unwrap())
+            .stderr(StdioMode::File(path.clone()).output().unwrap())
+            .spawn()
+            .unwrap();
+        let mut stdout = Capture::spawn(child.stdout.take().unwrap());
+        assert!(child.wait().unwrap().success());
+
+        let stderr = std::fs::read_to_string(&path);
+        _ = std::fs::remove_file(&path);
+        assert_eq!(stdout.take(true), b"out\n");
+        assert_eq!(stderr.unwrap(), "err\n");
+        assert!(StdioMode::File(path).input().is_err());
     }
 }
@@ -0,0 +1,132 @@
+//! Exports of parsed traces into formats consumed by other tools.
+//!
+//! Formats implement [`Exporter`] and are fed by [`export`], which visits the
+//! summary of a run, its annotations, each of its sites and functions in
+//! [`Profile`] order, each stack of failed allocations and each timeline
+//! sample, then finishes the exporter. Visited values live as long as the
+//! exported data, so exporters may keep references to them.
+
+pub mod preview;
+pub mod size_timeline;
+
+use crate::annotations::Annotations;
+use crate::model::{Cost, FailureSite, Function, Profile, Site};
+use crate::parser::{AccumulatedData, TimelinePoint};
+use std::time::Duration;
+
+/// Totals of a run, visited first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RunSummary {
+    pub total: Cost,
+    pub peak_rss: u64,
+    pub duration: Duration,
+    pub page_size: u64,
+    pub pages: u64,
+    /// Number of sites and timeline samples visited afterwards.
+    pub sites: usize,
+    pub timeline_points: usize,
+}
+
+impl RunSummary {
+    pub fn new(data: &AccumulatedData, profile: &Profile) -> Self {
+        Self {
+            total: profile.total,
+            peak_rss: data.peak_rss,
+            duration: data.duration,
+            page_size: data.page_size,
+            pages: data.pages,
+            sites: profile.sites.len(),
+            timeline_points: data.timeline.len(),
+        }
+    }
+}
+
+pub trait Exporter<'a> {
+    type Output;
+
+    fn visit_summary(&mut self, summary: &RunSummary);
+
+    fn visit_annotations(&mut self, _annotations: &'a Annotations) {}
+
+    fn visit_site(&mut self, _site: &'a Site) {}
+
+    fn visit_function(&mut self, _function: &'a Function) {}
+
+    fn visit_failure(&mut self, _failure: &'a FailureSite) {}
+
+    fn visit_timeline(&mut self, _point: &'a TimelinePoint) {}
+
+    fn finish(self) -> Self::Output;
+}
+
+/// Feeds a run to `exporter` and returns what it produced.
+pub fn export<'a, E: Exporter<'a>>(
+    data: &'a AccumulatedData,
+    profile: &'a Profile,
+    mut exporter: E,
+) -> E::Output {
+    exporter.visit_summary(&RunSummary::new(data, profile));
+    exporter.visit_annotations(&data.annotations);
+    for site in &profile.sites {
+        exporter.visit_site(site);
+    }
+    for function in &profile.functions {
+        exporter.visit_function(function);
+    }
+    for failure in &profile.failures {
+        exporter.visit_failure(failure);
+    }
+    for point in &data.timeline {
+        exporter.visit_timeline(point);
+    }
+    exporter.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::export::{export, Exporter, RunSummary};
+    use crate::model::tests::data;
+    use crate::model::{Profile, Site};
+    use crate::parser::TimelinePoint;
+
+    /// Collects the leaf functions of the visited sites.
+    #[derive(Default)]
+    struct Leaves<'a> {
+        summary: RunSummary,
+        leaves: Vec<&'a str>,
+        points: usize,
+    }
+
+    impl<'a> Exporter<'a> for Leaves<'a> {
+        type Output = Self;
+
+        fn visit_summary(&mut self, summary: &RunSummary) {
+            self.summary = *summary;
+        }
+
+        fn visit_site(&mut self, site: &'a Site) {
+            self.leaves.extend(site.leaf().map(|f| f.function.as_str()));
+        }
+
+        fn visit_timeline(&mut self, _point: &'a TimelinePoint) {
+            self.points += 1;
+        }
+
+        fn finish(self) -> Self {
+            self
+        }
+    }
+
+    #[test]
+    fn test_export() {
+        let data = data();
+        let profile = Profile::new(&data).unwrap();
+
+        let leaves = export(&data, &profile, Leaves::default());
+
+        assert_eq!(leaves.summary.sites, 2);
+        assert_eq!(leaves.summary.total.allocations, 3);
+        assert_eq!(leaves.leaves, ["malloc_a", "b"]);
+        assert_eq!(leaves.points, leaves.summary.timeline_points);
+    }
+}
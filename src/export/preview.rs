@@ -0,0 +1,279 @@
+//! Compact `.memtrack.json` preview bundles.
+//!
+//! A preview holds the summary, the heaviest stacks and functions and a
+//! downsampled timeline of a run, capped in size so web UIs and review bots can show a
+//! run without transferring the full trace.
+
+use crate::annotations::Annotations;
+use crate::export::{export, Exporter, RunSummary};
+use crate::model::{Cost, Function, Profile, Site};
+use crate::parser::{AccumulatedData, TimelinePoint};
+use serde::Serialize;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+pub const EXTENSION: &str = "memtrack.json";
+
+#[derive(Debug, Clone)]
+pub struct PreviewOptions {
+    pub top_sites: usize,
+    /// Functions kept, ranked by the peak and leaked bytes they allocate
+    /// themselves.
+    pub top_functions: usize,
+    pub max_stack_depth: usize,
+    pub max_timeline_points: usize,
+    /// Upper bound of the serialized preview. Sites, functions and timeline
+    /// points are halved until the preview fits.
+    pub max_bytes: usize,
+}
+
+impl Default for PreviewOptions {
+    fn default() -> Self {
+        Self {
+            top_sites: 20,
+            top_functions: 20,
+            max_stack_depth: 32,
+            max_timeline_points: 500,
+            max_bytes: 256 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Summary {
+    pub allocations: u64,
+    pub temporary: u64,
+    pub leaked: u64,
+    pub peak: u64,
+    pub peak_rss: u64,
+    pub duration_ms: u128,
+    pub sites: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PreviewSite {
+    /// Function names from the allocating frame outwards.
+    pub stack: Vec<String>,
+    pub allocations: u64,
+    pub temporary: u64,
+    pub leaked: u64,
+    pub peak: u64,
+    /// Notes attached to the site, see [`Annotations`].
+    pub notes: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PreviewFunction {
+    pub name: String,
+    pub module: Option<String>,
+    /// Cost of the allocations made by the function itself.
+    pub exclusive: Cost,
+    /// Cost of the allocations made by the function and its callees.
+    pub inclusive: Cost,
+}
+
+/// `[timestamp_ms, leaked, rss]`
+pub type PreviewPoint = (u128, u64, u64);
+
+#[derive(Debug, Serialize)]
+pub struct Preview {
+    pub summary: Summary,
+    pub top_sites: Vec<PreviewSite>,
+    pub top_functions: Vec<PreviewFunction>,
+    pub timeline: Vec<PreviewPoint>,
+}
+
+/// [`Exporter`] building a [`Preview`]. Sites and functions are kept by
+/// reference and only the top ones are copied into the preview.
+pub struct PreviewExporter<'a> {
+    options: &'a PreviewOptions,
+    summary: Option<Summary>,
+    annotations: Option<&'a Annotations>,
+    sites: Vec<&'a Site>,
+    functions: Vec<&'a Function>,
+    timeline: Vec<PreviewPoint>,
+    step: usize,
+    seen_points: usize,
+}
+
+impl<'a> PreviewExporter<'a> {
+    pub fn new(options: &'a PreviewOptions) -> Self {
+        Self {
+            options,
+            summary: None,
+            annotations: None,
+            sites: Vec::new(),
+            functions: Vec::new(),
+            timeline: Vec::new(),
+            step: 1,
+            seen_points: 0,
+        }
+    }
+}
+
+impl<'a> Exporter<'a> for PreviewExporter<'a> {
+    type Output = Preview;
+
+    fn visit_summary(&mut self, summary: &RunSummary) {
+        self.step = summary
+            .timeline_points
+            .div_ceil(self.options.max_timeline_points.max(1))
+            .max(1);
+        self.summary = Some(Summary {
+            allocations: summary.total.allocations,
+            temporary: summary.total.temporary,
+            leaked: summary.total.leaked,
+            peak: summary.total.peak,
+            peak_rss: summary.peak_rss,
+            duration_ms: summary.duration.as_millis(),
+            sites: summary.sites,
+        });
+    }
+
+    fn visit_annotations(&mut self, annotations: &'a Annotations) {
+        self.annotations = Some(annotations);
+    }
+
+    fn visit_site(&mut self, site: &'a Site) {
+        self.sites.push(site);
+    }
+
+    fn visit_function(&mut self, function: &'a Function) {
+        self.functions.push(function);
+    }
+
+    fn visit_timeline(&mut self, point: &TimelinePoint) {
+        if self.seen_points.is_multiple_of(self.step) {
+            self.timeline
+                .push((point.timestamp.as_millis(), point.leaked, point.rss));
+        }
+        self.seen_points += 1;
+    }
+
+    fn finish(mut self) -> Preview {
+        self.sites
+            .sort_by_key(|s| std::cmp::Reverse((s.cost.peak, s.cost.leaked)));
+        self.sites.truncate(self.options.top_sites);
+        self.functions
+            .sort_by_key(|f| std::cmp::Reverse((f.exclusive.peak, f.exclusive.leaked)));
+        self.functions.truncate(self.options.top_functions);
+
+        let top_sites = self
+            .sites
+            .iter()
+            .map(|site| PreviewSite {
+                stack: site
+                    .stack
+                    .iter()
+                    .take(self.options.max_stack_depth)
+                    .map(|f| f.function.clone())
+                    .collect(),
+                allocations: site.cost.allocations,
+                temporary: site.cost.temporary,
+                leaked: site.cost.leaked,
+                peak: site.cost.peak,
+                notes: self
+                    .annotations
+                    .map_or_else(Vec::new, |annotations| annotations.for_site(site).to_vec()),
+            })
+            .collect();
+        let top_functions = self
+            .functions
+            .iter()
+            .map(|function| PreviewFunction {
+                name: function.name.clone(),
+                module: function.module.clone(),
+                exclusive: function.exclusive,
+                inclusive: function.inclusive,
+            })
+            .collect();
+
+        Preview {
+            summary: self.summary.unwrap_or_default(),
+            top_sites,
+            top_functions,
+            timeline: self.timeline,
+        }
+    }
+}
+
+impl Preview {
+    pub fn new(data: &AccumulatedData, profile: &Profile, options: &PreviewOptions) -> Self {
+        export(data, profile, PreviewExporter::new(options))
+    }
+
+    /// Serializes the preview, dropping sites, functions and timeline points
+    /// until it fits into `max_bytes`.
+    pub fn to_json(mut self, max_bytes: usize) -> serde_json::Result<Vec<u8>> {
+        loop {
+            let json = serde_json::to_vec(&self)?;
+            if json.len() <= max_bytes
+                || (self.top_sites.is_empty()
+                    && self.top_functions.is_empty()
+                    && self.timeline.is_empty())
+            {
+                return Ok(json);
+            }
+
+            self.top_sites.truncate(self.top_sites.len() / 2);
+            self.top_functions.truncate(self.top_functions.len() / 2);
+            self.timeline = self.timeline.into_iter().step_by(2).collect();
+            if self.timeline.len() == 1 {
+                self.timeline.clear();
+            }
+        }
+    }
+}
+
+pub fn write(
+    path: impl AsRef<Path>,
+    data: &AccumulatedData,
+    profile: &Profile,
+    options: &PreviewOptions,
+) -> io::Result<()> {
+    let json = Preview::new(data, profile, options).to_json(options.max_bytes)?;
+
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(&json)?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::export::preview::{Preview, PreviewOptions};
+    use crate::model::tests::data;
+    use crate::model::Profile;
+
+    #[test]
+    fn test_preview_capped() {
+        let data = data();
+        let profile = Profile::new(&data).unwrap();
+
+        let preview = Preview::new(&data, &profile, &PreviewOptions::default());
+        assert_eq!(preview.top_sites.len(), 2);
+        assert_eq!(preview.top_sites[0].stack[0], "b");
+        assert_eq!(preview.timeline.len(), 1);
+        let functions: Vec<(&str, u64, u64)> = preview
+            .top_functions
+            .iter()
+            .map(|f| (f.name.as_str(), f.exclusive.leaked, f.inclusive.leaked))
+            .collect();
+        assert_eq!(
+            functions,
+            [
+                ("b", 0x20, 0x20),
+                ("malloc_a", 0x10, 0x10),
+                ("a", 0, 0x10),
+                ("main", 0, 0x30)
+            ]
+        );
+
+        let full = Preview::new(&data, &profile, &PreviewOptions::default())
+            .to_json(usize::MAX)
+            .unwrap();
+        let capped = preview.to_json(full.len() - 1).unwrap();
+        assert!(capped.len() < full.len());
+    }
+}
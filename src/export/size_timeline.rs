@@ -0,0 +1,224 @@
+//! Live bytes over time per size bucket, for stacked area charts.
+//!
+//! The chart tells whether the heap grows through many small objects or a
+//! few huge buffers. The parsed data keeps the size of every allocation but
+//! not the order of the events, so the trace is read again and the live
+//! bytes of every bucket are sampled at each `c` timestamp record, like the
+//! [timeline](AccumulatedData::timeline).
+
+use crate::export::{Exporter, RunSummary};
+use crate::numparse::parse_hex;
+use crate::parser;
+use crate::parser::{read_line, AccumulatedData};
+use serde::Serialize;
+use std::io;
+use std::io::{BufRead, Write};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SizeBucket {
+    pub name: String,
+    /// Largest allocation size of the bucket, `None` for the last bucket.
+    pub max_size: Option<u64>,
+}
+
+impl SizeBucket {
+    pub fn new(name: &str, max_size: Option<u64>) -> Self {
+        Self {
+            name: name.to_string(),
+            max_size,
+        }
+    }
+
+    /// Tiny allocations up to 64 bytes, small ones up to 1 KiB, medium
+    /// ones up to 64 KiB and large ones beyond.
+    pub fn defaults() -> Vec<Self> {
+        vec![
+            Self::new("tiny", Some(64)),
+            Self::new("small", Some(1024)),
+            Self::new("medium", Some(64 * 1024)),
+            Self::new("large", None),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SizeTimelinePoint {
+    pub timestamp_ms: u128,
+    /// Live bytes of every bucket, in the order of
+    /// [`SizeTimeline::buckets`].
+    pub live: Vec<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SizeTimeline {
+    pub buckets: Vec<SizeBucket>,
+    pub points: Vec<SizeTimelinePoint>,
+}
+
+impl SizeTimeline {
+    /// Computes the timeline of `data` by reading `input`, the trace it was
+    /// parsed from, again. Sizes beyond the last bounded bucket without an
+    /// unbounded one are left out.
+    pub fn new(
+        data: &AccumulatedData,
+        input: impl BufRead,
+        buckets: Vec<SizeBucket>,
+    ) -> Result<Self, parser::Error> {
+        Self::read(data, input, buckets, 0)
+    }
+
+    fn read(
+        data: &AccumulatedData,
+        mut input: impl BufRead,
+        buckets: Vec<SizeBucket>,
+        capacity: usize,
+    ) -> Result<Self, parser::Error> {
+        let bucket = |size: u64| {
+            buckets
+                .iter()
+                .position(|b| b.max_size.is_none_or(|max| size <= max))
+        };
+        let sizes: Vec<Option<(usize, u64)>> = data
+            .allocation_infos
+            .iter()
+            .map(|info| Some((bucket(info.size)?, info.size)))
+            .collect();
+
+        let mut live = vec![0u64; buckets.len()];
+        let mut points = Vec::with_capacity(capacity);
+        let mut line = Vec::new();
+        while read_line(&mut input, &mut line)? {
+            let mut fields = line.split(|&b| b == b' ').filter(|f| !f.is_empty());
+            let tag = fields.next();
+            let value = fields.next().and_then(parse_hex);
+            match tag {
+                Some(b"c") => points.push(SizeTimelinePoint {
+                    timestamp_ms: value.ok_or(parser::Error::InvalidFormat)? as u128,
+                    live: live.clone(),
+                }),
+                Some(b"+") | Some(b"-") => {
+                    let Some(&Some((idx, size))) = value.and_then(|idx| sizes.get(idx as usize))
+                    else {
+                        continue;
+                    };
+                    live[idx] = if tag == Some(b"+") {
+                        live[idx] + size
+                    } else {
+                        live[idx].saturating_sub(size)
+                    };
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self { buckets, points })
+    }
+
+    /// Writes a header with the bucket names, then a row per point.
+    pub fn write_csv(&self, mut out: impl Write) -> io::Result<()> {
+        write!(out, "timestamp_ms")?;
+        for bucket in &self.buckets {
+            write!(out, ",{}", bucket.name)?;
+        }
+        writeln!(out)?;
+
+        for point in &self.points {
+            write!(out, "{}", point.timestamp_ms)?;
+            for live in &point.live {
+                write!(out, ",{}", live)?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_json(&self, out: impl Write) -> io::Result<()> {
+        serde_json::to_writer_pretty(out, self)?;
+        Ok(())
+    }
+}
+
+/// [`Exporter`] computing the [`SizeTimeline`] of the exported run from
+/// `input`, the trace it was parsed from, like [`SizeTimeline::new`].
+pub struct SizeTimelineExporter<'a, R> {
+    data: &'a AccumulatedData,
+    input: R,
+    buckets: Vec<SizeBucket>,
+    points: usize,
+}
+
+impl<'a, R: BufRead> SizeTimelineExporter<'a, R> {
+    pub fn new(data: &'a AccumulatedData, input: R, buckets: Vec<SizeBucket>) -> Self {
+        Self {
+            data,
+            input,
+            buckets,
+            points: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Exporter<'_> for SizeTimelineExporter<'_, R> {
+    type Output = Result<SizeTimeline, parser::Error>;
+
+    fn visit_summary(&mut self, summary: &RunSummary) {
+        // a point is sampled at each `c` record, like the timeline
+        self.points = summary.timeline_points;
+    }
+
+    fn finish(self) -> Self::Output {
+        SizeTimeline::read(self.data, self.input, self.buckets, self.points)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::export::export;
+    use crate::export::size_timeline::{SizeBucket, SizeTimeline, SizeTimelineExporter};
+    use crate::model::tests::{parse, TRACE};
+    use crate::model::Profile;
+
+    #[test]
+    fn test_size_timeline() {
+        let trace = format!("{}a 2000 3\n+ 2\nc c8\n- 1\nc 12c\n", TRACE);
+        let data = parse(&trace);
+
+        let timeline = SizeTimeline::new(&data, trace.as_bytes(), SizeBucket::defaults()).unwrap();
+        let points: Vec<(u128, &[u64])> = timeline
+            .points
+            .iter()
+            .map(|p| (p.timestamp_ms, p.live.as_slice()))
+            .collect();
+        assert_eq!(
+            points,
+            [
+                (100, &[0x30, 0, 0, 0][..]),
+                (200, &[0x30, 0, 0x2000, 0][..]),
+                (300, &[0x10, 0, 0x2000, 0][..]),
+            ]
+        );
+
+        let mut csv = Vec::new();
+        timeline.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(
+            csv.lines().take(2).collect::<Vec<_>>(),
+            ["timestamp_ms,tiny,small,medium,large", "100,48,0,0,0"]
+        );
+    }
+
+    #[test]
+    fn test_size_timeline_exporter() {
+        let trace = format!("{}a 2000 3\n+ 2\nc c8\n", TRACE);
+        let data = parse(&trace);
+        let profile = Profile::new(&data).unwrap();
+
+        let exporter = SizeTimelineExporter::new(&data, trace.as_bytes(), SizeBucket::defaults());
+        let timeline = export(&data, &profile, exporter).unwrap();
+        assert_eq!(
+            timeline,
+            SizeTimeline::new(&data, trace.as_bytes(), SizeBucket::defaults()).unwrap()
+        );
+        assert_eq!(timeline.points.len(), 2);
+    }
+}
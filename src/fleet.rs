@@ -0,0 +1,222 @@
+//! Tracing several programs at once, e.g. a client and its server or a pool
+//! of workers.
+//!
+//! Every [`Target`] runs with its own [`Session`], so each has its own record
+//! fifo and trace file. The traces can be merged into one file afterwards;
+//! [`Parser::traces`](crate::parser::Parser::traces) splits such a file into
+//! its logical traces again. The interpreters of the targets share their
+//! debug info and symbol lookups, so libraries used by several targets are
+//! symbolized once.
+
+use crate::interpret::SharedSymbols;
+use crate::model::Cost;
+use crate::parser::AccumulatedData;
+use crate::session;
+use crate::session::Session;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+pub struct Target {
+    pub name: String,
+    pub program: OsString,
+    pub args: Vec<OsString>,
+    pub cwd: PathBuf,
+    pub session: Session,
+}
+
+impl Target {
+    pub fn new(name: &str, program: impl Into<OsString>, session: Session) -> Self {
+        Self {
+            name: name.to_string(),
+            program: program.into(),
+            args: Vec::new(),
+            cwd: PathBuf::from("."),
+            session,
+        }
+    }
+
+    pub fn with_args(mut self, args: impl IntoIterator<Item = impl Into<OsString>>) -> Self {
+        self.args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_cwd(mut self, cwd: impl AsRef<Path>) -> Self {
+        self.cwd = cwd.as_ref().to_path_buf();
+        self
+    }
+}
+
+#[derive(Default)]
+pub struct Fleet {
+    targets: Vec<Target>,
+    symbols: SharedSymbols,
+}
+
+pub struct TargetResult {
+    pub name: String,
+    pub output: PathBuf,
+    pub result: Result<AccumulatedData, session::Error>,
+}
+
+/// Totals of one target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetSummary {
+    pub name: String,
+    pub total: Cost,
+    pub peak_rss: u64,
+    pub duration: Duration,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FleetSummary {
+    /// Targets whose run succeeded, in the order they were added.
+    pub targets: Vec<TargetSummary>,
+    /// Names of the targets whose run failed.
+    pub failed: Vec<String>,
+    /// Sum of the target totals. The peak is the sum of the individual
+    /// peaks, an upper bound of the combined peak.
+    pub total: Cost,
+    pub peak_rss: u64,
+}
+
+pub struct FleetReport {
+    pub results: Vec<TargetResult>,
+}
+
+impl Fleet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a target. Its session shares the symbols of the fleet unless it
+    /// was given its own.
+    pub fn with_target(mut self, mut target: Target) -> Self {
+        if !target.session.has_shared_symbols() {
+            target.session = target.session.with_shared_symbols(self.symbols.clone());
+        }
+        self.targets.push(target);
+        self
+    }
+
+    /// Runs all targets concurrently and waits for every one to finish.
+    pub fn run(self) -> FleetReport {
+        let results = thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .targets
+                .iter()
+                .map(|target| {
+                    scope.spawn(move || {
+                        target
+                            .session
+                            .run(&target.program, &target.args, &target.cwd)
+                    })
+                })
+                .collect();
+
+            self.targets
+                .iter()
+                .zip(handles)
+                .map(|(target, handle)| TargetResult {
+                    name: target.name.clone(),
+                    output: target.session.output().to_path_buf(),
+                    result: handle.join().unwrap_or_else(|_| {
+                        Err(session::Error::Io(io::Error::other(
+                            "target thread panicked",
+                        )))
+                    }),
+                })
+                .collect()
+        });
+
+        FleetReport { results }
+    }
+}
+
+impl FleetReport {
+    pub fn summary(&self) -> FleetSummary {
+        let mut summary = FleetSummary::default();
+
+        for target in &self.results {
+            let Ok(data) = &target.result else {
+                summary.failed.push(target.name.clone());
+                continue;
+            };
+
+            let total = Cost::from(&data.total);
+            summary.total.add(&total);
+            summary.peak_rss += data.peak_rss;
+            summary.targets.push(TargetSummary {
+                name: target.name.clone(),
+                total,
+                peak_rss: data.peak_rss,
+                duration: data.duration,
+            });
+        }
+
+        summary
+    }
+
+    /// Concatenates the trace files of all targets into `path`.
+    pub fn merge(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        for target in &self.results {
+            io::copy(&mut File::open(&target.output)?, &mut out)?;
+        }
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fleet::{FleetReport, TargetResult};
+    use crate::model::tests::{data, TRACE};
+    use crate::parser::Parser;
+    use crate::session;
+    use std::io;
+
+    #[test]
+    fn test_summary_and_merge() {
+        let dir = std::env::temp_dir();
+        let outputs: Vec<_> = ["client", "server"]
+            .iter()
+            .map(|name| dir.join(format!("memtrack-fleet-{}-{}", std::process::id(), name)))
+            .collect();
+        for output in &outputs {
+            std::fs::write(output, TRACE).unwrap();
+        }
+
+        let report = FleetReport {
+            results: vec![
+                TargetResult {
+                    name: "client".into(),
+                    output: outputs[0].clone(),
+                    result: Ok(data()),
+                },
+                TargetResult {
+                    name: "server".into(),
+                    output: outputs[1].clone(),
+                    result: Err(session::Error::Io(io::ErrorKind::NotFound.into())),
+                },
+            ],
+        };
+
+        let summary = report.summary();
+        assert_eq!(summary.targets.len(), 1);
+        assert_eq!(summary.failed, ["server"]);
+        assert_eq!(summary.total.allocations, 3);
+
+        let merged = dir.join(format!("memtrack-fleet-{}-merged", std::process::id()));
+        report.merge(&merged).unwrap();
+        let traces = Parser::new().parse_file_all(&merged);
+        for path in outputs.iter().chain([&merged]) {
+            _ = std::fs::remove_file(path);
+        }
+
+        assert_eq!(traces.unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,665 @@
+//! Tools rewriting trace files and the description of their format.
+
+#[cfg(feature = "wire")]
+pub mod binary;
+pub mod record;
+pub mod schema;
+pub mod window;
+
+pub use record::{decode_record, encode_record, Record, Value};
+pub use schema::{FILE_VERSION, SCHEMA};
+pub use window::{peak_window, WindowStats};
+
+use crate::format::schema::FieldType;
+use crate::numparse::parse_hex;
+use crate::parser;
+use crate::parser::{read_line, AccumulatedData, Frame, Parser, TraceChecksum, STDIN_PATH};
+use crate::redact::Redaction;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use thiserror::Error;
+
+/// Leading bytes of a binary trace, read with the `wire` feature. A text
+/// trace never starts with a NUL.
+pub const BINARY_MAGIC: &[u8] = b"\0mtb";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("Parser")]
+    Parse(#[from] parser::Error),
+    #[error("Invalid format")]
+    InvalidFormat,
+    #[error("unsupported file version {0}")]
+    UnsupportedVersion(u16),
+}
+
+fn hex(field: Option<&str>) -> Result<u64, Error> {
+    field
+        .and_then(|field| parse_hex(field.as_bytes()))
+        .ok_or(Error::InvalidFormat)
+}
+
+/// Splits a length-prefixed string record like `s <len> <value>` into its
+/// value, or returns `None` when `line` is not such a record.
+fn string_value<'a>(line: &'a str, tag: &str) -> Option<&'a str> {
+    let rest = line.strip_prefix(tag)?.strip_prefix(' ')?;
+    let (len, _) = rest.split_once(' ')?;
+    let len = usize::from_str_radix(len, 16).ok()?;
+
+    line.get(line.len().checked_sub(len)?..)
+}
+
+/// Writes a line read by [`read_line`] unchanged.
+fn write_raw(output: &mut impl Write, line: &[u8]) -> io::Result<()> {
+    output.write_all(line)?;
+    output.write_all(b"\n")
+}
+
+/// Output of a rewritten trace. The `E` records of the input no longer
+/// match the rewritten lines, so they are replaced by fresh ones.
+struct Finalized<W> {
+    inner: W,
+    checksum: TraceChecksum,
+}
+
+impl<W: Write> Finalized<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            checksum: TraceChecksum::default(),
+        }
+    }
+
+    fn write_finished(&mut self) -> io::Result<()> {
+        self.checksum.write_record(&mut self.inner)?;
+        self.checksum = TraceChecksum::default();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Finalized<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.checksum.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Copies a trace from `input` to `output`, demangling all symbol names of a
+/// trace recorded with demangling disabled.
+pub fn demangle(mut input: impl BufRead, output: impl Write) -> io::Result<()> {
+    let mut output = Finalized::new(output);
+    let mut line = Vec::new();
+    while read_line(&mut input, &mut line)? {
+        if line.first() == Some(&b'E') {
+            output.write_finished()?;
+            continue;
+        }
+
+        // mangled names are ASCII, anything else is copied unchanged
+        match std::str::from_utf8(&line)
+            .ok()
+            .and_then(|line| string_value(line, "s"))
+        {
+            Some(value) => {
+                let value = rustc_demangle::demangle(value).to_string();
+                writeln!(output, "s {:x} {}", value.len(), value)?;
+            }
+            None => write_raw(&mut output, &line)?,
+        }
+    }
+
+    output.flush()
+}
+
+/// Copies a trace from `input` to `output`, redacting the command line and
+/// the metadata values with `redaction`. Returns the number of records
+/// changed.
+pub fn redact(
+    mut input: impl BufRead,
+    output: impl Write,
+    redaction: &Redaction,
+) -> Result<usize, Error> {
+    let mut output = Finalized::new(output);
+    let mut redacted = 0;
+    let mut line = Vec::new();
+    while read_line(&mut input, &mut line)? {
+        match line.first() {
+            Some(b'E') => {
+                output.write_finished()?;
+                continue;
+            }
+            Some(b'X' | b'M') => {}
+            _ => {
+                write_raw(&mut output, &line)?;
+                continue;
+            }
+        }
+
+        let mut record = decode_record(&line)?;
+        let changed = match record.values.as_mut_slice() {
+            [Value::Text(command)] if record.tag == schema::tag::EXEC => {
+                let value = redaction.redact_command(command);
+                (value != *command).then(|| *command = value.into())
+            }
+            [Value::Text(key), Value::Text(value)] => {
+                let new = redaction.redact_metadata(key, value);
+                (new != *value).then(|| *value = new.into())
+            }
+            _ => None,
+        };
+        match changed {
+            Some(()) => {
+                redacted += 1;
+                encode_record(&record, &mut output)?;
+            }
+            None => write_raw(&mut output, &line)?,
+        }
+    }
+
+    output.flush()?;
+    Ok(redacted)
+}
+
+const COLLAPSED: &str = "<collapsed sites>";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactStats {
+    pub kept_sites: usize,
+    pub collapsed_sites: usize,
+    pub strings: (usize, usize),
+    pub instruction_pointers: (usize, usize),
+    pub traces: (usize, usize),
+    pub allocation_infos: (usize, usize),
+}
+
+/// Maps old 1-based indices of kept entries to new dense 1-based indices.
+#[derive(Default)]
+struct Remap {
+    kept: HashSet<u64>,
+    map: HashMap<u64, u64>,
+    next: u64,
+}
+
+impl Remap {
+    fn keep(&mut self, old: u64) -> Option<u64> {
+        if !self.kept.contains(&old) {
+            return None;
+        }
+        self.next += 1;
+        self.map.insert(old, self.next);
+        Some(self.next)
+    }
+
+    fn get(&self, old: u64) -> Result<u64, Error> {
+        if old == 0 {
+            return Ok(0);
+        }
+        self.map.get(&old).copied().ok_or(Error::InvalidFormat)
+    }
+
+    fn add(&mut self) -> u64 {
+        self.next += 1;
+        self.next
+    }
+}
+
+/// Input read several times. The standard input is spooled to an unlinked
+/// temporary file rather than held in memory.
+struct Rereadable<P> {
+    path: P,
+    spool: Option<File>,
+}
+
+impl<P: AsRef<Path>> Rereadable<P> {
+    fn new(path: P) -> io::Result<Self> {
+        static SPOOLS: AtomicUsize = AtomicUsize::new(0);
+
+        let spool = if path.as_ref() == Path::new(STDIN_PATH) {
+            let spool_path = std::env::temp_dir().join(format!(
+                "memtrack-stdin-{}-{}",
+                std::process::id(),
+                SPOOLS.fetch_add(1, Ordering::Relaxed)
+            ));
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&spool_path)?;
+            // the open file keeps the data
+            fs::remove_file(&spool_path)?;
+            io::copy(&mut io::stdin().lock(), &mut file)?;
+            Some(file)
+        } else {
+            None
+        };
+        Ok(Self { path, spool })
+    }
+
+    fn open(&self) -> io::Result<Box<dyn BufRead + '_>> {
+        match &self.spool {
+            Some(spool) => {
+                let mut file = spool.try_clone()?;
+                file.seek(SeekFrom::Start(0))?;
+                Ok(Box::new(BufReader::new(file)))
+            }
+            None => Ok(Box::new(BufReader::new(File::open(&self.path)?))),
+        }
+    }
+}
+
+/// Marks the traces of the stacks of `trace_indices` and the instruction
+/// pointers and strings they reference as kept.
+fn keep_stacks(
+    data: &AccumulatedData,
+    trace_indices: impl IntoIterator<Item = u64>,
+    traces: &mut Remap,
+    ips: &mut Remap,
+    strings: &mut Remap,
+) -> Result<(), Error> {
+    for mut idx in trace_indices {
+        while idx != 0 && traces.kept.insert(idx) {
+            let trace = data
+                .traces
+                .get(idx as usize - 1)
+                .ok_or(Error::InvalidFormat)?;
+            ips.kept.insert(trace.ip_idx);
+            idx = trace.parent_idx;
+        }
+    }
+
+    for &ip_idx in &ips.kept {
+        let ip = data
+            .instruction_pointers
+            .get(ip_idx as usize - 1)
+            .ok_or(Error::InvalidFormat)?;
+        strings.kept.insert(ip.module_idx as u64);
+        for frame in std::iter::once(&ip.frame).chain(&ip.inlined) {
+            match frame {
+                Frame::Single { function_idx } => {
+                    strings.kept.insert(*function_idx as u64);
+                }
+                Frame::Multiple {
+                    function_idx,
+                    file_idx,
+                    ..
+                } => {
+                    strings.kept.insert(*function_idx as u64);
+                    strings.kept.insert(*file_idx as u64);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Rewrites the trace at `input`, which may be
+/// [`STDIN_PATH`](crate::parser::STDIN_PATH), keeping full detail for the `keep_top_k`
+/// sites with the highest peak and collapsing all other sites into a single
+/// `<collapsed sites>` pseudo site.
+///
+/// Strings, instruction pointers and traces only referenced by collapsed
+/// sites are dropped. All allocation events are kept, so totals and the
+/// peak are reproduced exactly; temporary counts of collapsed sites may
+/// grow since they now share one site.
+///
+/// The input is read twice, so the standard input is first copied to a
+/// temporary file.
+pub fn compact(
+    input: impl AsRef<Path>,
+    mut output: impl Write,
+    keep_top_k: usize,
+) -> Result<CompactStats, Error> {
+    let input = Rereadable::new(input)?;
+    let data = Parser::new().parse_reader(input.open()?)?;
+
+    let mut ranked: Vec<usize> = (0..data.allocations.len()).collect();
+    ranked.sort_by_key(|&idx| std::cmp::Reverse(data.allocations[idx].data.peak));
+    let kept_sites: HashSet<u64> = ranked.iter().take(keep_top_k).map(|&i| i as u64).collect();
+
+    let mut traces = Remap::default();
+    let mut ips = Remap::default();
+    let mut strings = Remap::default();
+
+    keep_stacks(
+        &data,
+        kept_sites
+            .iter()
+            .map(|&site| data.allocations[site as usize].trace_idx),
+        &mut traces,
+        &mut ips,
+        &mut strings,
+    )?;
+
+    let mut stats = CompactStats {
+        kept_sites: kept_sites.len(),
+        collapsed_sites: data.allocations.len() - kept_sites.len(),
+        ..Default::default()
+    };
+
+    let mut counts = (0, 0, 0, 0);
+    let mut infos: Vec<u64> = Vec::with_capacity(data.allocation_infos.len());
+    let mut collapsed_infos: HashMap<u64, u64> = HashMap::new();
+    let mut collapsed_trace = None;
+    let mut next_info = 0;
+
+    let mut reader = input.open()?;
+    let mut raw = Vec::new();
+    while read_line(&mut reader, &mut raw)? {
+        let line = String::from_utf8_lossy(&raw);
+        let mut split = line.split_whitespace();
+
+        match split.next() {
+            Some("s") => {
+                counts.0 += 1;
+                if strings.keep(counts.0).is_some() {
+                    write_raw(&mut output, &raw)?;
+                }
+            }
+            Some("i") => {
+                counts.1 += 1;
+                if ips.keep(counts.1).is_some() {
+                    let ip = hex(split.next())?;
+                    let module_idx = strings.get(hex(split.next())?)?;
+                    write!(output, "i {:x} {:x}", ip, module_idx)?;
+                    let fields: Vec<&str> = split.collect();
+                    for chunk in fields.chunks(3) {
+                        write!(output, " {:x}", strings.get(hex(chunk.first().copied())?)?)?;
+                        if chunk.len() == 3 {
+                            let file_idx = strings.get(hex(Some(chunk[1]))?)?;
+                            write!(output, " {:x} {}", file_idx, chunk[2])?;
+                        }
+                    }
+                    writeln!(output)?;
+                }
+            }
+            Some("t") => {
+                counts.2 += 1;
+                if traces.keep(counts.2).is_some() {
+                    let ip_idx = ips.get(hex(split.next())?)?;
+                    let parent_idx = traces.get(hex(split.next())?)?;
+                    writeln!(output, "t {:x} {:x}", ip_idx, parent_idx)?;
+                }
+            }
+            Some("a") => {
+                let info = data
+                    .allocation_infos
+                    .get(counts.3)
+                    .ok_or(Error::InvalidFormat)?;
+                counts.3 += 1;
+
+                if kept_sites.contains(&info.allocation_idx) {
+                    let size = hex(split.next())?;
+                    let trace_idx = traces.get(hex(split.next())?)?;
+                    writeln!(output, "a {:x} {:x}", size, trace_idx)?;
+                    infos.push(next_info);
+                    next_info += 1;
+                    continue;
+                }
+
+                let trace_idx = match collapsed_trace {
+                    Some(idx) => idx,
+                    None => {
+                        let string_idx = strings.add();
+                        writeln!(output, "s {:x} {}", COLLAPSED.len(), COLLAPSED)?;
+                        let ip_idx = ips.add();
+                        writeln!(output, "i 0 {:x} {:x}", string_idx, string_idx)?;
+                        let trace_idx = traces.add();
+                        writeln!(output, "t {:x} 0", ip_idx)?;
+                        collapsed_trace = Some(trace_idx);
+                        trace_idx
+                    }
+                };
+
+                let info_idx = match collapsed_infos.entry(info.size) {
+                    Entry::Occupied(e) => *e.get(),
+                    Entry::Vacant(e) => {
+                        writeln!(output, "a {:x} {:x}", info.size, trace_idx)?;
+                        next_info += 1;
+                        *e.insert(next_info - 1)
+                    }
+                };
+                infos.push(info_idx);
+            }
+            Some(tag @ ("+" | "-")) => {
+                let idx = hex(split.next())? as usize;
+                let info_idx = infos.get(idx).ok_or(Error::InvalidFormat)?;
+                match split.next() {
+                    Some(timestamp) => writeln!(output, "{} {:x} {}", tag, info_idx, timestamp)?,
+                    None => writeln!(output, "{} {:x}", tag, info_idx)?,
+                }
+            }
+            Some("#") | None => {}
+            Some(_) => write_raw(&mut output, &raw)?,
+        }
+    }
+
+    output.flush()?;
+
+    stats.strings = (counts.0 as usize, strings.next as usize);
+    stats.instruction_pointers = (counts.1 as usize, ips.next as usize);
+    stats.traces = (counts.2 as usize, traces.next as usize);
+    stats.allocation_infos = (counts.3, next_info as usize);
+
+    Ok(stats)
+}
+
+/// Records changed by [`convert`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConvertReport {
+    /// File version of every converted trace, from its `v` record.
+    pub from: Vec<u16>,
+    pub to: u16,
+    /// Records dropped by tag, either unknown or introduced after the
+    /// target version.
+    pub dropped: BTreeMap<String, usize>,
+    /// Records by tag that lost their fields introduced after the target
+    /// version.
+    pub stripped: BTreeMap<String, usize>,
+}
+
+impl ConvertReport {
+    /// Whether the conversion kept every record unchanged.
+    pub fn is_lossless(&self) -> bool {
+        self.dropped.is_empty() && self.stripped.is_empty()
+    }
+}
+
+impl fmt::Display for ConvertReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for from in &self.from {
+            writeln!(f, "converted version {} to {}", from, self.to)?;
+        }
+        for (tag, count) in &self.dropped {
+            writeln!(f, "dropped {} `{}` records", count, tag)?;
+        }
+        for (tag, count) in &self.stripped {
+            writeln!(f, "stripped newer fields of {} `{}` records", count, tag)?;
+        }
+        Ok(())
+    }
+}
+
+/// Copies a trace from `input` to `output` in the text format of
+/// `target_version`, so tools of different releases can read it.
+///
+/// Upgrading only rewrites the version. Downgrading drops the records
+/// introduced after the target version and strips optional trailing fields
+/// added to older records, like the timestamps of version 2. Records unknown
+/// to [`SCHEMA`], e.g. of a newer release, are dropped in both directions.
+pub fn convert(
+    mut input: impl BufRead,
+    output: impl Write,
+    target_version: u16,
+) -> Result<ConvertReport, Error> {
+    if target_version == 0 || target_version > FILE_VERSION {
+        return Err(Error::UnsupportedVersion(target_version));
+    }
+
+    let mut report = ConvertReport {
+        to: target_version,
+        ..Default::default()
+    };
+    let mut output = Finalized::new(output);
+
+    let mut line = Vec::new();
+    while read_line(&mut input, &mut line)? {
+        let mut fields = line
+            .split(|b| b.is_ascii_whitespace())
+            .filter(|field| !field.is_empty());
+        let Some(tag) = fields.next() else {
+            write_raw(&mut output, &line)?;
+            continue;
+        };
+        let tag_name = String::from_utf8_lossy(tag).into_owned();
+
+        let schema = match tag {
+            &[tag] => SCHEMA.record(tag as char),
+            _ => None,
+        };
+        let Some(schema) = schema else {
+            *report.dropped.entry(tag_name).or_default() += 1;
+            continue;
+        };
+
+        if schema.tag == 'v' {
+            let protocol = fields.next().ok_or(Error::InvalidFormat)?;
+            // the first traces did not record a file version
+            let from = fields.next().and_then(parse_hex).unwrap_or(1);
+            report.from.push(from as u16);
+            output.write_all(b"v ")?;
+            output.write_all(protocol)?;
+            writeln!(output, " {:x}", target_version)?;
+            continue;
+        }
+        if schema.tag == schema::tag::FINISHED && schema.since <= target_version {
+            output.write_finished()?;
+            continue;
+        }
+        if schema.since <= target_version {
+            write_raw(&mut output, &line)?;
+            continue;
+        }
+
+        // a record whose newer fields are optional existed before them
+        let kept = schema
+            .fields
+            .iter()
+            .take_while(|field| field.since <= target_version)
+            .count();
+        let newer = &schema.fields[kept..];
+        if newer.is_empty() || newer.iter().any(|f| f.ty != FieldType::OptionalHex) {
+            *report.dropped.entry(tag_name).or_default() += 1;
+            continue;
+        }
+        let mut line = line.trim_ascii_end();
+        let present = fields.count();
+        if present <= kept {
+            write_raw(&mut output, line)?;
+            continue;
+        }
+        for _ in kept..present {
+            let end = line
+                .iter()
+                .rposition(|b| b.is_ascii_whitespace())
+                .ok_or(Error::InvalidFormat)?;
+            line = line[..end].trim_ascii_end();
+        }
+        write_raw(&mut output, line)?;
+        *report.stripped.entry(tag_name).or_default() += 1;
+    }
+
+    output.flush()?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::format::{compact, convert, demangle, Error, FILE_VERSION};
+    use crate::model::tests::{data, parse, TRACE};
+
+    #[test]
+    fn test_demangle() {
+        let input = "s 21 _ZN4test4main17h0123456789abcdefE\ns 4 /a b\nt 1 0\n";
+        let mut output = Vec::new();
+        demangle(input.as_bytes(), &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "s 1d test::main::h0123456789abcdef\ns 4 /a b\nt 1 0\n"
+        );
+    }
+
+    #[test]
+    fn test_convert() {
+        let input = "v 3 3\nb 10 3 req\n+ 0 64\n+ 1\nZ 1\ne 20 3 req\nc 1\n";
+
+        let mut output = Vec::new();
+        let report = convert(input.as_bytes(), &mut output, 1).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "v 3 1\n+ 0\n+ 1\nc 1\n");
+        assert_eq!(report.from, [3]);
+        assert_eq!(report.dropped.len(), 3);
+        assert_eq!(report.dropped["b"], 1);
+        assert_eq!(report.stripped["+"], 1);
+
+        let owned = "v 1 8\no 0 2\n- 0 64 2\n";
+        let mut output = Vec::new();
+        let report = convert(owned.as_bytes(), &mut output, 7).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "v 1 7\n- 0 64\n");
+        assert_eq!((report.dropped["o"], report.stripped["-"]), (1, 1));
+        let mut output = Vec::new();
+        convert(owned.as_bytes(), &mut output, 1).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "v 1 1\n- 0\n");
+
+        let mut output = Vec::new();
+        let report = convert("v 1 2\n+ 0 64\n".as_bytes(), &mut output, FILE_VERSION).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "v 1 8\n+ 0 64\n");
+        assert!(report.is_lossless());
+
+        // the `E` record no longer matching the rewritten lines is replaced
+        let finished = format!("{}E 1 2\n", TRACE);
+        let mut output = Vec::new();
+        convert(finished.as_bytes(), &mut output, FILE_VERSION).unwrap();
+        assert!(parse(&String::from_utf8(output).unwrap()).complete);
+
+        assert!(matches!(
+            convert(input.as_bytes(), Vec::new(), FILE_VERSION + 1),
+            Err(Error::UnsupportedVersion(_))
+        ));
+    }
+
+    #[test]
+    fn test_compact() {
+        let path =
+            std::env::temp_dir().join(format!("memtrack-compact-{}.trace", std::process::id()));
+        std::fs::write(&path, TRACE).unwrap();
+
+        let mut output = Vec::new();
+        let stats = compact(&path, &mut output, 1).unwrap();
+        _ = std::fs::remove_file(&path);
+
+        assert_eq!(stats.kept_sites, 1);
+        assert_eq!(stats.collapsed_sites, 1);
+
+        let original = data();
+        let compacted = parse(&String::from_utf8(output).unwrap());
+        assert_eq!(compacted.total.allocations, original.total.allocations);
+        assert_eq!(compacted.total.peak, original.total.peak);
+        assert_eq!(compacted.total.leaked, original.total.leaked);
+        assert_eq!(compacted.allocations.len(), 2);
+    }
+}
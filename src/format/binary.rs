@@ -0,0 +1,180 @@
+//! Binary encoding of the text format, for writers trading readability for
+//! size and speed.
+//!
+//! A binary trace starts with [`BINARY_MAGIC`], followed by the [`Record`]s
+//! of the text format encoded with bincode, each prefixed by its length as a
+//! little-endian `u32`. [`BinaryReader`] turns them back into text lines, so
+//! [`open_input`](crate::parser::open_input) reads binary traces like text
+//! ones and every [`Parser`](crate::parser::Parser) API works on them.
+
+use crate::format::record::{decode_record, encode_record, Record};
+use crate::format::{Error, BINARY_MAGIC};
+use crate::parser::read_line;
+use bincode::Options;
+use std::io;
+use std::io::{BufRead, Read, Write};
+
+/// Encoding of records and commands, in binary traces and on the pipe:
+/// integers are fixed-width and little endian, so the writer and the reader
+/// may differ in pointer width and byte order.
+pub(crate) fn wire() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_little_endian()
+        .allow_trailing_bytes()
+}
+
+fn invalid_data(message: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Reads a binary trace as the lines of the text format.
+pub struct BinaryReader<R> {
+    inner: R,
+    frame: Vec<u8>,
+    /// Text line of the last decoded record and the part of it already read.
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: BufRead> BinaryReader<R> {
+    /// Reads the binary trace of `inner`, failing unless it starts with
+    /// [`BINARY_MAGIC`].
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut magic = [0u8; BINARY_MAGIC.len()];
+        inner.read_exact(&mut magic)?;
+        if magic != BINARY_MAGIC {
+            return Err(invalid_data("not a binary trace"));
+        }
+
+        Ok(Self {
+            inner,
+            frame: Vec::new(),
+            line: Vec::new(),
+            pos: 0,
+        })
+    }
+
+    /// Decodes the next record into `line`, false at the end of the trace.
+    fn next_line(&mut self) -> io::Result<bool> {
+        if self.inner.fill_buf()?.is_empty() {
+            return Ok(false);
+        }
+        let mut len = [0u8; 4];
+        self.inner.read_exact(&mut len)?;
+        self.frame.resize(u32::from_le_bytes(len) as usize, 0);
+        self.inner.read_exact(&mut self.frame)?;
+
+        let record: Record = wire().deserialize(&self.frame).map_err(invalid_data)?;
+        self.line.clear();
+        self.pos = 0;
+        encode_record(&record, &mut self.line)?;
+        Ok(true)
+    }
+}
+
+impl<R: BufRead> Read for BinaryReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for BinaryReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.line.len() && !self.next_line()? {
+            return Ok(&[]);
+        }
+        Ok(&self.line[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.line.len());
+    }
+}
+
+/// Writes [`Record`]s as a binary trace.
+pub struct BinaryWriter<W> {
+    inner: W,
+    frame: Vec<u8>,
+}
+
+impl<W: Write> BinaryWriter<W> {
+    pub fn new(mut inner: W) -> io::Result<Self> {
+        inner.write_all(BINARY_MAGIC)?;
+        Ok(Self {
+            inner,
+            frame: Vec::new(),
+        })
+    }
+
+    pub fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        self.frame.clear();
+        wire()
+            .serialize_into(&mut self.frame, record)
+            .map_err(invalid_data)?;
+        let len = u32::try_from(self.frame.len()).map_err(|_| invalid_data("record too large"))?;
+        self.inner.write_all(&len.to_le_bytes())?;
+        self.inner.write_all(&self.frame)
+    }
+
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// Converts the text trace of `input` to a binary trace, returning the
+/// number of records written. Empty lines are dropped.
+pub fn to_binary(mut input: impl BufRead, output: impl Write) -> Result<usize, Error> {
+    let mut writer = BinaryWriter::new(output)?;
+    let mut records = 0;
+    let mut line = Vec::new();
+    while read_line(&mut input, &mut line)? {
+        if line.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+        writer.write_record(&decode_record(&line)?)?;
+        records += 1;
+    }
+    writer.into_inner()?;
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::format::binary::{to_binary, BinaryReader};
+    use crate::format::BINARY_MAGIC;
+    use crate::model::tests::{data, TRACE};
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_binary_trace() {
+        let trace = format!("{}X ./app --flag\nm 3 a b\n", TRACE);
+        let mut binary = Vec::new();
+        let records = to_binary(trace.as_bytes(), &mut binary).unwrap();
+        assert_eq!(records, trace.lines().count());
+        assert!(binary.starts_with(BINARY_MAGIC));
+
+        let mut text = String::new();
+        std::io::Read::read_to_string(
+            &mut BinaryReader::new(binary.as_slice()).unwrap(),
+            &mut text,
+        )
+        .unwrap();
+        assert_eq!(text, trace);
+
+        let path = std::env::temp_dir().join(format!("memtrack-{}.trace.bin", std::process::id()));
+        std::fs::write(&path, &binary).unwrap();
+        let parsed = Parser::new().parse_file(&path);
+        _ = std::fs::remove_file(&path);
+        let parsed = parsed.unwrap();
+        assert_eq!(parsed.total.leaked, data().total.leaked);
+        assert_eq!(parsed.command.as_deref(), Some("./app --flag"));
+
+        assert!(BinaryReader::new(trace.as_bytes()).is_err());
+    }
+}
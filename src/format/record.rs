@@ -0,0 +1,220 @@
+//! Records of the text format as values, decoded and encoded following the
+//! [`SCHEMA`].
+//!
+//! [`Output`](crate::output::Output) and [`Parser`](crate::parser::Parser)
+//! handle the records they know directly for speed. These helpers handle any
+//! record of the schema, for tools working on single lines such as
+//! highlighters, fuzzers and converters.
+
+use crate::format::schema::{FieldType, RecordSchema};
+use crate::format::{Error, SCHEMA};
+use crate::numparse::parse_hex;
+use crate::parser::Frame;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::io;
+use std::io::Write;
+
+/// Value of a field, in the order of [`RecordSchema::fields`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Value<'a> {
+    /// [`FieldType::Hex`] and [`FieldType::OptionalHex`] fields.
+    Hex(u64),
+    /// [`FieldType::Word`], [`FieldType::String`] and [`FieldType::Rest`]
+    /// fields. Invalid UTF-8 is replaced.
+    Text(Cow<'a, str>),
+    Frames(Vec<Frame>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record<'a> {
+    pub tag: char,
+    /// Values of the fields, without the missing optional ones at the end.
+    pub values: Vec<Value<'a>>,
+}
+
+impl Record<'_> {
+    pub fn schema(&self) -> Option<&'static RecordSchema> {
+        SCHEMA.record(self.tag)
+    }
+}
+
+/// Splits a line into fields, keeping track of the position for the fields
+/// extending to the end of the line.
+struct Cursor<'a> {
+    line: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn next_field(&mut self) -> Option<&'a [u8]> {
+        let rest = &self.line[self.pos..];
+        let start = rest.iter().position(|b| !b.is_ascii_whitespace())?;
+        let len = rest[start..]
+            .iter()
+            .position(u8::is_ascii_whitespace)
+            .unwrap_or(rest.len() - start);
+        self.pos += start + len;
+        Some(&rest[start..start + len])
+    }
+
+    fn next_hex(&mut self) -> Result<u64, Error> {
+        self.next_field()
+            .and_then(parse_hex)
+            .ok_or(Error::InvalidFormat)
+    }
+
+    /// The rest of the line after the separator of the previous field.
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.line[(self.pos + 1).min(self.line.len())..];
+        self.pos = self.line.len();
+        rest
+    }
+}
+
+/// Decodes a line without its newline into the record of its tag.
+pub fn decode_record(line: &[u8]) -> Result<Record<'_>, Error> {
+    let mut cursor = Cursor { line, pos: 0 };
+    let schema = match cursor.next_field() {
+        Some(&[tag]) => SCHEMA.record(tag as char),
+        _ => None,
+    }
+    .ok_or(Error::InvalidFormat)?;
+
+    let mut values = Vec::with_capacity(schema.fields.len());
+    for field in schema.fields {
+        let value = match field.ty {
+            FieldType::Hex => Value::Hex(cursor.next_hex()?),
+            FieldType::OptionalHex => match cursor.next_field() {
+                Some(value) => Value::Hex(parse_hex(value).ok_or(Error::InvalidFormat)?),
+                None => break,
+            },
+            FieldType::Word => Value::Text(String::from_utf8_lossy(
+                cursor.next_field().ok_or(Error::InvalidFormat)?,
+            )),
+            FieldType::String => {
+                let len = cursor.next_hex()? as usize;
+                let start = line.len().checked_sub(len).ok_or(Error::InvalidFormat)?;
+                if len > 0 && start <= cursor.pos {
+                    return Err(Error::InvalidFormat);
+                }
+                cursor.pos = line.len();
+                Value::Text(String::from_utf8_lossy(&line[start..]))
+            }
+            FieldType::Rest => Value::Text(String::from_utf8_lossy(cursor.rest())),
+            FieldType::Frames => {
+                let mut frames = Vec::new();
+                while let Some(function) = cursor.next_field() {
+                    let function_idx = parse_hex(function).ok_or(Error::InvalidFormat)? as usize;
+                    frames.push(match cursor.next_field() {
+                        None => Frame::Single { function_idx },
+                        Some(file) => Frame::Multiple {
+                            function_idx,
+                            file_idx: parse_hex(file).ok_or(Error::InvalidFormat)? as usize,
+                            line_number: u32::try_from(cursor.next_hex()?)
+                                .map_err(|_| Error::InvalidFormat)?,
+                        },
+                    });
+                }
+                if frames.is_empty() {
+                    return Err(Error::InvalidFormat);
+                }
+                Value::Frames(frames)
+            }
+        };
+        values.push(value);
+    }
+
+    Ok(Record {
+        tag: schema.tag,
+        values,
+    })
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Writes `record` as a line, failing on tags missing from the [`SCHEMA`]
+/// and values not matching the types of its fields.
+pub fn encode_record(record: &Record, mut out: impl Write) -> io::Result<()> {
+    let schema = record
+        .schema()
+        .ok_or_else(|| invalid("unknown record tag"))?;
+    if record.values.len() > schema.fields.len() {
+        return Err(invalid("too many values"));
+    }
+
+    write!(out, "{}", record.tag)?;
+    for (i, field) in schema.fields.iter().enumerate() {
+        let value = match record.values.get(i) {
+            Some(value) => value,
+            None if field.ty == FieldType::OptionalHex => break,
+            None => return Err(invalid("missing value")),
+        };
+        match (field.ty, value) {
+            (FieldType::Hex | FieldType::OptionalHex, Value::Hex(value)) => {
+                write!(out, " {:x}", value)?
+            }
+            (FieldType::Word, Value::Text(value))
+                if !value.is_empty() && !value.contains(char::is_whitespace) =>
+            {
+                write!(out, " {}", value)?
+            }
+            (FieldType::String, Value::Text(value)) if !value.contains('\n') => {
+                write!(out, " {:x} {}", value.len(), value)?
+            }
+            (FieldType::Rest, Value::Text(value)) if !value.contains('\n') => {
+                write!(out, " {}", value)?
+            }
+            (FieldType::Frames, Value::Frames(frames)) if !frames.is_empty() => {
+                for frame in frames {
+                    match *frame {
+                        Frame::Single { function_idx } => write!(out, " {:x}", function_idx)?,
+                        Frame::Multiple {
+                            function_idx,
+                            file_idx,
+                            line_number,
+                        } => write!(out, " {:x} {:x} {:x}", function_idx, file_idx, line_number)?,
+                    }
+                }
+            }
+            _ => return Err(invalid("value does not match the field type")),
+        }
+    }
+    writeln!(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::format::record::{decode_record, encode_record, Record, Value};
+    use crate::format::schema::tag;
+    use crate::model::tests::TRACE;
+
+    #[test]
+    fn test_record_round_trip() {
+        let trace = format!(
+            "{}X ./app --flag\n# a comment\nm 3 a b\nb 5 1 x\ni 50 1 5 1 a 4 1 b\n",
+            TRACE
+        );
+        for line in trace.lines() {
+            let record = decode_record(line.as_bytes()).unwrap();
+            let mut encoded = Vec::new();
+            encode_record(&record, &mut encoded).unwrap();
+            assert_eq!(String::from_utf8(encoded).unwrap(), format!("{}\n", line));
+        }
+
+        let record = decode_record(b"M rlimit.as 13 unlimited unlimited").unwrap();
+        assert_eq!(record.tag, tag::METADATA);
+        assert_eq!(record.values[1], Value::Text("unlimited unlimited".into()));
+        assert_eq!(decode_record(b"+ 1").unwrap().values, [Value::Hex(1)]);
+
+        assert!(decode_record(b"Z 1").is_err());
+        assert!(decode_record(b"s 10 short").is_err());
+        let record = Record {
+            tag: tag::ALLOC,
+            values: vec![Value::Text("x".into())],
+        };
+        assert!(encode_record(&record, Vec::new()).is_err());
+    }
+}
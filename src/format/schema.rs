@@ -0,0 +1,336 @@
+//! Machine-readable description of the text trace format.
+//!
+//! Every line of a trace is a record starting with a one-character tag
+//! followed by space separated fields. Numbers are lowercase hex without
+//! prefix. Indices into strings, instruction pointers and traces are 1-based,
+//! 0 meaning none.
+
+use serde::Serialize;
+use std::io;
+use std::io::Write;
+
+/// Version of the record protocol, the first field of the `v` record.
+/// Version 2 added timestamps to the alloc, free and RSS records and the
+/// [`Record::Clock`](crate::pipe_io::Record::Clock) record, version 3 the
+/// transaction markers, version 4 the sequenced frames of multi-threaded
+/// writers and the reachability records sent at exit, version 5 the
+/// allocation pools declared by the program, version 6 the failed
+/// allocations, version 7 the ownership transfers.
+pub const PROTOCOL_VERSION: u16 = 7;
+
+/// Version of the text format, the second field of the `v` record.
+pub const FILE_VERSION: u16 = 8;
+
+/// Tags of the records, named after their [`RecordSchema::name`].
+pub mod tag {
+    pub const VERSION: char = 'v';
+    pub const EXEC: char = 'X';
+    pub const PAGE_INFO: char = 'I';
+    pub const STRING: char = 's';
+    pub const INSTRUCTION_POINTER: char = 'i';
+    pub const TRACE: char = 't';
+    pub const ALLOCATION_INFO: char = 'a';
+    pub const ALLOC: char = '+';
+    pub const FREE: char = '-';
+    pub const DURATION: char = 'c';
+    pub const RSS: char = 'R';
+    pub const METADATA: char = 'M';
+    pub const MARKER: char = 'm';
+    pub const TRANSACTION_BEGIN: char = 'b';
+    pub const TRANSACTION_END: char = 'e';
+    pub const REACHABLE: char = 'r';
+    pub const FINISHED: char = 'E';
+    pub const POOL: char = 'P';
+    pub const POOL_ALLOC: char = 'p';
+    pub const POOL_FREE: char = 'q';
+    pub const POOL_DESTROY: char = 'D';
+    pub const ALLOC_FAILED: char = 'f';
+    pub const TRANSFER: char = 'o';
+    pub const COMMENT: char = '#';
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldType {
+    Hex,
+    /// Hex number that may be missing at the end of the line.
+    OptionalHex,
+    /// Value without whitespace.
+    Word,
+    /// Hex byte length followed by that many bytes up to the end of the line.
+    String,
+    /// Everything up to the end of the line.
+    Rest,
+    /// Repeated frames of a function index, optionally followed by a file
+    /// index and a line number.
+    Frames,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct FieldSchema {
+    pub name: &'static str,
+    #[serde(rename = "type")]
+    pub ty: FieldType,
+    /// Format version that introduced the field, newer than its record for
+    /// the optional fields added to it later.
+    pub since: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RecordSchema {
+    pub tag: char,
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Format version that introduced the record or its last field.
+    pub since: u16,
+    pub fields: &'static [FieldSchema],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Schema {
+    pub protocol_version: u16,
+    pub file_version: u16,
+    pub records: &'static [RecordSchema],
+}
+
+impl Schema {
+    pub fn record(&self, tag: char) -> Option<&RecordSchema> {
+        self.records.iter().find(|r| r.tag == tag)
+    }
+
+    pub fn write_json(&self, out: impl Write) -> io::Result<()> {
+        serde_json::to_writer_pretty(out, self)?;
+        Ok(())
+    }
+}
+
+const fn field(name: &'static str, ty: FieldType) -> FieldSchema {
+    FieldSchema { name, ty, since: 1 }
+}
+
+/// An optional field added to an existing record in `since`.
+const fn added(name: &'static str, since: u16) -> FieldSchema {
+    FieldSchema {
+        name,
+        ty: OptionalHex,
+        since,
+    }
+}
+
+use FieldType::*;
+
+pub const SCHEMA: Schema = Schema {
+    protocol_version: PROTOCOL_VERSION,
+    file_version: FILE_VERSION,
+    records: &[
+        RecordSchema {
+            tag: tag::VERSION,
+            name: "version",
+            description: "Versions of the record protocol and of the text format",
+            since: 1,
+            fields: &[field("version", Hex), field("file_version", Hex)],
+        },
+        RecordSchema {
+            tag: tag::EXEC,
+            name: "exec",
+            description: "Command line of the traced program",
+            since: 1,
+            fields: &[field("command", Rest)],
+        },
+        RecordSchema {
+            tag: tag::PAGE_INFO,
+            name: "page_info",
+            description: "Page size and number of physical pages of the host",
+            since: 1,
+            fields: &[field("page_size", Hex), field("pages", Hex)],
+        },
+        RecordSchema {
+            tag: tag::STRING,
+            name: "string",
+            description: "Interned module path, function or file name",
+            since: 1,
+            fields: &[field("value", String)],
+        },
+        RecordSchema {
+            tag: tag::INSTRUCTION_POINTER,
+            name: "instruction_pointer",
+            description:
+                "Resolved instruction pointer with its module and frames, inlined frames last",
+            since: 1,
+            fields: &[
+                field("ip", Hex),
+                field("module_idx", Hex),
+                field("frames", Frames),
+            ],
+        },
+        RecordSchema {
+            tag: tag::TRACE,
+            name: "trace",
+            description: "Stack node of an instruction pointer and its caller's trace",
+            since: 1,
+            fields: &[field("ip_idx", Hex), field("parent_idx", Hex)],
+        },
+        RecordSchema {
+            tag: tag::ALLOCATION_INFO,
+            name: "allocation_info",
+            description: "Size and trace shared by allocations, referenced by `+` and `-`",
+            since: 1,
+            fields: &[field("size", Hex), field("trace_idx", Hex)],
+        },
+        RecordSchema {
+            tag: tag::ALLOC,
+            name: "alloc",
+            description: "Allocation, 0-based index of its allocation info",
+            since: 2,
+            fields: &[field("info_idx", Hex), added("timestamp_ns", 2)],
+        },
+        RecordSchema {
+            tag: tag::FREE,
+            name: "free",
+            description: "Free of an allocation, 0-based index of its allocation info and string index of its owner once transferred",
+            since: 8,
+            fields: &[
+                field("info_idx", Hex),
+                added("timestamp_ns", 2),
+                added("owner_idx", 8),
+            ],
+        },
+        RecordSchema {
+            tag: tag::DURATION,
+            name: "duration",
+            description: "Milliseconds since the start of the traced program",
+            since: 1,
+            fields: &[field("duration_ms", Hex)],
+        },
+        RecordSchema {
+            tag: tag::RSS,
+            name: "rss",
+            description: "Resident set size in pages",
+            since: 2,
+            fields: &[field("rss", Hex), added("timestamp_ns", 2)],
+        },
+        RecordSchema {
+            tag: tag::METADATA,
+            name: "metadata",
+            description: "Key and value describing the run, e.g. the captured environment",
+            since: 1,
+            fields: &[field("key", Word), field("value", String)],
+        },
+        RecordSchema {
+            tag: tag::MARKER,
+            name: "marker",
+            description: "Labeled point in the trace, e.g. a rules reload or an alert",
+            since: 1,
+            fields: &[field("label", String)],
+        },
+        RecordSchema {
+            tag: tag::TRANSACTION_BEGIN,
+            name: "transaction_begin",
+            description:
+                "Start of a transaction, events up to the matching `e` are attributed to it",
+            since: 3,
+            fields: &[field("timestamp_ns", Hex), field("label", String)],
+        },
+        RecordSchema {
+            tag: tag::TRANSACTION_END,
+            name: "transaction_end",
+            description: "End of the innermost open transaction with the label",
+            since: 3,
+            fields: &[field("timestamp_ns", Hex), field("label", String)],
+        },
+        RecordSchema {
+            tag: tag::REACHABLE,
+            name: "reachable",
+            description: "Allocation still referenced from a root at exit, 0-based index of its allocation info",
+            since: 4,
+            fields: &[field("info_idx", Hex)],
+        },
+        RecordSchema {
+            tag: tag::FINISHED,
+            name: "finished",
+            description: "Written on clean shutdown: number and FNV-1a hash of the lines since the previous `E` record",
+            since: 5,
+            fields: &[field("records", Hex), field("checksum", Hex)],
+        },
+        RecordSchema {
+            tag: tag::POOL,
+            name: "pool",
+            description: "Pool or arena declared by the program with its capacity in bytes, referenced by `p`, `q` and `D` by its 0-based index",
+            since: 6,
+            fields: &[field("capacity", Hex), field("name", String)],
+        },
+        RecordSchema {
+            tag: tag::POOL_ALLOC,
+            name: "pool_alloc",
+            description: "Allocation served by a pool, 0-based index of the pool",
+            since: 6,
+            fields: &[field("pool_idx", Hex), field("size", Hex)],
+        },
+        RecordSchema {
+            tag: tag::POOL_FREE,
+            name: "pool_free",
+            description: "Free of an allocation served by a pool, 0-based index of the pool",
+            since: 6,
+            fields: &[field("pool_idx", Hex), field("size", Hex)],
+        },
+        RecordSchema {
+            tag: tag::POOL_DESTROY,
+            name: "pool_destroy",
+            description: "Destruction of a pool releasing its remaining allocations",
+            since: 6,
+            fields: &[field("pool_idx", Hex)],
+        },
+        RecordSchema {
+            tag: tag::ALLOC_FAILED,
+            name: "alloc_failed",
+            description: "Allocation the allocator failed to serve, with its size and trace",
+            since: 7,
+            fields: &[
+                field("size", Hex),
+                field("trace_idx", Hex),
+                field("timestamp_ns", OptionalHex),
+            ],
+        },
+        RecordSchema {
+            tag: tag::TRANSFER,
+            name: "transfer",
+            description: "Live allocation handed over to a component, 0-based index of its allocation info, string indices of its new and previous owners",
+            since: 8,
+            fields: &[
+                field("info_idx", Hex),
+                field("owner_idx", Hex),
+                field("previous_idx", OptionalHex),
+            ],
+        },
+        RecordSchema {
+            tag: tag::COMMENT,
+            name: "comment",
+            description: "Ignored by readers",
+            since: 1,
+            fields: &[field("text", Rest)],
+        },
+    ],
+};
+
+#[cfg(test)]
+mod tests {
+    use crate::format::schema::SCHEMA;
+    use crate::model::tests::TRACE;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_schema() {
+        let tags: HashSet<char> = SCHEMA.records.iter().map(|r| r.tag).collect();
+        assert_eq!(tags.len(), SCHEMA.records.len());
+
+        for line in TRACE.lines() {
+            let tag = line.chars().next().unwrap();
+            assert!(SCHEMA.record(tag).is_some(), "no schema for `{}`", line);
+        }
+
+        let mut json = Vec::new();
+        SCHEMA.write_json(&mut json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["records"][4]["fields"][2]["type"], "frames");
+    }
+}
@@ -0,0 +1,278 @@
+//! Extraction of the time window around the heap peak of a trace.
+//!
+//! The window is written as a standalone trace holding only the strings,
+//! instruction pointers, traces and allocation infos its events refer to,
+//! so a visualizer too slow for the whole run can be pointed at the part
+//! that matters.
+
+use crate::format::{hex, keep_stacks, write_raw, Error, Remap, Rereadable};
+use crate::parser::{read_line, Frame, Parser};
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/// Label of the marker separating the replayed live allocations from the
+/// events of the window.
+pub const WINDOW_START: &str = "window start";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WindowStats {
+    /// Clock record time of the heap peak.
+    pub peak: Duration,
+    pub start: Duration,
+    pub end: Duration,
+    /// Allocations live at the start of the window, written as allocations
+    /// at its beginning.
+    pub live: u64,
+    /// Allocation and free records within the window.
+    pub events: u64,
+    pub allocation_infos: usize,
+}
+
+/// Writes the records within `half_width` before and after the heap peak of
+/// `input` as a trace of their own.
+///
+/// Times are those of the `c` clock records, events belong to the last
+/// clock record before them. Allocations live when the window starts are
+/// replayed at its beginning, so the peak and leaks inside the window are
+/// reproduced exactly, while the allocation counts include them.
+pub fn peak_window(
+    input: impl AsRef<Path>,
+    mut output: impl Write,
+    half_width: Duration,
+) -> Result<WindowStats, Error> {
+    let input = Rereadable::new(input)?;
+    let data = Parser::new().parse_reader(input.open()?)?;
+    let sizes: Vec<u64> = data.allocation_infos.iter().map(|i| i.size).collect();
+
+    let mut raw = Vec::new();
+    let mut now = 0;
+    let mut leaked = 0u64;
+    let mut reader = input.open()?;
+    while leaked < data.total.peak && read_line(&mut reader, &mut raw)? {
+        let line = String::from_utf8_lossy(&raw);
+        let mut split = line.split_whitespace();
+        match split.next() {
+            Some("c") => now = hex(split.next())?,
+            Some("+") => leaked += size(&sizes, hex(split.next())?)?,
+            Some("-") => leaked = leaked.saturating_sub(size(&sizes, hex(split.next())?)?),
+            _ => {}
+        }
+    }
+
+    let half_width = half_width.as_millis() as u64;
+    let (start, end) = (now.saturating_sub(half_width), now + half_width);
+    let mut stats = WindowStats {
+        peak: Duration::from_millis(now),
+        start: Duration::from_millis(start),
+        end: Duration::from_millis(end),
+        ..Default::default()
+    };
+
+    let mut header = Vec::new();
+    let mut events = Vec::new();
+    let mut live = vec![0u64; sizes.len()];
+    let mut used = vec![false; sizes.len()];
+    let mut now = 0;
+    let mut reader = input.open()?;
+    while read_line(&mut reader, &mut raw)? {
+        let line = String::from_utf8_lossy(&raw);
+        let mut split = line.split_whitespace();
+        let tag = split.next();
+        if let Some("c") = tag {
+            now = hex(split.next())?;
+            if now > end {
+                break;
+            }
+        }
+        let inside = now >= start;
+
+        match tag {
+            Some("v" | "X" | "I" | "M") => header.push(raw.clone()),
+            Some(tag @ ("+" | "-")) => {
+                let idx = hex(split.next())? as usize;
+                size(&sizes, idx as u64)?;
+                if inside {
+                    used[idx] = true;
+                    events.push(raw.clone());
+                    stats.events += 1;
+                } else if tag == "+" {
+                    live[idx] += 1;
+                } else {
+                    live[idx] = live[idx].saturating_sub(1);
+                }
+            }
+            Some("c" | "R" | "m" | "b" | "e") if inside => events.push(raw.clone()),
+            _ => {}
+        }
+    }
+
+    let mut traces = Remap::default();
+    let mut ips = Remap::default();
+    let mut strings = Remap::default();
+    let kept_infos: Vec<usize> = (0..sizes.len())
+        .filter(|&idx| used[idx] || live[idx] > 0)
+        .collect();
+    keep_stacks(
+        &data,
+        kept_infos.iter().map(|&idx| {
+            let allocation_idx = data.allocation_infos[idx].allocation_idx;
+            data.allocations[allocation_idx as usize].trace_idx
+        }),
+        &mut traces,
+        &mut ips,
+        &mut strings,
+    )?;
+
+    for line in &header {
+        write_raw(&mut output, line)?;
+    }
+
+    for (idx, value) in data.strings.iter().enumerate() {
+        if strings.keep(idx as u64 + 1).is_some() {
+            writeln!(output, "s {:x} {}", value.len(), value)?;
+        }
+    }
+    for (idx, ip) in data.instruction_pointers.iter().enumerate() {
+        if ips.keep(idx as u64 + 1).is_none() {
+            continue;
+        }
+        let module_idx = strings.get(ip.module_idx as u64)?;
+        write!(output, "i {:x} {:x}", ip.ip, module_idx)?;
+        for frame in std::iter::once(&ip.frame).chain(&ip.inlined) {
+            match *frame {
+                Frame::Single { function_idx } => {
+                    write!(output, " {:x}", strings.get(function_idx as u64)?)?
+                }
+                Frame::Multiple {
+                    function_idx,
+                    file_idx,
+                    line_number,
+                } => write!(
+                    output,
+                    " {:x} {:x} {:x}",
+                    strings.get(function_idx as u64)?,
+                    strings.get(file_idx as u64)?,
+                    line_number
+                )?,
+            }
+        }
+        writeln!(output)?;
+    }
+    for (idx, trace) in data.traces.iter().enumerate() {
+        if traces.keep(idx as u64 + 1).is_some() {
+            let ip_idx = ips.get(trace.ip_idx)?;
+            let parent_idx = traces.get(trace.parent_idx)?;
+            writeln!(output, "t {:x} {:x}", ip_idx, parent_idx)?;
+        }
+    }
+
+    let mut infos = vec![0; sizes.len()];
+    for (new_idx, &idx) in kept_infos.iter().enumerate() {
+        let allocation_idx = data.allocation_infos[idx].allocation_idx;
+        let trace_idx = traces.get(data.allocations[allocation_idx as usize].trace_idx)?;
+        writeln!(output, "a {:x} {:x}", sizes[idx], trace_idx)?;
+        infos[idx] = new_idx;
+    }
+    stats.allocation_infos = kept_infos.len();
+
+    writeln!(output, "c {:x}", start)?;
+    for &idx in &kept_infos {
+        for _ in 0..live[idx] {
+            writeln!(output, "+ {:x}", infos[idx])?;
+        }
+        stats.live += live[idx];
+    }
+    writeln!(output, "m {:x} {}", WINDOW_START.len(), WINDOW_START)?;
+
+    let mut clock = start;
+    for raw in &events {
+        let line = String::from_utf8_lossy(raw);
+        let mut split = line.split_whitespace();
+        match split.next() {
+            Some("c") => {
+                let now = hex(split.next())?;
+                if now != clock {
+                    write_raw(&mut output, raw)?;
+                    clock = now;
+                }
+            }
+            Some(tag @ ("+" | "-")) => {
+                let info_idx = infos[hex(split.next())? as usize];
+                match split.next() {
+                    Some(timestamp) => writeln!(output, "{} {:x} {}", tag, info_idx, timestamp)?,
+                    None => writeln!(output, "{} {:x}", tag, info_idx)?,
+                }
+            }
+            _ => write_raw(&mut output, raw)?,
+        }
+    }
+
+    output.flush()?;
+    Ok(stats)
+}
+
+fn size(sizes: &[u64], idx: u64) -> Result<u64, Error> {
+    sizes.get(idx as usize).copied().ok_or(Error::InvalidFormat)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::format::window::{peak_window, WINDOW_START};
+    use crate::model::tests::parse;
+    use std::time::Duration;
+
+    const TRACE: &str = "\
+v 1 3
+s 4 /bin
+s 4 main
+s 1 a
+s 1 b
+i 10 1 2
+i 20 1 3
+i 30 1 4
+t 1 0
+t 2 1
+t 3 1
+a 10 2
+a 100 3
+a 8 1
++ 2
+c 3e8
++ 0
++ 0
+c 7d0
++ 1
+c bb8
+- 1
+- 0
+c 1388
++ 1
+- 1
+";
+
+    #[test]
+    fn test_peak_window() {
+        let path =
+            std::env::temp_dir().join(format!("memtrack-window-{}.trace", std::process::id()));
+        std::fs::write(&path, TRACE).unwrap();
+
+        let mut output = Vec::new();
+        let stats = peak_window(&path, &mut output, Duration::from_secs(1)).unwrap();
+        _ = std::fs::remove_file(&path);
+
+        assert_eq!(stats.peak, Duration::from_secs(2));
+        assert_eq!(stats.start, Duration::from_secs(1));
+        assert_eq!(stats.live, 1);
+        assert_eq!(stats.events, 5);
+        assert_eq!(stats.allocation_infos, 3);
+
+        let window = parse(&String::from_utf8(output).unwrap());
+        let full = parse(TRACE);
+        assert_eq!(window.total.peak, full.total.peak);
+        assert_eq!(window.total.leaked, 0x18);
+        assert_eq!(window.duration, Duration::from_secs(3));
+        assert_eq!(window.strings.len(), 4);
+        assert_eq!(window.markers[0].label, WINDOW_START);
+    }
+}
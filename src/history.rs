@@ -0,0 +1,247 @@
+//! Local SQLite database of run summaries for tracking memory usage over
+//! time.
+//!
+//! Every recorded run stores its totals and the cost of each allocation
+//! site. Sites are keyed by [`site_key`], so the same site is found again in
+//! later builds as long as its call stack does not change.
+
+use crate::model::{Cost, Metric, Profile, Site};
+use crate::parser::AccumulatedData;
+use rusqlite::{params, Connection};
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("SQLite error")]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    allocations INTEGER NOT NULL,
+    temporary INTEGER NOT NULL,
+    leaked INTEGER NOT NULL,
+    peak INTEGER NOT NULL,
+    peak_rss INTEGER NOT NULL,
+    duration_ms INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS sites (
+    run_id INTEGER NOT NULL REFERENCES runs(id) ON DELETE CASCADE,
+    site TEXT NOT NULL,
+    allocations INTEGER NOT NULL,
+    temporary INTEGER NOT NULL,
+    leaked INTEGER NOT NULL,
+    peak INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS sites_site ON sites(site, run_id);
+";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunSummary {
+    pub id: i64,
+    pub name: String,
+    /// Seconds since the Unix epoch, as passed to [`History::record`].
+    pub timestamp: u64,
+    pub total: Cost,
+    pub peak_rss: u64,
+    pub duration_ms: u64,
+}
+
+/// Value of a metric for one site in one run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SitePoint {
+    pub run_id: i64,
+    pub timestamp: u64,
+    pub value: u64,
+}
+
+/// Identifies a site across runs, see [`Site::signature`].
+pub fn site_key(site: &Site) -> String {
+    site.signature()
+}
+
+fn column(metric: Metric) -> &'static str {
+    match metric {
+        Metric::Allocations => "allocations",
+        Metric::Temporary => "temporary",
+        Metric::Leaked => "leaked",
+        Metric::Peak => "peak",
+    }
+}
+
+pub struct History {
+    conn: Connection,
+}
+
+impl History {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::init(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self, Error> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self, Error> {
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+
+    /// Stores the summary and per-site costs of a run and returns its id.
+    pub fn record(
+        &mut self,
+        name: &str,
+        timestamp: u64,
+        data: &AccumulatedData,
+        profile: &Profile,
+    ) -> Result<i64, Error> {
+        let tx = self.conn.transaction()?;
+
+        tx.execute(
+            "INSERT INTO runs (name, timestamp, allocations, temporary, leaked, peak, peak_rss, duration_ms)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                name,
+                timestamp as i64,
+                profile.total.allocations as i64,
+                profile.total.temporary as i64,
+                profile.total.leaked as i64,
+                profile.total.peak as i64,
+                data.peak_rss as i64,
+                data.duration.as_millis() as i64,
+            ],
+        )?;
+        let run_id = tx.last_insert_rowid();
+
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO sites (run_id, site, allocations, temporary, leaked, peak)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?;
+            for site in &profile.sites {
+                insert.execute(params![
+                    run_id,
+                    site_key(site),
+                    site.cost.allocations as i64,
+                    site.cost.temporary as i64,
+                    site.cost.leaked as i64,
+                    site.cost.peak as i64,
+                ])?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(run_id)
+    }
+
+    /// The last `limit` runs, newest first.
+    pub fn runs(&self, limit: usize) -> Result<Vec<RunSummary>, Error> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, name, timestamp, allocations, temporary, leaked, peak, peak_rss, duration_ms
+             FROM runs ORDER BY timestamp DESC, id DESC LIMIT ?1",
+        )?;
+
+        let rows = stmt.query_map(params![limit as i64], |row| {
+            Ok(RunSummary {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                timestamp: row.get::<_, i64>(2)? as u64,
+                total: Cost {
+                    allocations: row.get::<_, i64>(3)? as u64,
+                    temporary: row.get::<_, i64>(4)? as u64,
+                    leaked: row.get::<_, i64>(5)? as u64,
+                    peak: row.get::<_, i64>(6)? as u64,
+                },
+                peak_rss: row.get::<_, i64>(7)? as u64,
+                duration_ms: row.get::<_, i64>(8)? as u64,
+            })
+        })?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// `metric` of the site with key `site` over the last `limit` runs,
+    /// oldest first. Runs without the site report 0.
+    pub fn site_history(
+        &self,
+        site: &str,
+        metric: Metric,
+        limit: usize,
+    ) -> Result<Vec<SitePoint>, Error> {
+        let sql = format!(
+            "SELECT id, timestamp, COALESCE(SUM(sites.{}), 0) FROM
+                 (SELECT id, timestamp FROM runs ORDER BY timestamp DESC, id DESC LIMIT ?2) AS last
+             LEFT JOIN sites ON sites.run_id = last.id AND sites.site = ?1
+             GROUP BY id ORDER BY timestamp, id",
+            column(metric)
+        );
+        let mut stmt = self.conn.prepare(&sql)?;
+
+        let rows = stmt.query_map(params![site, limit as i64], |row| {
+            Ok(SitePoint {
+                run_id: row.get(0)?,
+                timestamp: row.get::<_, i64>(1)? as u64,
+                value: row.get::<_, i64>(2)? as u64,
+            })
+        })?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Deletes all but the newest `keep` runs.
+    pub fn prune(&mut self, keep: usize) -> Result<usize, Error> {
+        Ok(self.conn.execute(
+            "DELETE FROM runs WHERE id NOT IN
+                 (SELECT id FROM runs ORDER BY timestamp DESC, id DESC LIMIT ?1)",
+            params![keep as i64],
+        )?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::history::{site_key, History};
+    use crate::model::tests::{data, parse, TRACE};
+    use crate::model::{Metric, Profile};
+
+    #[test]
+    fn test_record_and_query() {
+        let mut history = History::open_in_memory().unwrap();
+
+        let first = data();
+        let first_profile = Profile::new(&first).unwrap();
+        // the second run no longer frees the allocation of `a`
+        let second = parse(&TRACE.replace("- 0\n", ""));
+        let second_profile = Profile::new(&second).unwrap();
+
+        history.record("base", 10, &first, &first_profile).unwrap();
+        history
+            .record("next", 20, &second, &second_profile)
+            .unwrap();
+
+        let runs = history.runs(30).unwrap();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].name, "next");
+
+        let key = site_key(&first_profile.sites[0]);
+        assert_eq!(key, "main;a;malloc_a");
+
+        let points = history.site_history(&key, Metric::Leaked, 30).unwrap();
+        let values: Vec<_> = points.iter().map(|p| p.value).collect();
+        assert_eq!(values, [0x10, 0x20]);
+
+        assert_eq!(history.prune(1).unwrap(), 1);
+        assert_eq!(
+            history
+                .site_history(&key, Metric::Leaked, 30)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
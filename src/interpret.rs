@@ -1,12 +1,42 @@
+use crate::alerts::{AlertRule, Alerts};
+use crate::analysis::address_map::{AddressMap, LiveAllocation};
+use crate::analysis::size_class::SizeClasses;
+use crate::environment::EnvCapture;
+pub use crate::executor::{CapturedOutput, ChildStdio, ControlHandle, StdioMode};
+use crate::format::FILE_VERSION;
+use crate::observer::{
+    AllocEvent, FreeEvent, ImageEvent, LargeAllocation, LiveSite, LiveStats, Observer, TopSite,
+    TraceEvent,
+};
+pub use crate::output::QueueStats;
 use crate::output::{Frame, Output};
-use crate::pipe_io::Record;
+use crate::parser::{
+    AccumulatedData, FreeMismatch, FreeMismatchKind, FreeMismatches, Parser, SmallAllocations,
+    CAPTURE_STOPPED_KEY, CLOCK_OFFSET_KEY, CRASH_SIGNAL_KEY, FREE_MISMATCHES_KEY, ROOT_SCAN_KEY,
+    SAMPLING_RATE_KEY, SESSION_ID_KEY, SMALL_ALLOCATIONS_KEY, STACK_THRESHOLD_KEY, STREAM_LOST_KEY,
+};
+use crate::pipe_io::{Command, RecordRef};
+use crate::redact::Redaction;
 use crate::resolver::Resolver;
-use crate::{executor, resolver};
+pub use crate::resolver::{CacheLimits, CacheStats, SharedSymbols};
+use crate::rules::{Decision, Rules, RulesHandle};
+use crate::runtime::RuntimeDirs;
+use crate::topk::SpaceSaving;
+use crate::transform::{RecordTransformer, Transform};
+use crate::{executor, parser, resolver, rules};
 use indexmap::{IndexMap, IndexSet};
+use nix::sys::signal::Signal;
+use std::collections::{HashMap, VecDeque};
 use std::ffi::OsStr;
-use std::fs::OpenOptions;
+use std::fs::{File, OpenOptions};
 use std::io;
+use std::os::unix::process::ExitStatusExt;
 use std::path::Path;
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -17,15 +47,46 @@ pub enum Error {
     Io(#[from] io::Error),
     #[error("Resolver")]
     Resolver(#[from] resolver::Error),
+    #[error("Rules")]
+    Rules(#[from] rules::Error),
+    #[error("Parser")]
+    Parse(#[from] parser::Error),
     #[error("Custom error: {0}")]
     Custom(String),
 }
 
-#[derive(Default)]
-struct MemStats {
-    allocations: u64,
-    leaked_allocations: u64,
-    tmp_allocations: u64,
+/// Counters of the traced program, see [`Interpreter::stats`] and
+/// [`Interpreter::stats_channel`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemStats {
+    pub allocations: u64,
+    /// Allocations not freed yet.
+    pub leaked_allocations: u64,
+    pub tmp_allocations: u64,
+    /// Live bytes.
+    pub heap: u64,
+    pub peak_heap: u64,
+    /// Resident set size of the last clock record.
+    pub rss: u64,
+}
+
+struct AddressMapState {
+    region_size: u64,
+    dirty: bool,
+    map: AddressMap,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CategoryStats {
+    pub allocations: u64,
+    pub bytes: u64,
+}
+
+struct ActiveRules {
+    handle: RulesHandle,
+    generation: u64,
+    rules: std::sync::Arc<Rules>,
+    decisions: HashMap<u64, Decision>,
 }
 
 #[derive(Hash, PartialEq, Eq)]
@@ -36,6 +97,198 @@ struct AllocationInfo {
 
 const PAGE_SIZE: u64 = u16::MAX as u64 / 4;
 
+/// Counters of the top sites estimate per reported site. More counters
+/// tighten the error bound of the reported bytes.
+const TOP_SITES_COUNTERS: usize = 10;
+
+/// Number of sites listed in the crash summary.
+const CRASH_SITES: usize = 20;
+
+/// Shortest interval between two snapshots of
+/// [`Interpreter::stats_channel`].
+pub const MIN_STATS_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Number of mismatched frees kept as examples.
+const FREE_MISMATCH_EXAMPLES: usize = 16;
+
+/// Number of the most recently freed pointers remembered to tell double
+/// frees from frees of untracked pointers.
+const FREED_POINTERS: usize = 1 << 16;
+
+/// Allocation index of the last [`FREED_POINTERS`] freed pointers not
+/// allocated again. Older entries are evicted in free order, so a double
+/// free of a pointer freed longer ago is reported as untracked.
+#[derive(Default)]
+struct FreedPointers {
+    /// Allocation index and free sequence number by pointer.
+    indices: HashMap<u64, (usize, u64)>,
+    /// Pointer and sequence number of each free, oldest first. Entries of
+    /// pointers allocated or freed again since are skipped on eviction.
+    order: VecDeque<(u64, u64)>,
+    sequence: u64,
+}
+
+impl FreedPointers {
+    fn insert(&mut self, ptr: u64, allocation_idx: usize) {
+        self.sequence += 1;
+        self.indices.insert(ptr, (allocation_idx, self.sequence));
+        self.order.push_back((ptr, self.sequence));
+
+        if self.order.len() > FREED_POINTERS
+            && let Some((ptr, sequence)) = self.order.pop_front()
+            && self.indices.get(&ptr).is_some_and(|&(_, s)| s == sequence)
+        {
+            self.indices.remove(&ptr);
+        }
+    }
+
+    fn remove(&mut self, ptr: u64) {
+        self.indices.remove(&ptr);
+    }
+
+    fn get(&self, ptr: u64) -> Option<usize> {
+        self.indices
+            .get(&ptr)
+            .map(|&(allocation_idx, _)| allocation_idx)
+    }
+}
+
+/// When to stop recording a program that keeps running, e.g. to sample a
+/// window of a long-running service. The event limit is checked as
+/// records arrive, the duration on a timer, so an idle program is stopped
+/// too.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CaptureLimits {
+    /// Wall-clock time since the start of the program.
+    pub max_duration: Option<Duration>,
+    /// Number of allocation and free records.
+    pub max_events: Option<u64>,
+}
+
+impl CaptureLimits {
+    /// Name of the first limit reached, as written to
+    /// [`CAPTURE_STOPPED_KEY`].
+    fn reached(&self, elapsed: Duration, events: u64) -> Option<&'static str> {
+        if self.max_duration.is_some_and(|max| elapsed >= max) {
+            Some("duration")
+        } else if self.max_events.is_some_and(|max| events >= max) {
+            Some("events")
+        } else {
+            None
+        }
+    }
+}
+
+/// Stops the capture once [`CaptureLimits::max_duration`] elapsed, without
+/// waiting for the next record of the program.
+struct Watchdog {
+    cancel: Option<Sender<()>>,
+    handle: Option<thread::JoinHandle<Option<io::Result<()>>>>,
+}
+
+impl Watchdog {
+    fn spawn(deadline: Instant, control: executor::ControlHandle) -> Self {
+        let (cancel, cancelled) = mpsc::channel::<()>();
+        let handle = thread::spawn(move || {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match cancelled.recv_timeout(timeout) {
+                Err(mpsc::RecvTimeoutError::Timeout) => Some(control.stop()),
+                _ => None,
+            }
+        });
+        Self {
+            cancel: Some(cancel),
+            handle: Some(handle),
+        }
+    }
+
+    /// Whether the deadline passed and the stop command was sent.
+    fn fired(&self) -> bool {
+        self.handle
+            .as_ref()
+            .is_some_and(|handle| handle.is_finished())
+    }
+
+    /// Cancels the timer, returns the result of sending the stop command if
+    /// it already fired.
+    fn cancel(mut self) -> Option<io::Result<()>> {
+        self.cancel.take();
+        self.handle.take()?.join().ok().flatten()
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.cancel.take();
+        if let Some(handle) = self.handle.take() {
+            _ = handle.join();
+        }
+    }
+}
+
+/// Sends the counters published by [`Interpreter::exec`] on the channel of
+/// [`Interpreter::stats_channel`] every interval, without waiting for the
+/// next record of the program.
+struct StatsTimer {
+    stats: Arc<Mutex<MemStats>>,
+    cancel: Option<Sender<()>>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl StatsTimer {
+    fn spawn(interval: Duration, stats: MemStats, sender: Sender<MemStats>) -> Self {
+        let stats = Arc::new(Mutex::new(stats));
+        let (cancel, cancelled) = mpsc::channel::<()>();
+        let published = stats.clone();
+        let handle = thread::spawn(move || {
+            while let Err(mpsc::RecvTimeoutError::Timeout) = cancelled.recv_timeout(interval) {
+                let stats = *published.lock().unwrap_or_else(PoisonError::into_inner);
+                if sender.send(stats).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            stats,
+            cancel: Some(cancel),
+            handle: Some(handle),
+        }
+    }
+
+    /// Replaces the counters sent by the next snapshot.
+    fn publish(&self, stats: MemStats) {
+        *self.stats.lock().unwrap_or_else(PoisonError::into_inner) = stats;
+    }
+}
+
+impl Drop for StatsTimer {
+    fn drop(&mut self) {
+        self.cancel.take();
+        if let Some(handle) = self.handle.take() {
+            _ = handle.join();
+        }
+    }
+}
+
+/// Captures stacks only for allocations of at least `threshold` bytes, for
+/// programs dominated by tiny allocations. Smaller allocations are counted
+/// per size class and module of their allocating frame and left out of the
+/// heap figures of the trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackThreshold {
+    pub threshold: u64,
+    pub classes: SizeClasses,
+}
+
+impl StackThreshold {
+    pub fn new(threshold: u64) -> Self {
+        Self {
+            threshold,
+            classes: SizeClasses::jemalloc(),
+        }
+    }
+}
+
 struct SplitPointer {
     big: u64,
     small: u16,
@@ -50,6 +303,12 @@ impl SplitPointer {
     }
 }
 
+/// Pool declared by the program and its live allocations by pointer.
+struct LivePool {
+    idx: usize,
+    allocations: HashMap<u64, u64>,
+}
+
 #[derive(Default)]
 struct Indices {
     small_ptr_parts: Vec<u16>,
@@ -64,7 +323,60 @@ pub struct Interpreter {
     allocation_info: IndexSet<AllocationInfo>,
     resolver: Resolver,
     stats: MemStats,
-    last_ptr: usize,
+    last_ptr: u64,
+    traces: Vec<(usize, u64)>,
+    frame_functions: Vec<Vec<usize>>,
+    /// Module string index of every frame.
+    frame_modules: Vec<usize>,
+    /// (start address, size) of the mapped modules by string index. Images
+    /// are identified by path, so a binary loaded again, e.g. by an exec of
+    /// itself or a repeated `dlopen`, keeps a single module entry.
+    modules: HashMap<usize, (u64, u64)>,
+    /// Image records of modules already mapped at the same address.
+    duplicate_images: u64,
+    stack_threshold: Option<StackThreshold>,
+    /// (allocations, bytes) below the stack threshold by size class and
+    /// module string index.
+    small_allocations: IndexMap<(u64, usize), (u64, u64)>,
+    freed: FreedPointers,
+    free_mismatches: FreeMismatches,
+    /// String index of the component owning each transferred live pointer.
+    owners: HashMap<u64, usize>,
+    /// Live pools by the handle the program declared them with.
+    pools: HashMap<u64, LivePool>,
+    /// Number of pools of the trace, including the destroyed ones.
+    pool_count: usize,
+    rules: Option<ActiveRules>,
+    categories: HashMap<String, CategoryStats>,
+    address_map: Option<AddressMapState>,
+    env_capture: Option<EnvCapture>,
+    redaction: Option<Redaction>,
+    alerts: Alerts,
+    observers: Vec<Box<dyn Observer>>,
+    /// Wall time between two snapshots sent on the channel.
+    stats_channel: Option<(Duration, Sender<MemStats>)>,
+    transformers: Vec<Box<dyn RecordTransformer>>,
+    /// Live bytes per trace index, while observers request top sites.
+    site_bytes: Option<HashMap<u64, u64>>,
+    top_sites: Option<SpaceSaving<u64>>,
+    runtime_dirs: RuntimeDirs,
+    stdio: ChildStdio,
+    /// Output of the last program read from its piped streams.
+    captured: CapturedOutput,
+    capture_limits: CaptureLimits,
+    control: ControlHandle,
+    /// Whether to wait for the library to reopen a pipe closed while the
+    /// program keeps running.
+    reconnect: bool,
+    session_id: Option<String>,
+    /// Whether the trace has its header, so later runs append to it.
+    header_written: bool,
+    /// Whether the current run appends to a trace with a header.
+    appending: bool,
+    /// Number of traces before the current run. The program numbers its
+    /// traces from 1 in every run, so they are shifted past the existing
+    /// ones.
+    trace_offset: u64,
 }
 
 impl Interpreter {
@@ -75,7 +387,25 @@ impl Interpreter {
             .create(true)
             .open(out_filepath)?;
 
-        Ok(Self {
+        Ok(Self::with_file(file))
+    }
+
+    /// Continues the trace at `out_filepath`, e.g. one stopped by a
+    /// [`CaptureLimits`], so intermittent captures of a long-lived process
+    /// end up in one trace. Strings, frames, traces and allocation infos of
+    /// the file are reused; allocations live at the end of the previous run
+    /// are not matched with frees of the next one and stay leaked.
+    pub fn resume(out_filepath: impl AsRef<Path>) -> Result<Self, Error> {
+        let data = Parser::new().parse_file(&out_filepath)?;
+        let file = OpenOptions::new().append(true).open(out_filepath)?;
+
+        let mut interpreter = Self::with_file(file);
+        interpreter.restore(&data);
+        Ok(interpreter)
+    }
+
+    fn with_file(file: File) -> Self {
+        Self {
             output: Output::new(file),
             strings: IndexSet::new(),
             frames: IndexSet::new(),
@@ -84,103 +414,989 @@ impl Interpreter {
             resolver: Resolver::new(),
             stats: MemStats::default(),
             last_ptr: 0,
-        })
+            traces: Vec::new(),
+            frame_functions: Vec::new(),
+            frame_modules: Vec::new(),
+            modules: HashMap::new(),
+            duplicate_images: 0,
+            stack_threshold: None,
+            small_allocations: IndexMap::new(),
+            freed: FreedPointers::default(),
+            free_mismatches: FreeMismatches::default(),
+            owners: HashMap::new(),
+            pools: HashMap::new(),
+            pool_count: 0,
+            rules: None,
+            categories: HashMap::new(),
+            address_map: None,
+            env_capture: Some(EnvCapture::default()),
+            redaction: None,
+            alerts: Alerts::new(Vec::new()),
+            observers: Vec::new(),
+            stats_channel: None,
+            transformers: Vec::new(),
+            site_bytes: None,
+            top_sites: None,
+            runtime_dirs: RuntimeDirs::default(),
+            stdio: ChildStdio::default(),
+            captured: CapturedOutput::default(),
+            capture_limits: CaptureLimits::default(),
+            control: ControlHandle::new(),
+            reconnect: false,
+            session_id: None,
+            header_written: false,
+            appending: false,
+            trace_offset: 0,
+        }
     }
 
+    fn restore(&mut self, data: &AccumulatedData) {
+        self.strings = data.strings.iter().cloned().collect();
+        for ip in &data.instruction_pointers {
+            self.frames.insert(ip.ip);
+            self.frame_functions.push(
+                std::iter::once(&ip.frame)
+                    .chain(&ip.inlined)
+                    .map(|frame| frame.function_idx())
+                    .collect(),
+            );
+            self.frame_modules.push(ip.module_idx);
+        }
+        self.traces = data
+            .traces
+            .iter()
+            .map(|trace| (trace.ip_idx as usize, trace.parent_idx))
+            .collect();
+        self.allocation_info = data
+            .allocation_infos
+            .iter()
+            .map(|info| AllocationInfo {
+                size: info.size,
+                trace_idx: data.allocations[info.allocation_idx as usize].trace_idx,
+            })
+            .collect();
+
+        let total = &data.total;
+        self.stats = MemStats {
+            allocations: total.allocations,
+            leaked_allocations: total.allocations.saturating_sub(total.frees),
+            tmp_allocations: total.temporary,
+            heap: total.leaked,
+            peak_heap: total.peak,
+            rss: 0,
+        };
+        // like its allocations, pools of the previous run are not matched
+        // with records of the next one
+        self.pool_count = data.pools.len();
+
+        self.session_id = data.session_id().map(str::to_string);
+        self.header_written = !data.strings.is_empty() || !data.traces.is_empty();
+    }
+
+    /// Names the trace, recorded in its metadata when it starts. A resumed
+    /// trace keeps the name it was started with.
+    pub fn set_session_id(&mut self, id: &str) {
+        if !self.header_written {
+            self.session_id = Some(id.to_string());
+        }
+    }
+
+    pub fn session_id(&self) -> Option<&str> {
+        self.session_id.as_deref()
+    }
+
+    /// Sets what is recorded about the environment at the start of the
+    /// trace, or disables the capture with `None`.
+    pub fn set_env_capture(&mut self, capture: Option<EnvCapture>) {
+        self.env_capture = capture;
+    }
+
+    /// Redacts secrets from the command line and the captured environment
+    /// before they are written, see [`Redaction`].
+    pub fn set_redaction(&mut self, redaction: Option<Redaction>) {
+        self.redaction = redaction;
+    }
+
+    /// Keeps an [`AddressMap`] of the live allocations at the heap peak,
+    /// binned into regions of `region_size` bytes.
+    ///
+    /// The map is retaken when the heap starts shrinking after a peak at
+    /// least 5% above the one of the current map, so it may lag the exact
+    /// peak by that margin.
+    pub fn track_address_map(&mut self, region_size: u64) {
+        self.address_map = Some(AddressMapState {
+            region_size,
+            dirty: false,
+            map: AddressMap::default(),
+        });
+    }
+
+    pub fn address_map(&self) -> Option<&AddressMap> {
+        self.address_map.as_ref().map(|state| &state.map)
+    }
+
+    /// Applies suppression and categorization rules to subsequent records.
+    /// Changes made through the handle, including reloads on SIGHUP, take
+    /// effect on the next allocation and are noted with a marker record.
+    pub fn set_rules(&mut self, handle: RulesHandle) {
+        self.rules = Some(ActiveRules {
+            generation: handle.generation(),
+            rules: handle.current(),
+            handle,
+            decisions: HashMap::new(),
+        });
+    }
+
+    /// Writes raw mangled symbol names instead of demangled ones, e.g. for
+    /// feeding other demanglers. See [`format::demangle`](crate::format::demangle)
+    /// for demangling such a trace later.
+    pub fn set_demangle(&mut self, demangle: bool) {
+        self.resolver.set_demangle(demangle);
+    }
+
+    /// Evaluates `rules` on every clock record of the traced program. Fired
+    /// alerts are written as marker records and passed to the observers.
+    pub fn set_alerts(&mut self, rules: Vec<AlertRule>) {
+        self.alerts = Alerts::new(rules);
+    }
+
+    /// Passes the events of the traced program to `observer`, after the
+    /// observers added before, while the trace is written as usual, e.g. for
+    /// live views or custom storage. Observers are added before the run:
+    /// the top sites are counted from the last addition on.
+    pub fn add_observer(&mut self, observer: Box<dyn Observer>) {
+        self.observers.push(observer);
+        let top = self
+            .observers
+            .iter()
+            .map(|o| o.top_sites())
+            .max()
+            .filter(|&top| top > 0);
+        self.site_bytes = top.map(|_| HashMap::new());
+        self.top_sites = top.map(|top| SpaceSaving::new(top * TOP_SITES_COUNTERS));
+    }
+
+    /// Counters of the records interpreted so far.
+    pub fn stats(&self) -> MemStats {
+        self.stats
+    }
+
+    /// Sends a snapshot of the counters every `interval` of wall time while
+    /// [`exec`](Self::exec) runs, also while the program is idle, and one
+    /// when it returns. Intervals shorter than [`MIN_STATS_INTERVAL`] are
+    /// rounded up. Replaces the previous channel, and stops sending once the
+    /// receiver is dropped.
+    pub fn stats_channel(&mut self, interval: Duration) -> Receiver<MemStats> {
+        let (sender, receiver) = mpsc::channel();
+        self.stats_channel = Some((interval.max(MIN_STATS_INTERVAL), sender));
+        receiver
+    }
+
+    /// Passes the records of the traced program through `transformer` before
+    /// they are aggregated and written, after the transformers added before.
+    /// Capture limits count the records as read from the program.
+    pub fn add_transformer(&mut self, transformer: Box<dyn RecordTransformer>) {
+        self.transformers.push(transformer);
+    }
+
+    /// The `n` sites that allocated the most bytes so far, approximated in
+    /// bounded memory. Empty unless an observer requested top sites.
+    pub fn top_sites(&self, n: usize) -> Vec<TopSite> {
+        let Some(top_sites) = &self.top_sites else {
+            return Vec::new();
+        };
+
+        top_sites
+            .top(n)
+            .into_iter()
+            .map(|counter| TopSite {
+                function: self.trace_function(counter.key),
+                allocated: counter.count,
+                error: counter.error,
+            })
+            .collect()
+    }
+
+    /// The `n` sites with the most live bytes. Empty unless an observer
+    /// requested top sites.
+    pub fn top_live_sites(&self, n: usize) -> Vec<LiveSite> {
+        let Some(site_bytes) = &self.site_bytes else {
+            return Vec::new();
+        };
+
+        let mut sites: Vec<_> = site_bytes.iter().filter(|(_, b)| **b > 0).collect();
+        sites.sort_by_key(|(_, bytes)| std::cmp::Reverse(**bytes));
+        sites
+            .into_iter()
+            .take(n)
+            .map(|(&trace_idx, &bytes)| LiveSite {
+                function: self.trace_function(trace_idx),
+                bytes,
+            })
+            .collect()
+    }
+
+    /// Writes the trace on a dedicated thread fed by a queue of at most
+    /// `capacity` 64 KiB chunks, so disk stalls do not hold up reading the
+    /// traced program's records until the queue is full.
+    pub fn set_write_behind(&mut self, capacity: usize) -> io::Result<()> {
+        self.output.set_write_behind(capacity)
+    }
+
+    /// Counters of the write-behind queue, if enabled.
+    pub fn output_queue_stats(&self) -> Option<QueueStats> {
+        self.output.queue_stats()
+    }
+
+    /// Sets where runtime artifacts like the record fifo are created.
+    pub fn set_runtime_dirs(&mut self, dirs: RuntimeDirs) {
+        self.runtime_dirs = dirs;
+    }
+
+    /// Sets how the standard streams of the traced program are handled,
+    /// shared with this process by default.
+    pub fn set_stdio(&mut self, stdio: ChildStdio) {
+        self.stdio = stdio;
+    }
+
+    /// Output of the last [`exec`](Self::exec) read from the streams in
+    /// [`StdioMode::Pipe`], empty for the other modes.
+    pub fn take_captured_output(&mut self) -> CapturedOutput {
+        std::mem::take(&mut self.captured)
+    }
+
+    /// Stops recording once a limit is reached and finalizes the trace
+    /// without waiting for the program to exit.
+    pub fn set_capture_limits(&mut self, limits: CaptureLimits) {
+        self.capture_limits = limits;
+    }
+
+    /// Records stacks only for allocations of at least the threshold, see
+    /// [`StackThreshold`]. The library is asked to skip unwinding the
+    /// smaller ones.
+    pub fn set_stack_threshold(&mut self, threshold: Option<StackThreshold>) {
+        self.stack_threshold = threshold;
+    }
+
+    /// Allocations counted below the stack threshold so far, by size class
+    /// and module.
+    pub fn small_allocations(&self) -> Vec<SmallAllocations> {
+        self.small_allocations
+            .iter()
+            .map(
+                |(&(size_class, module_idx), &(allocations, bytes))| SmallAllocations {
+                    size_class,
+                    module: self.string(module_idx).unwrap_or_default().to_string(),
+                    allocations,
+                    bytes,
+                },
+            )
+            .collect()
+    }
+
+    /// Reports the frees not matching a live allocation as anomalies when
+    /// the trace is parsed, see [`FreeMismatches`].
+    pub fn set_strict_frees(&mut self, strict: bool) {
+        self.free_mismatches.strict = strict;
+    }
+
+    /// Frees that did not match a live allocation so far.
+    pub fn free_mismatches(&self) -> &FreeMismatches {
+        &self.free_mismatches
+    }
+
+    /// Handle to pause, resume or resample the program of a running
+    /// [`Interpreter::exec`] from another thread. Commands sent are noted as
+    /// markers in the trace.
+    pub fn control(&self) -> ControlHandle {
+        self.control.clone()
+    }
+
+    /// Uses `control` for the next runs instead of the interpreter's own
+    /// handle.
+    pub fn set_control(&mut self, control: ControlHandle) {
+        self.control = control;
+    }
+
+    /// Keeps the session going when the pipe of the library is closed while
+    /// the program still runs, e.g. after the library restarted its writer.
+    /// The records of the new connection follow a marker of the gap.
+    pub fn set_reconnect(&mut self, reconnect: bool) {
+        self.reconnect = reconnect;
+    }
+
+    /// Parses the trace while writing it, so [`Interpreter::take_summary`]
+    /// returns its data without a second pass over the file. Must be called
+    /// before [`Interpreter::exec`].
+    pub fn set_summary(&mut self) -> Result<(), Error> {
+        self.output.set_summary()?;
+        Ok(())
+    }
+
+    /// Data of the written trace if [`Interpreter::set_summary`] was called.
+    pub fn take_summary(&mut self) -> Option<Result<AccumulatedData, parser::Error>> {
+        self.output.take_summary()
+    }
+
+    /// Bounds the memory used for caching symbol lookups.
+    pub fn set_resolver_cache(&mut self, limits: CacheLimits) {
+        self.resolver.set_cache_limits(limits);
+    }
+
+    /// Shares the loaded debug info and the lookup cache with other
+    /// interpreters, e.g. ones tracing processes of the same program. Must
+    /// be called before [`Interpreter::exec`].
+    pub fn set_shared_symbols(&mut self, symbols: SharedSymbols) {
+        let mut resolver = Resolver::with_symbols(symbols);
+        resolver.set_demangle(self.resolver.demangle());
+        self.resolver = resolver;
+    }
+
+    /// Debug info and lookup cache of the interpreter, to share with others.
+    pub fn shared_symbols(&self) -> SharedSymbols {
+        self.resolver.symbols().clone()
+    }
+
+    pub fn resolver_cache_stats(&self) -> CacheStats {
+        self.resolver.cache_stats()
+    }
+
+    /// Allocation counts per category of the active rules.
+    pub fn category_stats(&self) -> &HashMap<String, CategoryStats> {
+        &self.categories
+    }
+
+    /// Runs `program` with the library at `lib_path` injected through
+    /// `LD_PRELOAD`, or `DYLD_INSERT_LIBRARIES` on macOS, and writes the
+    /// trace of the records it sends.
     pub fn exec<S, P>(
         &mut self,
         program: S,
         args: impl IntoIterator<Item = S>,
         cwd: P,
-        lib_path: &str,
+        lib_path: impl AsRef<Path>,
     ) -> Result<(), Error>
     where
         S: AsRef<OsStr>,
         P: AsRef<Path>,
     {
-        let mut exec = executor::exec_cmd(program, args, cwd, lib_path);
+        self.start_run()?;
+
+        let mut exec = executor::exec_cmd(
+            program,
+            args,
+            cwd,
+            lib_path,
+            &self.runtime_dirs,
+            &self.stdio,
+        )?
+        .with_control(self.control.clone())
+        .with_reconnect(self.reconnect);
+        if let Some(threshold) = &self.stack_threshold {
+            // libraries without a control channel still capture every stack
+            _ = self
+                .control
+                .send(Command::SetStackThreshold(threshold.threshold));
+        }
+
+        let start = Instant::now();
+        let mut events = 0;
+        let mut stopped = false;
+        let mut stop_result = Ok(());
+        let mut failed = None;
+        let mut watchdog = self
+            .capture_limits
+            .max_duration
+            .map(|max| Watchdog::spawn(start + max, self.control.clone()));
+        let stats_timer = self
+            .stats_channel
+            .as_ref()
+            .map(|(interval, sender)| StatsTimer::spawn(*interval, self.stats, sender.clone()));
+        'connection: loop {
+            while let Some(item) = exec.next_ref() {
+                let record = match item {
+                    Ok(record) => record,
+                    Err(executor::Error::CmdFailed(status)) => {
+                        failed = Some(status);
+                        break 'connection;
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+
+                if matches!(record, RecordRef::Alloc { .. } | RecordRef::Free { .. }) {
+                    events += 1;
+                }
+                self.transform_record(0, record)?;
+
+                for command in self.control.take_sent() {
+                    self.write_command(&command)?;
+                }
+
+                if let Some(timer) = &stats_timer {
+                    timer.publish(self.stats);
+                }
+
+                if stopped {
+                    continue;
+                }
+                if let Some(limit) = self.capture_limits.reached(start.elapsed(), events) {
+                    // records already written by the library are still consumed
+                    stop_result = match watchdog.take().and_then(Watchdog::cancel) {
+                        Some(result) => result.map_err(executor::Error::from),
+                        None => exec.stop(),
+                    };
+                    stopped = true;
+                    self.write_capture_stopped(limit)?;
+                }
+            }
 
-        while let Some(item) = exec.next() {
-            let record = item?;
+            match exec.reconnect() {
+                Ok(true) => self.output.write_marker("transport reconnected")?,
+                Ok(false) => break,
+                Err(executor::Error::CmdFailed(status)) => {
+                    failed = Some(status);
+                    break;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
 
-            self.handle_record(record)?;
+        // the timer fired while no records arrived
+        if !stopped
+            && watchdog.as_ref().is_some_and(Watchdog::fired)
+            && let Some(result) = watchdog.take().and_then(Watchdog::cancel)
+        {
+            stop_result = result.map_err(executor::Error::from);
+            self.write_capture_stopped("duration")?;
+        }
+        drop(watchdog);
+        drop(stats_timer);
+        for command in self.control.take_sent() {
+            self.write_command(&command)?;
+        }
+
+        self.captured = exec.captured();
+        self.send_stats();
+        self.snapshot_address_map();
+        self.write_small_allocations()?;
+        self.write_free_mismatches()?;
+
+        let stats = exec.stream_stats();
+        if stats.is_damaged() {
+            self.output
+                .write_metadata(STREAM_LOST_KEY, &stats.lost.to_string())?;
+            self.output.write_comment(&format!(
+                "record stream of {} threads: {} gaps, {} records lost, {} duplicates",
+                stats.threads, stats.gaps, stats.lost, stats.duplicates
+            ))?;
+        }
+
+        if let Some(signal) = failed.and_then(|status| status.signal()) {
+            self.write_crash(signal)?;
         }
 
         self.write_comments()?;
+        self.output.write_finished()?;
 
         self.output.flush()?;
 
+        match failed {
+            Some(status) => Err(executor::Error::CmdFailed(status).into()),
+            None => Ok(stop_result?),
+        }
+    }
+
+    /// Prepares the trace for the records of a new run: writes the metadata
+    /// of a new trace, or shifts the traces of a resumed one.
+    fn start_run(&mut self) -> Result<(), Error> {
+        self.appending = self.header_written;
+        self.trace_offset = self.traces.len() as u64;
+        if self.appending {
+            self.output.write_marker("session resumed")?;
+            return Ok(());
+        }
+
+        if let Some(capture) = &self.env_capture {
+            for (key, mut value) in capture.capture() {
+                if let Some(redaction) = &self.redaction {
+                    value = redaction.redact_metadata(&key, &value);
+                }
+                self.output.write_metadata(&key, &value)?;
+            }
+        }
+        if let Some(id) = &self.session_id {
+            self.output.write_metadata(SESSION_ID_KEY, id)?;
+        }
+        if let Some(threshold) = &self.stack_threshold {
+            self.output
+                .write_metadata(STACK_THRESHOLD_KEY, &threshold.threshold.to_string())?;
+        }
         Ok(())
     }
 
-    fn handle_record(&mut self, record: Record) -> Result<(), Error> {
+    fn write_capture_stopped(&mut self, limit: &str) -> Result<(), Error> {
+        self.output.write_metadata(CAPTURE_STOPPED_KEY, limit)?;
+        self.output
+            .write_marker(&format!("capture stopped: {} limit", limit))?;
+        Ok(())
+    }
+
+    fn write_command(&mut self, command: &Command) -> Result<(), Error> {
+        let label = match command {
+            Command::Stop => "stop".to_string(),
+            Command::Pause => "pause".to_string(),
+            Command::Resume => "resume".to_string(),
+            Command::Flush | Command::SetStackThreshold(_) => return Ok(()),
+            Command::SetSampling(rate) => {
+                self.output
+                    .write_metadata(SAMPLING_RATE_KEY, &rate.to_string())?;
+                format!("sampling {}", rate)
+            }
+        };
+        self.output.write_marker(&format!("control: {}", label))?;
+        Ok(())
+    }
+
+    fn write_small_allocations(&mut self) -> Result<(), Error> {
+        if self.small_allocations.is_empty() {
+            return Ok(());
+        }
+        let value = serde_json::to_string(&self.small_allocations())
+            .map_err(|e| Error::Custom(e.to_string()))?;
+        self.output.write_metadata(SMALL_ALLOCATIONS_KEY, &value)?;
+        Ok(())
+    }
+
+    fn write_free_mismatches(&mut self) -> Result<(), Error> {
+        if self.free_mismatches.is_empty() {
+            return Ok(());
+        }
+        let value = serde_json::to_string(&self.free_mismatches)
+            .map_err(|e| Error::Custom(e.to_string()))?;
+        self.output.write_metadata(FREE_MISMATCHES_KEY, &value)?;
+        self.output.write_comment(&format!(
+            "mismatched frees: {} of untracked pointers, {} double frees",
+            self.free_mismatches.untracked, self.free_mismatches.double_frees
+        ))?;
+        Ok(())
+    }
+
+    /// Notes the signal that killed the traced program and summarizes the
+    /// allocations live at that moment by site.
+    fn write_crash(&mut self, signal: i32) -> Result<(), Error> {
+        let name = Signal::try_from(signal)
+            .map(|s| s.as_str().to_string())
+            .unwrap_or_else(|_| signal.to_string());
+        self.output.write_metadata(CRASH_SIGNAL_KEY, &name)?;
+        self.output.write_marker(&format!("crash: {}", name))?;
+
+        // trace index -> (allocations, bytes)
+        let mut sites: HashMap<u64, (u64, u64)> = HashMap::new();
+        for indices in self.pointers.values() {
+            for &idx in &indices.allocation_indices {
+                if let Some(info) = self.allocation_info.get_index(idx) {
+                    let site = sites.entry(info.trace_idx).or_default();
+                    site.0 += 1;
+                    site.1 += info.size;
+                }
+            }
+        }
+        let mut sites: Vec<_> = sites.into_iter().collect();
+        sites.sort_by_key(|(_, (_, bytes))| std::cmp::Reverse(*bytes));
+
+        let (allocations, bytes) = sites
+            .iter()
+            .fold((0, 0), |acc, (_, site)| (acc.0 + site.0, acc.1 + site.1));
+        self.output.write_comment(&format!(
+            "live allocations at crash: {} allocations, {} bytes",
+            allocations, bytes
+        ))?;
+        for (trace_idx, (allocations, bytes)) in sites.into_iter().take(CRASH_SITES) {
+            let function = self.trace_function(trace_idx);
+            self.output.write_comment(&format!(
+                "  {} bytes in {} allocations: {}",
+                bytes, allocations, function
+            ))?;
+        }
+
+        Ok(())
+    }
+
+    fn string(&self, idx: usize) -> Option<&str> {
+        self.strings
+            .get_index(idx.wrapping_sub(1))
+            .map(String::as_str)
+    }
+
+    /// Module string index of the allocating frame of a trace.
+    fn trace_module(&self, trace_idx: u64) -> Option<usize> {
+        let &(ip_id, _) = self.traces.get((trace_idx as usize).wrapping_sub(1))?;
+        self.frame_modules.get(ip_id.wrapping_sub(1)).copied()
+    }
+
+    /// Functions of a trace, innermost first.
+    fn trace_stack(&self, mut trace_idx: u64) -> Vec<String> {
+        let mut stack = Vec::new();
+        while let Some(&(ip_id, parent_idx)) = self.traces.get((trace_idx as usize).wrapping_sub(1))
+        {
+            let functions = self.frame_functions.get(ip_id.wrapping_sub(1));
+            for &function_idx in functions.into_iter().flatten() {
+                stack.push(self.string(function_idx).unwrap_or_default().to_string());
+            }
+            trace_idx = parent_idx;
+        }
+        stack
+    }
+
+    /// Name of the allocating function of a trace.
+    fn trace_function(&self, trace_idx: u64) -> String {
+        self.traces
+            .get((trace_idx as usize).wrapping_sub(1))
+            .and_then(|&(ip_id, _)| self.frame_functions.get(ip_id - 1)?.first())
+            .and_then(|&function_idx| self.strings.get_index(function_idx - 1))
+            .cloned()
+            .unwrap_or_else(|| format!("trace {}", trace_idx))
+    }
+
+    /// Applies the transformers from `stage` on and handles what is left.
+    fn transform_record(&mut self, stage: usize, record: RecordRef) -> Result<(), Error> {
+        let Some(transformer) = self.transformers.get_mut(stage) else {
+            return self.handle_record(record);
+        };
+
+        match transformer.transform(&record) {
+            Transform::Keep => self.transform_record(stage + 1, record),
+            Transform::Drop => Ok(()),
+            Transform::Replace(records) => {
+                for record in &records {
+                    self.transform_record(stage + 1, record.as_ref())?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn handle_record(&mut self, record: RecordRef) -> Result<(), Error> {
+        let shift = |idx: u64| match idx {
+            0 => 0,
+            idx => idx + self.trace_offset,
+        };
+        let record = match record {
+            RecordRef::Trace { ip, parent_idx } => RecordRef::Trace {
+                ip,
+                parent_idx: shift(parent_idx),
+            },
+            RecordRef::Alloc {
+                ptr,
+                size,
+                parent_idx,
+                timestamp,
+            } => RecordRef::Alloc {
+                ptr,
+                size,
+                parent_idx: shift(parent_idx),
+                timestamp,
+            },
+            RecordRef::AllocFailed {
+                size,
+                parent_idx,
+                timestamp,
+            } => RecordRef::AllocFailed {
+                size,
+                parent_idx: shift(parent_idx),
+                timestamp,
+            },
+            record => record,
+        };
+
         match record {
-            Record::Version(version) => {
-                self.output.write_version(version, 3)?;
+            // the header of an appended run would start a new trace
+            RecordRef::Version(_) | RecordRef::Exec(_) | RecordRef::Clock { .. }
+                if self.appending => {}
+            RecordRef::Version(version) => {
+                self.output.write_version(version, FILE_VERSION)?;
+                self.header_written = true;
             }
-            Record::Exec(cmd) => {
-                self.output.write_exec(&cmd)?;
+            RecordRef::Exec(cmd) => {
+                let cmd = String::from_utf8_lossy(cmd);
+                match &self.redaction {
+                    Some(redaction) => self.output.write_exec(&redaction.redact_command(&cmd))?,
+                    None => self.output.write_exec(&cmd)?,
+                }
             }
-            Record::Image {
+            RecordRef::Image {
                 name,
                 start_address,
                 size,
             } => {
+                let name = String::from_utf8_lossy(name);
                 let module_id = self.write_string(&name)?;
-                _ = self.resolver.add_module(
-                    module_id,
-                    &name,
-                    start_address as u64,
-                    start_address as u64 + size as u64,
-                );
+                self.map_module(module_id, &name, start_address, size);
+                for observer in &mut self.observers {
+                    observer.on_image(&ImageEvent {
+                        name: &name,
+                        start_address,
+                        size,
+                        module_idx: module_id,
+                    });
+                }
             }
-            Record::PageInfo { size, pages } => {
-                self.output.write_page_info(size, pages as u64)?;
+            RecordRef::PageInfo { size, pages } => {
+                self.output.write_page_info(size, pages)?;
             }
-            Record::Trace { ip, parent_idx } => {
-                let ip_id = self.add_frame(ip as u64)?;
-                self.output.write_trace(ip_id, parent_idx as u64)?;
+            RecordRef::Trace { ip, parent_idx } => {
+                let ip_id = self.add_frame(ip)?;
+                self.traces.push((ip_id, parent_idx));
+                self.output.write_trace(ip_id, parent_idx)?;
+                for observer in &mut self.observers {
+                    observer.on_trace(&TraceEvent {
+                        trace_idx: self.traces.len() as u64,
+                        ip,
+                        ip_idx: ip_id,
+                        parent_idx,
+                    });
+                }
             }
-            Record::Alloc {
+            RecordRef::Alloc {
                 ptr,
                 size,
                 parent_idx,
+                timestamp,
             } => {
+                self.freed.remove(ptr);
+
+                match self.apply_rules(parent_idx)? {
+                    Decision::Keep => {}
+                    Decision::Suppress => return Ok(()),
+                    Decision::Category(name) => {
+                        let stats = self.categories.entry(name).or_default();
+                        stats.allocations += 1;
+                        stats.bytes += size;
+                    }
+                }
+
+                if let Some(threshold) = &self.stack_threshold {
+                    if size < threshold.threshold {
+                        let class = threshold.classes.usable(size);
+                        let module = self.trace_module(parent_idx).unwrap_or(0);
+                        let counts = self.small_allocations.entry((class, module)).or_default();
+                        counts.0 += 1;
+                        counts.1 += size;
+                        return Ok(());
+                    }
+                    if !self.observers.is_empty() {
+                        let allocation = LargeAllocation {
+                            size,
+                            function: self.trace_function(parent_idx),
+                            timestamp,
+                        };
+                        for observer in &mut self.observers {
+                            observer.on_large_allocation(&allocation);
+                        }
+                    }
+                }
+
                 self.stats.allocations += 1;
                 self.stats.leaked_allocations += 1;
+                self.stats.heap += size;
+                if self.stats.heap > self.stats.peak_heap {
+                    self.stats.peak_heap = self.stats.heap;
+                    if let Some(state) = &mut self.address_map {
+                        state.dirty |= self.stats.heap > state.map.heap + state.map.heap / 20;
+                    }
+                }
 
-                let idx = self.add_alloc(size as u64, parent_idx as u64)?;
+                if let Some(site_bytes) = &mut self.site_bytes {
+                    *site_bytes.entry(parent_idx).or_default() += size;
+                }
+                if let Some(top_sites) = &mut self.top_sites {
+                    top_sites.add(parent_idx, size);
+                }
 
-                self.add_pointer(ptr as u64, idx as u64);
+                let idx = self.add_alloc(size, parent_idx)?;
+
+                self.add_pointer(ptr, idx as u64);
                 self.last_ptr = ptr;
-                self.output.write_alloc(idx)?;
+                self.output.write_alloc(idx, timestamp)?;
+                for observer in &mut self.observers {
+                    observer.on_alloc(&AllocEvent {
+                        ptr,
+                        size,
+                        trace_idx: parent_idx,
+                        allocation_idx: idx,
+                        timestamp,
+                    });
+                }
             }
-            Record::Free { ptr } => {
+            RecordRef::Free { ptr, timestamp } => {
                 let temporary = self.last_ptr == ptr;
                 self.last_ptr = 0;
 
-                let Some(allocation_idx) = self.take_pointer(ptr as u64) else {
+                self.snapshot_address_map();
+
+                let Some(allocation_idx) = self.take_pointer(ptr) else {
+                    self.free_mismatch(ptr, timestamp);
                     return Ok(());
                 };
+                self.freed.insert(ptr, allocation_idx);
 
-                self.output.write_free(allocation_idx)?;
+                let (size, trace_idx) = self
+                    .allocation_info
+                    .get_index(allocation_idx)
+                    .map_or((0, 0), |info| (info.size, info.trace_idx));
+                self.stats.heap -= size;
+                if let Some(bytes) = self.site_bytes.as_mut().and_then(|s| s.get_mut(&trace_idx)) {
+                    *bytes -= size;
+                }
+
+                let owner_idx = self.owners.remove(&ptr).unwrap_or(0);
+                self.output
+                    .write_free(allocation_idx, timestamp, owner_idx)?;
+                for observer in &mut self.observers {
+                    observer.on_free(&FreeEvent {
+                        ptr,
+                        size,
+                        allocation_idx,
+                        timestamp,
+                        temporary,
+                    });
+                }
 
                 if temporary {
                     self.stats.tmp_allocations += 1;
                 }
                 self.stats.leaked_allocations -= 1;
             }
-            Record::Duration(duration) => {
+            RecordRef::Duration(duration) => {
                 self.output.write_duration(duration)?;
+                self.check_alerts(duration)?;
+                self.report_stats(duration);
+            }
+            RecordRef::RSS { rss, timestamp } => {
+                self.stats.rss = rss;
+                self.output.write_rss(rss, timestamp)?;
+            }
+            RecordRef::Clock {
+                source,
+                realtime_offset,
+            } => {
+                self.output.write_metadata("clock.source", source.name())?;
+                self.output
+                    .write_metadata(CLOCK_OFFSET_KEY, &realtime_offset.to_string())?;
+            }
+            RecordRef::MarkerBegin { label, timestamp } => {
+                self.output
+                    .write_transaction_begin(&String::from_utf8_lossy(label), timestamp)?;
+            }
+            RecordRef::MarkerEnd { label, timestamp } => {
+                self.output
+                    .write_transaction_end(&String::from_utf8_lossy(label), timestamp)?;
+            }
+            RecordRef::RootScan { roots } => {
+                self.output
+                    .write_metadata(ROOT_SCAN_KEY, &roots.to_string())?;
+            }
+            RecordRef::Reachable { ptr } => {
+                // pointers into untracked memory or freed blocks are ignored
+                if let Some(allocation_idx) = self.find_pointer(ptr) {
+                    self.output.write_reachable(allocation_idx)?;
+                }
+            }
+            RecordRef::PoolCreate {
+                pool,
+                name,
+                capacity,
+            } => {
+                let live = LivePool {
+                    idx: self.pool_count,
+                    allocations: HashMap::new(),
+                };
+                self.pool_count += 1;
+                if let Some(replaced) = self.pools.insert(pool, live) {
+                    self.output.write_pool_destroy(replaced.idx)?;
+                }
+                self.output
+                    .write_pool(&String::from_utf8_lossy(name), capacity)?;
+            }
+            RecordRef::PoolAlloc { pool, ptr, size } => {
+                // allocations of undeclared pools are ignored
+                let Some(live) = self.pools.get_mut(&pool) else {
+                    return Ok(());
+                };
+                if let Some(size) = live.allocations.insert(ptr, size) {
+                    self.output.write_pool_free(live.idx, size)?;
+                }
+                self.output.write_pool_alloc(live.idx, size)?;
+            }
+            RecordRef::PoolFree { pool, ptr } => {
+                if let Some(live) = self.pools.get_mut(&pool)
+                    && let Some(size) = live.allocations.remove(&ptr)
+                {
+                    self.output.write_pool_free(live.idx, size)?;
+                }
             }
-            Record::RSS(rss) => {
-                self.output.write_rss(rss)?;
+            RecordRef::PoolDestroy { pool } => {
+                if let Some(live) = self.pools.remove(&pool) {
+                    self.output.write_pool_destroy(live.idx)?;
+                }
+            }
+            RecordRef::AllocFailed {
+                size,
+                parent_idx,
+                timestamp,
+            } => {
+                // kept regardless of the rules, failures are what the run is
+                // investigated for
+                self.output
+                    .write_alloc_failed(size, parent_idx, timestamp)?;
+            }
+            RecordRef::Transfer { ptr, tag } => {
+                // allocations left out of the trace have no owner to track
+                let Some(allocation_idx) = self.find_pointer(ptr) else {
+                    return Ok(());
+                };
+                let owner_idx = self.write_string(&String::from_utf8_lossy(tag))?;
+                let previous_idx = self.owners.insert(ptr, owner_idx).unwrap_or(0);
+                if previous_idx != owner_idx {
+                    self.output
+                        .write_transfer(allocation_idx, owner_idx, previous_idx)?;
+                }
             }
         }
 
         Ok(())
     }
 
+    /// Counts a free that matched no live allocation. Allocations dropped
+    /// by the rules or the stack threshold have no pointer kept, so their
+    /// frees are not counted as untracked.
+    fn free_mismatch(&mut self, ptr: u64, timestamp: u64) {
+        let (kind, stack) = match self.freed.get(ptr) {
+            Some(allocation_idx) => {
+                let trace_idx = self
+                    .allocation_info
+                    .get_index(allocation_idx)
+                    .map_or(0, |info| info.trace_idx);
+                self.free_mismatches.double_frees += 1;
+                (FreeMismatchKind::DoubleFree, self.trace_stack(trace_idx))
+            }
+            None if ptr == 0 || self.rules.is_some() || self.stack_threshold.is_some() => return,
+            None => {
+                self.free_mismatches.untracked += 1;
+                (FreeMismatchKind::Untracked, Vec::new())
+            }
+        };
+
+        if self.free_mismatches.examples.len() < FREE_MISMATCH_EXAMPLES {
+            self.free_mismatches.examples.push(FreeMismatch {
+                kind,
+                ptr,
+                timestamp,
+                stack,
+            });
+        }
+    }
+
     fn add_frame(&mut self, ip: u64) -> Result<usize, Error> {
         match self.frames.get_full(&ip) {
             None => {
@@ -191,9 +1407,11 @@ impl Interpreter {
                 };
 
                 let mut frames = Vec::with_capacity(result.locations.len());
+                let mut functions = Vec::with_capacity(result.locations.len());
 
                 for location in result.locations {
                     let function_idx = self.write_string(&location.function_name)?;
+                    functions.push(function_idx);
 
                     let frame = if location.file_name.is_some() {
                         let file_idx = self.write_string(
@@ -218,6 +1436,9 @@ impl Interpreter {
                     frames.push(frame);
                 }
 
+                self.frame_functions.push(functions);
+                self.frame_modules.push(result.module_id);
+
                 self.output
                     .write_instruction(ip, result.module_id, &frames)?;
 
@@ -227,6 +1448,128 @@ impl Interpreter {
         }
     }
 
+    fn apply_rules(&mut self, trace_idx: u64) -> Result<Decision, Error> {
+        let Some(active) = &mut self.rules else {
+            return Ok(Decision::Keep);
+        };
+
+        let generation = active.handle.generation();
+        if generation != active.generation {
+            active.generation = generation;
+            active.rules = active.handle.current();
+            active.decisions.clear();
+            self.output
+                .write_marker(&format!("rules reloaded (generation {})", generation))?;
+        }
+
+        if active.rules.is_empty() {
+            return Ok(Decision::Keep);
+        }
+
+        if let Some(decision) = active.decisions.get(&trace_idx) {
+            return Ok(decision.clone());
+        }
+
+        let mut functions = Vec::new();
+        let mut idx = trace_idx;
+        let mut depth = 0;
+        while idx != 0 && depth <= self.traces.len() {
+            depth += 1;
+            let Some(&(ip_id, parent_idx)) = self.traces.get(idx as usize - 1) else {
+                break;
+            };
+            for function_idx in self.frame_functions.get(ip_id - 1).into_iter().flatten() {
+                if let Some(name) = self.strings.get_index(function_idx - 1) {
+                    functions.push(name.as_str());
+                }
+            }
+            idx = parent_idx;
+        }
+
+        let decision = active.rules.decide(functions);
+        active.decisions.insert(trace_idx, decision.clone());
+
+        Ok(decision)
+    }
+
+    fn check_alerts(&mut self, duration: u128) -> Result<(), Error> {
+        if self.alerts.is_empty() {
+            return Ok(());
+        }
+
+        let timestamp = Duration::from_millis(duration as u64);
+        for alert in self
+            .alerts
+            .update(timestamp, self.stats.heap, self.stats.tmp_allocations)
+        {
+            self.output
+                .write_marker(&format!("alert: {}", alert.message))?;
+            for observer in &mut self.observers {
+                observer.on_alert(&alert);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn report_stats(&mut self, duration: u128) {
+        let Some(top) = self.observers.iter().map(|o| o.top_sites()).max() else {
+            return;
+        };
+
+        let top_sites = self.top_live_sites(top);
+        let top_allocating = self.top_sites(top);
+        let stats = LiveStats {
+            timestamp: Duration::from_millis(duration as u64),
+            allocations: self.stats.allocations,
+            temporary: self.stats.tmp_allocations,
+            heap: self.stats.heap,
+            peak_heap: self.stats.peak_heap,
+            rss: self.stats.rss,
+            top_sites,
+            top_allocating,
+        };
+        for observer in &mut self.observers {
+            observer.on_stats(&stats);
+        }
+    }
+
+    fn send_stats(&mut self) {
+        if let Some((_, sender)) = &self.stats_channel
+            && sender.send(self.stats).is_err()
+        {
+            self.stats_channel = None;
+        }
+    }
+
+    fn snapshot_address_map(&mut self) {
+        let Some(state) = &mut self.address_map else {
+            return;
+        };
+        if !state.dirty {
+            return;
+        }
+
+        let allocation_info = &self.allocation_info;
+        let live = self.pointers.iter().flat_map(|(big, indices)| {
+            indices
+                .small_ptr_parts
+                .iter()
+                .zip(&indices.allocation_indices)
+                .filter_map(move |(&small, &idx)| {
+                    let info = allocation_info.get_index(idx)?;
+                    Some(LiveAllocation {
+                        ptr: big * PAGE_SIZE + small as u64,
+                        size: info.size,
+                        trace_idx: info.trace_idx,
+                    })
+                })
+        });
+
+        state.map = AddressMap::build(live, state.region_size);
+        state.dirty = false;
+    }
+
     fn add_alloc(&mut self, size: u64, parent_idx: u64) -> Result<usize, Error> {
         let info = AllocationInfo {
             size,
@@ -265,6 +1608,17 @@ impl Interpreter {
         }
     }
 
+    fn find_pointer(&self, ptr: u64) -> Option<usize> {
+        let pointer = SplitPointer::new(ptr);
+        let indices = self.pointers.get(&pointer.big)?;
+
+        let idx = indices
+            .small_ptr_parts
+            .iter()
+            .position(|&i| i == pointer.small)?;
+        Some(indices.allocation_indices[idx])
+    }
+
     fn take_pointer(&mut self, ptr: u64) -> Option<usize> {
         let pointer = SplitPointer::new(ptr);
         let indices = self.pointers.get_mut(&pointer.big)?;
@@ -296,6 +1650,29 @@ impl Interpreter {
         }
     }
 
+    /// Maps the image of `module_id`, moving the range of a module loaded
+    /// again to its new address.
+    fn map_module(&mut self, module_id: usize, name: &str, start_address: u64, size: u64) {
+        match self.modules.get(&module_id) {
+            Some(&range) if range == (start_address, size) => {
+                self.duplicate_images += 1;
+                return;
+            }
+            Some(&(start, size)) => self.resolver.remove_module(start, size),
+            None => {}
+        }
+
+        if self
+            .resolver
+            .add_module(module_id, name, start_address, size)
+            .is_ok()
+        {
+            self.modules.insert(module_id, (start_address, size));
+        } else {
+            self.modules.remove(&module_id);
+        }
+    }
+
     fn write_comments(&mut self) -> Result<(), Error> {
         self.output.write("")?;
 
@@ -303,7 +1680,605 @@ impl Interpreter {
             .write_comment(&format!("strings: {}", self.strings.len()))?;
         self.output
             .write_comment(&format!("ips: {}", self.frames.len()))?;
+        self.output.write_comment(&format!(
+            "modules: {} ({} duplicate images)",
+            self.modules.len(),
+            self.duplicate_images
+        ))?;
+
+        let cache = self.resolver.cache_stats();
+        self.output.write_comment(&format!(
+            "resolver cache: {} hits, {} misses, {} evictions, {} entries, {} bytes",
+            cache.hits, cache.misses, cache.evictions, cache.entries, cache.bytes
+        ))?;
+
+        if let Some(queue) = self.output.queue_stats() {
+            self.output.write_comment(&format!(
+                "output queue: {} chunks, {} overflows, stalled {} ms",
+                queue.chunks,
+                queue.overflows,
+                queue.stalled.as_millis()
+            ))?;
+        }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::ownership::OwnershipReport;
+    use crate::interpret::{
+        FreedPointers, Interpreter, MemStats, StackThreshold, StatsTimer, Watchdog, FREED_POINTERS,
+    };
+    use crate::model::tests::TRACE;
+    use crate::model::Profile;
+    use crate::observer::{AllocEvent, FreeEvent, ImageEvent, Observer};
+    use crate::parser::{
+        AccumulatedData, FreeMismatchKind, Parser, SmallAllocations, CLOCK_OFFSET_KEY,
+    };
+    use crate::pipe_io::{ClockSource, Command, Record, RecordRef};
+    use crate::report::{Report, ReportOptions};
+    use crate::transform::Transform;
+    use std::cell::RefCell;
+    use std::io;
+    use std::path::PathBuf;
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+
+    /// Trace file of a test, removed when dropped, also when the test panics.
+    struct TempTrace(PathBuf);
+
+    impl TempTrace {
+        fn new(name: &str, contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "memtrack-{}-{}.trace",
+                name,
+                std::process::id()
+            ));
+            std::fs::write(&path, contents).unwrap();
+            Self(path)
+        }
+
+        /// Finishes the run of `interpreter` like [`Interpreter::exec`] and
+        /// parses the single trace of the file.
+        fn parse(&self, mut interpreter: Interpreter) -> AccumulatedData {
+            interpreter.write_small_allocations().unwrap();
+            interpreter.write_free_mismatches().unwrap();
+            interpreter.output.flush().unwrap();
+            drop(interpreter);
+
+            let mut traces = Parser::new().parse_file_all(&self.0).unwrap();
+            assert_eq!(traces.len(), 1);
+            traces.pop().unwrap()
+        }
+    }
+
+    impl Drop for TempTrace {
+        fn drop(&mut self) {
+            _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    /// Interpreter resuming a copy of [`TRACE`] that handled `records` after
+    /// `setup`, and the trace file it writes to.
+    fn run<'a>(
+        name: &str,
+        setup: impl FnOnce(&mut Interpreter),
+        records: impl IntoIterator<Item = RecordRef<'a>>,
+    ) -> (TempTrace, Interpreter) {
+        let trace = TempTrace::new(name, TRACE);
+        let mut interpreter = Interpreter::resume(&trace.0).unwrap();
+        setup(&mut interpreter);
+        for record in records {
+            interpreter.transform_record(0, record).unwrap();
+        }
+        (trace, interpreter)
+    }
+
+    /// Parses the trace written by [`run`].
+    fn interpret<'a>(
+        name: &str,
+        setup: impl FnOnce(&mut Interpreter),
+        records: impl IntoIterator<Item = RecordRef<'a>>,
+    ) -> AccumulatedData {
+        let (trace, interpreter) = run(name, setup, records);
+        trace.parse(interpreter)
+    }
+
+    #[test]
+    fn test_resume() {
+        // the header of the second run
+        let records = [
+            RecordRef::Version(3),
+            RecordRef::Exec(b"/bin/main --again"),
+            RecordRef::Clock {
+                source: ClockSource::Realtime,
+                realtime_offset: 5,
+            },
+            RecordRef::Trace {
+                ip: 0x20,
+                parent_idx: 0,
+            },
+            RecordRef::Alloc {
+                ptr: 0x1000,
+                size: 0x10,
+                parent_idx: 1,
+                timestamp: 0,
+            },
+        ];
+        let data = interpret(
+            "resume",
+            |interpreter| interpreter.start_run().unwrap(),
+            records,
+        );
+
+        assert_eq!(data.traces.len(), 5);
+        assert_eq!(data.traces[4].ip_idx, 2);
+        assert_eq!(data.total.allocations, 4);
+        assert_eq!(data.allocations.last().unwrap().trace_idx, 5);
+        assert_eq!(data.strings.len(), 5);
+        assert_eq!(data.command, None);
+        assert!(!data.metadata.contains_key(CLOCK_OFFSET_KEY));
+        assert_eq!(data.markers[0].label, "session resumed");
+    }
+
+    #[test]
+    fn test_stack_threshold() {
+        let records =
+            [(0x1000, 0x10), (0x2000, 0x0c), (0x3000, 0x200)].map(|(ptr, size)| RecordRef::Alloc {
+                ptr,
+                size,
+                parent_idx: 3,
+                timestamp: 0,
+            });
+        let data = interpret(
+            "threshold",
+            |interpreter| interpreter.set_stack_threshold(Some(StackThreshold::new(0x100))),
+            records,
+        );
+
+        assert_eq!(data.total.allocations, 4);
+        assert_eq!(
+            data.small_allocations(),
+            [SmallAllocations {
+                size_class: 16,
+                module: "/bin".into(),
+                allocations: 2,
+                bytes: 0x1c,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_sampling_rate() {
+        let data = interpret(
+            "sampling",
+            |interpreter| {
+                for rate in [0.5, 0.25] {
+                    interpreter
+                        .write_command(&Command::SetSampling(rate))
+                        .unwrap();
+                }
+            },
+            [],
+        );
+
+        assert_eq!(data.sampling_rate(), Some(0.25));
+        assert_eq!(data.markers.len(), 2);
+
+        let report = Report::new(
+            &data,
+            &Profile::new(&data).unwrap(),
+            &ReportOptions::default(),
+        );
+        let sampling = report.sampling.unwrap();
+        assert_eq!(sampling.total.allocations.value, 12.0);
+        assert!(sampling.total.allocations.upper > 12.0);
+        assert!(report.sites[0].estimate.is_some());
+    }
+
+    #[test]
+    fn test_free_mismatches() {
+        let records = [
+            RecordRef::Alloc {
+                ptr: 0x1000,
+                size: 0x10,
+                parent_idx: 3,
+                timestamp: 1,
+            },
+            RecordRef::Free {
+                ptr: 0x1000,
+                timestamp: 2,
+            },
+            RecordRef::Free {
+                ptr: 0x1000,
+                timestamp: 3,
+            },
+            RecordRef::Free {
+                ptr: 0x2000,
+                timestamp: 4,
+            },
+            RecordRef::Free {
+                ptr: 0,
+                timestamp: 5,
+            },
+        ];
+        let data = interpret(
+            "frees",
+            |interpreter| interpreter.set_strict_frees(true),
+            records,
+        );
+
+        assert_eq!(data.anomalies.len(), 2);
+        let mismatches = data.free_mismatches();
+        assert_eq!((mismatches.untracked, mismatches.double_frees), (1, 1));
+        assert_eq!(mismatches.examples[0].kind, FreeMismatchKind::DoubleFree);
+        assert_eq!(mismatches.examples[0].stack, ["malloc_a", "a", "main"]);
+        assert_eq!(mismatches.examples[1].ptr, 0x2000);
+        assert!(mismatches.examples[1].stack.is_empty());
+    }
+
+    #[test]
+    fn test_freed_pointers() {
+        let mut freed = FreedPointers::default();
+        freed.insert(0x1000, 1);
+        freed.insert(0x2000, 2);
+        // freed again after being reallocated, the first entry is stale
+        freed.remove(0x2000);
+        freed.insert(0x2000, 3);
+        for ptr in 0..FREED_POINTERS as u64 - 2 {
+            freed.insert(0x10000 + ptr, 4);
+        }
+
+        assert_eq!(freed.get(0x1000), None);
+        assert_eq!(freed.get(0x2000), Some(3));
+        assert_eq!(freed.indices.len(), FREED_POINTERS - 1);
+    }
+
+    #[test]
+    fn test_pools() {
+        let records = [
+            RecordRef::PoolCreate {
+                pool: 0x100,
+                name: b"arena",
+                capacity: 0x400,
+            },
+            RecordRef::PoolAlloc {
+                pool: 0x100,
+                ptr: 0x1000,
+                size: 0x40,
+            },
+            RecordRef::PoolAlloc {
+                pool: 0x100,
+                ptr: 0x1040,
+                size: 0x20,
+            },
+            RecordRef::PoolFree {
+                pool: 0x100,
+                ptr: 0x1000,
+            },
+            // undeclared pool
+            RecordRef::PoolAlloc {
+                pool: 0x200,
+                ptr: 0x2000,
+                size: 0x10,
+            },
+            // the handle is reused by a new pool
+            RecordRef::PoolCreate {
+                pool: 0x100,
+                name: b"arena",
+                capacity: 0x400,
+            },
+        ];
+        let data = interpret("pools", |_| {}, records);
+
+        assert!(data.anomalies.is_empty());
+        assert_eq!(data.pools.len(), 2);
+        assert_eq!(data.pools[0].peak_requested, 0x60);
+        assert_eq!(data.pools[0].frees, 1);
+        assert!(data.pools[0].destroyed);
+        assert_eq!(data.pools[1].allocations, 0);
+    }
+
+    #[test]
+    fn test_non_utf8_records() {
+        // a new trace, as the command of a resumed one is kept
+        let trace = TempTrace::new("non-utf8", "");
+        let mut interpreter = Interpreter::new(&trace.0).unwrap();
+        let records = [
+            RecordRef::Version(3),
+            RecordRef::Exec(b"caf\xe9 -x"),
+            RecordRef::Image {
+                name: b"/lib/lib\xff.so",
+                start_address: 0x1000,
+                size: 0x1000,
+            },
+        ];
+        for record in records {
+            interpreter.handle_record(record).unwrap();
+        }
+        let data = trace.parse(interpreter);
+
+        assert_eq!(data.command.as_deref(), Some("caf\u{fffd} -x"));
+        assert!(data.strings.iter().any(|s| s == "/lib/lib\u{fffd}.so"));
+    }
+
+    #[test]
+    fn test_reloaded_modules() {
+        let exe = std::env::current_exe().unwrap();
+        let exe = exe.to_str().unwrap();
+        let records = [0x1000, 0x1000, 0x10000].map(|start_address| RecordRef::Image {
+            name: exe.as_bytes(),
+            start_address,
+            size: 0x1000,
+        });
+        let (trace, interpreter) = run("modules", |_| {}, records);
+
+        assert_eq!(interpreter.duplicate_images, 1);
+        assert_eq!(
+            interpreter.modules.values().collect::<Vec<_>>(),
+            [&(0x10000, 0x1000)]
+        );
+        assert!(interpreter.resolver.lookup(0x1800).is_none());
+
+        let data = trace.parse(interpreter);
+        assert_eq!(data.strings.iter().filter(|s| *s == exe).count(), 1);
+    }
+
+    #[test]
+    fn test_record_observer() {
+        struct Events(Rc<RefCell<Vec<String>>>);
+
+        impl Observer for Events {
+            fn on_image(&mut self, image: &ImageEvent) {
+                let event = format!("image {:#x}", image.start_address);
+                self.0.borrow_mut().push(event);
+            }
+
+            fn on_alloc(&mut self, alloc: &AllocEvent) {
+                let event = format!("alloc {:#x} {} {}", alloc.ptr, alloc.size, alloc.trace_idx);
+                self.0.borrow_mut().push(event);
+            }
+
+            fn on_free(&mut self, free: &FreeEvent) {
+                let event = format!("free {:#x} {} {}", free.ptr, free.size, free.temporary);
+                self.0.borrow_mut().push(event);
+            }
+        }
+
+        let exe = std::env::current_exe().unwrap();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let records = [
+            RecordRef::Image {
+                name: exe.to_str().unwrap().as_bytes(),
+                start_address: 0x1000,
+                size: 0x1000,
+            },
+            RecordRef::Alloc {
+                ptr: 0x2000,
+                size: 0x40,
+                parent_idx: 3,
+                timestamp: 0,
+            },
+            RecordRef::Free {
+                ptr: 0x2000,
+                timestamp: 0,
+            },
+            // untracked pointer
+            RecordRef::Free {
+                ptr: 0x3000,
+                timestamp: 0,
+            },
+        ];
+        let (_trace, mut interpreter) = run(
+            "observer",
+            |interpreter| interpreter.add_observer(Box::new(Events(events.clone()))),
+            records,
+        );
+
+        let stats = interpreter.stats_channel(Duration::ZERO);
+        interpreter.send_stats();
+        let snapshot = stats.try_recv().unwrap();
+        assert_eq!(snapshot, interpreter.stats());
+        assert_eq!(snapshot.allocations, 4);
+        assert_eq!(snapshot.heap, 0x30);
+        assert_eq!(snapshot.tmp_allocations, 1);
+        assert_eq!(
+            *events.borrow(),
+            ["image 0x1000", "alloc 0x2000 64 3", "free 0x2000 64 true"]
+        );
+    }
+
+    #[test]
+    fn test_top_sites() {
+        struct Top;
+
+        impl Observer for Top {
+            fn top_sites(&self) -> usize {
+                2
+            }
+        }
+
+        let allocs = [(0x1000, 0x100, 3), (0x2000, 0x10, 4), (0x3000, 0x10, 4)].map(
+            |(ptr, size, parent_idx)| RecordRef::Alloc {
+                ptr,
+                size,
+                parent_idx,
+                timestamp: 0,
+            },
+        );
+        let free = RecordRef::Free {
+            ptr: 0x1000,
+            timestamp: 0,
+        };
+        let (_trace, interpreter) = run(
+            "top",
+            |interpreter| interpreter.add_observer(Box::new(Top)),
+            allocs.into_iter().chain([free]),
+        );
+
+        let allocating: Vec<(String, u64)> = interpreter
+            .top_sites(2)
+            .into_iter()
+            .map(|site| (site.function, site.allocated))
+            .collect();
+        let live: Vec<(String, u64)> = interpreter
+            .top_live_sites(2)
+            .into_iter()
+            .map(|site| (site.function, site.bytes))
+            .collect();
+        assert_eq!(
+            allocating,
+            [("malloc_a".to_string(), 0x100), ("b".to_string(), 0x20)]
+        );
+        assert_eq!(live, [("b".to_string(), 0x20)]);
+    }
+
+    #[test]
+    fn test_alloc_failed() {
+        let records = [(0x200000000, 3), (0x10, 4), (0x20, 3)].map(|(size, parent_idx)| {
+            RecordRef::AllocFailed {
+                size,
+                parent_idx,
+                timestamp: 0x64,
+            }
+        });
+        let data = interpret("failed", |_| {}, records);
+
+        assert_eq!(data.total.allocations, 3);
+        let failures = data.failed_allocations[&3];
+        assert_eq!(failures.count, 2);
+        assert_eq!(failures.largest, 0x200000000);
+        assert_eq!(failures.bytes, 0x200000020);
+        assert_eq!(failures.first_timestamp, 0x64);
+
+        let profile = Profile::new(&data).unwrap();
+        let report = Report::new(&data, &profile, &ReportOptions::default());
+        assert_eq!(report.failures.len(), 2);
+        assert_eq!(report.failures[0].stack[0].function, "malloc_a");
+        let mut html = Vec::new();
+        report.write_html(&mut html).unwrap();
+        assert!(String::from_utf8(html)
+            .unwrap()
+            .contains("<h1>Failed allocations</h1>"));
+    }
+
+    #[test]
+    fn test_transfer() {
+        let alloc = |ptr| RecordRef::Alloc {
+            ptr,
+            size: 0x20,
+            parent_idx: 4,
+            timestamp: 0,
+        };
+        let transfer = |ptr, tag| RecordRef::Transfer { ptr, tag };
+        let records = [
+            alloc(0x2000),
+            alloc(0x3000),
+            transfer(0x2000, b"cache"),
+            transfer(0x2000, b"cache"),
+            transfer(0x2000, b"queue"),
+            transfer(0x3000, b"cache"),
+            // untracked pointer
+            transfer(0x4000, b"queue"),
+            RecordRef::Free {
+                ptr: 0x2000,
+                timestamp: 0,
+            },
+        ];
+        let data = interpret("transfer", |_| {}, records);
+
+        let report = OwnershipReport::new(&data);
+        assert_eq!(report.transferred, 0x60);
+        assert_eq!(report.leaked, 0x20);
+        let owners: Vec<_> = report
+            .owners
+            .iter()
+            .map(|o| (o.name.as_str(), o.ownership.transfers, o.ownership.frees))
+            .collect();
+        assert_eq!(owners, [("cache", 2, 0), ("queue", 1, 1)]);
+    }
+
+    #[test]
+    fn test_transformers() {
+        let records = [
+            RecordRef::Alloc {
+                ptr: 0x1000,
+                size: 0x10,
+                parent_idx: 3,
+                timestamp: 0,
+            },
+            RecordRef::Alloc {
+                ptr: 0x2000,
+                size: 0x40,
+                parent_idx: 3,
+                timestamp: 0,
+            },
+            RecordRef::Duration(200),
+        ];
+        let data = interpret(
+            "transform",
+            |interpreter| {
+                interpreter.add_transformer(Box::new(|record: &RecordRef| match *record {
+                    RecordRef::Alloc { size, .. } if size < 0x20 => Transform::Drop,
+                    _ => Transform::Keep,
+                }));
+                interpreter.add_transformer(Box::new(|record: &RecordRef| match *record {
+                    RecordRef::Duration(duration) => Transform::Replace(vec![
+                        Record::MarkerBegin {
+                            label: "tick".into(),
+                            timestamp: 1,
+                        },
+                        Record::Duration(duration),
+                        Record::MarkerEnd {
+                            label: "tick".into(),
+                            timestamp: 2,
+                        },
+                    ]),
+                    _ => Transform::Keep,
+                }));
+            },
+            records,
+        );
+
+        assert_eq!(data.total.allocations, 4);
+        assert_eq!(data.transactions.len(), 1);
+        assert_eq!(data.transactions[0].label, "tick");
+    }
+
+    #[test]
+    fn test_watchdog() {
+        let control = crate::executor::ControlHandle::new();
+        let watchdog = Watchdog::spawn(Instant::now() + Duration::from_secs(60), control.clone());
+        assert!(!watchdog.fired());
+        assert!(watchdog.cancel().is_none());
+
+        let watchdog = Watchdog::spawn(Instant::now(), control);
+        while !watchdog.fired() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        // no program is connected to receive the command
+        let result = watchdog.cancel().unwrap();
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotConnected);
+    }
+
+    #[test]
+    fn test_stats_timer() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let timer = StatsTimer::spawn(Duration::from_millis(1), MemStats::default(), sender);
+        let stats = MemStats {
+            allocations: 2,
+            heap: 0x40,
+            ..Default::default()
+        };
+        timer.publish(stats);
+
+        // snapshots keep coming without any record
+        assert!(receiver.iter().any(|snapshot| snapshot == stats));
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(stats));
+
+        drop(timer);
+        assert!(receiver.iter().all(|snapshot| snapshot == stats));
+    }
+}
@@ -5,14 +5,63 @@
 //!
 //! A library with utils used for parsing heap tracing files
 //!
-//! > **Platform support**: Currently tested only on macOS (aarch64-apple-darwin)
+//! > **Platform support**: macOS and Linux. The library is injected with
+//! > `DYLD_INSERT_LIBRARIES` on macOS and `LD_PRELOAD` on Linux, and downloaded
+//! > for the target triple of the build, e.g. `aarch64-apple-darwin` or
+//! > `x86_64-unknown-linux-gnu`. The end-to-end `LD_PRELOAD` test needs a built
+//! > library and example:
+//! >
+//! > ```sh
+//! > cargo build --example alloc_free
+//! > MEMTRACK_TEST_LIB=/path/to/libmemtrace_lib.so cargo test -- --ignored test_ld_preload
+//! > ```
 //!
 //! License: MIT
 
+#[cfg(feature = "exec")]
 pub(crate) mod executor;
+#[cfg(feature = "exec")]
 pub mod interpret;
+pub mod model;
+#[cfg(feature = "exec")]
 mod output;
 pub mod parser;
+#[cfg(feature = "exec")]
 pub mod pipe_io;
+pub mod alerts;
+pub mod analysis;
+pub mod annotations;
+pub mod budget;
+#[cfg(feature = "cli")]
+pub mod cli;
+#[cfg(feature = "download")]
 pub mod common;
-mod resolver;
+#[cfg(feature = "exec")]
+pub mod conformance;
+pub mod diff;
+#[cfg(feature = "exec")]
+pub mod environment;
+pub mod export;
+#[cfg(feature = "exec")]
+pub mod fleet;
+pub mod format;
+#[cfg(feature = "history")]
+pub mod history;
+pub mod numparse;
+pub mod observer;
+#[cfg(feature = "download")]
+pub mod otlp;
+#[cfg(feature = "resolve")]
+pub mod resolver;
+pub mod redact;
+pub mod replay;
+pub mod report;
+pub mod rules;
+#[cfg(feature = "exec")]
+pub mod runtime;
+#[cfg(feature = "exec")]
+pub mod session;
+pub mod soak;
+pub mod topk;
+#[cfg(feature = "exec")]
+pub mod transform;
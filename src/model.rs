@@ -0,0 +1,596 @@
+//! Owned, fully resolved view of a parsed trace.
+//!
+//! [`AccumulatedData`] mirrors the on-disk grammar and is addressed by raw
+//! indices. [`Profile`] resolves those indices once so consumers work with
+//! function names, modules and stacks directly and are not affected by
+//! changes of the raw format.
+
+use crate::analysis::allocators;
+use crate::diff::normalize;
+use crate::parser::{AccumulatedData, AllocationData, AllocationFailures, Frame as RawFrame};
+use indexmap::{IndexMap, IndexSet};
+use serde::Serialize;
+use std::fmt;
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid {kind} index {idx}")]
+    InvalidIndex { kind: &'static str, idx: u64 },
+    #[error("trace {0} has a cyclic parent chain")]
+    CyclicTrace(u64),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Cost {
+    pub allocations: u64,
+    pub temporary: u64,
+    pub leaked: u64,
+    pub peak: u64,
+}
+
+impl From<&AllocationData> for Cost {
+    fn from(data: &AllocationData) -> Self {
+        Self {
+            allocations: data.allocations,
+            temporary: data.temporary,
+            leaked: data.leaked,
+            peak: data.peak,
+        }
+    }
+}
+
+/// A single figure of a [`Cost`], used to rank functions and sites.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Metric {
+    Allocations,
+    Temporary,
+    Leaked,
+    Peak,
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Metric::Allocations => "allocations",
+            Metric::Temporary => "temporary",
+            Metric::Leaked => "leaked",
+            Metric::Peak => "peak",
+        })
+    }
+}
+
+/// Whether a function's cost includes its callees or only allocations
+/// made directly by the function itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum CostKind {
+    Inclusive,
+    Exclusive,
+}
+
+impl Cost {
+    pub fn get(&self, metric: Metric) -> u64 {
+        match metric {
+            Metric::Allocations => self.allocations,
+            Metric::Temporary => self.temporary,
+            Metric::Leaked => self.leaked,
+            Metric::Peak => self.peak,
+        }
+    }
+
+    pub fn add(&mut self, other: &Cost) {
+        self.allocations += other.allocations;
+        self.temporary += other.temporary;
+        self.leaked += other.leaked;
+        self.peak += other.peak;
+    }
+}
+
+/// Which functions the allocations made within inlined code are attributed
+/// to. Profilers differ here, so matching their numbers needs the same
+/// choice.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum InlineAttribution {
+    /// Inlined functions keep their frames and own the allocations made in
+    /// them.
+    #[default]
+    Inlinee,
+    /// Inlined frames are dropped, attributing their allocations to the
+    /// function they were inlined into.
+    Caller,
+    /// Inlined functions keep their frames and the exclusive cost of their
+    /// allocations is also given to the function they were inlined into.
+    Both,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Module {
+    pub path: String,
+}
+
+/// A single resolved frame. Inlined functions get their own frame, sharing
+/// the instruction pointer of the frame they were inlined into.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Frame {
+    pub ip: u64,
+    pub module: Option<String>,
+    pub function: String,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub inlined: bool,
+}
+
+/// An allocation site: a unique stack and everything allocated from it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Site {
+    /// Frames ordered from the allocating function to the outermost caller.
+    pub stack: Vec<Frame>,
+    pub cost: Cost,
+}
+
+impl Site {
+    pub fn leaf(&self) -> Option<&Frame> {
+        self.stack.first()
+    }
+
+    /// Identifies the site across runs and builds: its normalized function
+    /// names from the outermost frame to the allocating one, separated by
+    /// `;`.
+    pub fn signature(&self) -> String {
+        self.stack
+            .iter()
+            .rev()
+            .map(|f| normalize(&f.function))
+            .collect::<Vec<_>>()
+            .join(";")
+    }
+}
+
+/// A stack whose allocations the allocator failed to serve.
+#[derive(Debug, Clone, Serialize)]
+pub struct FailureSite {
+    /// Frames ordered from the allocating function to the outermost caller.
+    pub stack: Vec<Frame>,
+    pub failures: AllocationFailures,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Function {
+    pub name: String,
+    pub module: Option<String>,
+    /// Cost of all sites with this function anywhere on the stack, counted
+    /// once per site even for recursive calls.
+    pub inclusive: Cost,
+    /// Cost of the sites where this function is the allocating frame.
+    pub exclusive: Cost,
+}
+
+impl Function {
+    pub fn cost(&self, kind: CostKind) -> &Cost {
+        match kind {
+            CostKind::Inclusive => &self.inclusive,
+            CostKind::Exclusive => &self.exclusive,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CallNode {
+    pub function: String,
+    /// Cost of this node and everything below it.
+    pub inclusive: Cost,
+    /// Cost of the sites whose stack ends at this node.
+    pub exclusive: Cost,
+    pub children: Vec<CallNode>,
+}
+
+impl CallNode {
+    fn child_mut(&mut self, function: &str) -> &mut CallNode {
+        let idx = match self.children.iter().position(|c| c.function == function) {
+            Some(idx) => idx,
+            None => {
+                self.children.push(CallNode {
+                    function: function.to_string(),
+                    ..Default::default()
+                });
+                self.children.len() - 1
+            }
+        };
+
+        &mut self.children[idx]
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Profile {
+    /// Modules code was executed from, each once, in order of appearance.
+    pub modules: Vec<Module>,
+    /// Every function found on a stack, with the costs of its sites.
+    pub functions: Vec<Function>,
+    /// One site per allocating stack, see [`Site`].
+    pub sites: Vec<Site>,
+    /// Stacks of the failed allocations, attributed like the sites.
+    pub failures: Vec<FailureSite>,
+    /// Top-down call tree rooted at an unnamed node holding the total cost.
+    pub call_tree: CallNode,
+    /// Cost of the whole run.
+    pub total: Cost,
+    /// Run time of the traced program.
+    pub duration: Duration,
+    /// Highest resident set size sampled, in bytes.
+    pub peak_rss: u64,
+    /// Page size of the host in bytes.
+    pub page_size: u64,
+    /// Number of physical pages of the host.
+    pub pages: u64,
+    /// How inlined frames were attributed when building the functions, the
+    /// call tree and the sites, which exports inherit.
+    pub inlining: InlineAttribution,
+}
+
+/// Options controlling how a [`Profile`] attributes allocations.
+#[derive(Debug, Clone, Default)]
+pub struct ProfileOptions {
+    /// Functions treated as part of the allocator: they are removed from the
+    /// top of every stack so costs are attributed to their callers.
+    pub allocators: Vec<String>,
+    /// Detects custom allocator wrappers with [`allocators::detect`] and
+    /// treats them like `allocators`.
+    pub detect_allocators: bool,
+    pub inlining: InlineAttribution,
+}
+
+impl Profile {
+    pub fn new(data: &AccumulatedData) -> Result<Self, Error> {
+        Self::with_options(data, &ProfileOptions::default())
+    }
+
+    pub fn with_options(data: &AccumulatedData, options: &ProfileOptions) -> Result<Self, Error> {
+        let mut paths = IndexSet::new();
+        for ip in &data.instruction_pointers {
+            paths.insert(string(data, ip.module_idx)?);
+        }
+        let modules = paths
+            .into_iter()
+            .map(|path| Module {
+                path: path.to_string(),
+            })
+            .collect();
+
+        let mut sites = Vec::with_capacity(data.allocations.len());
+        for allocation in &data.allocations {
+            sites.push(Site {
+                stack: resolve_stack(data, allocation.trace_idx)?,
+                cost: Cost::from(&allocation.data),
+            });
+        }
+
+        if options.inlining == InlineAttribution::Caller {
+            for site in &mut sites {
+                site.stack.retain(|f| !f.inlined);
+            }
+        }
+
+        let detected = if options.detect_allocators {
+            allocators::detect(&sites, allocators::DOMINANCE_THRESHOLD)
+        } else {
+            Vec::new()
+        };
+        skip_allocators(&mut sites, &detected);
+        skip_allocators(&mut sites, &options.allocators);
+
+        let mut failures = Vec::with_capacity(data.failed_allocations.len());
+        for (&trace_idx, failed) in &data.failed_allocations {
+            let mut stack = resolve_stack(data, trace_idx)?;
+            if options.inlining == InlineAttribution::Caller {
+                stack.retain(|f| !f.inlined);
+            }
+            skip_allocator_frames(&mut stack, &detected);
+            skip_allocator_frames(&mut stack, &options.allocators);
+            failures.push(FailureSite {
+                stack,
+                failures: *failed,
+            });
+        }
+
+        let (functions, call_tree) = aggregate(&sites, options.inlining);
+
+        Ok(Self {
+            modules,
+            functions,
+            sites,
+            failures,
+            call_tree,
+            total: Cost::from(&data.total),
+            duration: data.duration,
+            peak_rss: data.peak_rss,
+            page_size: data.page_size,
+            pages: data.pages,
+            inlining: options.inlining,
+        })
+    }
+
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.functions.iter().find(|f| f.name == name)
+    }
+
+    /// Returns up to `limit` functions with the highest `metric`, skipping
+    /// functions where it is zero.
+    pub fn top_functions(&self, kind: CostKind, metric: Metric, limit: usize) -> Vec<&Function> {
+        let mut functions: Vec<&Function> = self
+            .functions
+            .iter()
+            .filter(|f| f.cost(kind).get(metric) > 0)
+            .collect();
+        functions.sort_by_key(|f| std::cmp::Reverse(f.cost(kind).get(metric)));
+        functions.truncate(limit);
+        functions
+    }
+}
+
+fn skip_allocators(sites: &mut [Site], allocators: &[String]) {
+    if allocators.is_empty() {
+        return;
+    }
+
+    for site in sites {
+        skip_allocator_frames(&mut site.stack, allocators);
+    }
+}
+
+fn skip_allocator_frames(stack: &mut Vec<Frame>, allocators: &[String]) {
+    let skip = stack
+        .iter()
+        .take_while(|f| allocators.contains(&f.function))
+        .count();
+    stack.drain(..skip);
+}
+
+/// Number of frames at the top of `stack` owning the exclusive cost of its
+/// site.
+fn allocating_frames(stack: &[Frame], inlining: InlineAttribution) -> usize {
+    match inlining {
+        InlineAttribution::Inlinee | InlineAttribution::Caller => 1,
+        // the inlined frames and the frame they were inlined into
+        InlineAttribution::Both => stack
+            .iter()
+            .position(|f| !f.inlined)
+            .map_or(stack.len(), |i| i + 1),
+    }
+}
+
+fn aggregate(sites: &[Site], inlining: InlineAttribution) -> (Vec<Function>, CallNode) {
+    let mut functions: IndexMap<&str, Function> = IndexMap::new();
+    let mut call_tree = CallNode::default();
+
+    for site in sites {
+        call_tree.inclusive.add(&site.cost);
+        let allocating = allocating_frames(&site.stack, inlining);
+
+        let mut node = &mut call_tree;
+        for (depth, frame) in site.stack.iter().enumerate().rev() {
+            node = node.child_mut(&frame.function);
+            node.inclusive.add(&site.cost);
+            if depth > 0 && depth < allocating {
+                node.exclusive.add(&site.cost);
+            }
+        }
+        node.exclusive.add(&site.cost);
+
+        let mut seen: Vec<&str> = Vec::new();
+        for (depth, frame) in site.stack.iter().enumerate() {
+            if seen.contains(&frame.function.as_str()) {
+                continue;
+            }
+            seen.push(&frame.function);
+
+            let function = functions
+                .entry(&frame.function)
+                .or_insert_with(|| Function {
+                    name: frame.function.clone(),
+                    module: frame.module.clone(),
+                    inclusive: Cost::default(),
+                    exclusive: Cost::default(),
+                });
+
+            function.inclusive.add(&site.cost);
+            if depth < allocating {
+                function.exclusive.add(&site.cost);
+            }
+        }
+    }
+
+    (functions.into_values().collect(), call_tree)
+}
+
+fn string(data: &AccumulatedData, idx: usize) -> Result<&str, Error> {
+    idx.checked_sub(1)
+        .and_then(|i| data.strings.get(i))
+        .map(String::as_str)
+        .ok_or(Error::InvalidIndex {
+            kind: "string",
+            idx: idx as u64,
+        })
+}
+
+fn resolve_frame(
+    data: &AccumulatedData,
+    ip: u64,
+    module: Option<&str>,
+    frame: &RawFrame,
+    inlined: bool,
+) -> Result<Frame, Error> {
+    let (function, file, line) = match frame {
+        RawFrame::Single { function_idx } => (string(data, *function_idx)?, None, None),
+        RawFrame::Multiple {
+            function_idx,
+            file_idx,
+            line_number,
+        } => (
+            string(data, *function_idx)?,
+            Some(string(data, *file_idx)?.to_string()),
+            Some(*line_number),
+        ),
+    };
+
+    Ok(Frame {
+        ip,
+        module: module.map(str::to_string),
+        function: function.to_string(),
+        file,
+        line,
+        inlined,
+    })
+}
+
+fn resolve_stack(data: &AccumulatedData, trace_idx: u64) -> Result<Vec<Frame>, Error> {
+    let mut stack = Vec::new();
+    let mut idx = trace_idx;
+    let mut depth = 0;
+
+    while idx != 0 {
+        if depth > data.traces.len() {
+            return Err(Error::CyclicTrace(trace_idx));
+        }
+        depth += 1;
+
+        let trace = data
+            .traces
+            .get(idx as usize - 1)
+            .ok_or(Error::InvalidIndex { kind: "trace", idx })?;
+        let ip = trace
+            .ip_idx
+            .checked_sub(1)
+            .and_then(|i| data.instruction_pointers.get(i as usize))
+            .ok_or(Error::InvalidIndex {
+                kind: "instruction pointer",
+                idx: trace.ip_idx,
+            })?;
+
+        let module = string(data, ip.module_idx).ok();
+
+        // the resolver reports the innermost inlined function first and the
+        // function owning the instruction pointer last
+        let frames: Vec<&RawFrame> = std::iter::once(&ip.frame).chain(&ip.inlined).collect();
+        for (i, frame) in frames.iter().enumerate() {
+            let inlined = i + 1 < frames.len();
+            stack.push(resolve_frame(data, ip.ip, module, frame, inlined)?);
+        }
+
+        idx = trace.parent_idx;
+    }
+
+    Ok(stack)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::model::{CostKind, InlineAttribution, Metric, Profile, ProfileOptions};
+    use crate::parser::{AccumulatedData, Parser};
+
+    /// Two sites sharing `main`: `main -> a -> malloc_a` and `main -> b`.
+    pub(crate) const TRACE: &str = "\
+v 1 3
+M rlimit.as 13 unlimited unlimited
+I 4000 100
+s 4 /bin
+s 4 main
+s 1 a
+s 1 b
+s 8 malloc_a
+i 10 1 2
+i 20 1 3
+i 30 1 4
+i 40 1 5
+t 1 0
+t 2 1
+t 4 2
+t 3 1
+a 10 3
+a 20 4
++ 0
++ 0
++ 1
+- 0
+c 64
+R 1000
+";
+
+    pub(crate) fn parse(trace: &str) -> AccumulatedData {
+        Parser::new().parse_reader(trace.as_bytes()).unwrap()
+    }
+
+    pub(crate) fn data() -> AccumulatedData {
+        parse(TRACE)
+    }
+
+    #[test]
+    fn test_profile() {
+        let profile = Profile::new(&data()).unwrap();
+
+        assert_eq!(profile.modules.len(), 1);
+        assert_eq!(profile.sites.len(), 2);
+        assert_eq!(profile.sites[0].stack.len(), 3);
+        assert_eq!(profile.sites[0].leaf().unwrap().function, "malloc_a");
+
+        let main = profile.function("main").unwrap();
+        assert_eq!(main.inclusive.allocations, 3);
+        assert_eq!(main.inclusive.leaked, 0x30);
+        assert_eq!(main.exclusive.allocations, 0);
+
+        assert_eq!(profile.call_tree.children.len(), 1);
+        assert_eq!(profile.call_tree.children[0].children.len(), 2);
+    }
+
+    #[test]
+    fn test_exclusive_cost() {
+        let profile = Profile::new(&data()).unwrap();
+
+        let b = profile.function("b").unwrap();
+        assert_eq!(b.inclusive, b.exclusive);
+        assert_eq!(b.exclusive.leaked, 0x20);
+
+        let a = profile.function("a").unwrap();
+        assert_eq!(a.inclusive.allocations, 2);
+        assert_eq!(a.exclusive.allocations, 0);
+
+        let top = profile.top_functions(CostKind::Exclusive, Metric::Allocations, 10);
+        let names: Vec<&str> = top.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["malloc_a", "b"]);
+    }
+
+    #[test]
+    fn test_inline_attribution() {
+        // malloc_a inlined into b
+        let data = parse(&TRACE.replace("i 40 1 5\n", "i 40 1 5 1 a 4 1 b\n"));
+        let profile = |inlining| {
+            let options = ProfileOptions {
+                inlining,
+                ..Default::default()
+            };
+            Profile::with_options(&data, &options).unwrap()
+        };
+
+        let inlinee = profile(InlineAttribution::Inlinee);
+        assert_eq!(inlinee.sites[0].stack.len(), 4);
+        assert_eq!(
+            inlinee.function("malloc_a").unwrap().exclusive.allocations,
+            2
+        );
+        assert_eq!(inlinee.function("b").unwrap().exclusive.allocations, 1);
+
+        let caller = profile(InlineAttribution::Caller);
+        assert_eq!(caller.sites[0].leaf().unwrap().function, "b");
+        assert!(caller.function("malloc_a").is_none());
+        assert_eq!(caller.function("b").unwrap().exclusive.allocations, 3);
+
+        let both = profile(InlineAttribution::Both);
+        assert_eq!(both.function("malloc_a").unwrap().exclusive.allocations, 2);
+        assert_eq!(both.function("b").unwrap().exclusive.allocations, 3);
+        let b = &both.call_tree.children[0].children[0].children[0];
+        assert_eq!(b.function, "b");
+        assert_eq!(b.exclusive.allocations, 2);
+        assert_eq!(b.children[0].exclusive.allocations, 2);
+    }
+}
@@ -0,0 +1,128 @@
+//! Fast scanning of the whitespace separated hex fields of trace lines.
+//!
+//! Parsing is dominated by number conversion and field splitting, so both
+//! work on bytes: digits are decoded through a lookup table instead of the
+//! generic radix handling of `from_str_radix`, and fields are split on ASCII
+//! whitespace without UTF-8 decoding.
+
+const INVALID: u8 = 0xff;
+
+static HEX: [u8; 256] = {
+    let mut table = [INVALID; 256];
+    let mut i = 0;
+    while i < 10 {
+        table[b'0' as usize + i] = i as u8;
+        i += 1;
+    }
+    let mut i = 0;
+    while i < 6 {
+        table[b'a' as usize + i] = 10 + i as u8;
+        table[b'A' as usize + i] = 10 + i as u8;
+        i += 1;
+    }
+    table
+};
+
+/// Parses a hex number without prefix. Returns `None` for empty input,
+/// invalid digits and values that do not fit into 64 bits.
+#[inline]
+pub fn parse_hex(field: &[u8]) -> Option<u64> {
+    if field.is_empty() {
+        return None;
+    }
+    if field.len() > 16 {
+        // leading zeros beyond 16 digits
+        return match field.iter().position(|&b| b != b'0') {
+            None => Some(0),
+            Some(start) if field.len() - start <= 16 => parse_hex(&field[start..]),
+            Some(_) => None,
+        };
+    }
+
+    let mut value = 0u64;
+    for &byte in field {
+        let digit = HEX[byte as usize];
+        if digit == INVALID {
+            return None;
+        }
+        value = (value << 4) | digit as u64;
+    }
+    Some(value)
+}
+
+/// Iterator over the fields of a line separated by ASCII whitespace.
+#[derive(Debug, Clone)]
+pub struct Fields<'a> {
+    line: &'a str,
+    pos: usize,
+}
+
+impl<'a> Fields<'a> {
+    pub fn new(line: &'a str) -> Self {
+        Self { line, pos: 0 }
+    }
+
+    /// Parses the next field as a hex number.
+    #[inline]
+    pub fn next_hex(&mut self) -> Option<u64> {
+        parse_hex(self.next()?.as_bytes())
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = &'a str;
+
+    #[inline]
+    fn next(&mut self) -> Option<&'a str> {
+        let bytes = self.line.as_bytes();
+
+        let mut start = self.pos;
+        while start < bytes.len() && bytes[start].is_ascii_whitespace() {
+            start += 1;
+        }
+        if start == bytes.len() {
+            self.pos = start;
+            return None;
+        }
+
+        let mut end = start;
+        while end < bytes.len() && !bytes[end].is_ascii_whitespace() {
+            end += 1;
+        }
+        self.pos = end;
+
+        // split points are ASCII, so both ends are char boundaries
+        Some(&self.line[start..end])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::numparse::{parse_hex, Fields};
+
+    #[test]
+    fn test_parse_hex() {
+        assert_eq!(parse_hex(b"0"), Some(0));
+        assert_eq!(parse_hex(b"1f4"), Some(500));
+        assert_eq!(parse_hex(b"DEADbeef"), Some(0xdeadbeef));
+        assert_eq!(parse_hex(b"ffffffffffffffff"), Some(u64::MAX));
+        assert_eq!(parse_hex(b"0000000000000000001"), Some(1));
+        assert_eq!(parse_hex(b"00000000000000000"), Some(0));
+        assert_eq!(parse_hex(b"10000000000000000"), None);
+        assert_eq!(parse_hex(b""), None);
+        assert_eq!(parse_hex(b"12g"), None);
+        assert_eq!(parse_hex(b"-1"), None);
+    }
+
+    #[test]
+    fn test_fields() {
+        let mut fields = Fields::new("+  1f\t2 ");
+        assert_eq!(fields.next(), Some("+"));
+        assert_eq!(fields.next_hex(), Some(0x1f));
+        assert_eq!(fields.next_hex(), Some(2));
+        assert_eq!(fields.next(), None);
+
+        let fields: Vec<_> = Fields::new("s 5 a bé").collect();
+        assert_eq!(fields, ["s", "5", "a", "bé"]);
+    }
+}
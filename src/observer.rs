@@ -0,0 +1,129 @@
+//! Callbacks invoked by the [`Interpreter`](crate::interpret::Interpreter)
+//! while a program is being traced.
+
+use crate::alerts::Alert;
+use std::time::Duration;
+
+/// Live bytes of an allocation site, named after its allocating function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiveSite {
+    pub function: String,
+    pub bytes: u64,
+}
+
+/// Bytes allocated by a site so far, named after its allocating function.
+/// Sites are tracked approximately in bounded memory, see
+/// [`SpaceSaving`](crate::topk::SpaceSaving).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopSite {
+    pub function: String,
+    /// Upper bound of the allocated bytes.
+    pub allocated: u64,
+    /// Maximum overestimation of `allocated`.
+    pub error: u64,
+}
+
+/// Heap state of the traced program at a clock record.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LiveStats {
+    /// Time since the start of the traced program.
+    pub timestamp: Duration,
+    pub allocations: u64,
+    pub temporary: u64,
+    pub heap: u64,
+    pub peak_heap: u64,
+    pub rss: u64,
+    /// The [`Observer::top_sites`] sites with the most live bytes.
+    pub top_sites: Vec<LiveSite>,
+    /// The [`Observer::top_sites`] sites that allocated the most bytes.
+    pub top_allocating: Vec<TopSite>,
+}
+
+/// An allocation at or above the stack threshold of the
+/// [`Interpreter`](crate::interpret::Interpreter), see
+/// [`StackThreshold`](crate::interpret::StackThreshold).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LargeAllocation {
+    pub size: u64,
+    /// Allocating function of the site.
+    pub function: String,
+    /// Record timestamp in nanoseconds.
+    pub timestamp: u64,
+}
+
+/// A module loaded by the traced program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageEvent<'a> {
+    pub name: &'a str,
+    pub start_address: u64,
+    pub size: u64,
+    /// 1-based string index of the module in the trace.
+    pub module_idx: usize,
+}
+
+/// A node of the trace tree, written as a `t` record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEvent {
+    /// 1-based index of the new trace.
+    pub trace_idx: u64,
+    pub ip: u64,
+    /// 1-based index of the instruction pointer, the `i` record.
+    pub ip_idx: usize,
+    pub parent_idx: u64,
+}
+
+/// An allocation written to the trace. Suppressed allocations and those
+/// below the stack threshold are left out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocEvent {
+    pub ptr: u64,
+    pub size: u64,
+    pub trace_idx: u64,
+    /// Index of the allocation info, the `a` record.
+    pub allocation_idx: usize,
+    /// Record timestamp in nanoseconds.
+    pub timestamp: u64,
+}
+
+/// A free of an allocation written to the trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FreeEvent {
+    pub ptr: u64,
+    pub size: u64,
+    pub allocation_idx: usize,
+    /// Record timestamp in nanoseconds.
+    pub timestamp: u64,
+    /// Freed right after being allocated.
+    pub temporary: bool,
+}
+
+/// Receives events of a running interpretation. All methods default to doing
+/// nothing, so implementors only override what they consume.
+pub trait Observer {
+    /// Number of sites to report in [`LiveStats::top_sites`] and
+    /// [`LiveStats::top_allocating`]. Sites are only tracked when this is not
+    /// 0.
+    fn top_sites(&self) -> usize {
+        0
+    }
+
+    /// Called on every clock record of the traced program.
+    fn on_stats(&mut self, _stats: &LiveStats) {}
+
+    /// An allocation was captured with its stack. Only called when a stack
+    /// threshold is set.
+    fn on_large_allocation(&mut self, _allocation: &LargeAllocation) {}
+
+    /// An [`AlertRule`](crate::alerts::AlertRule) fired.
+    fn on_alert(&mut self, _alert: &Alert) {}
+
+    /// Called for every image record, including those of modules already
+    /// mapped.
+    fn on_image(&mut self, _image: &ImageEvent) {}
+
+    fn on_trace(&mut self, _trace: &TraceEvent) {}
+
+    fn on_alloc(&mut self, _alloc: &AllocEvent) {}
+
+    fn on_free(&mut self, _free: &FreeEvent) {}
+}
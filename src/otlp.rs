@@ -0,0 +1,283 @@
+//! Publishes live heap statistics as OTLP metrics.
+//!
+//! [`OtlpBridge`] is an [`Observer`] posting OTLP/HTTP JSON requests to the
+//! `/v1/metrics` endpoint of a collector. Requests are sent from a
+//! background thread; snapshots arriving while a request is in flight are
+//! dropped, so a slow collector never stalls the interpreter.
+
+use crate::observer::{LiveStats, Observer};
+use serde_json::{json, Value};
+use std::sync::mpsc;
+use std::sync::mpsc::{SyncSender, TrySendError};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone)]
+pub struct OtlpOptions {
+    /// Base URL of the collector, e.g. `http://localhost:4318`.
+    pub endpoint: String,
+    pub service_name: String,
+    /// Minimum time between two exports.
+    pub interval: Duration,
+    /// Number of sites exported as `memtrack.site.bytes` gauges of their
+    /// live bytes and as `memtrack.site.allocated_bytes` gauges of the bytes
+    /// they allocated so far.
+    pub top_sites: usize,
+    pub timeout: Duration,
+}
+
+impl Default for OtlpOptions {
+    fn default() -> Self {
+        Self {
+            endpoint: "http://localhost:4318".to_string(),
+            service_name: "memtrack".to_string(),
+            interval: Duration::from_secs(10),
+            top_sites: 10,
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+struct Export {
+    stats: LiveStats,
+    /// Allocations per second since the previous export.
+    allocation_rate: f64,
+    time_unix_nano: u64,
+}
+
+pub struct OtlpBridge {
+    options: OtlpOptions,
+    last: Option<LiveStats>,
+    sender: Option<SyncSender<Export>>,
+    worker: Option<JoinHandle<()>>,
+    /// Snapshots dropped because the previous export was still running.
+    dropped: u64,
+}
+
+fn unix_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+fn int_point(start: u64, time: u64, value: u64, attributes: Value) -> Value {
+    json!({
+        "startTimeUnixNano": start.to_string(),
+        "timeUnixNano": time.to_string(),
+        "asInt": value.to_string(),
+        "attributes": attributes,
+    })
+}
+
+fn gauge(name: &str, unit: &str, points: Vec<Value>) -> Value {
+    json!({ "name": name, "unit": unit, "gauge": { "dataPoints": points } })
+}
+
+impl OtlpBridge {
+    pub fn new(options: OtlpOptions) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Export>(1);
+
+        let url = format!("{}/v1/metrics", options.endpoint.trim_end_matches('/'));
+        let timeout = options.timeout;
+        let worker_options = options.clone();
+        let start_unix_nano = unix_nanos();
+
+        let worker = thread::spawn(move || {
+            let Ok(client) = reqwest::blocking::Client::builder()
+                .timeout(timeout)
+                .build()
+            else {
+                return;
+            };
+
+            for export in receiver {
+                let body = payload(&worker_options, start_unix_nano, &export);
+                // export failures must not affect the traced program
+                _ = client
+                    .post(&url)
+                    .header("Content-Type", "application/json")
+                    .body(body.to_string())
+                    .send();
+            }
+        });
+
+        Self {
+            options,
+            last: None,
+            sender: Some(sender),
+            worker: Some(worker),
+            dropped: 0,
+        }
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+fn payload(options: &OtlpOptions, start: u64, export: &Export) -> Value {
+    let stats = &export.stats;
+    let time = export.time_unix_nano;
+    let point = |value| int_point(start, time, value, json!([]));
+
+    let site_point = |function: &str, value| {
+        int_point(
+            start,
+            time,
+            value,
+            json!([{ "key": "function", "value": { "stringValue": function } }]),
+        )
+    };
+    let live_sites = stats
+        .top_sites
+        .iter()
+        .map(|site| site_point(&site.function, site.bytes))
+        .collect();
+    let allocating_sites = stats
+        .top_allocating
+        .iter()
+        .map(|site| site_point(&site.function, site.allocated))
+        .collect();
+
+    let metrics = vec![
+        gauge("memtrack.heap.bytes", "By", vec![point(stats.heap)]),
+        gauge(
+            "memtrack.heap.peak_bytes",
+            "By",
+            vec![point(stats.peak_heap)],
+        ),
+        gauge("memtrack.rss.bytes", "By", vec![point(stats.rss)]),
+        json!({
+            "name": "memtrack.allocations",
+            "unit": "{allocation}",
+            "sum": {
+                "aggregationTemporality": 2,
+                "isMonotonic": true,
+                "dataPoints": [point(stats.allocations)],
+            },
+        }),
+        json!({
+            "name": "memtrack.temporary_allocations",
+            "unit": "{allocation}",
+            "sum": {
+                "aggregationTemporality": 2,
+                "isMonotonic": true,
+                "dataPoints": [point(stats.temporary)],
+            },
+        }),
+        json!({
+            "name": "memtrack.allocation.rate",
+            "unit": "{allocation}/s",
+            "gauge": { "dataPoints": [{
+                "timeUnixNano": time.to_string(),
+                "asDouble": export.allocation_rate,
+            }] },
+        }),
+        gauge("memtrack.site.bytes", "By", live_sites),
+        gauge("memtrack.site.allocated_bytes", "By", allocating_sites),
+    ];
+
+    json!({
+        "resourceMetrics": [{
+            "resource": { "attributes": [
+                { "key": "service.name", "value": { "stringValue": options.service_name } },
+            ] },
+            "scopeMetrics": [{
+                "scope": { "name": "memtrack-utils", "version": env!("CARGO_PKG_VERSION") },
+                "metrics": metrics,
+            }],
+        }],
+    })
+}
+
+impl Observer for OtlpBridge {
+    fn top_sites(&self) -> usize {
+        self.options.top_sites
+    }
+
+    fn on_stats(&mut self, stats: &LiveStats) {
+        let allocation_rate = match &self.last {
+            Some(last)
+                if stats.timestamp.saturating_sub(last.timestamp)
+                    < self.options.interval.max(Duration::from_millis(1)) =>
+            {
+                return;
+            }
+            Some(last) => {
+                let elapsed = (stats.timestamp - last.timestamp).as_secs_f64();
+                (stats.allocations - last.allocations) as f64 / elapsed
+            }
+            None => 0.0,
+        };
+        self.last = Some(stats.clone());
+
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        let export = Export {
+            stats: stats.clone(),
+            allocation_rate,
+            time_unix_nano: unix_nanos(),
+        };
+        if let Err(TrySendError::Full(_)) = sender.try_send(export) {
+            self.dropped += 1;
+        }
+    }
+}
+
+impl Drop for OtlpBridge {
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(worker) = self.worker.take() {
+            _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::observer::{LiveSite, LiveStats, TopSite};
+    use crate::otlp::{payload, Export, OtlpOptions};
+
+    #[test]
+    fn test_payload() {
+        let export = Export {
+            stats: LiveStats {
+                heap: 4096,
+                allocations: 10,
+                top_sites: vec![LiveSite {
+                    function: "app::load".into(),
+                    bytes: 1024,
+                }],
+                top_allocating: vec![TopSite {
+                    function: "app::parse".into(),
+                    allocated: 8192,
+                    error: 16,
+                }],
+                ..Default::default()
+            },
+            allocation_rate: 2.5,
+            time_unix_nano: 2000,
+        };
+
+        let body = payload(&OtlpOptions::default(), 1000, &export);
+        let metrics = &body["resourceMetrics"][0]["scopeMetrics"][0]["metrics"];
+
+        assert_eq!(metrics[0]["name"], "memtrack.heap.bytes");
+        assert_eq!(metrics[0]["gauge"]["dataPoints"][0]["asInt"], "4096");
+        assert_eq!(metrics[3]["sum"]["dataPoints"][0]["asInt"], "10");
+        assert_eq!(metrics[5]["gauge"]["dataPoints"][0]["asDouble"], 2.5);
+
+        assert_eq!(metrics[6]["name"], "memtrack.site.bytes");
+        let site = &metrics[6]["gauge"]["dataPoints"][0];
+        assert_eq!(site["attributes"][0]["value"]["stringValue"], "app::load");
+        assert_eq!(site["asInt"], "1024");
+
+        assert_eq!(metrics[7]["name"], "memtrack.site.allocated_bytes");
+        let site = &metrics[7]["gauge"]["dataPoints"][0];
+        assert_eq!(site["attributes"][0]["value"]["stringValue"], "app::parse");
+        assert_eq!(site["asInt"], "8192");
+    }
+}
@@ -1,8 +1,228 @@
+use crate::parser;
+use crate::parser::{AccumulatedData, StreamParser, TraceChecksum};
+use std::borrow::Cow;
 use std::fs::File;
+use std::io;
 use std::io::{BufWriter, Write};
+use std::mem;
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Size of the chunks handed to the write-behind thread.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Counters of the write-behind queue.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    /// Chunks handed to the writer thread.
+    pub chunks: u64,
+    /// Times the queue was full and recording had to wait for the disk.
+    pub overflows: u64,
+    /// Total time spent waiting for the full queue.
+    pub stalled: Duration,
+}
+
+/// Parser of the written trace, `Err` once a chunk failed to parse.
+type Summary = Option<Result<StreamParser, parser::Error>>;
+
+/// Feeds `buf` to the summary parser, if any.
+fn feed_summary(summary: &mut Summary, buf: &[u8]) {
+    if let Some(Ok(parser)) = summary
+        && let Err(e) = parser.feed(buf)
+    {
+        // the trace itself stays intact, only the summary is lost
+        *summary = Some(Err(e));
+    }
+}
+
+fn finish_summary(summary: Summary) -> Option<Result<AccumulatedData, parser::Error>> {
+    Some(summary?.and_then(StreamParser::finish))
+}
+
+enum Command {
+    Write(Vec<u8>),
+    Flush(SyncSender<io::Result<()>>),
+    /// Starts parsing the chunks written from now on.
+    StartSummary,
+    TakeSummary(SyncSender<Option<Result<AccumulatedData, parser::Error>>>),
+}
+
+/// Writes on a dedicated thread fed by a bounded queue of chunks, so flush
+/// stalls of the disk do not block the caller until the queue is full. The
+/// summary of the trace is parsed on the same thread.
+struct WriteBehind {
+    chunk: Vec<u8>,
+    sender: Option<SyncSender<Command>>,
+    worker: Option<JoinHandle<io::Result<()>>>,
+    stats: QueueStats,
+}
+
+impl WriteBehind {
+    fn new(mut out: BufWriter<File>, capacity: usize, mut summary: Summary) -> Self {
+        let (sender, receiver): (_, Receiver<Command>) = mpsc::sync_channel(capacity.max(1));
+
+        let worker = thread::spawn(move || {
+            let mut result = Ok(());
+            for command in receiver {
+                match command {
+                    Command::Write(chunk) => {
+                        if result.is_ok() {
+                            result = out.write_all(&chunk);
+                        }
+                        feed_summary(&mut summary, &chunk);
+                    }
+                    Command::StartSummary => summary = Some(Ok(StreamParser::new())),
+                    Command::TakeSummary(done) => {
+                        _ = done.send(finish_summary(summary.take()));
+                    }
+                    Command::Flush(done) => {
+                        let flushed = match &result {
+                            Ok(()) => out.flush(),
+                            Err(e) => Err(io::Error::new(e.kind(), e.to_string())),
+                        };
+                        _ = done.send(flushed);
+                    }
+                }
+            }
+            result.and_then(|_| out.flush())
+        });
+
+        Self {
+            chunk: Vec::with_capacity(CHUNK_SIZE),
+            sender: Some(sender),
+            worker: Some(worker),
+            stats: QueueStats::default(),
+        }
+    }
+
+    fn send(&mut self, command: Command) -> io::Result<()> {
+        let closed = || io::Error::new(io::ErrorKind::BrokenPipe, "output writer stopped");
+        let sender = self.sender.as_ref().ok_or_else(closed)?;
+
+        match sender.try_send(command) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(command)) => {
+                self.stats.overflows += 1;
+                let start = Instant::now();
+                let sent = sender.send(command).map_err(|_| closed());
+                self.stats.stalled += start.elapsed();
+                sent
+            }
+            Err(TrySendError::Disconnected(_)) => Err(closed()),
+        }
+    }
+
+    fn send_chunk(&mut self) -> io::Result<()> {
+        if self.chunk.is_empty() {
+            return Ok(());
+        }
+        let chunk = mem::replace(&mut self.chunk, Vec::with_capacity(CHUNK_SIZE));
+        self.stats.chunks += 1;
+        self.send(Command::Write(chunk))
+    }
+
+    fn take_summary(&mut self) -> Option<Result<AccumulatedData, parser::Error>> {
+        self.send_chunk().ok()?;
+
+        let (done, result) = mpsc::sync_channel(1);
+        self.send(Command::TakeSummary(done)).ok()?;
+        result.recv().ok()?
+    }
+}
+
+impl Write for WriteBehind {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.chunk.extend_from_slice(buf);
+        if self.chunk.len() >= CHUNK_SIZE {
+            self.send_chunk()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_chunk()?;
+
+        let (done, result) = mpsc::sync_channel(1);
+        self.send(Command::Flush(done))?;
+        result
+            .recv()
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "output writer stopped"))?
+    }
+}
+
+impl Drop for WriteBehind {
+    fn drop(&mut self) {
+        _ = self.send_chunk();
+        self.sender.take();
+        if let Some(worker) = self.worker.take() {
+            _ = worker.join();
+        }
+    }
+}
+
+enum Sink {
+    Direct(BufWriter<File>),
+    Queued(WriteBehind),
+    /// Placeholder while switching between the other two.
+    Closed,
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Sink::Direct(out) => out.write(buf),
+            Sink::Queued(out) => out.write(buf),
+            Sink::Closed => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::Direct(out) => out.flush(),
+            Sink::Queued(out) => out.flush(),
+            Sink::Closed => Ok(()),
+        }
+    }
+}
+
+/// Passes the written trace to the sink and, if enabled, to a parser
+/// building the summary of the run. With a queued sink the parser runs on
+/// the writer thread instead.
+struct Tee {
+    sink: Sink,
+    summary: Summary,
+    /// Lines written since the last `E` record.
+    checksum: TraceChecksum,
+}
+
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sink.write_all(buf)?;
+        self.checksum.update(buf);
+        feed_summary(&mut self.summary, buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+}
+
+/// Replaces line breaks, which would end a record early. String values are
+/// written with their byte length, so any other content is kept as is.
+fn single_line(value: &str) -> Cow<'_, str> {
+    if value.contains(['\n', '\r']) {
+        Cow::Owned(value.replace(['\n', '\r'], " "))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
 
 pub struct Output {
-    buffer: BufWriter<File>,
+    buffer: Tee,
 }
 
 pub enum Frame {
@@ -19,7 +239,58 @@ pub enum Frame {
 impl Output {
     pub fn new(out: File) -> Self {
         Self {
-            buffer: BufWriter::with_capacity(4096, out),
+            buffer: Tee {
+                sink: Sink::Direct(BufWriter::with_capacity(4096, out)),
+                summary: None,
+                checksum: TraceChecksum::default(),
+            },
+        }
+    }
+
+    /// Parses the trace while it is written, see [`Output::take_summary`].
+    /// Must be enabled before anything is written. With
+    /// [write-behind](Output::set_write_behind) the parser runs on the
+    /// writer thread.
+    pub fn set_summary(&mut self) -> io::Result<()> {
+        match &mut self.buffer.sink {
+            Sink::Queued(out) => {
+                out.send_chunk()?;
+                out.send(Command::StartSummary)
+            }
+            _ => {
+                self.buffer.summary = Some(Ok(StreamParser::new()));
+                Ok(())
+            }
+        }
+    }
+
+    /// Data of the trace written so far, if [`Output::set_summary`] was
+    /// called.
+    pub fn take_summary(&mut self) -> Option<Result<AccumulatedData, parser::Error>> {
+        match &mut self.buffer.sink {
+            Sink::Queued(out) => out.take_summary(),
+            _ => finish_summary(self.buffer.summary.take()),
+        }
+    }
+
+    /// Moves writing to a dedicated thread fed by a queue of at most
+    /// `capacity` chunks.
+    pub fn set_write_behind(&mut self, capacity: usize) -> io::Result<()> {
+        self.buffer.flush()?;
+        self.buffer.sink = match mem::replace(&mut self.buffer.sink, Sink::Closed) {
+            Sink::Direct(out) => {
+                let summary = self.buffer.summary.take();
+                Sink::Queued(WriteBehind::new(out, capacity, summary))
+            }
+            sink => sink,
+        };
+        Ok(())
+    }
+
+    pub fn queue_stats(&self) -> Option<QueueStats> {
+        match &self.buffer.sink {
+            Sink::Queued(out) => Some(out.stats),
+            _ => None,
         }
     }
 
@@ -27,17 +298,17 @@ impl Output {
         writeln!(self.buffer, "v {:x} {:x}", version, file_version)
     }
 
-    pub fn write_page_info(&mut self, page_size: usize, pages: u64) -> std::io::Result<()> {
+    pub fn write_page_info(&mut self, page_size: u64, pages: u64) -> std::io::Result<()> {
         writeln!(self.buffer, "I {:x} {:x}", page_size, pages)
     }
 
     pub fn write_exec(&mut self, command: &str) -> std::io::Result<()> {
-        writeln!(self.buffer, "X {}", command)
+        writeln!(self.buffer, "X {}", single_line(command))
     }
 
     pub fn write_string(&mut self, value: &str) -> std::io::Result<()> {
-        let size = value.len();
-        writeln!(self.buffer, "s {:x} {}", size, value)
+        let value = single_line(value);
+        writeln!(self.buffer, "s {:x} {}", value.len(), value)
     }
 
     pub fn write_instruction(
@@ -73,31 +344,171 @@ impl Output {
         writeln!(self.buffer, "a {:x} {:x}", size, idx)
     }
 
-    pub fn write_alloc(&mut self, idx: usize) -> std::io::Result<()> {
-        writeln!(self.buffer, "+ {:x}", idx)
+    pub fn write_alloc(&mut self, idx: usize, timestamp: u64) -> std::io::Result<()> {
+        writeln!(self.buffer, "+ {:x} {:x}", idx, timestamp)
     }
 
-    pub fn write_free(&mut self, idx: usize) -> std::io::Result<()> {
-        writeln!(self.buffer, "- {:x}", idx)
+    /// `owner_idx` is the string index of the component the allocation was
+    /// transferred to, 0 if none.
+    pub fn write_free(
+        &mut self,
+        idx: usize,
+        timestamp: u64,
+        owner_idx: usize,
+    ) -> std::io::Result<()> {
+        if owner_idx == 0 {
+            writeln!(self.buffer, "- {:x} {:x}", idx, timestamp)
+        } else {
+            writeln!(self.buffer, "- {:x} {:x} {:x}", idx, timestamp, owner_idx)
+        }
+    }
+
+    /// `previous_idx` is the string index of the component the allocation
+    /// is taken from, 0 if none.
+    pub fn write_transfer(
+        &mut self,
+        idx: usize,
+        owner_idx: usize,
+        previous_idx: usize,
+    ) -> std::io::Result<()> {
+        if previous_idx == 0 {
+            writeln!(self.buffer, "o {:x} {:x}", idx, owner_idx)
+        } else {
+            writeln!(
+                self.buffer,
+                "o {:x} {:x} {:x}",
+                idx, owner_idx, previous_idx
+            )
+        }
     }
 
     pub fn write_duration(&mut self, duration: u128) -> std::io::Result<()> {
         writeln!(self.buffer, "c {:x}", duration)
     }
 
-    pub fn write_rss(&mut self, rss: usize) -> std::io::Result<()> {
-        writeln!(self.buffer, "R {:x}", rss)
+    pub fn write_rss(&mut self, rss: u64, timestamp: u64) -> std::io::Result<()> {
+        writeln!(self.buffer, "R {:x} {:x}", rss, timestamp)
+    }
+
+    pub fn write_reachable(&mut self, idx: usize) -> std::io::Result<()> {
+        writeln!(self.buffer, "r {:x}", idx)
+    }
+
+    pub fn write_pool(&mut self, name: &str, capacity: u64) -> std::io::Result<()> {
+        let name = single_line(name);
+        writeln!(self.buffer, "P {:x} {:x} {}", capacity, name.len(), name)
+    }
+
+    pub fn write_pool_alloc(&mut self, pool_idx: usize, size: u64) -> std::io::Result<()> {
+        writeln!(self.buffer, "p {:x} {:x}", pool_idx, size)
+    }
+
+    pub fn write_pool_free(&mut self, pool_idx: usize, size: u64) -> std::io::Result<()> {
+        writeln!(self.buffer, "q {:x} {:x}", pool_idx, size)
+    }
+
+    pub fn write_pool_destroy(&mut self, pool_idx: usize) -> std::io::Result<()> {
+        writeln!(self.buffer, "D {:x}", pool_idx)
+    }
+
+    pub fn write_alloc_failed(
+        &mut self,
+        size: u64,
+        trace_idx: u64,
+        timestamp: u64,
+    ) -> std::io::Result<()> {
+        writeln!(self.buffer, "f {:x} {:x} {:x}", size, trace_idx, timestamp)
+    }
+
+    pub fn write_metadata(&mut self, key: &str, value: &str) -> std::io::Result<()> {
+        let value = single_line(value);
+        writeln!(self.buffer, "M {} {:x} {}", key, value.len(), value)
+    }
+
+    pub fn write_marker(&mut self, label: &str) -> std::io::Result<()> {
+        let label = single_line(label);
+        writeln!(self.buffer, "m {:x} {}", label.len(), label)
+    }
+
+    pub fn write_transaction_begin(&mut self, label: &str, timestamp: u64) -> std::io::Result<()> {
+        let label = single_line(label);
+        writeln!(self.buffer, "b {:x} {:x} {}", timestamp, label.len(), label)
+    }
+
+    pub fn write_transaction_end(&mut self, label: &str, timestamp: u64) -> std::io::Result<()> {
+        let label = single_line(label);
+        writeln!(self.buffer, "e {:x} {:x} {}", timestamp, label.len(), label)
     }
 
     pub fn write(&mut self, value: &str) -> std::io::Result<()> {
         writeln!(self.buffer, "{}", value)
     }
 
+    /// Finalizes the lines written so far with an `E` record.
+    pub fn write_finished(&mut self) -> std::io::Result<()> {
+        let checksum = self.buffer.checksum;
+        checksum.write_record(&mut self.buffer)?;
+        self.buffer.checksum = TraceChecksum::default();
+        Ok(())
+    }
+
     pub fn write_comment(&mut self, comment: &str) -> std::io::Result<()> {
-        writeln!(self.buffer, "# {}", comment)
+        writeln!(self.buffer, "# {}", single_line(comment))
     }
 
     pub fn flush(&mut self) -> std::io::Result<()> {
         self.buffer.flush()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::model::tests::TRACE;
+    use crate::output::Output;
+    use std::fs::File;
+
+    #[test]
+    fn test_write_behind() {
+        let path = std::env::temp_dir().join(format!("memtrack-output-{}", std::process::id()));
+
+        let mut output = Output::new(File::create(&path).unwrap());
+        output.write_version(1, 3).unwrap();
+        output.set_write_behind(1).unwrap();
+        for idx in 0..10000 {
+            output.write_alloc(idx, 0).unwrap();
+        }
+        output.flush().unwrap();
+
+        let stats = output.queue_stats().unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        _ = std::fs::remove_file(&path);
+
+        assert!(stats.chunks > 1);
+        assert!(content.starts_with("v 1 3\n+ 0 0\n"));
+        assert_eq!(content.lines().count(), 10001);
+    }
+
+    #[test]
+    fn test_write_behind_summary() {
+        let path = std::env::temp_dir().join(format!("memtrack-summary-{}", std::process::id()));
+
+        for summary_first in [true, false] {
+            let mut output = Output::new(File::create(&path).unwrap());
+            if summary_first {
+                output.set_summary().unwrap();
+                output.set_write_behind(1).unwrap();
+            } else {
+                output.set_write_behind(1).unwrap();
+                output.set_summary().unwrap();
+            }
+            let mut trace = TRACE.as_bytes();
+            std::io::copy(&mut trace, &mut output.buffer).unwrap();
+
+            let data = output.take_summary().unwrap().unwrap();
+            assert_eq!(data.total.allocations, 3);
+            assert_eq!(data.total.leaked, 0x30);
+            assert!(output.take_summary().is_none());
+        }
+        _ = std::fs::remove_file(&path);
+    }
+}
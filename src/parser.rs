@@ -1,8 +1,19 @@
+use crate::annotations::Annotations;
+#[cfg(feature = "wire")]
+use crate::format::binary::BinaryReader;
+use crate::format::{BINARY_MAGIC, FILE_VERSION, SCHEMA};
+use crate::model::{Cost, Metric};
+use crate::numparse::{parse_hex, Fields};
 use indexmap::map::Entry;
 use indexmap::IndexMap;
-use std::fs::OpenOptions;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "mmap")]
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
 use std::io;
-use std::io::BufRead;
+use std::io::{BufRead, Read};
 use std::path::Path;
 use std::time::Duration;
 use thiserror::Error;
@@ -13,10 +24,257 @@ pub enum Error {
     Io(#[from] io::Error),
     #[error("Invalid format")]
     InvalidFormat,
+    /// A line of the trace that could not be parsed, with its 1-based
+    /// number, tag and text, cut to [`SNIPPET_LEN`] bytes.
+    #[error("line {line}: invalid `{tag}` record: {text}")]
+    InvalidRecord {
+        line: u64,
+        tag: String,
+        text: String,
+    },
+    /// A record with a tag missing from the [`SCHEMA`], raised under
+    /// [`ParsePolicy::Strict`].
+    #[error("line {line}: unknown `{tag}` record")]
+    UnknownRecord { line: u64, tag: String },
+    /// A trace written in a newer format than [`FILE_VERSION`].
+    #[error("unsupported file version {0}, the newest supported is {FILE_VERSION}")]
+    UnsupportedVersion(u16),
     #[error("Internal {0}")]
     Internal(String),
 }
 
+/// Length up to which the text of a line is kept in
+/// [`Error::InvalidRecord`].
+pub const SNIPPET_LEN: usize = 200;
+
+/// How a [`Parser`] treats the lines it cannot use.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParsePolicy {
+    /// Fails on malformed lines and on traces newer than [`FILE_VERSION`],
+    /// ignoring records with unknown tags.
+    #[default]
+    Standard,
+    /// Fails on malformed lines, newer traces and records with unknown tags.
+    Strict,
+    /// Skips malformed lines and records with unknown tags, reading what it
+    /// knows of newer traces.
+    Lenient,
+    /// Skips like [`ParsePolicy::Lenient`], keeping a [`ParseWarning`] in
+    /// [`AccumulatedData::warnings`] for every skipped line.
+    CollectWarnings,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParseWarningKind {
+    UnknownRecord,
+    InvalidRecord,
+    UnsupportedVersion,
+}
+
+/// A line skipped under [`ParsePolicy::CollectWarnings`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ParseWarning {
+    /// 1-based line number.
+    pub line: u64,
+    pub kind: ParseWarningKind,
+    pub tag: String,
+    /// Text of the line, cut to [`SNIPPET_LEN`] bytes.
+    pub text: String,
+}
+
+impl ParsePolicy {
+    /// Whether lines that cannot be used are skipped instead of failing the
+    /// parse.
+    fn skips(self) -> bool {
+        matches!(self, Self::Lenient | Self::CollectWarnings)
+    }
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            ParseWarningKind::UnknownRecord => "unknown",
+            ParseWarningKind::InvalidRecord => "invalid",
+            ParseWarningKind::UnsupportedVersion => "unsupported",
+        };
+        write!(
+            f,
+            "line {}: skipped {} `{}` record: {}",
+            self.line, kind, self.tag, self.text
+        )
+    }
+}
+
+/// Metadata key of the offset in nanoseconds from the clock of the record
+/// timestamps to the realtime clock of the traced host.
+pub const CLOCK_OFFSET_KEY: &str = "clock.realtime_offset";
+
+/// Metadata key of the signal that killed the traced program. The leaked
+/// allocations of such a trace are the ones live at the crash.
+pub const CRASH_SIGNAL_KEY: &str = "crash.signal";
+
+/// Metadata key of the name under which a trace can be resumed, see
+/// [`Interpreter::resume`](crate::interpret::Interpreter::resume).
+pub const SESSION_ID_KEY: &str = "session.id";
+
+/// Metadata key of the number of records lost between the injected library
+/// and the interpreter, written when frames of a multi-threaded writer went
+/// missing or arrived twice.
+pub const STREAM_LOST_KEY: &str = "stream.lost";
+
+/// Metadata key of the size below which allocations were counted per size
+/// class instead of recorded with their stack.
+pub const STACK_THRESHOLD_KEY: &str = "capture.stack_threshold";
+
+/// Metadata key of the number of roots (globals and thread-local storage
+/// blocks) scanned at exit. Only traces with it carry `r` records telling
+/// reachable leaks from unreachable ones.
+pub const ROOT_SCAN_KEY: &str = "reachability.roots";
+
+/// Metadata key of the frees that did not match a live allocation, a JSON
+/// [`FreeMismatches`].
+pub const FREE_MISMATCHES_KEY: &str = "free_mismatches";
+
+/// Metadata key of the counted small allocations, a JSON list of
+/// [`SmallAllocations`].
+pub const SMALL_ALLOCATIONS_KEY: &str = "small_allocations";
+
+/// Metadata key of the probability with which allocations were recorded,
+/// written each time the rate is set. The last rate set applies to the
+/// whole trace, see [`Sampling`](crate::analysis::sampling::Sampling).
+pub const SAMPLING_RATE_KEY: &str = "capture.sampling_rate";
+
+/// Metadata key of the capture limit that stopped recording while the
+/// program kept running, `duration` or `events`.
+pub const CAPTURE_STOPPED_KEY: &str = "capture.stopped";
+
+fn hex<T: TryFrom<u64>>(fields: &mut Fields) -> Result<T, Error> {
+    fields
+        .next_hex()
+        .and_then(|value| T::try_from(value).ok())
+        .ok_or(Error::InvalidFormat)
+}
+
+/// The last `len` bytes of a line, the value of a length-prefixed record.
+fn tail(line: &[u8], len: usize) -> Result<String, Error> {
+    let start = line.len().checked_sub(len).ok_or(Error::InvalidFormat)?;
+    Ok(String::from_utf8_lossy(&line[start..]).into_owned())
+}
+
+/// Fast path of [`Parser::parse_summary`]. Works on the raw bytes and only
+/// looks at the records affecting the aggregates.
+fn summarize(mut reader: impl BufRead) -> Result<Summary, Error> {
+    let mut summary = Summary::default();
+    // allocation infos as (size, index into `summary.traces`, live count)
+    let mut infos: Vec<(u64, usize, u64)> = Vec::new();
+    let mut last_ptr = 0;
+    let mut rss = 0;
+
+    let mut line = Vec::new();
+    while read_line(&mut reader, &mut line)? {
+        let mut fields = line
+            .split(|b| b.is_ascii_whitespace())
+            .filter(|field| !field.is_empty());
+        let Some(&[record]) = fields.next() else {
+            continue;
+        };
+        if !matches!(record, b'a' | b'+' | b'-' | b'c' | b'R' | b'I') {
+            continue;
+        }
+        let mut next = || {
+            fields
+                .next()
+                .and_then(parse_hex)
+                .ok_or(Error::InvalidFormat)
+        };
+
+        match record {
+            b'a' => {
+                let size = next()?;
+                let trace_idx = next()?;
+                let entry = summary.traces.entry(trace_idx);
+                let idx = entry.index();
+                entry.or_default();
+                infos.push((size, idx, 0));
+            }
+            b'+' => {
+                let Some((size, idx, live)) = infos.get_mut(next()? as usize) else {
+                    continue;
+                };
+                let (size, idx) = (*size, *idx);
+                *live += 1;
+                last_ptr = idx;
+                let data = &mut summary.traces[idx];
+                data.leaked += size;
+                data.peak = data.peak.max(data.leaked);
+                data.allocations += 1;
+                data.peak_live = data.peak_live.max(data.live());
+
+                let total = &mut summary.total;
+                total.leaked += size;
+                total.peak = total.peak.max(total.leaked);
+                total.allocations += 1;
+                total.peak_live = total.peak_live.max(total.live());
+            }
+            b'-' => {
+                // unmatched frees are skipped like in `Parser::apply_line`
+                let Some((size, idx, live)) = infos.get_mut(next()? as usize) else {
+                    continue;
+                };
+                if *live == 0 {
+                    continue;
+                }
+                let (size, idx) = (*size, *idx);
+                *live -= 1;
+                let temporary = last_ptr == idx;
+                last_ptr = 0;
+
+                for data in [&mut summary.traces[idx], &mut summary.total] {
+                    data.leaked -= size;
+                    data.frees += 1;
+                    data.freed += size;
+                    if temporary {
+                        data.temporary += 1;
+                    }
+                }
+            }
+            b'c' => {
+                summary.duration = Duration::from_millis(next()?);
+                summary.timeline.push(TimelinePoint {
+                    timestamp: summary.duration,
+                    leaked: summary.total.leaked,
+                    allocations: summary.total.allocations,
+                    rss,
+                });
+            }
+            b'R' => {
+                rss = next()?;
+                summary.peak_rss = summary.peak_rss.max(rss);
+            }
+            _ => {
+                summary.page_size = next()?;
+                summary.pages = next()?;
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Reads a line into `buf` without its newline. Returns `false` at the end
+/// of the input.
+pub(crate) fn read_line(reader: &mut impl BufRead, buf: &mut Vec<u8>) -> io::Result<bool> {
+    buf.clear();
+    if reader.read_until(b'\n', buf)? == 0 {
+        return Ok(false);
+    }
+    if buf.last() == Some(&b'\n') {
+        buf.pop();
+    }
+    Ok(true)
+}
+
 #[derive(Debug)]
 pub struct Trace {
     pub ip_idx: u64,
@@ -31,7 +289,7 @@ pub struct InstructionPointer {
     pub inlined: Vec<Frame>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Frame {
     Single {
         function_idx: usize,
@@ -43,18 +301,44 @@ pub enum Frame {
     },
 }
 
+impl Frame {
+    pub fn function_idx(&self) -> usize {
+        match *self {
+            Frame::Single { function_idx } | Frame::Multiple { function_idx, .. } => function_idx,
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct AllocationData {
     pub allocations: u64,
+    pub frees: u64,
+    /// Bytes released by the `frees`.
+    pub freed: u64,
     pub temporary: u64,
     pub leaked: u64,
     pub peak: u64,
+    /// Largest number of allocations live at the same time.
+    pub peak_live: u64,
+    /// Part of `leaked` still referenced from a root at exit.
+    pub reachable: u64,
+}
+
+impl AllocationData {
+    /// Number of allocations currently live.
+    pub fn live(&self) -> u64 {
+        self.allocations - self.frees
+    }
 }
 
 #[derive(Debug)]
 pub struct AllocationInfo {
     pub allocation_idx: u64,
     pub size: u64,
+    /// Number of `+` records referring to this info.
+    pub allocations: u64,
+    /// Number of `-` records referring to this info, at most `allocations`.
+    pub frees: u64,
 }
 
 impl AllocationInfo {
@@ -62,14 +346,54 @@ impl AllocationInfo {
         Self {
             allocation_idx,
             size,
+            allocations: 0,
+            frees: 0,
         }
     }
 }
 
+/// Number of buckets of a [`SizeHistogram`].
+pub const SIZE_BUCKETS: usize = 32;
+
+/// Allocations by power-of-two size: bucket `i` counts the sizes above
+/// `2^(i-1)` up to `2^i` bytes, the last bucket all larger ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct SizeHistogram {
+    pub buckets: [u64; SIZE_BUCKETS],
+}
+
+impl SizeHistogram {
+    pub fn bucket(size: u64) -> usize {
+        let bucket = u64::BITS - size.saturating_sub(1).leading_zeros();
+        (bucket as usize).min(SIZE_BUCKETS - 1)
+    }
+
+    /// Largest size counted in `bucket`, `None` for the last one.
+    pub fn upper_bound(bucket: usize) -> Option<u64> {
+        (bucket < SIZE_BUCKETS - 1).then(|| 1 << bucket)
+    }
+
+    pub fn add(&mut self, size: u64) {
+        self.buckets[Self::bucket(size)] += 1;
+    }
+
+    pub fn merge(&mut self, other: &SizeHistogram) {
+        for (count, other) in self.buckets.iter_mut().zip(other.buckets) {
+            *count += other;
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+}
+
 #[derive(Debug)]
 pub struct Allocation {
     pub trace_idx: u64,
     pub data: AllocationData,
+    /// Sizes of the allocations, see [`Parser::with_size_histograms`].
+    pub sizes: Option<Box<SizeHistogram>>,
 }
 
 impl Allocation {
@@ -77,12 +401,271 @@ impl Allocation {
         Self {
             trace_idx,
             data: Default::default(),
+            sizes: None,
+        }
+    }
+}
+
+/// A point in the trace labeled by the interpreter, e.g. a rules reload.
+#[derive(Debug, Clone)]
+pub struct Marker {
+    pub label: String,
+    pub timestamp: Duration,
+}
+
+/// When the heap reached the peak of [`AccumulatedData::total`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PeakInfo {
+    /// Number of `+` and `-` records up to the one reaching the peak.
+    pub event: u64,
+    /// Time of the last `c` record before the peak.
+    pub time: Duration,
+    /// Clock timestamp of the `+` record, `None` for traces without
+    /// timestamps.
+    pub timestamp: Option<u64>,
+    /// Allocations live at the peak, including the one reaching it.
+    pub live_allocations: u64,
+}
+
+/// Heap state sampled at a `c` timestamp record.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimelinePoint {
+    pub timestamp: Duration,
+    pub leaked: u64,
+    pub allocations: u64,
+    pub rss: u64,
+}
+
+/// Allocations below the [`STACK_THRESHOLD_KEY`] of one size class made
+/// from one module.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SmallAllocations {
+    pub size_class: u64,
+    /// Module of the allocating frame, empty if it is unknown.
+    pub module: String,
+    pub allocations: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FreeMismatchKind {
+    /// Free of a pointer that was never allocated while tracing.
+    Untracked,
+    /// Free of a pointer already freed and not allocated again since.
+    DoubleFree,
+}
+
+impl fmt::Display for FreeMismatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FreeMismatchKind::Untracked => write!(f, "free of untracked pointer"),
+            FreeMismatchKind::DoubleFree => write!(f, "double free"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FreeMismatch {
+    pub kind: FreeMismatchKind,
+    pub ptr: u64,
+    pub timestamp: u64,
+    /// Functions of the stack that allocated the block, innermost first.
+    /// Frees carry no stack, so it is empty for untracked pointers.
+    pub stack: Vec<String>,
+}
+
+/// Frees the interpreter could not match with a live allocation, which point
+/// at blind spots of the tracer, e.g. memory allocated before the library
+/// was loaded, or at bugs of the program.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FreeMismatches {
+    pub untracked: u64,
+    /// Frees of a pointer already freed. Only the most recently freed
+    /// pointers are remembered, double frees of older ones are counted as
+    /// untracked.
+    pub double_frees: u64,
+    /// The first mismatches of the run.
+    pub examples: Vec<FreeMismatch>,
+    /// Whether the mismatches are reported as [`Anomaly`]s when parsed.
+    pub strict: bool,
+}
+
+impl FreeMismatches {
+    pub fn is_empty(&self) -> bool {
+        self.untracked == 0 && self.double_frees == 0
+    }
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Number and FNV-1a hash of the lines of a trace, newlines included, as
+/// recorded by its `E` record on clean shutdown. Each `E` record covers the
+/// lines since the previous one, so runs appended to a trace finalize their
+/// own part.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceChecksum {
+    pub records: u64,
+    pub hash: u64,
+}
+
+impl Default for TraceChecksum {
+    fn default() -> Self {
+        Self {
+            records: 0,
+            hash: FNV_OFFSET,
+        }
+    }
+}
+
+impl TraceChecksum {
+    pub fn update(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            if b == b'\n' {
+                self.records += 1;
+            }
+            self.hash = (self.hash ^ b as u64).wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    /// Writes the `E` record finalizing the lines hashed so far.
+    pub fn write_record(&self, mut out: impl io::Write) -> io::Result<()> {
+        writeln!(out, "E {:x} {:x}", self.records, self.hash)
+    }
+}
+
+/// First and last record timestamp of a trace in nanoseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockRange {
+    pub first: u64,
+    pub last: u64,
+}
+
+/// Memory cost of the events between a `b` and the matching `e` record.
+/// Transactions may nest or overlap: every event counts towards all
+/// transactions open at the time, so an outer transaction includes the costs
+/// of the inner ones.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transaction {
+    pub label: String,
+    /// Record timestamps of the begin and end markers in nanoseconds.
+    pub start: u64,
+    pub end: u64,
+    pub allocations: u64,
+    pub allocated: u64,
+    pub frees: u64,
+    pub freed: u64,
+    pub temporary: u64,
+    /// Highest value of allocated minus freed bytes during the transaction.
+    pub peak: u64,
+}
+
+/// Pool or arena declared by the program with a `P` record.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Pool {
+    pub name: String,
+    /// Bytes the pool serves its allocations from.
+    pub capacity: u64,
+    pub allocations: u64,
+    pub frees: u64,
+    /// Bytes of the live allocations of the pool.
+    pub requested: u64,
+    pub peak_requested: u64,
+    pub destroyed: bool,
+}
+
+/// Allocations of a trace the allocator failed to serve, from its `f`
+/// records.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct AllocationFailures {
+    pub count: u64,
+    /// Bytes requested by all failed allocations.
+    pub bytes: u64,
+    pub largest: u64,
+    /// Timestamp of the first failure in nanoseconds, 0 if not recorded.
+    pub first_timestamp: u64,
+}
+
+/// Allocations handed over to a component, from the `o` records and the
+/// frees carrying its string index.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Ownership {
+    pub transfers: u64,
+    /// Bytes of the allocations transferred to the component.
+    pub transferred: u64,
+    /// Allocations transferred again to another component.
+    pub handed_over: u64,
+    pub frees: u64,
+    pub freed: u64,
+    /// Bytes owned by the component and not freed.
+    pub leaked: u64,
+}
+
+impl Transaction {
+    /// Bytes allocated and not freed within the transaction; negative if it
+    /// freed memory allocated before it began.
+    pub fn retained(&self) -> i64 {
+        self.allocated as i64 - self.freed as i64
+    }
+}
+
+/// A record that could not be applied and was skipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Anomaly {
+    /// 1-based line number in the trace file.
+    pub line: u64,
+    pub kind: AnomalyKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnomalyKind {
+    /// A `+` or `-` record referring to an allocation info that was never
+    /// defined.
+    UnknownAllocationInfo { record: char, idx: u64 },
+    /// Frees not matching a live allocation, reported for traces recorded
+    /// with [`FreeMismatches::strict`].
+    FreeMismatch { kind: FreeMismatchKind, count: u64 },
+    /// A `p`, `q` or `D` record referring to a pool that was never
+    /// declared.
+    UnknownPool { record: char, idx: u64 },
+    /// A `-` record freeing an allocation info none of whose allocations
+    /// is live, from a double free or a corrupt trace.
+    UnmatchedFree { idx: u64 },
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            AnomalyKind::UnknownAllocationInfo { record, idx } => write!(
+                f,
+                "line {}: `{}` record refers to unknown allocation info {:#x}",
+                self.line, record, idx
+            ),
+            AnomalyKind::FreeMismatch { kind, count } => {
+                write!(f, "line {}: {} x {}", self.line, count, kind)
+            }
+            AnomalyKind::UnknownPool { record, idx } => write!(
+                f,
+                "line {}: `{}` record refers to unknown pool {:#x}",
+                self.line, record, idx
+            ),
+            AnomalyKind::UnmatchedFree { idx } => write!(
+                f,
+                "line {}: `-` record frees allocation info {:#x} without live allocations",
+                self.line, idx
+            ),
         }
     }
 }
 
 #[derive(Debug)]
 pub struct AccumulatedData {
+    /// Protocol version of the writer, from the `v` record.
+    pub version: u16,
+    /// Version of the text format, from the `v` record. 0 for traces
+    /// without one.
+    pub file_version: u16,
     pub strings: Vec<String>,
     pub traces: Vec<Trace>,
     pub instruction_pointers: Vec<InstructionPointer>,
@@ -94,11 +677,49 @@ pub struct AccumulatedData {
     pub peak_rss: u64,
     pub page_size: u64,
     pub pages: u64,
+    pub markers: Vec<Marker>,
+    pub timeline: Vec<TimelinePoint>,
+    /// Heap state sampled every N `+` and `-` records, see
+    /// [`Parser::with_event_series`]. Points carry the time of the last `c`
+    /// record.
+    pub series: Vec<TimelinePoint>,
+    /// Metadata recorded by the interpreter, e.g. the captured environment.
+    pub metadata: IndexMap<String, String>,
+    /// Range of the alloc, free and RSS timestamps. `None` for traces
+    /// written before timestamps were recorded.
+    pub clock: Option<ClockRange>,
+    pub peak_at: PeakInfo,
+    /// Command line of the traced program from the `X` record.
+    pub command: Option<String>,
+    /// Sizes of all allocations, see [`Parser::with_size_histograms`].
+    pub sizes: Option<SizeHistogram>,
+    /// Records skipped because they were inconsistent with the rest of the
+    /// trace.
+    pub anomalies: Vec<Anomaly>,
+    /// Completed transactions in the order they ended. Transactions without
+    /// an end marker are not reported.
+    pub transactions: Vec<Transaction>,
+    /// Whether the trace ends with an `E` record matching the records before
+    /// it. `false` for truncated traces, e.g. of a killed interpreter.
+    pub complete: bool,
+    /// Pools declared by the program in the order of their `P` records.
+    pub pools: Vec<Pool>,
+    /// Failed allocations by the 1-based index of their trace.
+    pub failed_allocations: IndexMap<u64, AllocationFailures>,
+    /// Transferred allocations by the 1-based string index of their owner.
+    pub owners: IndexMap<usize, Ownership>,
+    /// Notes on the sites of the trace, read from the sidecar file of
+    /// [`Parser::parse_file`].
+    pub annotations: Annotations,
+    /// Lines skipped under [`ParsePolicy::CollectWarnings`].
+    pub warnings: Vec<ParseWarning>,
 }
 
 impl AccumulatedData {
     pub fn new() -> Self {
         Self {
+            version: 0,
+            file_version: 0,
             strings: Vec::with_capacity(4096),
             traces: Vec::with_capacity(65536),
             instruction_pointers: Vec::with_capacity(16384),
@@ -110,63 +731,1179 @@ impl AccumulatedData {
             peak_rss: 0,
             page_size: 0,
             pages: 0,
+            markers: Vec::new(),
+            timeline: Vec::new(),
+            series: Vec::new(),
+            metadata: IndexMap::new(),
+            clock: None,
+            peak_at: PeakInfo::default(),
+            command: None,
+            sizes: None,
+            anomalies: Vec::new(),
+            transactions: Vec::new(),
+            complete: false,
+            pools: Vec::new(),
+            failed_allocations: IndexMap::new(),
+            owners: IndexMap::new(),
+            annotations: Annotations::default(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Attaches `note` to the site with the signature `site_signature`, see
+    /// [`Site::signature`](crate::model::Site::signature). Save the
+    /// annotations to keep them with the trace.
+    pub fn annotate(&mut self, site_signature: &str, note: &str) {
+        self.annotations.add(site_signature, note);
+    }
+
+    /// Name of the signal that killed the traced program, see
+    /// [`CRASH_SIGNAL_KEY`].
+    pub fn crash_signal(&self) -> Option<&str> {
+        self.metadata.get(CRASH_SIGNAL_KEY).map(String::as_str)
+    }
+
+    /// Name of the trace session, see [`SESSION_ID_KEY`].
+    pub fn session_id(&self) -> Option<&str> {
+        self.metadata.get(SESSION_ID_KEY).map(String::as_str)
+    }
+
+    /// Limit that stopped the capture early, see [`CAPTURE_STOPPED_KEY`].
+    pub fn capture_stopped(&self) -> Option<&str> {
+        self.metadata.get(CAPTURE_STOPPED_KEY).map(String::as_str)
+    }
+
+    /// Size below which allocations were not recorded with their stack, see
+    /// [`STACK_THRESHOLD_KEY`].
+    pub fn stack_threshold(&self) -> Option<u64> {
+        self.metadata.get(STACK_THRESHOLD_KEY)?.parse().ok()
+    }
+
+    /// Probability with which allocations were recorded, see
+    /// [`SAMPLING_RATE_KEY`]. `None` for traces recording every allocation.
+    pub fn sampling_rate(&self) -> Option<f64> {
+        self.metadata
+            .get(SAMPLING_RATE_KEY)?
+            .parse()
+            .ok()
+            .filter(|&rate| rate > 0.0 && rate < 1.0)
+    }
+
+    /// The allocations counted below the [`stack_threshold`](Self::stack_threshold).
+    pub fn small_allocations(&self) -> Vec<SmallAllocations> {
+        self.metadata
+            .get(SMALL_ALLOCATIONS_KEY)
+            .and_then(|value| serde_json::from_str(value).ok())
+            .unwrap_or_default()
+    }
+
+    /// Frees that did not match a live allocation, see
+    /// [`FREE_MISMATCHES_KEY`].
+    pub fn free_mismatches(&self) -> FreeMismatches {
+        self.metadata
+            .get(FREE_MISMATCHES_KEY)
+            .and_then(|value| serde_json::from_str(value).ok())
+            .unwrap_or_default()
+    }
+
+    /// Number of roots scanned for reachable leaks at exit, see
+    /// [`ROOT_SCAN_KEY`].
+    pub fn scanned_roots(&self) -> Option<u64> {
+        self.metadata.get(ROOT_SCAN_KEY)?.parse().ok()
+    }
+
+    /// Number of records lost in transfer, see [`STREAM_LOST_KEY`].
+    pub fn lost_records(&self) -> Option<u64> {
+        self.metadata.get(STREAM_LOST_KEY)?.parse().ok()
+    }
+
+    /// Offset of the record clock to the realtime clock, see
+    /// [`CLOCK_OFFSET_KEY`].
+    pub fn realtime_offset(&self) -> Option<i64> {
+        self.metadata.get(CLOCK_OFFSET_KEY)?.parse().ok()
+    }
+
+    /// Nanoseconds to add to the timestamps of this trace to put them on the
+    /// clock of `reference`, e.g. to merge streams of processes on different
+    /// hosts.
+    pub fn skew_to(&self, reference: &AccumulatedData) -> Option<i64> {
+        Some(self.realtime_offset()? - reference.realtime_offset()?)
+    }
+
+    /// Moves the timestamps of this trace by `skew` nanoseconds.
+    pub fn apply_skew(&mut self, skew: i64) {
+        if let Some(clock) = &mut self.clock {
+            clock.first = clock.first.saturating_add_signed(skew);
+            clock.last = clock.last.saturating_add_signed(skew);
+        }
+        if let Some(offset) = self.realtime_offset() {
+            self.metadata
+                .insert(CLOCK_OFFSET_KEY.to_string(), (offset - skew).to_string());
+        }
+    }
+}
+
+impl Default for AccumulatedData {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Aggregates of a trace computed by [`Parser::parse_summary`], without
+/// strings, instruction pointers or stacks.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub total: AllocationData,
+    pub duration: Duration,
+    pub peak_rss: u64,
+    pub page_size: u64,
+    pub pages: u64,
+    pub timeline: Vec<TimelinePoint>,
+    /// Cost of every allocating trace, keyed by trace index.
+    pub traces: IndexMap<u64, AllocationData>,
+}
+
+impl Summary {
+    /// The `k` trace indices ranking highest by `metric`, highest first.
+    pub fn top(&self, metric: Metric, k: usize) -> Vec<(u64, &AllocationData)> {
+        let mut traces: Vec<_> = self.traces.iter().map(|(&idx, data)| (idx, data)).collect();
+        traces.sort_by_key(|(idx, data)| (std::cmp::Reverse(Cost::from(*data).get(metric)), *idx));
+        traces.truncate(k);
+        traces
+    }
+}
+
+/// A record as seen by [`Parser::feed_line`], with the indices of the trace
+/// already resolved against the data parsed before it.
+#[derive(Debug, Clone, Copy)]
+pub enum ParsedRecord<'a> {
+    String(&'a str),
+    InstructionPointer(&'a InstructionPointer),
+    Trace(&'a Trace),
+    AllocationInfo(&'a AllocationInfo),
+    Alloc {
+        info_idx: u64,
+        size: u64,
+        trace_idx: u64,
+        timestamp: Option<u64>,
+    },
+    Free {
+        info_idx: u64,
+        size: u64,
+        trace_idx: u64,
+        timestamp: Option<u64>,
+    },
+    Clock(Duration),
+    Rss {
+        rss: u64,
+        timestamp: Option<u64>,
+    },
+    Metadata {
+        key: &'a str,
+        value: &'a str,
+    },
+    Marker(&'a Marker),
+    /// A transaction closed by an `e` record.
+    Transaction(&'a Transaction),
+    /// Any other record, e.g. the header or comments, by its tag.
+    Other(&'a str),
+}
+
+/// Fields of a line applied by the [`Parser`], to build its
+/// [`ParsedRecord`] without parsing the line again.
+#[derive(Debug, Clone, Copy, Default)]
+struct Applied {
+    /// Allocation info of a `+` or `-` record.
+    info_idx: u64,
+    /// Timestamp field of a `+`, `-` or `R` record.
+    timestamp: Option<u64>,
+}
+
+/// Records skipped by a [`Parser`] configured
+/// [`with_filter`](Parser::with_filter), e.g. to extract the strings, stacks
+/// and modules of a huge trace without accounting for its allocations.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EventFilter {
+    /// Keeps only the header, metadata, strings, instruction pointers,
+    /// traces and allocation infos, skipping every event of the run.
+    pub metadata_only: bool,
+    /// Skips the `+` and `-` records, keeping the timeline, markers and
+    /// transactions.
+    pub skip_allocations: bool,
+    /// Skips the `+` and `-` records of allocation infos smaller than this.
+    pub min_size: u64,
+}
+
+impl EventFilter {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn skips(&self, tag: &[u8], line: &[u8], data: &AccumulatedData) -> bool {
+        match tag {
+            b"+" | b"-" | b"o" if self.metadata_only || self.skip_allocations => true,
+            b"+" | b"-" | b"o" if self.min_size > 0 => {
+                let size = line
+                    .split(u8::is_ascii_whitespace)
+                    .filter(|f| !f.is_empty())
+                    .nth(1)
+                    .and_then(parse_hex)
+                    .and_then(|idx| data.allocation_infos.get(idx as usize))
+                    .map(|info| info.size);
+                // unknown infos are left to the parser to report
+                size.is_some_and(|size| size < self.min_size)
+            }
+            b"c" | b"R" | b"m" | b"b" | b"e" | b"r" | b"P" | b"p" | b"q" | b"D" | b"f" => {
+                self.metadata_only
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Part of a run aggregated by a [`Parser`] configured
+/// [`with_steady_state`](Parser::with_steady_state).
+///
+/// Startup allocations and the frees at exit often dominate a run and hide
+/// the steady state of a long-running program. Allocations, frees, RSS
+/// samples and clock records before the start or within `skip_last` of the
+/// end are left out; frees of allocations left out are too. Times are
+/// those of the `c` clock records, events belong to the last clock record
+/// before them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SteadyState {
+    /// Time from the start of the run left out.
+    pub skip_first: Duration,
+    /// Label of the `m` marker or `b` transaction after which the
+    /// aggregation starts, e.g. one the program records once it is ready.
+    /// Combined with `skip_first`, the later of both starts it.
+    pub start_marker: Option<String>,
+    /// Time before the end of the run left out.
+    pub skip_last: Duration,
+}
+
+#[derive(Debug)]
+struct SteadyStateFilter {
+    options: SteadyState,
+    started: bool,
+    marker_seen: bool,
+    /// Time of the last clock record in milliseconds.
+    now: u64,
+    /// Allocations left out before the start and not freed yet, by
+    /// allocation info.
+    excluded_live: Vec<u64>,
+    /// Lines still within `skip_last` of the last clock record, with their
+    /// line number and time.
+    pending: VecDeque<(u64, u64, Vec<u8>)>,
+}
+
+impl SteadyStateFilter {
+    fn new(options: SteadyState) -> Self {
+        Self {
+            options,
+            started: false,
+            marker_seen: false,
+            now: 0,
+            excluded_live: Vec::new(),
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+/// Label of an `m` or `b` record.
+fn marker_label(line: &[u8]) -> Option<&[u8]> {
+    let mut fields = line.split(|&b| b == b' ');
+    let tag = fields.next()?;
+    if tag == b"b" {
+        fields.next()?;
+    }
+    let len = parse_hex(fields.next()?)? as usize;
+    line.get(line.len().checked_sub(len)?..)
+}
+
+pub struct Parser {
+    data: AccumulatedData,
+    last_ptr: u64,
+    rss: u64,
+    in_body: bool,
+    empty: bool,
+    line: u64,
+    /// Open transactions, innermost last.
+    open_transactions: Vec<Transaction>,
+    /// Lines since the last `E` record.
+    checksum: TraceChecksum,
+    steady_state: Option<SteadyStateFilter>,
+    policy: ParsePolicy,
+    filter: EventFilter,
+    /// Events between two points of [`AccumulatedData::series`].
+    series_interval: Option<u64>,
+    series_events: u64,
+    /// `+` and `-` records applied so far.
+    events: u64,
+    size_histograms: bool,
+}
+
+/// Iterator over the logical traces of a file holding several concatenated
+/// traces, see [`Parser::traces`].
+pub struct Traces<R> {
+    reader: R,
+    line: Vec<u8>,
+    parser: Parser,
+    done: bool,
+    /// Metadata records following allocation data. They belong to the next
+    /// trace if a version record follows them, to the current one otherwise.
+    pending: Vec<Vec<u8>>,
+}
+
+impl<R> Traces<R> {
+    fn apply_pending(&mut self) -> Result<(), Error> {
+        for line in self.pending.drain(..) {
+            self.parser.parse_line(&line)?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Iterator for Traces<R> {
+    type Item = Result<AccumulatedData, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        loop {
+            match read_line(&mut self.reader, &mut self.line) {
+                Ok(true) => {}
+                Ok(false) => {
+                    self.done = true;
+                    if let Err(e) = self.apply_pending() {
+                        return Some(Err(e));
+                    }
+                    if self.parser.empty {
+                        return None;
+                    }
+                    return Some(Ok(self.parser.finish()));
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e.into()));
+                }
+            }
+
+            let first = first_field(&self.line);
+            if self.parser.in_body && first == Some(b"M") {
+                self.pending.push(self.line.clone());
+                continue;
+            }
+
+            let finished =
+                (self.parser.in_body && first == Some(b"v")).then(|| self.parser.finish());
+
+            if let Err(e) = self
+                .apply_pending()
+                .and_then(|_| self.parser.parse_line(&self.line))
+            {
+                self.done = true;
+                return Some(Err(e));
+            }
+
+            if let Some(data) = finished {
+                return Some(Ok(data));
+            }
+        }
+    }
+}
+
+/// Path naming the standard input in place of a trace file, e.g. for
+/// `zstd -dc trace.zst | tool -`.
+pub const STDIN_PATH: &str = "-";
+
+/// Leading bytes of a gzip stream.
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+/// Leading bytes of a zstd frame.
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Opens a trace for reading, [`STDIN_PATH`] reads the standard input.
+///
+/// Traces compressed with gzip or zstd are recognized by their magic bytes
+/// and decompressed on the fly when the `gzip` or `zstd` feature is
+/// enabled, and rejected with an error naming the feature otherwise.
+/// Binary traces are read as text through a
+/// [`BinaryReader`](crate::format::binary::BinaryReader) with the `wire`
+/// feature.
+pub fn open_input(path: impl AsRef<Path>) -> io::Result<Box<dyn BufRead>> {
+    let path = path.as_ref();
+    if path == Path::new(STDIN_PATH) {
+        return decompressed(io::BufReader::new(io::stdin()));
+    }
+    decompressed(io::BufReader::new(File::open(path)?))
+}
+
+fn decompressed(mut reader: impl BufRead + 'static) -> io::Result<Box<dyn BufRead>> {
+    let head = reader.fill_buf()?;
+    if head.starts_with(GZIP_MAGIC) {
+        #[cfg(feature = "gzip")]
+        return Ok(Box::new(io::BufReader::new(
+            flate2::bufread::MultiGzDecoder::new(reader),
+        )));
+        #[cfg(not(feature = "gzip"))]
+        return Err(unsupported_compression("gzip"));
+    }
+    if head.starts_with(ZSTD_MAGIC) {
+        #[cfg(feature = "zstd")]
+        return match ruzstd::StreamingDecoder::new(reader) {
+            Ok(decoder) => Ok(Box::new(io::BufReader::new(decoder))),
+            Err(err) => Err(io::Error::new(io::ErrorKind::InvalidData, err.to_string())),
+        };
+        #[cfg(not(feature = "zstd"))]
+        return Err(unsupported_compression("zstd"));
+    }
+    if head.starts_with(BINARY_MAGIC) {
+        #[cfg(feature = "wire")]
+        return Ok(Box::new(BinaryReader::new(reader)?));
+        #[cfg(not(feature = "wire"))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "binary trace, build with the `wire` feature",
+        ));
+    }
+    Ok(Box::new(reader))
+}
+
+#[cfg(not(all(feature = "gzip", feature = "zstd")))]
+fn unsupported_compression(feature: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{feature} compressed trace, build with the `{feature}` feature"),
+    )
+}
+
+/// Error of an operation needing a seekable file when given [`STDIN_PATH`].
+fn stdin_unsupported(operation: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("the standard input cannot be {operation}"),
+    )
+}
+
+/// Trace file mapped into memory, see [`Parser::parse_mmap`]. Records are
+/// borrowed from the mapping instead of being copied into a buffer.
+///
+/// Appending to the file while it is mapped is fine, but truncating it
+/// makes accesses to the mapping crash the process.
+#[cfg(feature = "mmap")]
+pub struct MappedTrace {
+    map: memmap2::Mmap,
+}
+
+#[cfg(feature = "mmap")]
+impl MappedTrace {
+    /// Maps the trace at `path`. [`STDIN_PATH`] cannot be mapped.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        if path == Path::new(STDIN_PATH) {
+            return Err(stdin_unsupported("mapped"));
+        }
+        let file = File::open(path)?;
+        // SAFETY: the mapping is read-only and the traces are only ever
+        // appended to
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Ok(Self { map })
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.map
+    }
+
+    /// Lines of the trace without their newline, like [`read_line`].
+    pub fn lines(&self) -> impl Iterator<Item = &[u8]> {
+        self.map
+            .split_inclusive(|&b| b == b'\n')
+            .map(|line| line.strip_suffix(b"\n").unwrap_or(line))
+    }
+
+    /// Values of the `s` records in the order of their 1-based indices,
+    /// borrowed from the mapping unless they are not valid UTF-8.
+    pub fn strings(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        self.lines().filter_map(|line| {
+            let rest = line.strip_prefix(b"s ")?;
+            let space = rest.iter().position(|&b| b == b' ')?;
+            let len: usize = parse_hex(&rest[..space])? as usize;
+            let value = rest.get(space + 1..)?;
+            let value = value.get(value.len().checked_sub(len)?..)?;
+            Some(String::from_utf8_lossy(value))
+        })
+    }
+
+    fn needs_decoding(&self) -> bool {
+        [GZIP_MAGIC, ZSTD_MAGIC, BINARY_MAGIC]
+            .iter()
+            .any(|magic| self.map.starts_with(magic))
+    }
+}
+
+/// Loads the sidecar [`Annotations`] of the file at `path`.
+fn load_annotations(data: &mut AccumulatedData, path: &Path) -> io::Result<()> {
+    if path != Path::new(STDIN_PATH) {
+        data.annotations = Annotations::load(path)?;
+    }
+    Ok(())
+}
+
+fn first_field(line: &[u8]) -> Option<&[u8]> {
+    line.split(u8::is_ascii_whitespace).find(|f| !f.is_empty())
+}
+
+fn tag_text(line: &[u8]) -> String {
+    String::from_utf8_lossy(first_field(line).unwrap_or_default()).into_owned()
+}
+
+/// Text of `line` cut to [`SNIPPET_LEN`] bytes.
+fn snippet(line: &[u8]) -> String {
+    // cut before a UTF-8 continuation byte, not within a character
+    let mut end = line.len().min(SNIPPET_LEN);
+    while end > 0 && end < line.len() && line[end] & 0xc0 == 0x80 {
+        end -= 1;
+    }
+    let mut text = String::from_utf8_lossy(&line[..end]).into_owned();
+    if end < line.len() {
+        text.push_str("...");
+    }
+    text
+}
+
+/// Parses a trace from chunks of bytes as they are produced, e.g. while the
+/// interpreter writes it, so the data is available without reading the file
+/// again.
+#[derive(Default)]
+pub struct StreamParser {
+    parser: Parser,
+    partial: Vec<u8>,
+}
+
+impl StreamParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses the complete lines of `bytes` and keeps the rest for the next
+    /// chunk.
+    pub fn feed(&mut self, mut bytes: &[u8]) -> Result<(), Error> {
+        while let Some(end) = bytes.iter().position(|&b| b == b'\n') {
+            let line = if self.partial.is_empty() {
+                &bytes[..end]
+            } else {
+                self.partial.extend_from_slice(&bytes[..end]);
+                &self.partial[..]
+            };
+            self.parser.parse_line(line)?;
+
+            self.partial.clear();
+            bytes = &bytes[end + 1..];
+        }
+        self.partial.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Parses a trailing line without newline and returns the data.
+    pub fn finish(mut self) -> Result<AccumulatedData, Error> {
+        if !self.partial.is_empty() {
+            self.parser.parse_line(&self.partial)?;
+        }
+        Ok(self.parser.data)
+    }
+}
+
+/// Totals of a trace followed by [`Parser::follow`] after new lines were
+/// parsed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FollowUpdate {
+    /// Lines parsed since the previous update.
+    pub lines: u64,
+    pub total: Cost,
+    pub duration: Duration,
+    pub peak_rss: u64,
+    /// Whether the trace ended with a matching `E` record, after which
+    /// nothing is expected to be appended.
+    pub complete: bool,
+}
+
+/// A trace file parsed while the traced process appends to it, see
+/// [`Parser::follow`].
+///
+/// [`Follow::poll`] parses what was appended since the previous call. As an
+/// iterator, it polls every [`Follow::with_interval`] until lines were
+/// appended and ends after the update of a complete trace.
+pub struct Follow {
+    stream: StreamParser,
+    file: File,
+    buffer: Vec<u8>,
+    interval: Duration,
+    done: bool,
+}
+
+impl Follow {
+    /// Interval between the polls of the iterator, 200ms by default.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Parses the complete lines appended to the file since the previous
+    /// poll. `None` if there were none.
+    pub fn poll(&mut self) -> Result<Option<FollowUpdate>, Error> {
+        let lines = self.stream.parser.line;
+        loop {
+            let read = match self.file.read(&mut self.buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            self.stream.feed(&self.buffer[..read])?;
+        }
+
+        let parser = &self.stream.parser;
+        if parser.line == lines {
+            return Ok(None);
+        }
+        Ok(Some(FollowUpdate {
+            lines: parser.line - lines,
+            total: Cost::from(&parser.data.total),
+            duration: parser.data.duration,
+            peak_rss: parser.data.peak_rss,
+            complete: parser.data.complete,
+        }))
+    }
+
+    /// Data parsed so far.
+    pub fn data(&self) -> &AccumulatedData {
+        &self.stream.parser.data
+    }
+
+    /// Stops following and returns the data, parsing a trailing line
+    /// without newline.
+    pub fn finish(self) -> Result<AccumulatedData, Error> {
+        self.stream.finish()
+    }
+}
+
+impl Iterator for Follow {
+    type Item = Result<FollowUpdate, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        loop {
+            match self.poll() {
+                Ok(Some(update)) => {
+                    self.done = update.complete;
+                    return Some(Ok(update));
+                }
+                Ok(None) => std::thread::sleep(self.interval),
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self {
+            data: AccumulatedData::new(),
+            last_ptr: 0,
+            rss: 0,
+            in_body: false,
+            empty: true,
+            line: 0,
+            open_transactions: Vec::new(),
+            checksum: TraceChecksum::default(),
+            steady_state: None,
+            policy: ParsePolicy::Standard,
+            filter: EventFilter::default(),
+            series_interval: None,
+            series_events: 0,
+            events: 0,
+            size_histograms: false,
+        }
+    }
+
+    pub fn with_policy(mut self, policy: ParsePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Samples the total leaked bytes and allocations every `events` `+`
+    /// and `-` records into [`AccumulatedData::series`], for a finer
+    /// consumption chart than the [timeline](AccumulatedData::timeline) of
+    /// the timestamp records.
+    pub fn with_event_series(mut self, events: u64) -> Self {
+        self.series_interval = Some(events.max(1));
+        self
+    }
+
+    /// Counts the sizes of the allocations of every [`Allocation`] and of
+    /// the whole trace in a [`SizeHistogram`].
+    pub fn with_size_histograms(mut self) -> Self {
+        self.size_histograms = true;
+        self
+    }
+
+    /// Skips the records matching `filter` before they are aggregated.
+    pub fn with_filter(mut self, filter: EventFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Aggregates only the [`SteadyState`] of the run, leaving out its
+    /// startup and teardown.
+    pub fn with_steady_state(mut self, options: SteadyState) -> Self {
+        self.steady_state = Some(SteadyStateFilter::new(options));
+        self
+    }
+
+    /// Parses the file as a single trace, [`STDIN_PATH`] parses the
+    /// standard input. Compressed files are decompressed as described at
+    /// [`open_input`]. Files holding several concatenated traces are merged
+    /// into one; use [`Parser::traces`] or [`Parser::parse_file_all`] to
+    /// keep them apart. The [`Annotations`] of the file are loaded from its
+    /// sidecar.
+    pub fn parse_file(self, file_path: impl AsRef<Path>) -> Result<AccumulatedData, Error> {
+        let file_path = file_path.as_ref();
+        let mut data = self.parse_reader(open_input(file_path)?)?;
+        load_annotations(&mut data, file_path)?;
+        Ok(data)
+    }
+
+    /// Parses the file as a single trace like [`Parser::parse_file`], but
+    /// reads it through a [`MappedTrace`], saving the copies of buffered
+    /// reads on huge traces. Compressed and binary files and [`STDIN_PATH`]
+    /// cannot be mapped and are read like [`Parser::parse_file`] does.
+    #[cfg(feature = "mmap")]
+    pub fn parse_mmap(mut self, file_path: impl AsRef<Path>) -> Result<AccumulatedData, Error> {
+        let file_path = file_path.as_ref();
+        if file_path == Path::new(STDIN_PATH) {
+            return self.parse_file(file_path);
+        }
+        let trace = MappedTrace::open(file_path)?;
+        if trace.needs_decoding() {
+            return self.parse_file(file_path);
+        }
+
+        for line in trace.lines() {
+            self.parse_line(line)?;
+        }
+        load_annotations(&mut self.data, file_path)?;
+        Ok(self.data)
+    }
+
+    /// Follows the trace file at `file_path` as the traced process appends
+    /// to it, e.g. for a live view of a recording in progress. Compressed
+    /// files and [`STDIN_PATH`] cannot be followed, read the standard input
+    /// with [`Parser::parse_file`] instead.
+    pub fn follow(self, file_path: impl AsRef<Path>) -> Result<Follow, Error> {
+        if file_path.as_ref() == Path::new(STDIN_PATH) {
+            return Err(stdin_unsupported("followed").into());
+        }
+        Ok(Follow {
+            stream: StreamParser {
+                parser: self,
+                partial: Vec::new(),
+            },
+            file: File::open(file_path)?,
+            buffer: vec![0; 64 * 1024],
+            interval: Duration::from_millis(200),
+            done: false,
+        })
+    }
+
+    /// Parses a trace read from `reader`, e.g. a socket or an in-memory
+    /// buffer, as a single trace like [`Parser::parse_file`].
+    pub fn parse_reader(mut self, mut reader: impl BufRead) -> Result<AccumulatedData, Error> {
+        let mut line = Vec::new();
+        while read_line(&mut reader, &mut line)? {
+            self.parse_line(&line)?;
+        }
+
+        Ok(self.data)
+    }
+
+    /// Parses a trace read from `reader` like [`Parser::parse_reader`],
+    /// passing every record to `on_record` as it is parsed.
+    pub fn parse_reader_with(
+        mut self,
+        mut reader: impl BufRead,
+        mut on_record: impl FnMut(ParsedRecord),
+    ) -> Result<AccumulatedData, Error> {
+        let mut line = Vec::new();
+        while read_line(&mut reader, &mut line)? {
+            if let Some(record) = self.feed_line(&line)? {
+                on_record(record);
+            }
+        }
+
+        Ok(self.data)
+    }
+
+    /// Parses one line without its newline and returns its record, `None`
+    /// for empty lines and for records that were not applied: skipped by
+    /// the [`ParsePolicy`], dropped by the [`EventFilter`], deferred or
+    /// excluded by the [`SteadyState`], or referring to unknown allocation
+    /// infos. Deferred records are not returned once applied. The data
+    /// parsed so far is available with [`Parser::data`].
+    pub fn feed_line<'a>(&'a mut self, line: &'a [u8]) -> Result<Option<ParsedRecord<'a>>, Error> {
+        let transactions = self.data.transactions.len();
+        let Some(applied) = self.parse_line(line)? else {
+            return Ok(None);
+        };
+
+        // only the values of length-prefixed records may be invalid UTF-8
+        let Some(tag) = first_field(line).and_then(|tag| std::str::from_utf8(tag).ok()) else {
+            return Ok(None);
+        };
+        let data = &self.data;
+
+        let record = match tag {
+            "s" => data.strings.last().map(|s| ParsedRecord::String(s)),
+            "i" => data
+                .instruction_pointers
+                .last()
+                .map(ParsedRecord::InstructionPointer),
+            "t" => data.traces.last().map(ParsedRecord::Trace),
+            "a" => data
+                .allocation_infos
+                .last()
+                .map(ParsedRecord::AllocationInfo),
+            "+" | "-" => {
+                let Applied {
+                    info_idx,
+                    timestamp,
+                } = applied;
+                data.allocation_infos.get(info_idx as usize).map(|info| {
+                    let size = info.size;
+                    let trace_idx = data.allocations[info.allocation_idx as usize].trace_idx;
+                    if tag == "+" {
+                        ParsedRecord::Alloc {
+                            info_idx,
+                            size,
+                            trace_idx,
+                            timestamp,
+                        }
+                    } else {
+                        ParsedRecord::Free {
+                            info_idx,
+                            size,
+                            trace_idx,
+                            timestamp,
+                        }
+                    }
+                })
+            }
+            "c" => Some(ParsedRecord::Clock(data.duration)),
+            "R" => Some(ParsedRecord::Rss {
+                rss: self.rss,
+                timestamp: applied.timestamp,
+            }),
+            "M" => {
+                let text = String::from_utf8_lossy(line);
+                let key = Fields::new(&text).nth(1).unwrap_or_default();
+                data.metadata
+                    .get_key_value(key)
+                    .map(|(key, value)| ParsedRecord::Metadata { key, value })
+            }
+            "m" => data.markers.last().map(ParsedRecord::Marker),
+            "e" if data.transactions.len() > transactions => {
+                data.transactions.last().map(ParsedRecord::Transaction)
+            }
+            tag => Some(ParsedRecord::Other(tag)),
+        };
+
+        Ok(record)
+    }
+
+    /// Data parsed so far.
+    pub fn data(&self) -> &AccumulatedData {
+        &self.data
+    }
+
+    pub fn into_data(self) -> AccumulatedData {
+        self.data
+    }
+
+    /// Iterates over the logical traces of a file, see
+    /// [`Parser::traces_reader`].
+    pub fn traces(self, file_path: impl AsRef<Path>) -> Result<Traces<Box<dyn BufRead>>, Error> {
+        Ok(self.traces_reader(open_input(file_path)?))
+    }
+
+    /// Iterates over the logical traces read from `reader`. A new trace
+    /// starts at every version record following allocation data, together
+    /// with the metadata records right before it, as produced by rotation or
+    /// by several processes writing to one file.
+    pub fn traces_reader<R: BufRead>(self, reader: R) -> Traces<R> {
+        Traces {
+            reader,
+            line: Vec::new(),
+            parser: self,
+            done: false,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Parses every logical trace of a file, see [`Parser::traces`].
+    pub fn parse_file_all(
+        self,
+        file_path: impl AsRef<Path>,
+    ) -> Result<Vec<AccumulatedData>, Error> {
+        self.traces(file_path)?.collect()
+    }
+
+    /// Computes the totals, the timeline and the cost per trace index of a
+    /// file without building the trace, instruction pointer and string
+    /// tables, for callers that never resolve stacks.
+    pub fn parse_summary(self, file_path: impl AsRef<Path>) -> Result<Summary, Error> {
+        summarize(open_input(file_path)?)
+    }
+
+    fn finish(&mut self) -> AccumulatedData {
+        self.last_ptr = 0;
+        self.rss = 0;
+        self.in_body = false;
+        self.empty = true;
+        self.open_transactions.clear();
+        self.checksum = TraceChecksum::default();
+        if let Some(state) = &mut self.steady_state {
+            *state = SteadyStateFilter::new(std::mem::take(&mut state.options));
+        }
+        std::mem::take(&mut self.data)
+    }
+
+    /// Parses one line without its newline and returns what was applied of
+    /// it, `None` if it was skipped, filtered out or deferred. Only the
+    /// string values of length-prefixed records may hold non-ASCII bytes;
+    /// invalid UTF-8 in them is replaced.
+    fn parse_line(&mut self, line: &[u8]) -> Result<Option<Applied>, Error> {
+        match self
+            .read_record(line)
+            .map_err(|e| self.invalid_record(e, line))
+        {
+            Err(Error::InvalidRecord { line, tag, text }) if self.policy.skips() => {
+                self.warn(ParseWarning {
+                    line,
+                    kind: ParseWarningKind::InvalidRecord,
+                    tag,
+                    text,
+                });
+                Ok(None)
+            }
+            result => result,
+        }
+    }
+
+    /// Adds the location to an [`Error::InvalidFormat`] raised by `line`.
+    fn invalid_record(&self, error: Error, line: &[u8]) -> Error {
+        if !matches!(error, Error::InvalidFormat) {
+            return error;
+        }
+
+        Error::InvalidRecord {
+            line: self.line,
+            tag: tag_text(line),
+            text: snippet(line),
+        }
+    }
+
+    /// Handles a record of `line` with a tag missing from the [`SCHEMA`].
+    fn unknown_record(&mut self, line: &[u8]) -> Result<(), Error> {
+        if self.policy == ParsePolicy::Strict {
+            return Err(Error::UnknownRecord {
+                line: self.line,
+                tag: tag_text(line),
+            });
+        }
+        self.warn(ParseWarning {
+            line: self.line,
+            kind: ParseWarningKind::UnknownRecord,
+            tag: tag_text(line),
+            text: snippet(line),
+        });
+        Ok(())
+    }
+
+    /// Handles a `v` record of a format newer than [`FILE_VERSION`]
+    /// according to the [`ParsePolicy`].
+    fn unsupported_version(&mut self, line: &[u8]) -> Result<(), Error> {
+        if !self.policy.skips() {
+            return Err(Error::UnsupportedVersion(self.data.file_version));
+        }
+        self.warn(ParseWarning {
+            line: self.line,
+            kind: ParseWarningKind::UnsupportedVersion,
+            tag: tag_text(line),
+            text: snippet(line),
+        });
+        Ok(())
+    }
+
+    fn warn(&mut self, warning: ParseWarning) {
+        if self.policy == ParsePolicy::CollectWarnings {
+            self.data.warnings.push(warning);
         }
     }
-}
 
-pub struct Parser {
-    data: AccumulatedData,
-    last_ptr: u64,
-}
+    fn read_record(&mut self, line: &[u8]) -> Result<Option<Applied>, Error> {
+        self.line += 1;
+        if line.first() != Some(&b'E') {
+            self.checksum.update(line);
+            self.checksum.update(b"\n");
+            self.data.complete = false;
+        }
 
-impl Parser {
-    pub fn new() -> Self {
-        Self {
-            data: AccumulatedData::new(),
-            last_ptr: 0,
+        let Some(tag) = first_field(line) else {
+            return Ok(None);
+        };
+
+        self.empty = false;
+        if !matches!(tag, b"v" | b"M" | b"#") {
+            self.in_body = true;
+        }
+
+        if !self.filter.is_empty() && self.filter.skips(tag, line, &self.data) {
+            return Ok(None);
+        }
+        if self.steady_state.is_some() {
+            return self.parse_steady_state(tag, line);
         }
+        self.apply_line(line)
     }
 
-    pub fn parse_file(mut self, file_path: impl AsRef<Path>) -> Result<AccumulatedData, Error> {
-        let file = OpenOptions::new().read(true).open(file_path)?;
-        let reader = io::BufReader::new(file);
+    /// Applies the line to the aggregates if it falls into the
+    /// [`SteadyState`], deferring it while the end of the run may still be
+    /// too close.
+    fn parse_steady_state(&mut self, tag: &[u8], line: &[u8]) -> Result<Option<Applied>, Error> {
+        let Some(state) = &mut self.steady_state else {
+            return Ok(None);
+        };
 
-        for line in reader.lines() {
-            self.parse_line(&line?)?
+        if tag == b"c" {
+            let text = String::from_utf8_lossy(line);
+            let mut split = Fields::new(&text);
+            split.next();
+            state.now = hex::<u64>(&mut split)?;
+        }
+        if !state.started {
+            state.marker_seen |= matches!(tag, b"m" | b"b")
+                && state
+                    .options
+                    .start_marker
+                    .as_deref()
+                    .is_some_and(|marker| marker_label(line) == Some(marker.as_bytes()));
+            state.started = state.now >= state.options.skip_first.as_millis() as u64
+                && (state.marker_seen || state.options.start_marker.is_none());
         }
 
-        Ok(self.data)
+        if matches!(tag, b"+" | b"-") {
+            let text = String::from_utf8_lossy(line);
+            let mut split = Fields::new(&text);
+            split.next();
+            let idx = hex::<usize>(&mut split)?;
+            // unknown allocation infos are reported when the line is applied
+            let infos = self.data.allocation_infos.len();
+            if idx < infos && state.excluded_live.len() < infos {
+                state.excluded_live.resize(infos, 0);
+            }
+
+            let mut unknown = 0;
+            let excluded = state.excluded_live.get_mut(idx).unwrap_or(&mut unknown);
+            if !state.started {
+                match tag {
+                    b"+" => *excluded += 1,
+                    _ => *excluded = excluded.saturating_sub(1),
+                }
+                return Ok(None);
+            }
+            if tag == b"-" && *excluded > 0 {
+                *excluded -= 1;
+                return Ok(None);
+            }
+        } else if !state.started && matches!(tag, b"c" | b"R") {
+            return Ok(None);
+        }
+
+        let skip_last = state.options.skip_last.as_millis() as u64;
+        if skip_last == 0 || !matches!(tag, b"+" | b"-" | b"c" | b"R" | b"m" | b"b" | b"e") {
+            return self.apply_line(line);
+        }
+
+        state
+            .pending
+            .push_back((self.line, state.now, line.to_vec()));
+        if tag != b"c" {
+            return Ok(None);
+        }
+
+        let line_number = self.line;
+        while let Some(state) = &mut self.steady_state
+            && let Some(&(_, time, _)) = state.pending.front()
+            && time + skip_last <= state.now
+        {
+            let (number, _, pending) = state.pending.pop_front().unwrap();
+            self.line = number;
+            self.apply_line(&pending)
+                .map_err(|e| self.invalid_record(e, &pending))?;
+        }
+        self.line = line_number;
+        Ok(None)
     }
 
-    fn parse_line(&mut self, line: &str) -> Result<(), Error> {
-        let mut split = line.split_whitespace();
+    fn apply_line(&mut self, line: &[u8]) -> Result<Option<Applied>, Error> {
+        let text = String::from_utf8_lossy(line);
+        let mut split = Fields::new(&text);
 
         let Some(first) = split.next() else {
-            return Ok(());
+            return Ok(None);
         };
+        let mut applied = Applied::default();
 
         match first {
+            "v" => {
+                self.data.version = hex::<u16>(&mut split)?;
+                // the first traces did not record a file version
+                self.data.file_version = match split.next() {
+                    Some(field) => parse_hex(field.as_bytes())
+                        .and_then(|v| u16::try_from(v).ok())
+                        .ok_or(Error::InvalidFormat)?,
+                    None => 1,
+                };
+                if self.data.file_version > FILE_VERSION {
+                    self.unsupported_version(line)?;
+                }
+            }
             "s" => {
-                let str_len = usize::from_str_radix(split.next().ok_or(Error::InvalidFormat)?, 16)
-                    .map_err(|_| Error::InvalidFormat)?;
-                self.data
-                    .strings
-                    .push(line[line.len() - str_len..].to_string());
+                let str_len = hex::<usize>(&mut split)?;
+                self.data.strings.push(tail(line, str_len)?);
             }
             "t" => {
-                let ip_idx = u64::from_str_radix(split.next().ok_or(Error::InvalidFormat)?, 16)
-                    .map_err(|_| Error::InvalidFormat)?;
-                let parent_idx = u64::from_str_radix(split.next().ok_or(Error::InvalidFormat)?, 16)
-                    .map_err(|_| Error::InvalidFormat)?;
+                let ip_idx = hex::<u64>(&mut split)?;
+                let parent_idx = hex::<u64>(&mut split)?;
 
                 self.data.traces.push(Trace { ip_idx, parent_idx })
             }
             "i" => {
-                let ip = u64::from_str_radix(split.next().ok_or(Error::InvalidFormat)?, 16)
-                    .map_err(|_| Error::InvalidFormat)?;
-                let module_idx =
-                    usize::from_str_radix(split.next().ok_or(Error::InvalidFormat)?, 16)
-                        .map_err(|_| Error::InvalidFormat)?;
+                let ip = hex::<u64>(&mut split)?;
+                let module_idx = hex::<usize>(&mut split)?;
 
                 let frame = Self::parse_frame(&mut split)?.ok_or(Error::InvalidFormat)?;
                 let mut inlined = Vec::new();
@@ -183,10 +1920,8 @@ impl Parser {
                 })
             }
             "a" => {
-                let size = u64::from_str_radix(split.next().ok_or(Error::InvalidFormat)?, 16)
-                    .map_err(|_| Error::InvalidFormat)?;
-                let trace_idx = u64::from_str_radix(split.next().ok_or(Error::InvalidFormat)?, 16)
-                    .map_err(|_| Error::InvalidFormat)?;
+                let size = hex::<u64>(&mut split)?;
+                let trace_idx = hex::<u64>(&mut split)?;
 
                 let allocation_idx = self.add_allocation(trace_idx);
                 self.data
@@ -194,11 +1929,16 @@ impl Parser {
                     .push(AllocationInfo::new(allocation_idx, size));
             }
             "+" => {
-                let allocation_info_idx =
-                    u64::from_str_radix(split.next().ok_or(Error::InvalidFormat)?, 16)
-                        .map_err(|_| Error::InvalidFormat)?;
+                let allocation_info_idx = hex::<u64>(&mut split)?;
 
-                let info = &mut self.data.allocation_infos[allocation_info_idx as usize];
+                let Some(info) = self
+                    .data
+                    .allocation_infos
+                    .get_mut(allocation_info_idx as usize)
+                else {
+                    self.unknown_allocation_info('+', allocation_info_idx);
+                    return Ok(None);
+                };
 
                 let allocation = self
                     .data
@@ -207,26 +1947,72 @@ impl Parser {
                     .ok_or_else(|| Error::Internal("allocation not found".into()))?;
 
                 self.last_ptr = info.allocation_idx;
+                info.allocations += 1;
+                let size = info.size;
 
-                allocation.data.leaked += info.size;
+                allocation.data.leaked += size;
                 if allocation.data.leaked > allocation.data.peak {
                     allocation.data.peak = allocation.data.leaked;
                 }
                 allocation.data.allocations += 1;
+                allocation.data.peak_live = allocation.data.peak_live.max(allocation.data.live());
+                if self.size_histograms {
+                    allocation.sizes.get_or_insert_default().add(size);
+                    self.data.sizes.get_or_insert_default().add(size);
+                }
 
-                self.data.total.leaked += info.size;
+                self.data.total.leaked += size;
                 self.data.total.allocations += 1;
+                self.data.total.peak_live = self.data.total.peak_live.max(self.data.total.live());
+                self.events += 1;
 
-                if self.data.total.leaked > self.data.total.peak {
+                let peaked = self.data.total.leaked > self.data.total.peak;
+                if peaked {
                     self.data.total.peak = self.data.total.leaked;
                 }
+
+                for transaction in &mut self.open_transactions {
+                    transaction.allocations += 1;
+                    transaction.allocated += size;
+                    transaction.peak = transaction.peak.max(transaction.retained().max(0) as u64);
+                }
+
+                let timestamp = self.record_timestamp(split.next())?;
+                applied = Applied {
+                    info_idx: allocation_info_idx,
+                    timestamp,
+                };
+                if peaked {
+                    self.data.peak_at = PeakInfo {
+                        event: self.events,
+                        time: self.data.duration,
+                        timestamp,
+                        live_allocations: self.data.total.live(),
+                    };
+                }
+                self.sample_series();
             }
             "-" => {
-                let allocation_info_idx =
-                    u64::from_str_radix(split.next().ok_or(Error::InvalidFormat)?, 16)
-                        .map_err(|_| Error::InvalidFormat)?;
+                let allocation_info_idx = hex::<u64>(&mut split)?;
 
-                let info = &mut self.data.allocation_infos[allocation_info_idx as usize];
+                let Some(info) = self
+                    .data
+                    .allocation_infos
+                    .get_mut(allocation_info_idx as usize)
+                else {
+                    self.unknown_allocation_info('-', allocation_info_idx);
+                    return Ok(None);
+                };
+                if info.frees == info.allocations {
+                    self.data.anomalies.push(Anomaly {
+                        line: self.line,
+                        kind: AnomalyKind::UnmatchedFree {
+                            idx: allocation_info_idx,
+                        },
+                    });
+                    return Ok(None);
+                }
+                info.frees += 1;
 
                 let allocation = self
                     .data
@@ -235,6 +2021,11 @@ impl Parser {
                     .ok_or_else(|| Error::Internal("allocation not found".into()))?;
 
                 self.data.total.leaked -= info.size;
+                self.data.total.frees += 1;
+                self.data.total.freed += info.size;
+                allocation.data.frees += 1;
+                allocation.data.freed += info.size;
+                self.events += 1;
 
                 let temporary = self.last_ptr == info.allocation_idx;
                 self.last_ptr = 0;
@@ -247,33 +2038,273 @@ impl Parser {
                 if temporary {
                     allocation.data.temporary += 1;
                 }
+
+                for transaction in &mut self.open_transactions {
+                    transaction.frees += 1;
+                    transaction.freed += info.size;
+                    if temporary {
+                        transaction.temporary += 1;
+                    }
+                }
+
+                let timestamp = split.next();
+                if let Some(owner_idx) = split.next_hex() {
+                    let owner = self.data.owners.entry(owner_idx as usize).or_default();
+                    owner.frees += 1;
+                    owner.freed += info.size;
+                    owner.leaked = owner.leaked.saturating_sub(info.size);
+                }
+
+                applied = Applied {
+                    info_idx: allocation_info_idx,
+                    timestamp: self.record_timestamp(timestamp)?,
+                };
+                self.sample_series();
+            }
+            "o" => {
+                let allocation_info_idx = hex::<u64>(&mut split)?;
+                let owner_idx = hex::<usize>(&mut split)?;
+
+                let Some(info) = self.data.allocation_infos.get(allocation_info_idx as usize)
+                else {
+                    self.unknown_allocation_info('o', allocation_info_idx);
+                    return Ok(None);
+                };
+                let size = info.size;
+
+                if let Some(previous_idx) = split.next_hex() {
+                    let previous = self.data.owners.entry(previous_idx as usize).or_default();
+                    previous.handed_over += 1;
+                    previous.leaked = previous.leaked.saturating_sub(size);
+                }
+                let owner = self.data.owners.entry(owner_idx).or_default();
+                owner.transfers += 1;
+                owner.transferred += size;
+                owner.leaked += size;
             }
             "c" => {
-                let timestamp = u64::from_str_radix(split.next().ok_or(Error::InvalidFormat)?, 16)
-                    .map_err(|_| Error::InvalidFormat)?;
+                let timestamp = hex::<u64>(&mut split)?;
                 self.data.duration = Duration::from_millis(timestamp);
+                self.data.timeline.push(TimelinePoint {
+                    timestamp: self.data.duration,
+                    leaked: self.data.total.leaked,
+                    allocations: self.data.total.allocations,
+                    rss: self.rss,
+                });
             }
             "R" => {
-                let rss = u64::from_str_radix(split.next().ok_or(Error::InvalidFormat)?, 16)
-                    .map_err(|_| Error::InvalidFormat)?;
+                let rss = hex::<u64>(&mut split)?;
+                self.rss = rss;
                 if rss > self.data.peak_rss {
                     self.data.peak_rss = rss;
                 }
+
+                applied.timestamp = self.record_timestamp(split.next())?;
             }
             "I" => {
-                self.data.page_size =
-                    u64::from_str_radix(split.next().ok_or(Error::InvalidFormat)?, 16)
-                        .map_err(|_| Error::InvalidFormat)?;
-                self.data.pages =
-                    u64::from_str_radix(split.next().ok_or(Error::InvalidFormat)?, 16)
-                        .map_err(|_| Error::InvalidFormat)?;
+                self.data.page_size = hex::<u64>(&mut split)?;
+                self.data.pages = hex::<u64>(&mut split)?;
+            }
+            "m" => {
+                let label_len = hex::<usize>(&mut split)?;
+                self.data.markers.push(Marker {
+                    label: tail(line, label_len)?,
+                    timestamp: self.data.duration,
+                });
+            }
+            "M" => {
+                let key = split.next().ok_or(Error::InvalidFormat)?;
+                let value_len = hex::<usize>(&mut split)?;
+                let value = tail(line, value_len)?;
+                if key == FREE_MISMATCHES_KEY {
+                    self.free_mismatch_anomalies(&value);
+                }
+                self.data.metadata.insert(key.to_string(), value);
+            }
+            "b" => {
+                let start = hex::<u64>(&mut split)?;
+                let label_len = hex::<usize>(&mut split)?;
+                self.open_transactions.push(Transaction {
+                    label: tail(line, label_len)?,
+                    start,
+                    ..Default::default()
+                });
+            }
+            "e" => {
+                let end = hex::<u64>(&mut split)?;
+                let label_len = hex::<usize>(&mut split)?;
+                let label = tail(line, label_len)?;
+                if let Some(pos) = self
+                    .open_transactions
+                    .iter()
+                    .rposition(|t| t.label == label)
+                {
+                    let mut transaction = self.open_transactions.remove(pos);
+                    transaction.end = end;
+                    self.data.transactions.push(transaction);
+                }
+            }
+            "r" => {
+                let allocation_info_idx = hex::<u64>(&mut split)?;
+
+                let Some(info) = self.data.allocation_infos.get(allocation_info_idx as usize)
+                else {
+                    self.unknown_allocation_info('r', allocation_info_idx);
+                    return Ok(None);
+                };
+
+                let allocation = self
+                    .data
+                    .allocations
+                    .get_mut(info.allocation_idx as usize)
+                    .ok_or_else(|| Error::Internal("allocation not found".into()))?;
+
+                allocation.data.reachable += info.size;
+                self.data.total.reachable += info.size;
+            }
+            "E" => {
+                let expected = TraceChecksum {
+                    records: hex::<u64>(&mut split)?,
+                    hash: hex::<u64>(&mut split)?,
+                };
+                self.data.complete = expected == self.checksum;
+                self.checksum = TraceChecksum::default();
+            }
+            "P" => {
+                let capacity = hex::<u64>(&mut split)?;
+                let name_len = hex::<usize>(&mut split)?;
+                self.data.pools.push(Pool {
+                    name: tail(line, name_len)?,
+                    capacity,
+                    ..Default::default()
+                });
+            }
+            "f" => {
+                let size = hex::<u64>(&mut split)?;
+                let trace_idx = hex::<u64>(&mut split)?;
+                let timestamp = split.next_hex().unwrap_or(0);
+
+                let failures = self.data.failed_allocations.entry(trace_idx).or_default();
+                if failures.count == 0 {
+                    failures.first_timestamp = timestamp;
+                }
+                failures.count += 1;
+                failures.bytes += size;
+                failures.largest = failures.largest.max(size);
+            }
+            tag @ ("p" | "q" | "D") => {
+                let pool_idx = hex::<u64>(&mut split)?;
+                let Some(pool) = self.data.pools.get_mut(pool_idx as usize) else {
+                    self.data.anomalies.push(Anomaly {
+                        line: self.line,
+                        kind: AnomalyKind::UnknownPool {
+                            record: tag.chars().next().unwrap_or_default(),
+                            idx: pool_idx,
+                        },
+                    });
+                    return Ok(None);
+                };
+
+                match tag {
+                    "p" => {
+                        pool.allocations += 1;
+                        pool.requested += hex::<u64>(&mut split)?;
+                        pool.peak_requested = pool.peak_requested.max(pool.requested);
+                    }
+                    "q" => {
+                        pool.frees += 1;
+                        pool.requested = pool.requested.saturating_sub(hex::<u64>(&mut split)?);
+                    }
+                    _ => {
+                        pool.requested = 0;
+                        pool.destroyed = true;
+                    }
+                }
+            }
+            "X" => {
+                let command = line.get(2..).ok_or(Error::InvalidFormat)?;
+                self.data.command = Some(String::from_utf8_lossy(command).into_owned());
             }
             "#" => {
                 // comment
             }
-            _ => {}
+            _ => {
+                let mut tag = first.chars();
+                let known =
+                    tag.next().and_then(|c| SCHEMA.record(c)).is_some() && tag.next().is_none();
+                if !known {
+                    self.unknown_record(line)?;
+                    return Ok(None);
+                }
+            }
+        }
+        Ok(Some(applied))
+    }
+
+    /// Records a `+` or `-` record referring to a missing allocation info.
+    /// The record is skipped so the totals stay consistent.
+    fn unknown_allocation_info(&mut self, record: char, idx: u64) {
+        self.data.anomalies.push(Anomaly {
+            line: self.line,
+            kind: AnomalyKind::UnknownAllocationInfo { record, idx },
+        });
+    }
+
+    fn free_mismatch_anomalies(&mut self, value: &str) {
+        let Ok(mismatches) = serde_json::from_str::<FreeMismatches>(value) else {
+            return;
+        };
+        if !mismatches.strict {
+            return;
+        }
+
+        for (kind, count) in [
+            (FreeMismatchKind::Untracked, mismatches.untracked),
+            (FreeMismatchKind::DoubleFree, mismatches.double_frees),
+        ] {
+            if count > 0 {
+                self.data.anomalies.push(Anomaly {
+                    line: self.line,
+                    kind: AnomalyKind::FreeMismatch { kind, count },
+                });
+            }
         }
-        Ok(())
+    }
+
+    /// Appends a point to `data.series` every `series_interval` events.
+    fn sample_series(&mut self) {
+        let Some(interval) = self.series_interval else {
+            return;
+        };
+        self.series_events += 1;
+        if self.series_events < interval {
+            return;
+        }
+        self.series_events = 0;
+        self.data.series.push(TimelinePoint {
+            timestamp: self.data.duration,
+            leaked: self.data.total.leaked,
+            allocations: self.data.total.allocations,
+            rss: self.rss,
+        });
+    }
+
+    /// Extends the clock range by the optional timestamp field of a record
+    /// and returns the timestamp, if any.
+    fn record_timestamp(&mut self, field: Option<&str>) -> Result<Option<u64>, Error> {
+        let Some(field) = field else {
+            return Ok(None);
+        };
+        let timestamp = parse_hex(field.as_bytes()).ok_or(Error::InvalidFormat)?;
+
+        let clock = self.data.clock.get_or_insert(ClockRange {
+            first: timestamp,
+            last: timestamp,
+        });
+        clock.first = clock.first.min(timestamp);
+        clock.last = clock.last.max(timestamp);
+
+        Ok(Some(timestamp))
     }
 
     fn add_allocation(&mut self, trace_idx: u64) -> u64 {
@@ -294,15 +2325,17 @@ impl Parser {
             return Ok(None);
         };
 
-        let function_idx = usize::from_str_radix(first, 16).map_err(|_| Error::InvalidFormat)?;
+        let function_idx = parse_hex(first.as_bytes()).ok_or(Error::InvalidFormat)? as usize;
 
         let Some(file_val) = iter.next() else {
             return Ok(Some(Frame::Single { function_idx }));
         };
 
-        let file_idx = usize::from_str_radix(file_val, 16).map_err(|_| Error::InvalidFormat)?;
-        let line_number = u32::from_str_radix(iter.next().ok_or(Error::InvalidFormat)?, 16)
-            .map_err(|_| Error::InvalidFormat)?;
+        let file_idx = parse_hex(file_val.as_bytes()).ok_or(Error::InvalidFormat)? as usize;
+        let line_number = iter
+            .next()
+            .and_then(|field| u32::try_from(parse_hex(field.as_bytes())?).ok())
+            .ok_or(Error::InvalidFormat)?;
 
         Ok(Some(Frame::Multiple {
             function_idx,
@@ -314,9 +2347,692 @@ impl Parser {
 
 #[cfg(test)]
 mod tests {
-    use crate::parser::Parser;
+    use crate::format::FILE_VERSION;
+    use crate::model::tests::{data, parse, TRACE};
+    use crate::model::{Metric, Profile};
+    use crate::parser::{
+        summarize, Anomaly, AnomalyKind, ClockRange, Error, EventFilter, ParsePolicy,
+        ParseWarningKind, ParsedRecord, Parser, PeakInfo, SizeHistogram, SteadyState, StreamParser,
+        TraceChecksum, CLOCK_OFFSET_KEY, SIZE_BUCKETS, SNIPPET_LEN,
+    };
+    use crate::report::{Report, ReportOptions};
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_concatenated() {
+        let path =
+            std::env::temp_dir().join(format!("memtrack-concat-{}.trace", std::process::id()));
+        // metadata after allocation data stays with its trace
+        let content = format!("{}M x 1 y\nc c8\n{}", TRACE, TRACE);
+        std::fs::write(&path, &content).unwrap();
+
+        let traces = Parser::new().parse_file_all(&path).unwrap();
+        let merged = Parser::new().parse_file(&path).unwrap();
+        _ = std::fs::remove_file(&path);
+
+        assert_eq!(traces.len(), 2);
+        assert_eq!(traces[0].metadata.len(), 2);
+        assert_eq!(traces[1].total.allocations, 3);
+        assert_eq!(traces[1].metadata.len(), 1);
+        assert_eq!(merged.total.allocations, 6);
+        let buffered = Parser::new().traces_reader(content.as_bytes());
+        assert_eq!(buffered.count(), 2);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_parse_gzip() {
+        use std::io::Write;
+
+        let path = std::env::temp_dir().join(format!("memtrack-{}.trace.gz", std::process::id()));
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+        encoder.write_all(TRACE.as_bytes()).unwrap();
+        std::fs::write(&path, encoder.finish().unwrap()).unwrap();
+
+        let parsed = Parser::new().parse_file(&path);
+        _ = std::fs::remove_file(&path);
+        assert_eq!(parsed.unwrap().total.leaked, data().total.leaked);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_parse_zstd() {
+        let path = std::env::temp_dir().join(format!("memtrack-{}.trace.zst", std::process::id()));
+        // a single segment frame holding the trace as one raw block
+        let size = TRACE.len() as u32;
+        let mut frame = vec![0x28, 0xb5, 0x2f, 0xfd, 0x20, size as u8];
+        frame.extend_from_slice(&(size << 3 | 1).to_le_bytes()[..3]);
+        frame.extend_from_slice(TRACE.as_bytes());
+        std::fs::write(&path, frame).unwrap();
+
+        let parsed = Parser::new().parse_file(&path);
+        _ = std::fs::remove_file(&path);
+        assert_eq!(parsed.unwrap().total.leaked, data().total.leaked);
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_parse_mmap() {
+        use crate::parser::MappedTrace;
+
+        let path = std::env::temp_dir().join(format!("memtrack-mmap-{}.trace", std::process::id()));
+        std::fs::write(&path, TRACE).unwrap();
+
+        let mapped = Parser::new().parse_mmap(&path).unwrap();
+        let trace = MappedTrace::open(&path).unwrap();
+        let strings: Vec<_> = trace.strings().collect();
+        _ = std::fs::remove_file(&path);
+
+        let data = data();
+        assert_eq!(mapped.total.leaked, data.total.leaked);
+        assert_eq!(mapped.metadata, data.metadata);
+        assert_eq!(strings, data.strings);
+        assert_eq!(trace.lines().count(), TRACE.lines().count());
+    }
+
+    #[test]
+    fn test_steady_state() {
+        let trace = "\
+v 1 3
+s 4 /bin
+s 4 main
+i 10 1 2
+t 1 0
+a 10 1
+a 100 1
++ 0
+c 3e8
+m 5 ready
++ 1
+- 0
++ 0
+c 7d0
++ 0
+c bb8
+c fa0
+- 0
+- 0
+- 1
+";
+        let options = SteadyState {
+            start_marker: Some("ready".to_string()),
+            skip_last: Duration::from_secs(1),
+            ..Default::default()
+        };
+        let data = Parser::new()
+            .with_steady_state(options)
+            .parse_reader(trace.as_bytes())
+            .unwrap();
+
+        assert_eq!(data.total.allocations, 3);
+        assert_eq!(data.total.frees, 0);
+        assert_eq!(data.total.leaked, 0x120);
+        assert_eq!(data.duration, Duration::from_secs(3));
+        assert_eq!(data.markers.len(), 1);
+        assert_eq!(parse(trace).total.allocations, 4);
+    }
+
+    #[test]
+    fn test_parse_summary() {
+        let data = data();
+        let summary = summarize(TRACE.as_bytes()).unwrap();
+        assert_eq!((data.total.frees, data.total.freed), (1, 0x10));
+        assert_eq!(data.allocations[0].data.freed, 0x10);
+        assert_eq!(data.total.peak_live, 3);
+        assert_eq!(data.allocations[0].data.peak_live, 2);
+
+        assert_eq!(summary.total.allocations, data.total.allocations);
+        assert_eq!(summary.total.temporary, data.total.temporary);
+        assert_eq!(summary.total.leaked, data.total.leaked);
+        assert_eq!(summary.total.peak, data.total.peak);
+        assert_eq!(summary.total.freed, data.total.freed);
+        assert_eq!(summary.total.peak_live, data.total.peak_live);
+        assert_eq!(summary.timeline, data.timeline);
+        assert_eq!(summary.peak_rss, data.peak_rss);
+        assert_eq!(summary.traces.len(), data.allocations.len());
+        for (allocation, (&trace_idx, cost)) in data.allocations.iter().zip(&summary.traces) {
+            assert_eq!(trace_idx, allocation.trace_idx);
+            assert_eq!(cost.leaked, allocation.data.leaked);
+            assert_eq!(cost.freed, allocation.data.freed);
+            assert_eq!(cost.peak_live, allocation.data.peak_live);
+        }
+
+        let top = summary.top(Metric::Allocations, 1);
+        assert_eq!(top.len(), 1);
+        assert_eq!(top[0].0, 3);
+    }
+
+    #[test]
+    fn test_parse_reader_with() {
+        let mut allocated = 0;
+        let mut freed_traces = Vec::new();
+        let mut metadata = Vec::new();
+        let data = Parser::new()
+            .parse_reader_with(TRACE.as_bytes(), |record| match record {
+                ParsedRecord::Alloc { size, .. } => allocated += size,
+                ParsedRecord::Free { trace_idx, .. } => freed_traces.push(trace_idx),
+                ParsedRecord::Metadata { key, value } => {
+                    metadata.push(format!("{}={}", key, value))
+                }
+                _ => {}
+            })
+            .unwrap();
+
+        assert_eq!(allocated, 0x40);
+        assert_eq!(freed_traces, [3]);
+        assert_eq!(metadata, ["rlimit.as=unlimited unlimited"]);
+        assert_eq!(data.total.leaked, 0x30);
+    }
+
+    /// `+` and `-` records returned by [`Parser::feed_line`], by tag and
+    /// allocation info.
+    fn fed_events(mut parser: Parser, trace: &str) -> Vec<(char, u64)> {
+        let mut events = Vec::new();
+        for line in trace.lines() {
+            match parser.feed_line(line.as_bytes()).unwrap() {
+                Some(ParsedRecord::Alloc { info_idx, .. }) => events.push(('+', info_idx)),
+                Some(ParsedRecord::Free { info_idx, .. }) => events.push(('-', info_idx)),
+                _ => {}
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn test_feed_line_skipped() {
+        let mut parser = Parser::new().with_policy(ParsePolicy::Lenient);
+        for line in TRACE.lines() {
+            parser.feed_line(line.as_bytes()).unwrap();
+        }
+        assert!(parser.feed_line(b"+ zz").unwrap().is_none());
+        assert!(parser.feed_line(b"s 10 short").unwrap().is_none());
+        assert!(parser.feed_line(b"+ 5").unwrap().is_none());
+        assert!(matches!(
+            parser.feed_line(b"+ 1 64").unwrap(),
+            Some(ParsedRecord::Alloc {
+                info_idx: 1,
+                size: 0x20,
+                timestamp: Some(0x64),
+                ..
+            })
+        ));
+
+        assert_eq!(
+            fed_events(Parser::new(), TRACE),
+            [('+', 0), ('+', 0), ('+', 1), ('-', 0)]
+        );
+        let filter = EventFilter {
+            min_size: 0x11,
+            ..Default::default()
+        };
+        assert_eq!(
+            fed_events(Parser::new().with_filter(filter), TRACE),
+            [('+', 1)]
+        );
+    }
+
+    #[test]
+    fn test_feed_line_steady_state() {
+        let trace = "\
+v 1 3
+s 4 main
+i 10 0 0
+t 1 0
+a 10 1
+a 20 1
++ 0
+c 3e8
+m 5 ready
++ 1
+- 0
++ 0
+c 7d0
+- 0
+- 1
+";
+        let options = |skip_last| SteadyState {
+            start_marker: Some("ready".to_string()),
+            skip_last,
+            ..Default::default()
+        };
+
+        // the free of the block allocated before the marker is excluded
+        let parser = Parser::new().with_steady_state(options(Duration::ZERO));
+        assert_eq!(
+            fed_events(parser, trace),
+            [('+', 1), ('+', 0), ('-', 0), ('-', 1)]
+        );
+
+        // the events are deferred until the end of the run is far enough
+        let parser = Parser::new().with_steady_state(options(Duration::from_secs(1)));
+        assert!(fed_events(parser, trace).is_empty());
+    }
+
+    #[test]
+    fn test_complete() {
+        assert!(!data().complete);
+
+        let mut checksum = TraceChecksum::default();
+        checksum.update(TRACE.as_bytes());
+        let mut trace = TRACE.as_bytes().to_vec();
+        checksum.write_record(&mut trace).unwrap();
+        let trace = String::from_utf8(trace).unwrap();
+
+        assert!(parse(&trace).complete);
+        assert!(!parse(&format!("{}+ 0\n", trace)).complete);
+        assert!(!parse(&trace.replace("R 1000", "R 2000")).complete);
+    }
+
+    #[test]
+    fn test_parse_timestamps() {
+        assert_eq!(data().clock, None);
+
+        let trace = |offset: i64| {
+            let offset = offset.to_string();
+            TRACE
+                .replace("+ 0\n+ 0\n+ 1\n- 0", "+ 0 64\n+ 0 c8\n+ 1 12c\n- 0 190")
+                .replace("R 1000", "R 1000 1f4")
+                .replace(
+                    "I 4000",
+                    &format!(
+                        "M {} {:x} {}\nI 4000",
+                        CLOCK_OFFSET_KEY,
+                        offset.len(),
+                        offset
+                    ),
+                )
+        };
+        let reference = parse(&trace(1000));
+        let mut other = parse(&trace(1500));
+
+        assert_eq!(
+            reference.clock,
+            Some(ClockRange {
+                first: 0x64,
+                last: 0x1f4
+            })
+        );
+
+        let skew = other.skew_to(&reference).unwrap();
+        assert_eq!(skew, 500);
+        other.apply_skew(skew);
+        assert_eq!(other.clock.unwrap().first, 0x64 + 500);
+        assert_eq!(other.skew_to(&reference), Some(0));
+    }
+
+    #[test]
+    fn test_parse_unknown_allocation_info() {
+        let reference = data();
+        let skipped = parse(&TRACE.replace("- 0\n", "+ 7\n- 0\n- 9\n"));
+
+        assert_eq!(skipped.total.allocations, reference.total.allocations);
+        assert_eq!(skipped.total.leaked, reference.total.leaked);
+        assert_eq!(skipped.anomalies.len(), 2);
+        assert_eq!(
+            skipped.anomalies[1].kind,
+            AnomalyKind::UnknownAllocationInfo {
+                record: '-',
+                idx: 9
+            }
+        );
+        assert_eq!(skipped.anomalies[1].line, skipped.anomalies[0].line + 2);
+    }
+
+    #[test]
+    fn test_parse_unmatched_free() {
+        let reference = data();
+        let trace = TRACE.replace("- 0\n", "- 0\n- 0\n- 1\n- 1\n");
+        let data = parse(&trace);
+
+        assert_eq!(data.total.frees, reference.total.frees + 2);
+        assert_eq!(data.total.leaked, 0);
+        let line = trace.lines().position(|l| l == "- 1").unwrap() as u64 + 2;
+        assert_eq!(
+            data.anomalies,
+            [Anomaly {
+                line,
+                kind: AnomalyKind::UnmatchedFree { idx: 1 }
+            }]
+        );
+
+        let summary = summarize(trace.as_bytes()).unwrap();
+        assert_eq!(summary.total.leaked, data.total.leaked);
+    }
+
+    #[test]
+    fn test_parse_invalid_record() {
+        let trace = TRACE.replace("a 20 4\n", "a 20\n");
+        let err = Parser::new().parse_reader(trace.as_bytes()).unwrap_err();
+
+        let line = TRACE.lines().position(|l| l == "a 20 4").unwrap() as u64 + 1;
+        assert!(matches!(
+            &err,
+            Error::InvalidRecord { line: l, tag, text } if *l == line && tag == "a" && text == "a 20"
+        ));
+        assert_eq!(
+            err.to_string(),
+            format!("line {}: invalid `a` record: a 20", line)
+        );
+
+        let long = format!("s ffff {}\n", "x".repeat(0x400));
+        let err = Parser::new().parse_reader(long.as_bytes()).unwrap_err();
+        let Error::InvalidRecord { text, .. } = err else {
+            panic!("unexpected error {}", err);
+        };
+        assert_eq!(text.len(), SNIPPET_LEN + 3);
+    }
+
+    #[test]
+    fn test_parse_version() {
+        let data = data();
+        assert_eq!((data.version, data.file_version), (1, 3));
+        assert_eq!(parse("v 1\n+ 0\n").file_version, 1);
+
+        let trace = TRACE.replace("v 1 3", &format!("v 1 {:x}", FILE_VERSION + 1));
+        let err = Parser::new().parse_reader(trace.as_bytes()).unwrap_err();
+        assert!(matches!(err, Error::UnsupportedVersion(v) if v == FILE_VERSION + 1));
+
+        let data = Parser::new()
+            .with_policy(ParsePolicy::CollectWarnings)
+            .parse_reader(trace.as_bytes())
+            .unwrap();
+        assert_eq!(data.total.allocations, 3);
+        assert_eq!(data.warnings[0].kind, ParseWarningKind::UnsupportedVersion);
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        let filtered = |filter| {
+            Parser::new()
+                .with_filter(filter)
+                .parse_reader(TRACE.as_bytes())
+                .unwrap()
+        };
+
+        let data = filtered(EventFilter {
+            metadata_only: true,
+            ..Default::default()
+        });
+        assert_eq!(data.strings.len(), 5);
+        assert_eq!(data.traces.len(), 4);
+        assert_eq!(data.allocations.len(), 2);
+        assert_eq!(data.total.allocations, 0);
+        assert!(data.timeline.is_empty());
+        assert_eq!(data.peak_rss, 0);
+
+        let data = filtered(EventFilter {
+            skip_allocations: true,
+            ..Default::default()
+        });
+        assert_eq!(data.total.allocations, 0);
+        assert_eq!(data.peak_rss, 0x1000);
+
+        let data = filtered(EventFilter {
+            min_size: 0x11,
+            ..Default::default()
+        });
+        assert_eq!(data.total.allocations, 1);
+        assert_eq!(data.total.leaked, 0x20);
+        assert!(data.anomalies.is_empty());
+    }
+
+    #[test]
+    fn test_event_series() {
+        let trace = format!("{}+ 1\n- 1\n", TRACE);
+        let data = Parser::new()
+            .with_event_series(2)
+            .parse_reader(trace.as_bytes())
+            .unwrap();
+
+        let points: Vec<_> = data
+            .series
+            .iter()
+            .map(|p| (p.timestamp, p.leaked, p.allocations))
+            .collect();
+        assert_eq!(
+            points,
+            [
+                (Duration::ZERO, 0x20, 2),
+                (Duration::ZERO, 0x30, 3),
+                (Duration::from_millis(100), 0x30, 4),
+            ]
+        );
+        assert!(parse(&trace).series.is_empty());
+    }
+
+    #[test]
+    fn test_peak_at() {
+        let data = data();
+        assert_eq!(
+            data.peak_at,
+            PeakInfo {
+                event: 3,
+                time: Duration::ZERO,
+                timestamp: None,
+                live_allocations: 3,
+            }
+        );
+
+        let data = parse(&format!("{}+ 1 3e8\n- 1\n", TRACE));
+        assert_eq!(data.total.peak, 0x50);
+        assert_eq!(
+            data.peak_at,
+            PeakInfo {
+                event: 5,
+                time: Duration::from_millis(100),
+                timestamp: Some(0x3e8),
+                live_allocations: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn test_size_histograms() {
+        assert_eq!(SizeHistogram::bucket(1), 0);
+        assert_eq!(SizeHistogram::bucket(0x10), 4);
+        assert_eq!(SizeHistogram::bucket(0x11), 5);
+        assert_eq!(SizeHistogram::bucket(u64::MAX), SIZE_BUCKETS - 1);
+        assert_eq!(SizeHistogram::upper_bound(5), Some(0x20));
+        assert_eq!(SizeHistogram::upper_bound(SIZE_BUCKETS - 1), None);
+
+        let data = Parser::new()
+            .with_size_histograms()
+            .parse_reader(TRACE.as_bytes())
+            .unwrap();
+        let sizes = data.allocations[0].sizes.as_deref().unwrap();
+        assert_eq!(sizes.count(), 2);
+        assert_eq!(sizes.buckets[4], 2);
+        let total = data.sizes.unwrap();
+        assert_eq!((total.buckets[4], total.buckets[5]), (2, 1));
+
+        assert!(data.allocations[1].sizes.is_some());
+        assert!(parse(TRACE).sizes.is_none());
+        assert!(parse(TRACE).allocations[0].sizes.is_none());
+    }
+
+    #[test]
+    fn test_parse_exec() {
+        let data = parse(&TRACE.replace("v 1 3\n", "v 1 3\nX ./app --size 2 <in>\n"));
+        assert_eq!(data.command.as_deref(), Some("./app --size 2 <in>"));
+        assert_eq!(parse(TRACE).command, None);
+
+        let profile = Profile::new(&data).unwrap();
+        let mut html = Vec::new();
+        Report::new(&data, &profile, &ReportOptions::default())
+            .write_html(&mut html)
+            .unwrap();
+        assert!(String::from_utf8(html)
+            .unwrap()
+            .contains("<code>./app --size 2 &lt;in&gt;</code>"));
+    }
+
+    #[test]
+    fn test_follow() {
+        let path =
+            std::env::temp_dir().join(format!("memtrack-follow-{}.trace", std::process::id()));
+        let (head, tail) = TRACE.split_at(TRACE.find("+ 1\n").unwrap());
+        std::fs::write(&path, format!("{}+ 1", head)).unwrap();
+
+        let mut follow = Parser::new()
+            .follow(&path)
+            .unwrap()
+            .with_interval(Duration::from_millis(1));
+        let update = follow.poll().unwrap().unwrap();
+        assert_eq!(update.lines, head.lines().count() as u64);
+        assert_eq!(update.total.allocations, 2);
+        assert!(follow.poll().unwrap().is_none());
+
+        // the partial line is parsed once completed
+        let mut checksum = TraceChecksum::default();
+        for line in TRACE.lines() {
+            checksum.update(line.as_bytes());
+            checksum.update(b"\n");
+        }
+        let mut rest = tail.as_bytes()["+ 1".len()..].to_vec();
+        checksum.write_record(&mut rest).unwrap();
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        std::io::Write::write_all(&mut file, &rest).unwrap();
+
+        let updates: Vec<_> = follow.by_ref().collect::<Result<_, _>>().unwrap();
+        _ = std::fs::remove_file(&path);
+        assert_eq!(updates.len(), 1);
+        assert!(updates[0].complete);
+        assert_eq!(updates[0].total.allocations, 3);
+        assert_eq!(updates[0].duration, Duration::from_millis(100));
+        assert!(follow.finish().unwrap().complete);
+    }
+
+    #[test]
+    fn test_follow_stdin() {
+        let err = Parser::new().follow("-").err().unwrap();
+        assert!(matches!(err, Error::Io(e) if e.kind() == std::io::ErrorKind::InvalidInput));
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        let trace = format!(
+            "{}Z 1 2
++ zz
++ 1
+",
+            TRACE
+        );
+        let lines = TRACE.lines().count() as u64;
+
+        let err = Parser::new().parse_reader(trace.as_bytes()).unwrap_err();
+        assert!(matches!(err, Error::InvalidRecord { line, .. } if line == lines + 2));
+
+        let err = Parser::new()
+            .with_policy(ParsePolicy::Strict)
+            .parse_reader(trace.as_bytes())
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::UnknownRecord { line, tag } if line == lines + 1 && tag == "Z")
+        );
+
+        let data = Parser::new()
+            .with_policy(ParsePolicy::Lenient)
+            .parse_reader(trace.as_bytes())
+            .unwrap();
+        assert_eq!(data.total.allocations, parse(TRACE).total.allocations + 1);
+        assert!(data.warnings.is_empty());
+
+        let data = Parser::new()
+            .with_policy(ParsePolicy::CollectWarnings)
+            .parse_reader(trace.as_bytes())
+            .unwrap();
+        let kinds: Vec<_> = data.warnings.iter().map(|w| w.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                ParseWarningKind::UnknownRecord,
+                ParseWarningKind::InvalidRecord
+            ]
+        );
+        assert_eq!(
+            data.warnings[1].to_string(),
+            format!("line {}: skipped invalid `+` record: + zz", lines + 2)
+        );
+    }
+
+    #[test]
+    fn test_parse_transactions() {
+        let trace = TRACE
+            .replace("+ 0\n+ 0\n", "b 1 5 req-1\n+ 0\n+ 0\ne 2 5 req-1\n")
+            .replace("+ 1\n- 0\n", "b 3 5 req-2\n+ 1\n- 0\ne 4 5 req-2\n");
+        let data = parse(&trace);
+
+        assert_eq!(data.transactions.len(), 2);
+        assert_eq!(data.transactions[0].label, "req-1");
+        assert_eq!(data.transactions[0].allocations, 2);
+        assert_eq!(data.transactions[0].allocated, 0x20);
+        assert_eq!(data.transactions[1].start, 3);
+        assert_eq!(data.transactions[1].freed, 0x10);
+        assert_eq!(data.transactions[1].retained(), 0x10);
+        assert_eq!(data.transactions[1].peak, 0x20);
+    }
+
+    #[test]
+    fn test_parse_nested_transactions() {
+        let trace = TRACE
+            .replace("+ 0\n+ 0\n", "b 1 5 outer\n+ 0\nb 2 5 inner\n+ 0\n")
+            .replace("+ 1\n- 0\n", "+ 1\ne 3 5 inner\n- 0\ne 4 5 outer\n");
+        let data = parse(&trace);
+
+        let [inner, outer] = &data.transactions[..] else {
+            panic!("{:?}", data.transactions);
+        };
+        assert_eq!(inner.label, "inner");
+        assert_eq!((inner.allocations, inner.allocated), (2, 0x30));
+        assert_eq!(inner.frees, 0);
+        assert_eq!(outer.label, "outer");
+        assert_eq!((outer.allocations, outer.allocated), (3, 0x40));
+        assert_eq!((outer.frees, outer.freed), (1, 0x10));
+        assert_eq!(outer.peak, 0x40);
+    }
+
+    #[test]
+    fn test_stream_parser() {
+        let mut stream = StreamParser::new();
+        for chunk in TRACE.as_bytes().chunks(7) {
+            stream.feed(chunk).unwrap();
+        }
+        let streamed = stream.finish().unwrap();
+        let parsed = data();
+
+        assert_eq!(streamed.total.allocations, parsed.total.allocations);
+        assert_eq!(streamed.total.leaked, parsed.total.leaked);
+        assert_eq!(streamed.strings, parsed.strings);
+        assert_eq!(streamed.peak_rss, parsed.peak_rss);
+    }
+
+    #[test]
+    fn test_parse_non_utf8() {
+        let mut trace = TRACE.replace("s 1 b\n", "s 3 \u{e9}b\n").into_bytes();
+        trace.extend_from_slice(b"s 2 a\xff\nm 2 x\xfe\n");
+        let mut stream = StreamParser::new();
+        stream.feed(&trace).unwrap();
+        let data = stream.finish().unwrap();
+
+        assert_eq!(data.strings[3], "\u{e9}b");
+        assert_eq!(data.strings[5], "a\u{fffd}");
+        assert_eq!(data.markers[0].label, "x\u{fffd}");
+
+        let mut stream = StreamParser::new();
+        assert!(stream.feed(b"s 10 short\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_metadata() {
+        let data = data();
+        assert_eq!(data.metadata["rlimit.as"], "unlimited unlimited");
+    }
 
     #[test]
+    #[ignore = "requires a local trace at /tmp/pipe.out"]
     fn test_read_trace_file() {
         let file = "/tmp/pipe.out";
         let data = Parser::new().parse_file(file).unwrap();
@@ -1,13 +1,84 @@
+use crate::format::binary::wire;
+use bincode::Options;
+use nix::time::{clock_gettime, ClockId};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::fs::File;
 use std::io;
-use std::io::{BufReader, BufWriter, Read, Write};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
 use std::num::ParseIntError;
 use thiserror::Error;
 
+/// Length prefix announcing a sequenced frame: a thread id (`u32`), a
+/// per-thread sequence number (`u64`) and the length of the record (`u16`)
+/// follow before the record itself. Records are at most 1024 bytes, so the
+/// value never is the length of a plain frame.
+pub const SEQUENCED_FRAME: u16 = u16::MAX;
+
+/// Largest write to a pipe the kernel keeps atomic: `PIPE_BUF` of Linux, and
+/// the POSIX minimum of 512 bytes elsewhere, e.g. on macOS.
+const PIPE_BUF: usize = if cfg!(target_os = "linux") { 4096 } else { 512 };
+
+/// Default number of frames held back per thread while waiting for a
+/// missing one, see [`PipeReader::with_reorder_window`].
+pub const DEFAULT_REORDER_WINDOW: usize = 64;
+
+/// Counters of the sequenced frames read by a [`PipeReader`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamStats {
+    /// Writer threads seen.
+    pub threads: usize,
+    pub sequenced: u64,
+    /// Frames that arrived ahead of an earlier frame of their thread and
+    /// were held back.
+    pub reordered: u64,
+    /// Frames with an already delivered sequence number, dropped.
+    pub duplicates: u64,
+    /// Runs of missing frames given up on.
+    pub gaps: u64,
+    /// Frames missing in those gaps.
+    pub lost: u64,
+}
+
+impl StreamStats {
+    /// Whether frames were lost or duplicated.
+    pub fn is_damaged(&self) -> bool {
+        self.gaps > 0 || self.duplicates > 0
+    }
+    /// Adds the counters of another connection of the same program.
+    pub(crate) fn add(&mut self, other: &StreamStats) {
+        self.threads = self.threads.max(other.threads);
+        self.sequenced += other.sequenced;
+        self.reordered += other.reordered;
+        self.duplicates += other.duplicates;
+        self.gaps += other.gaps;
+        self.lost += other.lost;
+    }
+}
+
+#[derive(Debug, Default)]
+struct ThreadStream {
+    next: u64,
+    held: BTreeMap<u64, Vec<u8>>,
+}
+
 pub struct PipeReader {
-    reader: BufReader<File>,
+    reader: BufReader<io::Chain<io::Cursor<Vec<u8>>, File>>,
     buf: [u8; 1024],
+    streams: HashMap<u32, ThreadStream>,
+    /// Sequenced frames released in order, not yet returned.
+    ready: VecDeque<Vec<u8>>,
+    /// The released frame last returned.
+    frame: Vec<u8>,
+    window: usize,
+    stats: StreamStats,
+}
+
+/// What [`PipeReader::read_frame`] read.
+enum Frame {
+    /// Plain frame of the given length in `buf`.
+    Plain(usize),
+    Sequenced,
 }
 
 #[derive(Debug, Error)]
@@ -24,115 +95,617 @@ impl From<ParseIntError> for Error {
     }
 }
 
+pub use crate::format::schema::PROTOCOL_VERSION;
+
+/// Clock used to timestamp records.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ClockSource {
+    /// `CLOCK_MONOTONIC`: never jumps, shared by all processes of a host.
+    #[default]
+    Monotonic,
+    /// `CLOCK_REALTIME`: comparable across hosts, but may jump.
+    Realtime,
+}
+
+impl ClockSource {
+    fn id(self) -> ClockId {
+        match self {
+            ClockSource::Monotonic => ClockId::CLOCK_MONOTONIC,
+            ClockSource::Realtime => ClockId::CLOCK_REALTIME,
+        }
+    }
+
+    /// Current time of the clock in nanoseconds.
+    pub fn now(self) -> u64 {
+        clock_gettime(self.id())
+            .map(|t| t.tv_sec() as u64 * 1_000_000_000 + t.tv_nsec() as u64)
+            .unwrap_or(0)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ClockSource::Monotonic => "monotonic",
+            ClockSource::Realtime => "realtime",
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Record {
     Version(u16),
-    Exec(String),
+    /// Command line of the program. Like the module path of
+    /// [`Record::Image`], the marker labels, pool names and transfer tags it
+    /// is not necessarily valid UTF-8, consumers convert it lossily.
+    Exec(Vec<u8>),
     Image {
-        name: String,
-        start_address: usize,
-        size: usize,
+        name: Vec<u8>,
+        start_address: u64,
+        size: u64,
     },
     PageInfo {
-        size: usize,
-        pages: usize,
+        size: u64,
+        pages: u64,
     },
     Trace {
-        ip: usize,
-        parent_idx: usize,
+        ip: u64,
+        parent_idx: u64,
     },
     Alloc {
-        ptr: usize,
-        size: usize,
-        parent_idx: usize,
+        ptr: u64,
+        size: u64,
+        parent_idx: u64,
+        timestamp: u64,
     },
     Free {
-        ptr: usize,
+        ptr: u64,
+        timestamp: u64,
     },
     Duration(u128),
-    RSS(usize),
+    RSS {
+        rss: u64,
+        timestamp: u64,
+    },
+    /// Clock of the record timestamps and its offset to the realtime clock
+    /// in nanoseconds, sent after the version.
+    Clock {
+        source: ClockSource,
+        realtime_offset: i64,
+    },
+    /// Start of a transaction, e.g. a request handled by a server. Events up
+    /// to the matching [`Record::MarkerEnd`] are attributed to it.
+    MarkerBegin {
+        label: Vec<u8>,
+        timestamp: u64,
+    },
+    MarkerEnd {
+        label: Vec<u8>,
+        timestamp: u64,
+    },
+    /// Sent at exit after scanning the globals and thread-local storage of
+    /// the program for pointers, followed by a [`Record::Reachable`] for
+    /// every live allocation found reachable from them.
+    RootScan {
+        roots: u64,
+    },
+    /// A live allocation still referenced at exit.
+    Reachable {
+        ptr: u64,
+    },
+    /// Declares a pool or arena of the program serving allocations of its
+    /// own out of `capacity` bytes, identified by `pool` until it is
+    /// destroyed. Declaring a live pool again replaces it.
+    PoolCreate {
+        pool: u64,
+        name: Vec<u8>,
+        capacity: u64,
+    },
+    /// Allocation served by a pool rather than by the allocator.
+    PoolAlloc {
+        pool: u64,
+        ptr: u64,
+        size: u64,
+    },
+    PoolFree {
+        pool: u64,
+        ptr: u64,
+    },
+    /// Destruction of a pool, releasing its remaining allocations.
+    PoolDestroy {
+        pool: u64,
+    },
+    /// Allocation of `size` bytes the allocator failed to serve.
+    AllocFailed {
+        size: u64,
+        parent_idx: u64,
+        timestamp: u64,
+    },
+    /// Hands the live allocation at `ptr` over to the component named
+    /// `tag`, which its free and its leak are then attributed to. Transferring
+    /// it again moves it to the new owner.
+    Transfer {
+        ptr: u64,
+        tag: Vec<u8>,
+    },
+}
+
+/// Borrowed view of a [`Record`] decoded without heap allocations. String
+/// and byte fields point into the reader's buffer and are only valid until
+/// the next read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum RecordRef<'a> {
+    Version(u16),
+    Exec(&'a [u8]),
+    Image {
+        name: &'a [u8],
+        start_address: u64,
+        size: u64,
+    },
+    PageInfo {
+        size: u64,
+        pages: u64,
+    },
+    Trace {
+        ip: u64,
+        parent_idx: u64,
+    },
+    Alloc {
+        ptr: u64,
+        size: u64,
+        parent_idx: u64,
+        timestamp: u64,
+    },
+    Free {
+        ptr: u64,
+        timestamp: u64,
+    },
+    Duration(u128),
+    RSS {
+        rss: u64,
+        timestamp: u64,
+    },
+    Clock {
+        source: ClockSource,
+        realtime_offset: i64,
+    },
+    MarkerBegin {
+        label: &'a [u8],
+        timestamp: u64,
+    },
+    MarkerEnd {
+        label: &'a [u8],
+        timestamp: u64,
+    },
+    RootScan {
+        roots: u64,
+    },
+    Reachable {
+        ptr: u64,
+    },
+    PoolCreate {
+        pool: u64,
+        name: &'a [u8],
+        capacity: u64,
+    },
+    PoolAlloc {
+        pool: u64,
+        ptr: u64,
+        size: u64,
+    },
+    PoolFree {
+        pool: u64,
+        ptr: u64,
+    },
+    PoolDestroy {
+        pool: u64,
+    },
+    AllocFailed {
+        size: u64,
+        parent_idx: u64,
+        timestamp: u64,
+    },
+    Transfer {
+        ptr: u64,
+        tag: &'a [u8],
+    },
+}
+
+impl Record {
+    /// Borrowed view of the record, see [`RecordRef`].
+    pub fn as_ref(&self) -> RecordRef<'_> {
+        match *self {
+            Record::Version(version) => RecordRef::Version(version),
+            Record::Exec(ref cmd) => RecordRef::Exec(cmd),
+            Record::Image {
+                ref name,
+                start_address,
+                size,
+            } => RecordRef::Image {
+                name,
+                start_address,
+                size,
+            },
+            Record::PageInfo { size, pages } => RecordRef::PageInfo { size, pages },
+            Record::Trace { ip, parent_idx } => RecordRef::Trace { ip, parent_idx },
+            Record::Alloc {
+                ptr,
+                size,
+                parent_idx,
+                timestamp,
+            } => RecordRef::Alloc {
+                ptr,
+                size,
+                parent_idx,
+                timestamp,
+            },
+            Record::Free { ptr, timestamp } => RecordRef::Free { ptr, timestamp },
+            Record::Duration(duration) => RecordRef::Duration(duration),
+            Record::RSS { rss, timestamp } => RecordRef::RSS { rss, timestamp },
+            Record::Clock {
+                source,
+                realtime_offset,
+            } => RecordRef::Clock {
+                source,
+                realtime_offset,
+            },
+            Record::MarkerBegin {
+                ref label,
+                timestamp,
+            } => RecordRef::MarkerBegin { label, timestamp },
+            Record::MarkerEnd {
+                ref label,
+                timestamp,
+            } => RecordRef::MarkerEnd { label, timestamp },
+            Record::RootScan { roots } => RecordRef::RootScan { roots },
+            Record::Reachable { ptr } => RecordRef::Reachable { ptr },
+            Record::PoolCreate {
+                pool,
+                ref name,
+                capacity,
+            } => RecordRef::PoolCreate {
+                pool,
+                name,
+                capacity,
+            },
+            Record::PoolAlloc { pool, ptr, size } => RecordRef::PoolAlloc { pool, ptr, size },
+            Record::PoolFree { pool, ptr } => RecordRef::PoolFree { pool, ptr },
+            Record::PoolDestroy { pool } => RecordRef::PoolDestroy { pool },
+            Record::AllocFailed {
+                size,
+                parent_idx,
+                timestamp,
+            } => RecordRef::AllocFailed {
+                size,
+                parent_idx,
+                timestamp,
+            },
+            Record::Transfer { ptr, ref tag } => RecordRef::Transfer { ptr, tag },
+        }
+    }
+}
+
+impl RecordRef<'_> {
+    pub fn to_owned(&self) -> Record {
+        match *self {
+            RecordRef::Version(version) => Record::Version(version),
+            RecordRef::Exec(cmd) => Record::Exec(cmd.to_vec()),
+            RecordRef::Image {
+                name,
+                start_address,
+                size,
+            } => Record::Image {
+                name: name.to_vec(),
+                start_address,
+                size,
+            },
+            RecordRef::PageInfo { size, pages } => Record::PageInfo { size, pages },
+            RecordRef::Trace { ip, parent_idx } => Record::Trace { ip, parent_idx },
+            RecordRef::Alloc {
+                ptr,
+                size,
+                parent_idx,
+                timestamp,
+            } => Record::Alloc {
+                ptr,
+                size,
+                parent_idx,
+                timestamp,
+            },
+            RecordRef::Free { ptr, timestamp } => Record::Free { ptr, timestamp },
+            RecordRef::Duration(duration) => Record::Duration(duration),
+            RecordRef::RSS { rss, timestamp } => Record::RSS { rss, timestamp },
+            RecordRef::Clock {
+                source,
+                realtime_offset,
+            } => Record::Clock {
+                source,
+                realtime_offset,
+            },
+            RecordRef::MarkerBegin { label, timestamp } => Record::MarkerBegin {
+                label: label.to_vec(),
+                timestamp,
+            },
+            RecordRef::MarkerEnd { label, timestamp } => Record::MarkerEnd {
+                label: label.to_vec(),
+                timestamp,
+            },
+            RecordRef::RootScan { roots } => Record::RootScan { roots },
+            RecordRef::Reachable { ptr } => Record::Reachable { ptr },
+            RecordRef::PoolCreate {
+                pool,
+                name,
+                capacity,
+            } => Record::PoolCreate {
+                pool,
+                name: name.to_vec(),
+                capacity,
+            },
+            RecordRef::PoolAlloc { pool, ptr, size } => Record::PoolAlloc { pool, ptr, size },
+            RecordRef::PoolFree { pool, ptr } => Record::PoolFree { pool, ptr },
+            RecordRef::PoolDestroy { pool } => Record::PoolDestroy { pool },
+            RecordRef::AllocFailed {
+                size,
+                parent_idx,
+                timestamp,
+            } => Record::AllocFailed {
+                size,
+                parent_idx,
+                timestamp,
+            },
+            RecordRef::Transfer { ptr, tag } => Record::Transfer {
+                ptr,
+                tag: tag.to_vec(),
+            },
+        }
+    }
 }
 
 impl PipeReader {
     pub fn new(file: File) -> Self {
+        Self::with_prefix(file, Vec::new())
+    }
+
+    /// Reads `prefix` before the content of `file`, for bytes already taken
+    /// from the pipe, e.g. while waiting for the writer to connect.
+    pub fn with_prefix(file: File, prefix: Vec<u8>) -> Self {
         Self {
-            reader: BufReader::with_capacity(4096, file),
+            reader: BufReader::with_capacity(4096, io::Cursor::new(prefix).chain(file)),
             buf: [0; 1024],
+            streams: HashMap::new(),
+            ready: VecDeque::new(),
+            frame: Vec::new(),
+            window: DEFAULT_REORDER_WINDOW,
+            stats: StreamStats::default(),
         }
     }
 
+    /// Sets how many frames of a thread are held back waiting for a missing
+    /// one before it is counted as lost.
+    pub fn with_reorder_window(mut self, window: usize) -> Self {
+        self.window = window.max(1);
+        self
+    }
+
+    pub fn stats(&self) -> StreamStats {
+        self.stats
+    }
+
     pub fn read_record(&mut self) -> Option<Result<Record, Error>> {
+        Some(self.read_record_ref()?.map(|record| record.to_owned()))
+    }
+
+    /// Whether the writer closed the pipe and all records were read. Blocks
+    /// until either more data arrives or the pipe is closed.
+    pub fn is_eof(&mut self) -> io::Result<bool> {
+        if !self.ready.is_empty() || self.streams.values().any(|s| !s.held.is_empty()) {
+            return Ok(false);
+        }
+        Ok(self.reader.fill_buf()?.is_empty())
+    }
+
+    /// Reads the next record without allocating, see [`RecordRef`].
+    /// Sequenced frames are returned in the order of their thread; frames
+    /// still missing at the end of the stream are skipped.
+    pub fn read_record_ref(&mut self) -> Option<Result<RecordRef<'_>, Error>> {
+        loop {
+            if let Some(frame) = self.ready.pop_front() {
+                self.frame = frame;
+                return Some(decode(&self.frame));
+            }
+
+            match self.read_frame() {
+                None if self.release_held() => {}
+                None => return None,
+                Some(Err(e)) => return Some(Err(e)),
+                Some(Ok(Frame::Plain(len))) => return Some(decode(&self.buf[..len])),
+                Some(Ok(Frame::Sequenced)) => {}
+            }
+        }
+    }
+
+    fn read_frame(&mut self) -> Option<Result<Frame, Error>> {
         let mut length_buf = [0u8; 2];
-        if let Err(_) = self.reader.read_exact(&mut length_buf) {
+        if self.reader.read_exact(&mut length_buf).is_err() {
             return None;
         }
-        let len = u16::from_le_bytes(length_buf) as usize;
+        let len = u16::from_le_bytes(length_buf);
 
-        let mut buf = &mut self.buf[..len];
-        if let Err(e) = self.reader.read_exact(&mut buf) {
+        if len != SEQUENCED_FRAME {
+            let len = len as usize;
+            let Some(buf) = self.buf.get_mut(..len) else {
+                return Some(Err(Error::InvalidFormat));
+            };
+            if let Err(e) = self.reader.read_exact(buf) {
+                return Some(Err(e.into()));
+            }
+            return Some(Ok(Frame::Plain(len)));
+        }
+
+        let mut header = [0u8; 14];
+        if let Err(e) = self.reader.read_exact(&mut header) {
             return Some(Err(e.into()));
         }
+        let thread = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let seq = u64::from_le_bytes(header[4..12].try_into().unwrap());
+        let len = u16::from_le_bytes(header[12..].try_into().unwrap()) as usize;
+        if len > self.buf.len() {
+            return Some(Err(Error::InvalidFormat));
+        }
 
-        let record = bincode::deserialize(&buf).map_err(|_| Error::InvalidFormat);
+        let mut payload = vec![0; len];
+        if let Err(e) = self.reader.read_exact(&mut payload) {
+            return Some(Err(e.into()));
+        }
+        self.sequence(thread, seq, payload);
 
-        Some(record)
+        Some(Ok(Frame::Sequenced))
     }
+
+    /// Releases the frame and the held frames following it once it is the
+    /// next of its thread.
+    fn sequence(&mut self, thread: u32, seq: u64, payload: Vec<u8>) {
+        self.stats.sequenced += 1;
+        if !self.streams.contains_key(&thread) {
+            self.stats.threads += 1;
+        }
+        let stream = self.streams.entry(thread).or_default();
+
+        if seq < stream.next || stream.held.contains_key(&seq) {
+            self.stats.duplicates += 1;
+            return;
+        }
+
+        if seq == stream.next {
+            self.ready.push_back(payload);
+            stream.next += 1;
+        } else {
+            self.stats.reordered += 1;
+            stream.held.insert(seq, payload);
+            if stream.held.len() > self.window {
+                // give up on the missing frames
+                let first = *stream.held.keys().next().unwrap();
+                self.stats.gaps += 1;
+                self.stats.lost += first - stream.next;
+                stream.next = first;
+            }
+        }
+
+        while let Some(payload) = stream.held.remove(&stream.next) {
+            self.ready.push_back(payload);
+            stream.next += 1;
+        }
+    }
+
+    /// Releases the frames held at the end of the stream, skipping the
+    /// missing ones. Returns whether there were any.
+    fn release_held(&mut self) -> bool {
+        let mut threads: Vec<_> = self.streams.keys().copied().collect();
+        threads.sort_unstable();
+
+        for thread in threads {
+            let stream = self.streams.get_mut(&thread).unwrap();
+            for (seq, payload) in std::mem::take(&mut stream.held) {
+                if seq > stream.next {
+                    self.stats.gaps += 1;
+                    self.stats.lost += seq - stream.next;
+                }
+                self.ready.push_back(payload);
+                stream.next = seq + 1;
+            }
+        }
+
+        !self.ready.is_empty()
+    }
+}
+
+fn decode(frame: &[u8]) -> Result<RecordRef<'_>, Error> {
+    wire().deserialize(frame).map_err(|_| Error::InvalidFormat)
 }
 
 pub struct PipeWriter {
     writer: BufWriter<File>,
+    clock: ClockSource,
+    /// Thread id and next sequence number of a sequenced writer.
+    sequence: Option<(u32, u64)>,
+    frame: Vec<u8>,
 }
 
 impl PipeWriter {
     pub fn new(file: File) -> Self {
+        Self::with_clock(file, ClockSource::default())
+    }
+
+    pub fn with_clock(file: File, clock: ClockSource) -> Self {
         Self {
-            writer: BufWriter::with_capacity(4096, file),
+            writer: BufWriter::with_capacity(PIPE_BUF, file),
+            clock,
+            sequence: None,
+            frame: Vec::new(),
         }
     }
 
+    /// Writes [sequenced frames](SEQUENCED_FRAME) tagged with `thread`, for
+    /// writers sharing the pipe with writers of other threads.
+    pub fn with_thread(mut self, thread: u32) -> Self {
+        self.sequence = Some((thread, 0));
+        self
+    }
+
     pub fn write_version(&mut self, version: u16) {
         let record = Record::Version(version);
-        self.write_record(record)
+        self.write_record(record);
+
+        let realtime_offset = ClockSource::Realtime.now() as i64 - self.clock.now() as i64;
+        self.write_record(Record::Clock {
+            source: self.clock,
+            realtime_offset,
+        })
     }
 
-    pub fn write_image(&mut self, name: String, start_address: usize, size: usize) {
+    pub fn write_image(&mut self, name: impl Into<Vec<u8>>, start_address: usize, size: usize) {
         let record = Record::Image {
-            name,
-            start_address,
-            size,
+            name: name.into(),
+            start_address: start_address as u64,
+            size: size as u64,
         };
         self.write_record(record)
     }
 
-    pub fn write_exec(&mut self, ex: &str) {
-        let record = Record::Exec(ex.to_string());
+    pub fn write_exec(&mut self, ex: impl AsRef<[u8]>) {
+        let record = Record::Exec(ex.as_ref().to_vec());
         self.write_record(record)
     }
 
     pub fn write_page_info(&mut self, page_size: usize, phys_pages: usize) {
         let record = Record::PageInfo {
-            size: page_size,
-            pages: phys_pages,
+            size: page_size as u64,
+            pages: phys_pages as u64,
         };
         self.write_record(record)
     }
 
     pub fn write_trace(&mut self, ip: usize, parent_idx: usize) {
-        let record = Record::Trace { ip, parent_idx };
+        let record = Record::Trace {
+            ip: ip as u64,
+            parent_idx: parent_idx as u64,
+        };
         self.write_record(record)
     }
 
     pub fn write_alloc(&mut self, size: usize, parent_idx: usize, ptr: usize) {
         let record = Record::Alloc {
-            ptr,
-            size,
-            parent_idx,
+            ptr: ptr as u64,
+            size: size as u64,
+            parent_idx: parent_idx as u64,
+            timestamp: self.clock.now(),
         };
         self.write_record(record)
     }
 
     pub fn write_free(&mut self, ptr: usize) {
-        let record = Record::Free { ptr };
+        let record = Record::Free {
+            ptr: ptr as u64,
+            timestamp: self.clock.now(),
+        };
         self.write_record(record)
     }
 
@@ -142,14 +715,105 @@ impl PipeWriter {
     }
 
     pub fn write_rss(&mut self, rss: usize) {
-        let record = Record::RSS(rss);
+        let record = Record::RSS {
+            rss: rss as u64,
+            timestamp: self.clock.now(),
+        };
+        self.write_record(record)
+    }
+
+    pub fn write_marker_begin(&mut self, label: impl AsRef<[u8]>) {
+        let record = Record::MarkerBegin {
+            label: label.as_ref().to_vec(),
+            timestamp: self.clock.now(),
+        };
+        self.write_record(record)
+    }
+
+    pub fn write_marker_end(&mut self, label: impl AsRef<[u8]>) {
+        let record = Record::MarkerEnd {
+            label: label.as_ref().to_vec(),
+            timestamp: self.clock.now(),
+        };
+        self.write_record(record)
+    }
+
+    pub fn write_root_scan(&mut self, roots: usize) {
+        self.write_record(Record::RootScan {
+            roots: roots as u64,
+        })
+    }
+
+    pub fn write_reachable(&mut self, ptr: usize) {
+        self.write_record(Record::Reachable { ptr: ptr as u64 })
+    }
+
+    pub fn write_pool_create(&mut self, pool: usize, name: impl AsRef<[u8]>, capacity: usize) {
+        let record = Record::PoolCreate {
+            pool: pool as u64,
+            name: name.as_ref().to_vec(),
+            capacity: capacity as u64,
+        };
         self.write_record(record)
     }
 
+    pub fn write_pool_alloc(&mut self, pool: usize, ptr: usize, size: usize) {
+        self.write_record(Record::PoolAlloc {
+            pool: pool as u64,
+            ptr: ptr as u64,
+            size: size as u64,
+        })
+    }
+
+    pub fn write_pool_free(&mut self, pool: usize, ptr: usize) {
+        self.write_record(Record::PoolFree {
+            pool: pool as u64,
+            ptr: ptr as u64,
+        })
+    }
+
+    pub fn write_pool_destroy(&mut self, pool: usize) {
+        self.write_record(Record::PoolDestroy { pool: pool as u64 })
+    }
+
+    pub fn write_alloc_failed(&mut self, size: usize, parent_idx: usize) {
+        let record = Record::AllocFailed {
+            size: size as u64,
+            parent_idx: parent_idx as u64,
+            timestamp: self.clock.now(),
+        };
+        self.write_record(record)
+    }
+
+    pub fn write_transfer(&mut self, ptr: usize, tag: impl AsRef<[u8]>) {
+        self.write_record(Record::Transfer {
+            ptr: ptr as u64,
+            tag: tag.as_ref().to_vec(),
+        })
+    }
+
     fn write_record(&mut self, record: Record) {
-        let s = bincode::serialize(&record).unwrap();
-        _ = self.writer.write_all(&(s.len() as u16).to_le_bytes());
-        _ = self.writer.write_all(&s);
+        let s = wire().serialize(&record).unwrap();
+
+        self.frame.clear();
+        if let Some((thread, seq)) = &mut self.sequence {
+            self.frame.extend_from_slice(&SEQUENCED_FRAME.to_le_bytes());
+            self.frame.extend_from_slice(&thread.to_le_bytes());
+            self.frame.extend_from_slice(&seq.to_le_bytes());
+            *seq += 1;
+        }
+        self.frame
+            .extend_from_slice(&(s.len() as u16).to_le_bytes());
+        self.frame.extend_from_slice(&s);
+
+        // frames never straddle a flush and flushes never exceed PIPE_BUF,
+        // so writers sharing the pipe do not interleave their frames. Only
+        // a frame longer than PIPE_BUF on its own, e.g. of a long image
+        // name, is not atomic.
+        if self.writer.capacity() - self.writer.buffer().len() < self.frame.len() {
+            _ = self.writer.flush();
+        }
+        _ = self.writer.write_all(&self.frame);
     }
 
     pub fn flush(&mut self) {
@@ -157,12 +821,315 @@ impl PipeWriter {
     }
 }
 
+/// Command sent to the injected library over the control fifo, whose path is
+/// passed in `CONTROL_FILEPATH`. Commands are framed like records.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Command {
+    /// Stop recording, flush and close the record fifo. The program keeps
+    /// running untraced.
+    Stop,
+    /// Stop recording allocations and frees until [`Command::Resume`].
+    /// Frees of allocations made before the pause are still recorded.
+    Pause,
+    Resume,
+    /// Flush the buffered records.
+    Flush,
+    /// Record each allocation with the given probability, see
+    /// [`Sampling`](crate::analysis::sampling::Sampling).
+    SetSampling(f64),
+    /// Unwind the full stack only for allocations of at least the given
+    /// size; smaller ones are recorded with their allocating frame only.
+    SetStackThreshold(u64),
+}
+
+pub struct CommandWriter {
+    file: File,
+}
+
+impl CommandWriter {
+    pub fn new(file: File) -> Self {
+        Self { file }
+    }
+
+    pub fn write_command(&mut self, command: &Command) -> io::Result<()> {
+        let s = wire().serialize(command).map_err(io::Error::other)?;
+        let mut frame = (s.len() as u16).to_le_bytes().to_vec();
+        frame.extend_from_slice(&s);
+        // a single write keeps frames of small commands atomic
+        self.file.write_all(&frame)
+    }
+}
+
+pub struct CommandReader {
+    reader: BufReader<File>,
+}
+
+impl CommandReader {
+    pub fn new(file: File) -> Self {
+        Self {
+            reader: BufReader::new(file),
+        }
+    }
+
+    /// Reads the next command. Open the fifo non-blocking to poll it, a
+    /// `WouldBlock` error means no command is pending.
+    pub fn read_command(&mut self) -> Option<Result<Command, Error>> {
+        let mut length_buf = [0u8; 2];
+        match self.reader.read_exact(&mut length_buf) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return None,
+            Err(e) => return Some(Err(e.into())),
+        }
+        let mut buf = vec![0; u16::from_le_bytes(length_buf) as usize];
+        if let Err(e) = self.reader.read_exact(&mut buf) {
+            return Some(Err(e.into()));
+        }
+
+        Some(wire().deserialize(&buf).map_err(|_| Error::InvalidFormat))
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::pipe_io::PipeReader;
+    use crate::pipe_io::{
+        Command, CommandReader, CommandWriter, PipeReader, PipeWriter, Record, RecordRef,
+        StreamStats, SEQUENCED_FRAME,
+    };
     use std::fs::OpenOptions;
 
+    fn frame(sequence: Option<(u32, u64)>, duration: u128) -> Vec<u8> {
+        let s = bincode::serialize(&Record::Duration(duration)).unwrap();
+        let mut frame = Vec::new();
+        if let Some((thread, seq)) = sequence {
+            frame.extend_from_slice(&SEQUENCED_FRAME.to_le_bytes());
+            frame.extend_from_slice(&thread.to_le_bytes());
+            frame.extend_from_slice(&seq.to_le_bytes());
+        }
+        frame.extend_from_slice(&(s.len() as u16).to_le_bytes());
+        frame.extend_from_slice(&s);
+        frame
+    }
+
+    #[test]
+    fn test_decode_borrowed() {
+        let record = Record::Image {
+            name: "/usr/lib/libc.so".into(),
+            start_address: 0x1000,
+            size: 0x2000,
+        };
+        let encoded = bincode::serialize(&record).unwrap();
+
+        let decoded: RecordRef = bincode::deserialize(&encoded).unwrap();
+        assert_eq!(
+            decoded,
+            RecordRef::Image {
+                name: b"/usr/lib/libc.so",
+                start_address: 0x1000,
+                size: 0x2000,
+            }
+        );
+        assert_eq!(bincode::serialize(&decoded.to_owned()).unwrap(), encoded);
+        assert_eq!(record.as_ref(), decoded);
+    }
+
+    #[test]
+    fn test_non_utf8_names() {
+        let path = std::env::temp_dir().join(format!("memtrack-non-utf8-{}", std::process::id()));
+        let mut writer = PipeWriter::new(std::fs::File::create(&path).unwrap());
+        writer.write_exec(b"/bin/caf\xe9 --x");
+        writer.write_image(b"/lib/lib\xff.so".to_vec(), 0x1000, 0x2000);
+        writer.write_marker_begin(b"req-\xfe");
+        writer.write_pool_create(1, b"arena-\xfd", 0x100);
+        writer.write_transfer(0x10, b"cache-\xfc");
+        drop(writer);
+
+        let mut reader = PipeReader::new(std::fs::File::open(&path).unwrap());
+        let exec = reader.read_record().unwrap().unwrap();
+        let image = reader.read_record_ref().unwrap().unwrap().to_owned();
+        let marker = reader.read_record().unwrap().unwrap();
+        let pool = reader.read_record().unwrap().unwrap();
+        let transfer = reader.read_record().unwrap().unwrap();
+        assert!(reader.read_record().is_none());
+        _ = std::fs::remove_file(&path);
+
+        assert!(matches!(exec, Record::Exec(ref cmd) if cmd == b"/bin/caf\xe9 --x"));
+        assert_eq!(
+            image.as_ref(),
+            RecordRef::Image {
+                name: b"/lib/lib\xff.so",
+                start_address: 0x1000,
+                size: 0x2000,
+            }
+        );
+        assert!(matches!(marker, Record::MarkerBegin { ref label, .. } if label == b"req-\xfe"));
+        assert!(matches!(pool, Record::PoolCreate { ref name, .. } if name == b"arena-\xfd"));
+        assert!(matches!(transfer, Record::Transfer { ref tag, .. } if tag == b"cache-\xfc"));
+    }
+
+    #[test]
+    fn test_command_roundtrip() {
+        let path = std::env::temp_dir().join(format!("memtrack-commands-{}", std::process::id()));
+        let mut writer = CommandWriter::new(std::fs::File::create(&path).unwrap());
+        writer.write_command(&Command::Stop).unwrap();
+        writer.write_command(&Command::SetSampling(0.25)).unwrap();
+
+        let mut reader = CommandReader::new(std::fs::File::open(&path).unwrap());
+        let commands = [
+            reader.read_command(),
+            reader.read_command(),
+            reader.read_command(),
+        ];
+        _ = std::fs::remove_file(&path);
+
+        let [stop, sampling, end] = commands;
+        assert_eq!(stop.unwrap().unwrap(), Command::Stop);
+        assert_eq!(sampling.unwrap().unwrap(), Command::SetSampling(0.25));
+        assert!(end.is_none());
+    }
+
+    #[test]
+    fn test_sequenced_frames() {
+        let path = std::env::temp_dir().join(format!("memtrack-frames-{}", std::process::id()));
+        let frames = [
+            frame(Some((1, 0)), 10),
+            frame(None, 0),
+            frame(Some((1, 2)), 12),
+            frame(Some((2, 0)), 20),
+            frame(Some((1, 1)), 11),
+            frame(Some((2, 0)), 20),
+            frame(Some((2, 2)), 22),
+        ];
+        std::fs::write(&path, frames.concat()).unwrap();
+
+        let mut reader = PipeReader::new(std::fs::File::open(&path).unwrap());
+        let mut durations = Vec::new();
+        while let Some(record) = reader.read_record() {
+            match record.unwrap() {
+                Record::Duration(duration) => durations.push(duration),
+                record => panic!("unexpected {:?}", record),
+            }
+        }
+        assert_eq!(durations, [10, 0, 20, 11, 12, 22]);
+        assert_eq!(
+            reader.stats(),
+            StreamStats {
+                threads: 2,
+                sequenced: 6,
+                reordered: 2,
+                duplicates: 1,
+                gaps: 1,
+                lost: 1,
+            }
+        );
+
+        let mut writer = PipeWriter::new(std::fs::File::create(&path).unwrap()).with_thread(7);
+        writer.write_duration(1);
+        writer.write_duration(2);
+        writer.flush();
+        let mut reader = PipeReader::new(std::fs::File::open(&path).unwrap());
+        let records = [reader.read_record(), reader.read_record()];
+        _ = std::fs::remove_file(&path);
+
+        assert!(matches!(
+            records,
+            [Some(Ok(Record::Duration(1))), Some(Ok(Record::Duration(2)))]
+        ));
+        assert_eq!(reader.stats().threads, 1);
+        assert!(!reader.stats().is_damaged());
+    }
+
+    #[test]
+    fn test_fixed_width_records() {
+        let path = std::env::temp_dir().join(format!("memtrack-fixed-{}", std::process::id()));
+        let frame = |variant: u32, fields: &[u64]| {
+            let mut payload = variant.to_le_bytes().to_vec();
+            for field in fields {
+                payload.extend_from_slice(&field.to_le_bytes());
+            }
+            let mut frame = (payload.len() as u16).to_le_bytes().to_vec();
+            frame.extend_from_slice(&payload);
+            frame
+        };
+        // records of a 32-bit writer: every integer widened to 64 bits
+        let trace = frame(4, &[0x8000_1000, 1]);
+        let alloc = frame(5, &[0xdead_beef, 24, 2, 7]);
+
+        let mut writer = PipeWriter::new(std::fs::File::create(&path).unwrap());
+        writer.write_trace(0x8000_1000, 1);
+        writer.flush();
+        assert_eq!(std::fs::read(&path).unwrap(), trace);
+
+        std::fs::write(&path, [trace, alloc, frame(4, &[u64::MAX, 2])].concat()).unwrap();
+        let mut reader = PipeReader::new(std::fs::File::open(&path).unwrap());
+        let mut records = Vec::new();
+        while let Some(record) = reader.read_record_ref() {
+            records.push(format!("{:?}", record.unwrap()));
+        }
+        _ = std::fs::remove_file(&path);
+
+        let expected = [
+            RecordRef::Trace {
+                ip: 0x8000_1000,
+                parent_idx: 1,
+            },
+            RecordRef::Alloc {
+                ptr: 0xdead_beef,
+                size: 24,
+                parent_idx: 2,
+                timestamp: 7,
+            },
+            // addresses beyond the pointer width of the reader
+            RecordRef::Trace {
+                ip: u64::MAX,
+                parent_idx: 2,
+            },
+        ]
+        .map(|record| format!("{:?}", record));
+        assert_eq!(records, expected);
+    }
+
+    #[test]
+    fn test_prefixed_reader() {
+        let path = std::env::temp_dir().join(format!("memtrack-prefix-{}", std::process::id()));
+        let first = frame(None, 1);
+        let (head, tail) = first.split_at(1);
+        std::fs::write(&path, [tail, &frame(None, 2)].concat()).unwrap();
+
+        let file = std::fs::File::open(&path).unwrap();
+        let mut reader = PipeReader::with_prefix(file, head.to_vec());
+        let records = [
+            reader.read_record(),
+            reader.read_record(),
+            reader.read_record(),
+        ];
+        _ = std::fs::remove_file(&path);
+
+        assert!(matches!(
+            records,
+            [
+                Some(Ok(Record::Duration(1))),
+                Some(Ok(Record::Duration(2))),
+                None
+            ]
+        ));
+
+        let mut stats = StreamStats {
+            threads: 2,
+            gaps: 1,
+            lost: 3,
+            ..StreamStats::default()
+        };
+        stats.add(&StreamStats {
+            threads: 1,
+            gaps: 1,
+            lost: 1,
+            ..StreamStats::default()
+        });
+        assert_eq!((stats.threads, stats.gaps, stats.lost), (2, 2, 4));
+    }
+
     #[test]
+    #[ignore = "requires a local record stream at /tmp/trace"]
     fn test_read_record() {
         let file = OpenOptions::new().read(true).open("/tmp/trace").unwrap();
         let mut reader = PipeReader::new(file);
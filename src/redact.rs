@@ -0,0 +1,153 @@
+//! Redaction of secrets from the command line and the environment recorded
+//! in a trace.
+//!
+//! Credentials are often passed as arguments or variables, so traces meant
+//! to be archived or shared can have them replaced by [`REDACTED`] when
+//! they are written, see [`Interpreter::set_redaction`], or afterwards with
+//! [`format::redact`].
+//!
+//! [`Interpreter::set_redaction`]: crate::interpret::Interpreter::set_redaction
+//! [`format::redact`]: crate::format::redact
+
+use std::borrow::Cow;
+
+pub const REDACTED: &str = "<redacted>";
+
+/// Names of options and variables holding secrets, see
+/// [`Redaction::secret_names`].
+pub const DEFAULT_SECRET_NAMES: &[&str] = &[
+    "password",
+    "passwd",
+    "secret",
+    "token",
+    "api_key",
+    "apikey",
+    "auth",
+    "credential",
+    "private_key",
+];
+
+/// Prefix of the metadata keys of captured variables, see
+/// [`EnvCapture`](crate::environment::EnvCapture).
+const ENV_PREFIX: &str = "env.";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redaction {
+    /// Substrings of the names of options and variables whose values are
+    /// secrets, matched case-insensitively.
+    pub secret_names: Vec<String>,
+    /// Values redacted wherever they appear, e.g. a known token.
+    pub patterns: Vec<String>,
+}
+
+impl Default for Redaction {
+    fn default() -> Self {
+        Self {
+            secret_names: DEFAULT_SECRET_NAMES.iter().map(|n| n.to_string()).collect(),
+            patterns: Vec::new(),
+        }
+    }
+}
+
+impl Redaction {
+    pub fn with_pattern(mut self, pattern: &str) -> Self {
+        if !pattern.is_empty() {
+            self.patterns.push(pattern.to_string());
+        }
+        self
+    }
+
+    fn is_secret(&self, name: &str) -> bool {
+        let name = name.trim_start_matches('-').to_ascii_lowercase();
+        !name.is_empty()
+            && self
+                .secret_names
+                .iter()
+                .any(|secret| name.contains(&secret.to_ascii_lowercase()))
+    }
+
+    fn redact_patterns<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        let mut value = Cow::Borrowed(value);
+        for pattern in &self.patterns {
+            if value.contains(pattern.as_str()) {
+                value = Cow::Owned(value.replace(pattern.as_str(), REDACTED));
+            }
+        }
+        value
+    }
+
+    /// Redacts the values of secret options, given as `--name=value`,
+    /// `NAME=value` or `--name value`, and the patterns.
+    pub fn redact_command(&self, command: &str) -> String {
+        let mut redacted = Vec::new();
+        let mut secret_option = false;
+        for arg in command.split(' ') {
+            let arg = match arg.split_once('=') {
+                _ if secret_option && !arg.is_empty() && !arg.starts_with('-') => {
+                    Cow::Borrowed(REDACTED)
+                }
+                Some((name, _)) if self.is_secret(name) => {
+                    Cow::Owned(format!("{}={}", name, REDACTED))
+                }
+                Some(_) => self.redact_patterns(arg),
+                None => {
+                    secret_option = arg.starts_with('-') && self.is_secret(arg);
+                    redacted.push(self.redact_patterns(arg));
+                    continue;
+                }
+            };
+            secret_option = false;
+            redacted.push(arg);
+        }
+        redacted.join(" ")
+    }
+
+    /// Redacts the value of the metadata record `key`: entirely for the
+    /// captured variables with a secret name, the patterns otherwise.
+    pub fn redact_metadata(&self, key: &str, value: &str) -> String {
+        match key.strip_prefix(ENV_PREFIX) {
+            Some(name) if self.is_secret(name) => REDACTED.to_string(),
+            _ => self.redact_patterns(value).into_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::format::redact;
+    use crate::redact::{Redaction, REDACTED};
+
+    #[test]
+    fn test_redaction() {
+        let redaction = Redaction::default().with_pattern("hunter2");
+
+        assert_eq!(
+            redaction.redact_command("./app --password s3cret --verbose DB_TOKEN=abc --out=x"),
+            format!(
+                "./app --password {} --verbose DB_TOKEN={} --out=x",
+                REDACTED, REDACTED
+            )
+        );
+        assert_eq!(
+            redaction.redact_command("./app --auth --name hunter2"),
+            format!("./app --auth --name {}", REDACTED)
+        );
+        assert_eq!(redaction.redact_metadata("env.API_TOKEN", "abc"), REDACTED);
+        assert_eq!(redaction.redact_metadata("env.MALLOC_CONF", "x"), "x");
+        assert_eq!(
+            redaction.redact_metadata("session.id", "hunter2-1"),
+            format!("{}-1", REDACTED)
+        );
+
+        let trace =
+            "v 1 7\nX ./app --token abc\nM env.SECRET_KEY 3 abc\nM env.HOME 5 /root\nE 3 1\n";
+        let mut output = Vec::new();
+        let redacted = redact(trace.as_bytes(), &mut output, &redaction).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(redacted, 2);
+        assert!(output.starts_with(&format!(
+            "v 1 7\nX ./app --token {}\nM env.SECRET_KEY a {}\nM env.HOME 5 /root\nE ",
+            REDACTED, REDACTED
+        )));
+    }
+}
@@ -0,0 +1,249 @@
+//! Replay of the allocations of a trace against an allocator.
+//!
+//! A [`Workload`] is the sequence of allocations and frees of a trace. It
+//! can be replayed against the system allocator or any other
+//! [`GlobalAlloc`], measuring the time spent and the RSS reached, so
+//! allocators can be compared on captured workloads instead of synthetic
+//! benchmarks.
+//!
+//! Frees refer to an allocation info rather than to a pointer, so a free
+//! releases the most recent live block of its info. Blocks of one info have
+//! the same size, which keeps the heap shape of the original run.
+
+pub mod simulation;
+
+use crate::numparse::parse_hex;
+use crate::parser::{open_input, read_line};
+use std::alloc::{GlobalAlloc, Layout};
+use std::io;
+use std::io::BufRead;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("Invalid format")]
+    InvalidFormat,
+    #[error("allocation of {0} bytes failed")]
+    AllocationFailed(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Alloc {
+        size: u64,
+    },
+    /// Free of the block allocated by the `slot`-th allocation.
+    Free {
+        slot: usize,
+    },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Workload {
+    pub events: Vec<Event>,
+    pub allocations: usize,
+    /// Frees of an allocation info without a live block, e.g. of blocks
+    /// allocated before a resumed capture.
+    pub unmatched_frees: u64,
+}
+
+impl Workload {
+    /// Loads the trace at `path`, [`STDIN_PATH`](crate::parser::STDIN_PATH)
+    /// reads the standard input.
+    /// Compressed traces are decompressed as described at [`open_input`].
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::from_reader(open_input(path)?)
+    }
+
+    pub fn from_reader(mut reader: impl BufRead) -> Result<Self, Error> {
+        let mut workload = Self::default();
+        let mut sizes = Vec::new();
+        // live slots of every allocation info, most recent last
+        let mut live: Vec<Vec<usize>> = Vec::new();
+
+        let mut line = Vec::new();
+        while read_line(&mut reader, &mut line)? {
+            let mut fields = line.split(|&b| b == b' ');
+            let tag = fields.next();
+            let mut hex = || {
+                fields
+                    .next()
+                    .and_then(parse_hex)
+                    .ok_or(Error::InvalidFormat)
+            };
+
+            match tag {
+                Some(b"a") => {
+                    sizes.push(hex()?);
+                    live.push(Vec::new());
+                }
+                Some(b"+") => {
+                    let idx = hex()? as usize;
+                    let size = *sizes.get(idx).ok_or(Error::InvalidFormat)?;
+                    live[idx].push(workload.allocations);
+                    workload.allocations += 1;
+                    workload.events.push(Event::Alloc { size });
+                }
+                Some(b"-") => {
+                    let idx = hex()? as usize;
+                    let slots = live.get_mut(idx).ok_or(Error::InvalidFormat)?;
+                    match slots.pop() {
+                        Some(slot) => workload.events.push(Event::Free { slot }),
+                        None => workload.unmatched_frees += 1,
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(workload)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayOptions {
+    pub align: usize,
+    /// Writes to every page of each block, as the program would, so the
+    /// blocks count towards the RSS.
+    pub touch: bool,
+    /// Events between two RSS samples.
+    pub rss_interval: usize,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        Self {
+            align: 16,
+            touch: true,
+            rss_interval: 4096,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplayStats {
+    pub allocations: u64,
+    pub frees: u64,
+    pub bytes: u64,
+    /// Time spent replaying, without freeing the blocks leaked by the trace.
+    pub elapsed: Duration,
+    /// RSS before the replay. `None` on platforms without `/proc`.
+    pub initial_rss: Option<u64>,
+    /// Highest RSS sampled during the replay.
+    pub peak_rss: Option<u64>,
+}
+
+/// Resident set size of the current process.
+pub fn current_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// Replays `workload` against `allocator`. Blocks still live at the end are
+/// freed once the measurement is done.
+pub fn replay<A: GlobalAlloc>(
+    workload: &Workload,
+    allocator: &A,
+    options: &ReplayOptions,
+) -> Result<ReplayStats, Error> {
+    let mut blocks: Vec<(*mut u8, Layout)> = Vec::with_capacity(workload.allocations);
+    let mut stats = ReplayStats {
+        initial_rss: current_rss(),
+        ..Default::default()
+    };
+    stats.peak_rss = stats.initial_rss;
+
+    let start = Instant::now();
+    let result = run(workload, allocator, options, &mut blocks, &mut stats);
+    stats.elapsed = start.elapsed();
+
+    for &(ptr, layout) in &blocks {
+        if !ptr.is_null() {
+            // SAFETY: the block was allocated by `allocator` with `layout`
+            unsafe { allocator.dealloc(ptr, layout) };
+        }
+    }
+
+    result.map(|()| stats)
+}
+
+fn run<A: GlobalAlloc>(
+    workload: &Workload,
+    allocator: &A,
+    options: &ReplayOptions,
+    blocks: &mut Vec<(*mut u8, Layout)>,
+    stats: &mut ReplayStats,
+) -> Result<(), Error> {
+    for (i, event) in workload.events.iter().enumerate() {
+        match *event {
+            Event::Alloc { size } => {
+                let layout = Layout::from_size_align(size.max(1) as usize, options.align)
+                    .map_err(|_| Error::AllocationFailed(size))?;
+                // SAFETY: the layout has a non-zero size
+                let ptr = unsafe { allocator.alloc(layout) };
+                if ptr.is_null() {
+                    return Err(Error::AllocationFailed(size));
+                }
+                if options.touch {
+                    for offset in (0..layout.size()).step_by(4096) {
+                        // SAFETY: the offset lies within the block
+                        unsafe { ptr.add(offset).write_volatile(0) };
+                    }
+                }
+                blocks.push((ptr, layout));
+                stats.allocations += 1;
+                stats.bytes += size;
+            }
+            Event::Free { slot } => {
+                let (ptr, layout) = std::mem::replace(
+                    blocks.get_mut(slot).ok_or(Error::InvalidFormat)?,
+                    (std::ptr::null_mut(), Layout::new::<u8>()),
+                );
+                if !ptr.is_null() {
+                    // SAFETY: the block was allocated by `allocator` with
+                    // `layout` and is freed once
+                    unsafe { allocator.dealloc(ptr, layout) };
+                    stats.frees += 1;
+                }
+            }
+        }
+
+        if options.rss_interval > 0
+            && i % options.rss_interval == 0
+            && let Some(rss) = current_rss()
+        {
+            stats.peak_rss = stats.peak_rss.max(Some(rss));
+        }
+    }
+
+    if let Some(rss) = current_rss() {
+        stats.peak_rss = stats.peak_rss.max(Some(rss));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::tests::TRACE;
+    use crate::replay::{replay, Event, ReplayOptions, Workload};
+    use std::alloc::System;
+
+    #[test]
+    fn test_replay() {
+        let workload = Workload::from_reader(TRACE.as_bytes()).unwrap();
+        assert_eq!(workload.allocations, 3);
+        // the free releases the most recent block of its info
+        assert_eq!(workload.events[3], Event::Free { slot: 1 });
+
+        let stats = replay(&workload, &System, &ReplayOptions::default()).unwrap();
+        assert_eq!(stats.allocations, 3);
+        assert_eq!(stats.frees, 1);
+        assert_eq!(stats.bytes, 0x40);
+    }
+}
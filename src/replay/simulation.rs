@@ -0,0 +1,316 @@
+//! Simulation of pool allocators on a [`Workload`].
+//!
+//! A pool allocator serves each request from the smallest of its size
+//! classes that fits it. Every class carves its blocks out of arenas of a
+//! fixed size; an arena is reserved when a class runs out of blocks and
+//! returned once its last block is freed. Requests above the largest class
+//! are served directly, rounded up to the large quantum of the classes.
+//!
+//! Running the allocations of a trace through candidate configurations
+//! tells the memory each would reserve and where it would be wasted:
+//! internally by rounding requests up to their class, or externally by
+//! blocks of reserved arenas left unused.
+
+use crate::analysis::size_class::SizeClasses;
+use crate::replay::{Event, Workload};
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolConfig {
+    pub name: String,
+    pub classes: SizeClasses,
+    /// Bytes of every arena, raised to the class size for larger classes.
+    pub arena_size: u64,
+}
+
+impl PoolConfig {
+    pub fn new(name: &str, classes: SizeClasses, arena_size: u64) -> Self {
+        Self {
+            name: name.to_string(),
+            classes,
+            arena_size,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ClassUsage {
+    pub size: u64,
+    pub allocations: u64,
+    /// Bytes requested by the allocations of the class.
+    pub requested: u64,
+    /// Bytes lost to rounding the requests up to the class size.
+    pub wasted: u64,
+    /// Highest number of arenas reserved at once.
+    pub peak_arenas: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SimulationReport {
+    pub name: String,
+    /// Highest number of bytes reserved from the system.
+    pub peak_reserved: u64,
+    /// Bytes requested by the live allocations at the reserved peak.
+    pub requested_at_peak: u64,
+    /// Bytes of the live blocks beyond their requests at the reserved peak.
+    pub internal_waste: u64,
+    /// Bytes of reserved arenas not handed out at the reserved peak.
+    pub external_waste: u64,
+    /// Allocations above the largest class.
+    pub large_allocations: u64,
+    /// Classes that served allocations, smallest first.
+    pub classes: Vec<ClassUsage>,
+}
+
+impl SimulationReport {
+    /// Share of the reserved peak not requested by the program.
+    pub fn fragmentation(&self) -> f64 {
+        if self.peak_reserved == 0 {
+            return 0.0;
+        }
+        (self.peak_reserved - self.requested_at_peak) as f64 / self.peak_reserved as f64
+    }
+}
+
+struct Class {
+    usage: ClassUsage,
+    blocks_per_arena: u64,
+    arena_size: u64,
+    /// Blocks in use of every arena.
+    arenas: Vec<u64>,
+    /// Arenas with a free block, the next one to use last.
+    open: Vec<usize>,
+    reserved_arenas: u64,
+}
+
+#[derive(Default)]
+struct Heap {
+    requested: u64,
+    usable: u64,
+    reserved: u64,
+}
+
+/// Where the block of a workload slot lives.
+#[derive(Clone, Copy)]
+enum Block {
+    Free,
+    Pooled {
+        class: usize,
+        arena: usize,
+        size: u64,
+    },
+    Large {
+        size: u64,
+        usable: u64,
+    },
+}
+
+/// Runs `workload` through every configuration.
+pub fn simulate(workload: &Workload, configs: &[PoolConfig]) -> Vec<SimulationReport> {
+    configs
+        .iter()
+        .map(|config| simulate_one(workload, config))
+        .collect()
+}
+
+fn simulate_one(workload: &Workload, config: &PoolConfig) -> SimulationReport {
+    let mut classes: Vec<Class> = config
+        .classes
+        .classes
+        .iter()
+        .map(|&size| {
+            let arena_size = config.arena_size.max(size);
+            Class {
+                usage: ClassUsage {
+                    size,
+                    ..Default::default()
+                },
+                blocks_per_arena: arena_size / size.max(1),
+                arena_size,
+                arenas: Vec::new(),
+                open: Vec::new(),
+                reserved_arenas: 0,
+            }
+        })
+        .collect();
+
+    let mut report = SimulationReport {
+        name: config.name.clone(),
+        ..Default::default()
+    };
+    let mut heap = Heap::default();
+    let mut blocks = Vec::with_capacity(workload.allocations);
+
+    for event in &workload.events {
+        match *event {
+            Event::Alloc { size } => {
+                let idx = config
+                    .classes
+                    .classes
+                    .partition_point(|&class| class < size);
+                let block = match classes.get_mut(idx) {
+                    Some(class) => {
+                        let arena = class.take_block(&mut heap);
+                        class.usage.allocations += 1;
+                        class.usage.requested += size;
+                        class.usage.wasted += class.usage.size - size;
+                        heap.usable += class.usage.size;
+                        Block::Pooled {
+                            class: idx,
+                            arena,
+                            size,
+                        }
+                    }
+                    None => {
+                        let usable = config.classes.usable(size);
+                        report.large_allocations += 1;
+                        heap.usable += usable;
+                        heap.reserved += usable;
+                        Block::Large { size, usable }
+                    }
+                };
+                heap.requested += size;
+                blocks.push(block);
+
+                if heap.reserved > report.peak_reserved {
+                    report.peak_reserved = heap.reserved;
+                    report.requested_at_peak = heap.requested;
+                    report.internal_waste = heap.usable - heap.requested;
+                    report.external_waste = heap.reserved - heap.usable;
+                }
+            }
+            Event::Free { slot } => {
+                let Some(block) = blocks.get_mut(slot) else {
+                    continue;
+                };
+                match std::mem::replace(block, Block::Free) {
+                    Block::Free => {}
+                    Block::Pooled { class, arena, size } => {
+                        let class = &mut classes[class];
+                        class.release_block(arena, &mut heap);
+                        heap.usable -= class.usage.size;
+                        heap.requested -= size;
+                    }
+                    Block::Large { size, usable } => {
+                        heap.usable -= usable;
+                        heap.reserved -= usable;
+                        heap.requested -= size;
+                    }
+                }
+            }
+        }
+    }
+
+    report.classes = classes
+        .into_iter()
+        .map(|class| class.usage)
+        .filter(|usage| usage.allocations > 0)
+        .collect();
+    report
+}
+
+impl Class {
+    fn take_block(&mut self, heap: &mut Heap) -> usize {
+        while let Some(&arena) = self.open.last() {
+            if self.arenas[arena] < self.blocks_per_arena {
+                break;
+            }
+            self.open.pop();
+        }
+
+        let arena = match self.open.last() {
+            Some(&arena) => arena,
+            None => {
+                self.arenas.push(0);
+                self.open.push(self.arenas.len() - 1);
+                self.arenas.len() - 1
+            }
+        };
+
+        if self.arenas[arena] == 0 {
+            heap.reserved += self.arena_size;
+            self.reserved_arenas += 1;
+            self.usage.peak_arenas = self.usage.peak_arenas.max(self.reserved_arenas);
+        }
+        self.arenas[arena] += 1;
+        arena
+    }
+
+    fn release_block(&mut self, arena: usize, heap: &mut Heap) {
+        if self.arenas[arena] == self.blocks_per_arena {
+            self.open.push(arena);
+        }
+        self.arenas[arena] -= 1;
+
+        if self.arenas[arena] == 0 {
+            heap.reserved -= self.arena_size;
+            self.reserved_arenas -= 1;
+        }
+    }
+}
+
+impl fmt::Display for SimulationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}: peak reserved {}, internal waste {}, external waste {}, fragmentation {:.1}%",
+            self.name,
+            self.peak_reserved,
+            self.internal_waste,
+            self.external_waste,
+            self.fragmentation() * 100.0
+        )?;
+        for class in &self.classes {
+            writeln!(
+                f,
+                "  {}: {} allocations, {} bytes wasted, {} arenas",
+                class.size, class.allocations, class.wasted, class.peak_arenas
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::size_class::SizeClasses;
+    use crate::replay::simulation::{simulate, PoolConfig};
+    use crate::replay::Workload;
+
+    const TRACE: &str = "\
+a 10 1
+a 18 1
+a 1000 1
++ 0
++ 0
++ 1
++ 2
+- 1
+- 2
+";
+
+    #[test]
+    fn test_simulate() {
+        let workload = Workload::from_reader(TRACE.as_bytes()).unwrap();
+        let configs = [
+            PoolConfig::new("fine", SizeClasses::new(vec![16, 32], 4096), 64),
+            PoolConfig::new("coarse", SizeClasses::new(vec![64], 4096), 256),
+        ];
+        let reports = simulate(&workload, &configs);
+
+        let fine = &reports[0];
+        // two arenas of 64 bytes and a page for the large allocation
+        assert_eq!(fine.peak_reserved, 64 + 64 + 4096);
+        assert_eq!(fine.requested_at_peak, 0x10 + 0x10 + 0x18 + 0x1000);
+        assert_eq!(fine.internal_waste, 8);
+        assert_eq!(fine.large_allocations, 1);
+        assert_eq!(fine.classes[1].wasted, 8);
+
+        let coarse = &reports[1];
+        assert_eq!(coarse.peak_reserved, 256 + 4096);
+        assert_eq!(coarse.classes[0].allocations, 3);
+        assert_eq!(coarse.internal_waste, 3 * 64 - 0x38);
+        assert!(coarse.fragmentation() > fine.fragmentation());
+    }
+}
@@ -0,0 +1,855 @@
+//! Human-oriented reports of a parsed trace in JSON and HTML.
+
+use crate::analysis::churn::{AllocatorCosts, ChurnEstimate};
+use crate::analysis::crates::{CrateOptions, CrateReport};
+use crate::analysis::rate::RateSpikes;
+use crate::analysis::registry;
+use crate::analysis::registry::AnalysisResult;
+use crate::analysis::rss::RssBreakdown;
+use crate::analysis::sampling::{EstimatedCost, Sampling};
+use crate::annotations::Annotations;
+use crate::export::{export, Exporter, RunSummary};
+use crate::model::{Cost, FailureSite, Frame, Function, Metric, Profile, Site};
+use crate::parser::{AccumulatedData, TimelinePoint};
+use serde::Serialize;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone)]
+pub struct ReportOptions {
+    pub top_sites: usize,
+    /// Functions kept, ranked by the `metric` of the allocations they make
+    /// themselves.
+    pub top_functions: usize,
+    pub metric: Metric,
+    /// Root of the traced program's sources. When set, the source lines
+    /// around the first frame of each top site that lies below the root are
+    /// embedded into the report.
+    pub source_root: Option<PathBuf>,
+    /// Lines shown before and after the allocating line.
+    pub context_lines: usize,
+    /// Adds the costs by crate, see [`CrateReport`].
+    pub crates: Option<CrateOptions>,
+    /// Names of the [registered](crate::analysis::registry) analysis passes
+    /// to run, all of them if `None`.
+    pub analyses: Option<Vec<String>>,
+}
+
+impl Default for ReportOptions {
+    fn default() -> Self {
+        Self {
+            top_sites: 20,
+            top_functions: 20,
+            metric: Metric::Peak,
+            source_root: None,
+            context_lines: 3,
+            crates: None,
+            analyses: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Snippet {
+    pub file: String,
+    pub line: u32,
+    /// Line number of the first entry of `lines`.
+    pub first_line: u32,
+    pub lines: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportSite {
+    pub stack: Vec<Frame>,
+    pub cost: Cost,
+    pub source: Option<Snippet>,
+    /// Notes attached to the site, see [`Annotations`].
+    pub notes: Vec<String>,
+    /// Full-run estimate of the cost for sampled traces.
+    pub estimate: Option<EstimatedCost>,
+}
+
+/// Full-run estimates of a sampled trace, see [`Sampling`].
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportSampling {
+    pub rate: f64,
+    /// Confidence level of the intervals of the estimates.
+    pub confidence: f64,
+    pub total: EstimatedCost,
+}
+
+/// Allocation rate spike, see [`rate_spikes`](crate::analysis::rate::rate_spikes).
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportSpike {
+    pub start_ms: u128,
+    pub end_ms: u128,
+    pub allocations: u64,
+    /// Allocations per second.
+    pub rate: f64,
+    /// Leaf functions of the dominant sites and their allocations.
+    pub sites: Vec<(String, u64)>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    /// Command line of the traced program, if the trace recorded it.
+    pub command: Option<String>,
+    pub total: Cost,
+    /// `None` unless the trace was recorded with sampling, see
+    /// [`AccumulatedData::sampling_rate`].
+    pub sampling: Option<ReportSampling>,
+    pub peak_rss: u64,
+    /// `None` for traces without RSS samples.
+    pub rss: Option<RssBreakdown>,
+    pub duration_ms: u128,
+    pub sites: Vec<ReportSite>,
+    /// Self and inclusive costs of the top functions.
+    pub functions: Vec<Function>,
+    pub crates: Option<CrateReport>,
+    /// Empty unless added with [`Report::with_spikes`].
+    pub spikes: Vec<ReportSpike>,
+    /// Stacks of failed allocations, largest request first.
+    pub failures: Vec<FailureSite>,
+    /// Results of the registered analysis passes.
+    pub analyses: Vec<AnalysisResult>,
+}
+
+fn source_path(root: &Path, file: &str) -> Option<PathBuf> {
+    let path = Path::new(file);
+    let path = if path.is_absolute() {
+        path.starts_with(root).then(|| path.to_path_buf())?
+    } else {
+        root.join(path)
+    };
+
+    path.is_file().then_some(path)
+}
+
+/// Reads the lines around `line` of the first frame of `site` found below
+/// `root`.
+pub fn snippet(root: &Path, site: &Site, context_lines: usize) -> Option<Snippet> {
+    site.stack.iter().find_map(|frame| {
+        let file = frame.file.as_deref()?;
+        let line = frame.line.filter(|&l| l > 0)?;
+        let path = source_path(root, file)?;
+        let content = fs::read_to_string(path).ok()?;
+
+        let first = (line as usize).saturating_sub(context_lines).max(1);
+        let lines: Vec<String> = content
+            .lines()
+            .skip(first - 1)
+            .take(line as usize - first + context_lines + 1)
+            .map(str::to_string)
+            .collect();
+        if lines.is_empty() {
+            return None;
+        }
+
+        Some(Snippet {
+            file: file.to_string(),
+            line,
+            first_line: first as u32,
+            lines,
+        })
+    })
+}
+
+/// [`Exporter`] building a [`Report`].
+pub struct ReportExporter<'a, 'o> {
+    options: &'o ReportOptions,
+    summary: Option<RunSummary>,
+    annotations: Option<&'a Annotations>,
+    sites: Vec<&'a Site>,
+    functions: Vec<&'a Function>,
+    failures: Vec<&'a FailureSite>,
+    timeline: Vec<TimelinePoint>,
+}
+
+impl<'o> ReportExporter<'_, 'o> {
+    pub fn new(options: &'o ReportOptions) -> Self {
+        Self {
+            options,
+            summary: None,
+            annotations: None,
+            sites: Vec::new(),
+            functions: Vec::new(),
+            failures: Vec::new(),
+            timeline: Vec::new(),
+        }
+    }
+}
+
+impl<'a> Exporter<'a> for ReportExporter<'a, '_> {
+    type Output = Report;
+
+    fn visit_summary(&mut self, summary: &RunSummary) {
+        self.summary = Some(*summary);
+    }
+
+    fn visit_annotations(&mut self, annotations: &'a Annotations) {
+        self.annotations = Some(annotations);
+    }
+
+    fn visit_site(&mut self, site: &'a Site) {
+        self.sites.push(site);
+    }
+
+    fn visit_function(&mut self, function: &'a Function) {
+        self.functions.push(function);
+    }
+
+    fn visit_failure(&mut self, failure: &'a FailureSite) {
+        self.failures.push(failure);
+    }
+
+    fn visit_timeline(&mut self, point: &'a TimelinePoint) {
+        self.timeline.push(*point);
+    }
+
+    fn finish(mut self) -> Report {
+        let options = self.options;
+        let crates = options
+            .crates
+            .as_ref()
+            .map(|crates| CrateReport::new(self.sites.iter().copied(), crates));
+
+        self.sites
+            .sort_by_key(|s| std::cmp::Reverse(s.cost.get(options.metric)));
+
+        let sites = self
+            .sites
+            .into_iter()
+            .take(options.top_sites)
+            .map(|site| ReportSite {
+                stack: site.stack.clone(),
+                cost: site.cost,
+                source: options
+                    .source_root
+                    .as_deref()
+                    .and_then(|root| snippet(root, site, options.context_lines)),
+                notes: self
+                    .annotations
+                    .map(|annotations| annotations.for_site(site).to_vec())
+                    .unwrap_or_default(),
+                estimate: None,
+            })
+            .collect();
+
+        self.functions
+            .sort_by_key(|f| std::cmp::Reverse(f.exclusive.get(options.metric)));
+        let functions = self
+            .functions
+            .into_iter()
+            .take(options.top_functions)
+            .cloned()
+            .collect();
+
+        self.failures
+            .sort_by_key(|f| std::cmp::Reverse(f.failures.largest));
+        let failures = self
+            .failures
+            .into_iter()
+            .take(options.top_sites)
+            .cloned()
+            .collect();
+
+        let summary = self.summary.unwrap_or_default();
+        Report {
+            command: None,
+            total: summary.total,
+            sampling: None,
+            peak_rss: summary.peak_rss,
+            rss: RssBreakdown::from_samples(
+                &self.timeline,
+                summary.total.peak,
+                summary.page_size * summary.pages,
+            ),
+            duration_ms: summary.duration.as_millis(),
+            sites,
+            functions,
+            crates,
+            spikes: Vec::new(),
+            failures,
+            analyses: Vec::new(),
+        }
+    }
+}
+impl Report {
+    pub fn new(data: &AccumulatedData, profile: &Profile, options: &ReportOptions) -> Self {
+        let mut report = export(data, profile, ReportExporter::new(options));
+        report.command = data.command.clone();
+        report.analyses = registry::run(data, options.analyses.as_deref());
+        if let Some(rate) = data.sampling_rate() {
+            report = report.with_sampling(&Sampling::new(rate));
+        }
+        report
+    }
+
+    /// Adds the full-run estimates of the totals and of the top sites of a
+    /// trace recorded with `sampling`.
+    pub fn with_sampling(mut self, sampling: &Sampling) -> Self {
+        for site in &mut self.sites {
+            site.estimate = Some(sampling.cost(&site.cost));
+        }
+        self.sampling = Some(ReportSampling {
+            rate: sampling.rate(),
+            confidence: sampling.confidence(),
+            total: sampling.cost(&self.total),
+        });
+        self
+    }
+
+    /// Adds the allocation rate spikes found in the run, naming their sites
+    /// with `profile`.
+    pub fn with_spikes(mut self, spikes: &RateSpikes, profile: &Profile) -> Self {
+        self.spikes = spikes
+            .spikes
+            .iter()
+            .map(|spike| ReportSpike {
+                start_ms: spike.start.as_millis(),
+                end_ms: spike.end.as_millis(),
+                allocations: spike.allocations,
+                rate: spike.rate,
+                sites: spike
+                    .functions(profile)
+                    .into_iter()
+                    .zip(&spike.sites)
+                    .map(|(function, site)| (function.to_string(), site.allocations))
+                    .collect(),
+            })
+            .collect();
+        self
+    }
+
+    pub fn write_json(&self, out: impl Write) -> io::Result<()> {
+        serde_json::to_writer_pretty(out, self)?;
+        Ok(())
+    }
+
+    pub fn write_html(&self, mut out: impl Write) -> io::Result<()> {
+        writeln!(out, "<!DOCTYPE html>")?;
+        writeln!(
+            out,
+            "<html><head><meta charset=\"utf-8\"><title>memtrack report</title>"
+        )?;
+        writeln!(
+            out,
+            "<style>body{{font-family:sans-serif}}pre{{background:#f4f4f4;padding:4px}}\
+             .hl{{background:#ffe08a}}td{{padding:2px 8px}}</style></head><body>"
+        )?;
+
+        if let Some(command) = &self.command {
+            writeln!(out, "<p><code>{}</code></p>", escape(command))?;
+        }
+        writeln!(out, "<h1>Summary</h1><table>")?;
+        let estimates = self.sampling.as_ref().map(|sampling| sampling.total);
+        for (name, value, estimate) in [
+            (
+                "allocations",
+                self.total.allocations,
+                estimates.map(|e| e.allocations),
+            ),
+            (
+                "temporary",
+                self.total.temporary,
+                estimates.map(|e| e.temporary),
+            ),
+            ("leaked", self.total.leaked, estimates.map(|e| e.leaked)),
+            ("peak", self.total.peak, estimates.map(|e| e.peak)),
+            ("peak RSS", self.peak_rss, None),
+        ] {
+            match estimate {
+                Some(estimate) => writeln!(
+                    out,
+                    "<tr><td>{}</td><td>{} sampled, estimated {}</td></tr>",
+                    name, value, estimate
+                )?,
+                None => writeln!(out, "<tr><td>{}</td><td>{}</td></tr>", name, value)?,
+            }
+        }
+        if let Some(sampling) = &self.sampling {
+            writeln!(
+                out,
+                "<tr><td>sampling</td><td>rate {}, {:.0}% confidence intervals</td></tr>",
+                sampling.rate,
+                sampling.confidence * 100.0
+            )?;
+        }
+        if let Some(rss) = &self.rss {
+            for (name, value) in [
+                ("peak RSS heap", rss.heap),
+                ("peak RSS mappings", rss.mappings),
+                ("peak RSS unexplained", rss.unexplained),
+            ] {
+                writeln!(
+                    out,
+                    "<tr><td>{}</td><td>{} ({:.0}%)</td></tr>",
+                    name,
+                    value,
+                    rss.share(value) * 100.0
+                )?;
+            }
+        }
+        writeln!(
+            out,
+            "<tr><td>duration</td><td>{} ms</td></tr></table>",
+            self.duration_ms
+        )?;
+
+        if let Some(crates) = &self.crates {
+            writeln!(out, "<h1>Crates</h1><table>")?;
+            writeln!(
+                out,
+                "<tr><th>crate</th><th>allocations</th><th>leaked</th><th>peak</th><th>top functions</th></tr>"
+            )?;
+            for krate in &crates.crates {
+                let functions: Vec<String> = krate
+                    .top_functions
+                    .iter()
+                    .map(|f| escape(&f.name))
+                    .collect();
+                writeln!(
+                    out,
+                    "<tr><td>{}{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    escape(&krate.name),
+                    if krate.workspace { " (workspace)" } else { "" },
+                    krate.cost.allocations,
+                    krate.cost.leaked,
+                    krate.cost.peak,
+                    functions.join("<br>")
+                )?;
+            }
+            writeln!(out, "</table>")?;
+        }
+
+        if !self.spikes.is_empty() {
+            writeln!(out, "<h1>Spikes</h1><table>")?;
+            writeln!(
+                out,
+                "<tr><th>time</th><th>allocations</th><th>rate</th><th>top sites</th></tr>"
+            )?;
+            for spike in &self.spikes {
+                let sites: Vec<String> = spike
+                    .sites
+                    .iter()
+                    .map(|(function, allocations)| {
+                        format!("{} ({})", escape(function), allocations)
+                    })
+                    .collect();
+                writeln!(
+                    out,
+                    "<tr><td>{}&ndash;{} ms</td><td>{}</td><td>{:.0}/s</td><td>{}</td></tr>",
+                    spike.start_ms,
+                    spike.end_ms,
+                    spike.allocations,
+                    spike.rate,
+                    sites.join("<br>")
+                )?;
+            }
+            writeln!(out, "</table>")?;
+        }
+
+        if !self.failures.is_empty() {
+            writeln!(out, "<h1>Failed allocations</h1><table>")?;
+            writeln!(
+                out,
+                "<tr><th>function</th><th>failures</th><th>largest</th><th>bytes</th></tr>"
+            )?;
+            for failure in &self.failures {
+                writeln!(
+                    out,
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    escape(failure.stack.first().map_or("??", |f| f.function.as_str())),
+                    failure.failures.count,
+                    failure.failures.largest,
+                    failure.failures.bytes
+                )?;
+            }
+            writeln!(out, "</table>")?;
+        }
+
+        if !self.analyses.is_empty() {
+            writeln!(out, "<h1>Analyses</h1><table>")?;
+            for analysis in &self.analyses {
+                writeln!(
+                    out,
+                    "<tr><td>{}</td><td>{}</td></tr>",
+                    escape(&analysis.name),
+                    escape(&analysis.output.summary)
+                )?;
+            }
+            writeln!(out, "</table>")?;
+        }
+
+        if !self.functions.is_empty() {
+            writeln!(out, "<h1>Top functions</h1><table>")?;
+            writeln!(
+                out,
+                "<tr><th>function</th><th>self allocations</th><th>self leaked</th><th>self peak</th>\
+                 <th>inclusive allocations</th><th>inclusive leaked</th><th>inclusive peak</th></tr>"
+            )?;
+            for function in &self.functions {
+                writeln!(
+                    out,
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    escape(&function.name),
+                    function.exclusive.allocations,
+                    function.exclusive.leaked,
+                    function.exclusive.peak,
+                    function.inclusive.allocations,
+                    function.inclusive.leaked,
+                    function.inclusive.peak
+                )?;
+            }
+            writeln!(out, "</table>")?;
+        }
+
+        writeln!(out, "<h1>Top sites</h1>")?;
+        for site in &self.sites {
+            let title = if site.notes.is_empty() {
+                String::new()
+            } else {
+                format!(" title=\"{}\"", escape(&site.notes.join("\n")))
+            };
+            writeln!(
+                out,
+                "<h3{}>{}</h3><p>allocations {} &middot; temporary {} &middot; leaked {} &middot; peak {}</p>",
+                title,
+                escape(site.stack.first().map_or("??", |f| f.function.as_str())),
+                site.cost.allocations,
+                site.cost.temporary,
+                site.cost.leaked,
+                site.cost.peak
+            )?;
+            if let Some(estimate) = &site.estimate {
+                writeln!(
+                    out,
+                    "<p>estimated allocations {} &middot; leaked {} &middot; peak {}</p>",
+                    estimate.allocations, estimate.leaked, estimate.peak
+                )?;
+            }
+
+            for note in &site.notes {
+                writeln!(out, "<p><em>{}</em></p>", escape(note))?;
+            }
+
+            writeln!(out, "<ol>")?;
+            for frame in &site.stack {
+                match (&frame.file, frame.line) {
+                    (Some(file), Some(line)) => writeln!(
+                        out,
+                        "<li>{} <small>{}:{}</small></li>",
+                        escape(&frame.function),
+                        escape(file),
+                        line
+                    )?,
+                    _ => writeln!(out, "<li>{}</li>", escape(&frame.function))?,
+                }
+            }
+            writeln!(out, "</ol>")?;
+
+            if let Some(snippet) = &site.source {
+                writeln!(out, "<pre>")?;
+                for (idx, line) in snippet.lines.iter().enumerate() {
+                    let number = snippet.first_line + idx as u32;
+                    let class = if number == snippet.line {
+                        " class=\"hl\""
+                    } else {
+                        ""
+                    };
+                    writeln!(out, "<span{}>{:>5} {}</span>", class, number, escape(line))?;
+                }
+                writeln!(out, "</pre>")?;
+            }
+        }
+
+        writeln!(out, "</body></html>")
+    }
+}
+
+/// Share of the allocations that are temporary above which a run is churn
+/// bound.
+const CHURN_TEMPORARY_SHARE: f64 = 0.5;
+/// Share of the run time spent in the allocator above which a run is churn
+/// bound, see [`ChurnEstimate::share`].
+const CHURN_ALLOCATOR_SHARE: f64 = 0.1;
+/// Ratio of the peak RSS to the peak heap above which a run is
+/// fragmentation bound.
+const FRAGMENTATION_RATIO: f64 = 2.0;
+/// Share of the peak heap above which a site dominates the run.
+const DOMINANT_SHARE: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Finding {
+    /// Memory is still allocated at exit.
+    Leak,
+    /// Many short-lived allocations or much time in the allocator.
+    Churn,
+    /// The RSS is much larger than the heap.
+    Fragmentation,
+    /// A single site holds most of the peak heap.
+    DominantSite,
+}
+
+/// Site holding most of the peak heap.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct DominantSite {
+    pub trace_idx: u64,
+    pub peak: u64,
+    /// Share of the peak heap.
+    pub share: f64,
+}
+
+/// Classification of a run with the numbers behind it, printed first by
+/// front-ends as the short version of the [`Report`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Verdict {
+    /// Empty for runs without anything remarkable.
+    pub findings: Vec<Finding>,
+    pub allocations: u64,
+    pub leaked: u64,
+    pub peak: u64,
+    pub peak_rss: u64,
+    /// Share of the allocations freed right after being allocated.
+    pub temporary_share: f64,
+    /// Estimated share of the run spent in the allocator.
+    pub allocator_share: f64,
+    pub dominant_site: Option<DominantSite>,
+}
+
+impl Verdict {
+    pub fn has(&self, finding: Finding) -> bool {
+        self.findings.contains(&finding)
+    }
+}
+
+fn share(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    part as f64 / total as f64
+}
+
+/// Classifies the run of `data` with the default [`AllocatorCosts`].
+pub fn verdict(data: &AccumulatedData) -> Verdict {
+    let total = &data.total;
+    let temporary_share = share(total.temporary, total.allocations);
+    let allocator_share = ChurnEstimate::new(data, &AllocatorCosts::default()).share();
+    let dominant_site = data
+        .allocations
+        .iter()
+        .max_by_key(|allocation| allocation.data.peak)
+        .map(|allocation| DominantSite {
+            trace_idx: allocation.trace_idx,
+            peak: allocation.data.peak,
+            share: share(allocation.data.peak, total.peak),
+        })
+        .filter(|site| site.share > DOMINANT_SHARE);
+
+    let mut findings = Vec::new();
+    if total.leaked > 0 {
+        findings.push(Finding::Leak);
+    }
+    if temporary_share > CHURN_TEMPORARY_SHARE || allocator_share > CHURN_ALLOCATOR_SHARE {
+        findings.push(Finding::Churn);
+    }
+    if total.peak > 0 && data.peak_rss as f64 > total.peak as f64 * FRAGMENTATION_RATIO {
+        findings.push(Finding::Fragmentation);
+    }
+    if dominant_site.is_some() {
+        findings.push(Finding::DominantSite);
+    }
+
+    Verdict {
+        findings,
+        allocations: total.allocations,
+        leaked: total.leaked,
+        peak: total.peak,
+        peak_rss: data.peak_rss,
+        temporary_share,
+        allocator_share,
+        dominant_site,
+    }
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} allocations peaking at {} bytes of heap",
+            self.allocations, self.peak
+        )?;
+        if self.peak_rss > 0 {
+            write!(f, " and {} bytes of RSS", self.peak_rss)?;
+        }
+        write!(f, ".")?;
+        if self.findings.is_empty() {
+            return write!(f, " Nothing stands out.");
+        }
+        for finding in &self.findings {
+            match finding {
+                Finding::Leak => write!(f, " Leaks {} bytes at exit.", self.leaked)?,
+                Finding::Churn => write!(
+                    f,
+                    " Churn bound: {:.0}% of the allocations are temporary, \
+                     ≈{:.0}% of the run is spent in the allocator.",
+                    self.temporary_share * 100.0,
+                    self.allocator_share * 100.0
+                )?,
+                Finding::Fragmentation => write!(
+                    f,
+                    " Fragmentation bound: the peak RSS is {:.1}× the peak heap.",
+                    share(self.peak_rss, self.peak)
+                )?,
+                Finding::DominantSite => {
+                    if let Some(site) = &self.dominant_site {
+                        write!(
+                            f,
+                            " Trace {} holds {:.0}% of the peak heap.",
+                            site.trace_idx,
+                            site.share * 100.0
+                        )?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::tests::{data, parse, TRACE};
+    use crate::model::{Cost, Frame, Metric, Profile, Site};
+    use crate::report::{snippet, verdict, Finding, Report, ReportOptions};
+
+    #[test]
+    fn test_snippet() {
+        let root = std::env::temp_dir().join(format!("memtrack-src-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::write(
+            root.join("src/main.rs"),
+            "fn main() {\n    let mut v = Vec::new();\n    v.push(1);\n}\n",
+        )
+        .unwrap();
+
+        let frame = |file: &str, line| Frame {
+            ip: 0,
+            module: None,
+            function: "f".into(),
+            file: Some(file.into()),
+            line: Some(line),
+            inlined: false,
+        };
+        let site = Site {
+            stack: vec![
+                frame("/rustc/library/alloc/src/vec.rs", 10),
+                frame("src/main.rs", 3),
+            ],
+            cost: Cost::default(),
+        };
+
+        let snippet = snippet(&root, &site, 1).unwrap();
+        _ = std::fs::remove_dir_all(&root);
+
+        assert_eq!(snippet.file, "src/main.rs");
+        assert_eq!(snippet.first_line, 2);
+        assert_eq!(
+            snippet.lines,
+            ["    let mut v = Vec::new();", "    v.push(1);", "}"]
+        );
+    }
+
+    #[test]
+    fn test_report_functions() {
+        let data = data();
+        let profile = Profile::new(&data).unwrap();
+        let options = ReportOptions {
+            top_functions: 2,
+            metric: Metric::Allocations,
+            ..Default::default()
+        };
+
+        let report = Report::new(&data, &profile, &options);
+        let functions: Vec<(&str, u64, u64)> = report
+            .functions
+            .iter()
+            .map(|f| {
+                (
+                    f.name.as_str(),
+                    f.exclusive.allocations,
+                    f.inclusive.allocations,
+                )
+            })
+            .collect();
+        assert_eq!(functions, [("malloc_a", 2, 2), ("b", 1, 1)]);
+
+        let mut html = Vec::new();
+        report.write_html(&mut html).unwrap();
+        let html = String::from_utf8(html).unwrap();
+        assert!(html.contains("<h1>Top functions</h1>"));
+        assert!(html.contains("<tr><td>malloc_a</td><td>2</td>"));
+    }
+
+    #[test]
+    fn test_report_sampling() {
+        let data = data();
+        let profile = Profile::new(&data).unwrap();
+        assert!(Report::new(&data, &profile, &ReportOptions::default())
+            .sampling
+            .is_none());
+
+        let data = parse(&format!("{}M capture.sampling_rate 4 0.25\n", TRACE));
+        let report = Report::new(&data, &profile, &ReportOptions::default());
+        let sampling = report.sampling.as_ref().unwrap();
+        assert_eq!(sampling.rate, 0.25);
+        assert_eq!(sampling.total.allocations.value, 12.0);
+
+        let mut html = Vec::new();
+        report.write_html(&mut html).unwrap();
+        let html = String::from_utf8(html).unwrap();
+        assert!(html.contains(&format!(
+            "<td>3 sampled, estimated {}</td>",
+            sampling.total.allocations
+        )));
+        assert!(html.contains("rate 0.25, 95% confidence intervals"));
+    }
+
+    #[test]
+    fn test_verdict() {
+        let verdict = verdict(&data());
+        assert_eq!(verdict.findings, [Finding::Leak, Finding::Fragmentation]);
+        assert_eq!((verdict.leaked, verdict.peak), (0x30, 0x40));
+        assert_eq!(verdict.dominant_site, None);
+        assert_eq!(
+            verdict.to_string(),
+            "3 allocations peaking at 64 bytes of heap and 4096 bytes of RSS. \
+             Leaks 48 bytes at exit. Fragmentation bound: the peak RSS is 64.0× the peak heap."
+        );
+
+        let trace = format!("{}{}- 1\n- 0\n", TRACE, "+ 1\n- 1\n".repeat(4));
+        let verdict = super::verdict(&parse(&trace));
+        assert!(!verdict.has(Finding::Leak));
+        assert!(verdict.has(Finding::Churn));
+        let site = verdict.dominant_site.unwrap();
+        assert_eq!((site.trace_idx, site.peak), (4, 0x40));
+    }
+}
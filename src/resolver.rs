@@ -1,8 +1,17 @@
+pub mod compare;
+
 use addr2line::Loader;
+use lru::LruCache;
 use rangemap::RangeMap;
+use serde::Serialize;
 use std::collections::HashMap;
+use std::mem::size_of;
+use std::sync::{Arc, Mutex, MutexGuard};
 use thiserror::Error;
 
+/// Number of independently locked parts of the shared lookup cache.
+const CACHE_SHARDS: usize = 16;
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("module not found")]
@@ -14,31 +23,38 @@ struct Module {
     id: usize,
     pub start_address: u64,
     pub end_address: u64,
-    path: String,
+    /// Index of the module's file in the [`SharedSymbols`].
+    loader: usize,
 }
 
 impl Module {
-    pub fn new(id: usize, path: String, start_address: u64, size: u64) -> Self {
+    pub fn new(id: usize, loader: usize, start_address: u64, size: u64) -> Self {
         Self {
             id,
-            path,
+            loader,
             start_address,
             end_address: start_address + size,
         }
     }
+}
 
-    pub fn lookup(&self, ip: u64, loader: &Loader) -> Option<LookupResult> {
-        let mut locations = Vec::new();
-
-        let mut iter = loader.find_frames(ip).unwrap();
-        while let Some(frame) = iter.next().unwrap() {
-            let function_name =
-                rustc_demangle::demangle(frame.function.unwrap().name.to_string().unwrap())
-                    .to_string();
+fn find_locations(ip: u64, loader: &Loader, demangle: bool) -> Option<Vec<Location>> {
+    let mut locations = Vec::new();
+
+    if let Ok(mut iter) = loader.find_frames(ip) {
+        while let Ok(Some(frame)) = iter.next() {
+            // symbols are not necessarily valid UTF-8
+            let Some(name) = frame
+                .function
+                .map(|function| function.name.to_string_lossy().into_owned())
+            else {
+                continue;
+            };
+            let function_name = symbol_name(&name, demangle);
             let location = match frame.location {
                 Some(location) => Location {
                     function_name,
-                    file_name: Some(location.file.unwrap().to_string()),
+                    file_name: location.file.map(str::to_string),
                     line_number: Some(location.line.unwrap_or_default()),
                 },
                 None => Location {
@@ -50,23 +66,28 @@ impl Module {
 
             locations.push(location);
         }
+    }
 
-        if locations.is_empty() {
-            let symbol = loader.find_symbol(ip).unwrap();
-            let function_name = rustc_demangle::demangle(symbol).to_string();
+    if locations.is_empty() {
+        let symbol = loader.find_symbol(ip)?;
+        let function_name = symbol_name(symbol, demangle);
 
-            locations.push(Location {
-                function_name,
-                file_name: None,
-                line_number: None,
-            })
-        }
-
-        Some(LookupResult {
-            module_id: self.id,
-            locations,
+        locations.push(Location {
+            function_name,
+            file_name: None,
+            line_number: None,
         })
     }
+
+    Some(locations)
+}
+
+fn symbol_name(symbol: &str, demangle: bool) -> String {
+    if demangle {
+        rustc_demangle::demangle(symbol).to_string()
+    } else {
+        symbol.to_string()
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -75,28 +96,290 @@ pub struct LookupResult {
     pub locations: Vec<Location>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
 pub struct Location {
     pub function_name: String,
     pub file_name: Option<String>,
     pub line_number: Option<u32>,
 }
 
+impl LookupResult {
+    /// Approximate heap footprint of the result when cached.
+    fn cost(&self) -> usize {
+        size_of::<u64>()
+            + size_of::<Self>()
+            + self
+                .locations
+                .iter()
+                .map(|l| {
+                    size_of::<Location>()
+                        + l.function_name.len()
+                        + l.file_name.as_ref().map_or(0, |f| f.len())
+                })
+                .sum::<usize>()
+    }
+}
+
+/// Bounds of the resolver's lookup cache. The least recently used entries
+/// are evicted once either limit is exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheLimits {
+    pub max_entries: usize,
+    pub max_bytes: usize,
+}
+
+impl Default for CacheLimits {
+    fn default() -> Self {
+        Self {
+            max_entries: 1 << 20,
+            max_bytes: 256 << 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+/// Cache key: the file of the module, the instruction pointer looked up in
+/// it and whether names are demangled. Processes mapping the file at the
+/// same address share entries.
+type CacheKey = (usize, u64, bool);
+
+struct LookupCache {
+    limits: CacheLimits,
+    entries: LruCache<CacheKey, LookupResult>,
+    stats: CacheStats,
+}
+
+impl LookupCache {
+    fn new(limits: CacheLimits) -> Self {
+        Self {
+            limits,
+            entries: LruCache::unbounded(),
+            stats: CacheStats::default(),
+        }
+    }
+
+    fn get(&mut self, key: CacheKey) -> Option<LookupResult> {
+        match self.entries.get(&key) {
+            Some(result) => {
+                self.stats.hits += 1;
+                Some(result.clone())
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    fn insert(&mut self, key: CacheKey, result: LookupResult) {
+        self.stats.bytes += result.cost();
+        if let Some(old) = self.entries.put(key, result) {
+            self.stats.bytes -= old.cost();
+        }
+
+        while self.entries.len() > self.limits.max_entries
+            || (self.stats.bytes > self.limits.max_bytes && self.entries.len() > 1)
+        {
+            let Some((_, evicted)) = self.entries.pop_lru() else {
+                break;
+            };
+            self.stats.bytes -= evicted.cost();
+            self.stats.evictions += 1;
+        }
+        self.stats.entries = self.entries.len();
+    }
+}
+
+/// Debug info of the modules seen so far and the cache of their lookups.
+///
+/// Clones share the same data, so interpreters of several processes running
+/// the same system libraries load and symbolize each of them once, see
+/// [`Resolver::with_symbols`]. The cache is split into shards with their own
+/// locks and every file has a pool of loaders, so concurrent lookups do not
+/// wait on each other.
+#[derive(Clone)]
+pub struct SharedSymbols {
+    inner: Arc<SymbolsInner>,
+}
+
+struct SymbolsInner {
+    loaders: Mutex<Loaders>,
+    shards: Vec<Mutex<LookupCache>>,
+}
+
+/// Loaders of every module file, indexed by the position of its path.
+#[derive(Default)]
+struct Loaders {
+    by_path: HashMap<String, usize>,
+    loaders: Vec<Arc<LoaderPool>>,
+}
+
+/// Loaders of one module file. A lookup takes an idle loader and the file is
+/// only loaded again when all of them are busy with other lookups.
+struct LoaderPool {
+    path: String,
+    idle: Mutex<Vec<Loader>>,
+}
+
+impl LoaderPool {
+    fn with_loader<T>(&self, f: impl FnOnce(&Loader) -> T) -> Option<T> {
+        let idle = lock(&self.idle).pop();
+        let loader = match idle {
+            Some(loader) => loader,
+            None => Loader::new(&self.path).ok()?,
+        };
+        let result = f(&loader);
+        lock(&self.idle).push(loader);
+        Some(result)
+    }
+}
+
+impl Default for SharedSymbols {
+    fn default() -> Self {
+        Self::new(CacheLimits::default())
+    }
+}
+
+impl SharedSymbols {
+    pub fn new(limits: CacheLimits) -> Self {
+        let shards = (0..CACHE_SHARDS)
+            .map(|_| Mutex::new(LookupCache::new(shard_limits(limits))))
+            .collect();
+
+        Self {
+            inner: Arc::new(SymbolsInner {
+                loaders: Mutex::new(Loaders::default()),
+                shards,
+            }),
+        }
+    }
+
+    /// Changes the cache limits. Entries beyond them are not evicted by the
+    /// call itself but when a new entry is next inserted into their shard.
+    pub fn set_cache_limits(&self, limits: CacheLimits) {
+        for shard in &self.inner.shards {
+            lock(shard).limits = shard_limits(limits);
+        }
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        let mut stats = CacheStats::default();
+        for shard in &self.inner.shards {
+            let shard = lock(shard).stats;
+            stats.hits += shard.hits;
+            stats.misses += shard.misses;
+            stats.evictions += shard.evictions;
+            stats.entries += shard.entries;
+            stats.bytes += shard.bytes;
+        }
+        stats
+    }
+
+    /// Index of the loader of `path`, loading its debug info on first use.
+    fn loader_idx(&self, path: &str) -> Result<usize, Error> {
+        let mut loaders = lock(&self.inner.loaders);
+        if let Some(&idx) = loaders.by_path.get(path) {
+            return Ok(idx);
+        }
+
+        let loader = Loader::new(path).map_err(|_| Error::ModuleNotFound)?;
+        let idx = loaders.loaders.len();
+        loaders.loaders.push(Arc::new(LoaderPool {
+            path: path.to_string(),
+            idle: Mutex::new(vec![loader]),
+        }));
+        loaders.by_path.insert(path.to_string(), idx);
+        Ok(idx)
+    }
+
+    fn loader(&self, idx: usize) -> Option<Arc<LoaderPool>> {
+        lock(&self.inner.loaders).loaders.get(idx).cloned()
+    }
+
+    fn shard(&self, key: CacheKey) -> MutexGuard<'_, LookupCache> {
+        let (loader, ip, _) = key;
+        let hash = (ip >> 4) ^ (loader as u64).rotate_left(32);
+        lock(&self.inner.shards[hash as usize % CACHE_SHARDS])
+    }
+}
+
+fn shard_limits(limits: CacheLimits) -> CacheLimits {
+    CacheLimits {
+        max_entries: limits.max_entries.div_ceil(CACHE_SHARDS),
+        max_bytes: limits.max_bytes.div_ceil(CACHE_SHARDS),
+    }
+}
+
+/// Locks `mutex`, ignoring poisoning: the guarded caches stay consistent
+/// when a lookup panics.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Maps the instruction pointers of one process to source locations.
+#[derive(Clone)]
 pub struct Resolver {
     modules: RangeMap<u64, Module>,
-    cached: HashMap<u64, LookupResult>,
-    loaders: HashMap<u64, Loader>,
+    symbols: SharedSymbols,
+    demangle: bool,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Resolver {
     pub fn new() -> Self {
+        Self::with_cache_limits(CacheLimits::default())
+    }
+
+    pub fn with_cache_limits(limits: CacheLimits) -> Self {
+        Self::with_symbols(SharedSymbols::new(limits))
+    }
+
+    /// A resolver sharing the loaded debug info and the lookup cache with
+    /// the other users of `symbols`.
+    pub fn with_symbols(symbols: SharedSymbols) -> Self {
         Self {
             modules: RangeMap::new(),
-            cached: HashMap::new(),
-            loaders: HashMap::new(),
+            symbols,
+            demangle: true,
         }
     }
 
+    pub fn symbols(&self) -> &SharedSymbols {
+        &self.symbols
+    }
+
+    /// Changes the cache limits, see [`SharedSymbols::set_cache_limits`].
+    /// Applies to every resolver sharing the cache.
+    pub fn set_cache_limits(&mut self, limits: CacheLimits) {
+        self.symbols.set_cache_limits(limits);
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.symbols.cache_stats()
+    }
+
+    /// Reports raw mangled symbol names instead of demangling them.
+    pub fn set_demangle(&mut self, demangle: bool) {
+        self.demangle = demangle;
+    }
+
+    pub fn demangle(&self) -> bool {
+        self.demangle
+    }
+
     pub fn add_module(
         &mut self,
         id: usize,
@@ -104,13 +387,8 @@ impl Resolver {
         start_address: u64,
         size: u64,
     ) -> Result<(), Error> {
-        let module = Module::new(id, file_path.to_string(), start_address, size);
-
-        let Ok(loader) = Loader::new(file_path.to_string()) else {
-            return Err(Error::ModuleNotFound);
-        };
-
-        self.loaders.insert(start_address, loader);
+        let loader = self.symbols.loader_idx(file_path)?;
+        let module = Module::new(id, loader, start_address, size);
 
         self.modules
             .insert(module.start_address..module.end_address, module);
@@ -118,35 +396,59 @@ impl Resolver {
         Ok(())
     }
 
-    pub fn lookup(&mut self, ip: u64) -> Option<LookupResult> {
-        if let Some(location) = self.cached.get(&ip).cloned() {
-            return Some(location);
-        }
+    /// Unmaps the module at `start_address`, e.g. before it is loaded again
+    /// elsewhere.
+    pub fn remove_module(&mut self, start_address: u64, size: u64) {
+        self.modules.remove(start_address..start_address + size);
+    }
 
+    pub fn lookup(&self, ip: u64) -> Option<LookupResult> {
         let module = self.modules.get(&ip)?;
-        let loader = self.loaders.get(&module.start_address)?;
-
-        let locations = module.lookup(ip, loader)?;
-
-        self.cached.insert(ip, locations.clone());
+        let key = (module.loader, ip, self.demangle);
+
+        let cached = self.symbols.shard(key).get(key);
+        let mut result = match cached {
+            Some(result) => result,
+            None => {
+                let loader = self.symbols.loader(module.loader)?;
+                let locations =
+                    loader.with_loader(|loader| find_locations(ip, loader, self.demangle))??;
+                let result = LookupResult {
+                    module_id: module.id,
+                    locations,
+                };
+                self.symbols.shard(key).insert(key, result.clone());
+                result
+            }
+        };
+        // cached results may come from another process
+        result.module_id = module.id;
 
-        Some(locations)
+        Some(result)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::resolver::Resolver;
+    use crate::resolver::{
+        lock, CacheLimits, LoaderPool, Location, LookupCache, LookupResult, Resolver, SharedSymbols,
+    };
+    use addr2line::Loader;
+    #[cfg(target_os = "macos")]
     use std::ffi::c_void;
+    use std::sync::Mutex;
 
+    #[cfg(target_os = "macos")]
     unsafe extern "C" {
         fn _dyld_get_image_header(index: u32) -> *const c_void;
         fn _dyld_get_image_vmaddr_slide(index: u32) -> isize;
     }
 
+    #[cfg(target_os = "macos")]
     fn boo() {}
 
     #[test]
+    #[cfg(target_os = "macos")]
     fn test_lookup() {
         let exe = std::env::current_exe()
             .unwrap()
@@ -158,15 +460,115 @@ mod tests {
         let addr = unsafe { _dyld_get_image_header(0) } as u64 - slide;
 
         let mut resolver = Resolver::new();
-        resolver.add_module(0, &exe, addr, 0x1000000);
+        resolver.add_module(0, &exe, addr, 0x1000000).unwrap();
 
-        let ip = boo as u64 - slide;
+        let ip = boo as *const () as u64 - slide;
 
         let res = resolver.lookup(ip);
         println!("{:#?}", res);
     }
 
     #[test]
+    fn test_cache_eviction() {
+        let result = |name: &str| LookupResult {
+            module_id: 0,
+            locations: vec![Location {
+                function_name: name.to_string(),
+                file_name: None,
+                line_number: None,
+            }],
+        };
+
+        let key = |ip| (0, ip, true);
+
+        let mut cache = LookupCache::new(CacheLimits {
+            max_entries: 2,
+            max_bytes: usize::MAX,
+        });
+        cache.insert(key(1), result("a"));
+        cache.insert(key(2), result("b"));
+        assert!(cache.get(key(1)).is_some());
+        cache.insert(key(3), result("c"));
+
+        // 2 was the least recently used entry
+        assert!(cache.get(key(2)).is_none());
+        assert!(cache.get(key(1)).is_some());
+        assert_eq!(cache.stats.hits, 2);
+        assert_eq!(cache.stats.misses, 1);
+        assert_eq!(cache.stats.evictions, 1);
+        assert_eq!(cache.stats.entries, 2);
+
+        let bytes = cache.stats.bytes;
+        cache.limits.max_bytes = bytes - 1;
+        cache.insert(key(4), result("d"));
+        assert!(cache.stats.bytes < bytes);
+    }
+
+    #[test]
+    fn test_shared_symbols() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Resolver>();
+
+        let symbols = SharedSymbols::new(CacheLimits {
+            max_entries: 32,
+            max_bytes: usize::MAX,
+        });
+        let mut resolver = Resolver::with_symbols(symbols.clone());
+        assert!(resolver
+            .add_module(1, "/nonexistent", 0x1000, 0x1000)
+            .is_err());
+
+        let exe = std::env::current_exe().unwrap();
+        let exe = exe.to_str().unwrap();
+        resolver.add_module(1, exe, 0x1000, 0x1000).unwrap();
+        let mut other = Resolver::with_symbols(symbols.clone());
+        other.add_module(7, exe, 0x1000, 0x1000).unwrap();
+        assert_eq!(lock(&symbols.inner.loaders).loaders.len(), 1);
+
+        let first = resolver.lookup(0x1800);
+        let second = other.lookup(0x1800);
+        assert_eq!(
+            first.as_ref().map(|result| &result.locations),
+            second.as_ref().map(|result| &result.locations)
+        );
+        if let Some(second) = second {
+            assert_eq!(second.module_id, 7);
+            assert_eq!(symbols.cache_stats().hits, 1);
+        }
+
+        // the file mapped elsewhere resolves its own addresses
+        let mut moved = Resolver::with_symbols(symbols.clone());
+        moved.add_module(9, exe, 0x8000, 0x1000).unwrap();
+        let mut alone = Resolver::new();
+        alone.add_module(9, exe, 0x8000, 0x1000).unwrap();
+        let hits = symbols.cache_stats().hits;
+        assert_eq!(
+            moved.lookup(0x8800).map(|result| result.locations),
+            alone.lookup(0x8800).map(|result| result.locations)
+        );
+        assert_eq!(symbols.cache_stats().hits, hits);
+    }
+
+    #[test]
+    fn test_loader_pool() {
+        let exe = std::env::current_exe().unwrap();
+        let path = exe.to_str().unwrap().to_string();
+        let pool = LoaderPool {
+            idle: Mutex::new(vec![Loader::new(&path).unwrap()]),
+            path,
+        };
+
+        // a lookup running while another one holds the loader gets its own
+        let nested = pool.with_loader(|_| pool.with_loader(|_| ()));
+        assert_eq!(nested, Some(Some(())));
+        assert_eq!(lock(&pool.idle).len(), 2);
+
+        pool.with_loader(|_| ()).unwrap();
+        assert_eq!(lock(&pool.idle).len(), 2);
+    }
+
+    #[test]
+    #[ignore = "requires a locally built binary"]
     fn test_lookup_binary() {
         let exe = "/Users/id/devel/Rust/memtrack-rs/.local/simple";
         let addr = 0x100001874;
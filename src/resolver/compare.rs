@@ -0,0 +1,225 @@
+//! Comparison of symbolization backends.
+//!
+//! Resolves the same instruction pointers through two backends and lists
+//! where they disagree, to diagnose wrong line numbers in reports and to
+//! validate a new backend against [`Resolver`].
+
+use crate::resolver::{Location, Resolver};
+use serde::Serialize;
+use std::fmt;
+
+/// Maps instruction pointers to source locations, innermost inlined frame
+/// first.
+pub trait SymbolBackend {
+    /// Name shown in the reported disagreements, e.g. `addr2line`.
+    fn name(&self) -> &str;
+
+    fn lookup(&self, ip: u64) -> Option<Vec<Location>>;
+}
+
+impl SymbolBackend for Resolver {
+    fn name(&self) -> &str {
+        "addr2line"
+    }
+
+    fn lookup(&self, ip: u64) -> Option<Vec<Location>> {
+        Resolver::lookup(self, ip).map(|result| result.locations)
+    }
+}
+
+/// First field the two backends disagree on for an instruction pointer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mismatch {
+    /// Only one backend resolved the address.
+    Unresolved,
+    Function,
+    File,
+    Line,
+    /// Same locations, but one backend reported more inlined frames.
+    InlineDepth,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Unresolved => "resolution",
+            Self::Function => "function",
+            Self::File => "file",
+            Self::Line => "line",
+            Self::InlineDepth => "inline depth",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Disagreement {
+    pub ip: u64,
+    pub mismatch: Mismatch,
+    /// Locations of the first backend, empty if it did not resolve `ip`.
+    pub first: Vec<Location>,
+    pub second: Vec<Location>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BackendComparison {
+    pub first: String,
+    pub second: String,
+    /// Addresses both backends resolved to the same locations.
+    pub agreed: usize,
+    pub disagreements: Vec<Disagreement>,
+}
+
+/// Finds the first field on which two location lists differ.
+fn mismatch(first: &[Location], second: &[Location]) -> Option<Mismatch> {
+    for (a, b) in first.iter().zip(second) {
+        if a.function_name != b.function_name {
+            return Some(Mismatch::Function);
+        }
+        if a.file_name != b.file_name {
+            return Some(Mismatch::File);
+        }
+        if a.line_number != b.line_number {
+            return Some(Mismatch::Line);
+        }
+    }
+    (first.len() != second.len()).then_some(Mismatch::InlineDepth)
+}
+
+/// Resolves every address of `ips` through both backends.
+pub fn compare_backends(
+    first: &dyn SymbolBackend,
+    second: &dyn SymbolBackend,
+    ips: impl IntoIterator<Item = u64>,
+) -> BackendComparison {
+    let mut agreed = 0;
+    let mut disagreements = Vec::new();
+    for ip in ips {
+        let (a, b) = (first.lookup(ip), second.lookup(ip));
+        let mismatch = match (&a, &b) {
+            (None, None) => None,
+            (Some(a), Some(b)) => mismatch(a, b),
+            _ => Some(Mismatch::Unresolved),
+        };
+        match mismatch {
+            Some(mismatch) => disagreements.push(Disagreement {
+                ip,
+                mismatch,
+                first: a.unwrap_or_default(),
+                second: b.unwrap_or_default(),
+            }),
+            None => agreed += 1,
+        }
+    }
+
+    BackendComparison {
+        first: first.name().to_string(),
+        second: second.name().to_string(),
+        agreed,
+        disagreements,
+    }
+}
+
+fn describe(locations: &[Location]) -> String {
+    let Some(location) = locations.first() else {
+        return "unresolved".to_string();
+    };
+    match (&location.file_name, location.line_number) {
+        (Some(file), Some(line)) => format!("{} at {}:{}", location.function_name, file, line),
+        (Some(file), None) => format!("{} at {}", location.function_name, file),
+        _ => location.function_name.clone(),
+    }
+}
+
+impl fmt::Display for BackendComparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} vs {}: {} agreed, {} disagreements",
+            self.first,
+            self.second,
+            self.agreed,
+            self.disagreements.len()
+        )?;
+        for disagreement in &self.disagreements {
+            writeln!(
+                f,
+                "  {:#x}: {} differs: {} ({}) vs {} ({})",
+                disagreement.ip,
+                disagreement.mismatch,
+                describe(&disagreement.first),
+                self.first,
+                describe(&disagreement.second),
+                self.second
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::resolver::compare::{compare_backends, Mismatch, SymbolBackend};
+    use crate::resolver::Location;
+    use std::collections::HashMap;
+
+    struct Table(&'static str, HashMap<u64, Vec<Location>>);
+
+    impl SymbolBackend for Table {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn lookup(&self, ip: u64) -> Option<Vec<Location>> {
+            self.1.get(&ip).cloned()
+        }
+    }
+
+    fn location(function: &str, line: u32) -> Location {
+        Location {
+            function_name: function.to_string(),
+            file_name: Some("src/main.rs".to_string()),
+            line_number: Some(line),
+        }
+    }
+
+    #[test]
+    fn test_compare_backends() {
+        let first = Table(
+            "first",
+            HashMap::from([
+                (1, vec![location("main", 3)]),
+                (2, vec![location("run", 10)]),
+                (3, vec![location("helper", 4), location("run", 12)]),
+                (4, vec![location("alloc", 1)]),
+            ]),
+        );
+        let second = Table(
+            "second",
+            HashMap::from([
+                (1, vec![location("main", 3)]),
+                (2, vec![location("run", 11)]),
+                (3, vec![location("helper", 4)]),
+            ]),
+        );
+
+        let comparison = compare_backends(&first, &second, 1..=5);
+        assert_eq!(comparison.agreed, 2);
+        let mismatches: Vec<_> = comparison
+            .disagreements
+            .iter()
+            .map(|d| (d.ip, d.mismatch))
+            .collect();
+        assert_eq!(
+            mismatches,
+            [
+                (2, Mismatch::Line),
+                (3, Mismatch::InlineDepth),
+                (4, Mismatch::Unresolved),
+            ]
+        );
+        assert!(comparison.to_string().contains(
+            "0x2: line differs: run at src/main.rs:10 (first) vs run at src/main.rs:11 (second)"
+        ));
+    }
+}
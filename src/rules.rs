@@ -0,0 +1,219 @@
+//! Suppression and categorization rules applied by the [`Interpreter`](crate::interpret::Interpreter).
+//!
+//! Rules files are line based:
+//!
+//! ```text
+//! # comment
+//! suppress <function substring>
+//! category <name> <function substring>
+//! ```
+//!
+//! An allocation is suppressed when any frame of its stack matches a
+//! `suppress` rule. Otherwise it is assigned the first category with a
+//! matching frame.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("IO error")]
+    Io(#[from] io::Error),
+    #[error("invalid rule at line {line}: {text}")]
+    InvalidRule { line: usize, text: String },
+    #[error("no rules file to reload")]
+    NoPath,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rules {
+    pub suppress: Vec<String>,
+    pub categories: Vec<(String, String)>,
+}
+
+/// Outcome of matching a stack against [`Rules`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    Keep,
+    Suppress,
+    Category(String),
+}
+
+impl Rules {
+    pub fn parse(content: &str) -> Result<Self, Error> {
+        let mut rules = Rules::default();
+
+        for (idx, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || Error::InvalidRule {
+                line: idx + 1,
+                text: line.to_string(),
+            };
+
+            let (kind, rest) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            match kind {
+                "suppress" => rules.suppress.push(rest.trim().to_string()),
+                "category" => {
+                    let (name, pattern) = rest
+                        .trim()
+                        .split_once(char::is_whitespace)
+                        .ok_or_else(invalid)?;
+                    rules
+                        .categories
+                        .push((name.to_string(), pattern.trim().to_string()));
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(rules)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.suppress.is_empty() && self.categories.is_empty()
+    }
+
+    /// Matches the function names of a stack against the rules.
+    pub fn decide<'a>(&self, functions: impl IntoIterator<Item = &'a str> + Clone) -> Decision {
+        if functions
+            .clone()
+            .into_iter()
+            .any(|f| self.suppress.iter().any(|p| f.contains(p.as_str())))
+        {
+            return Decision::Suppress;
+        }
+
+        for (name, pattern) in &self.categories {
+            if functions
+                .clone()
+                .into_iter()
+                .any(|f| f.contains(pattern.as_str()))
+            {
+                return Decision::Category(name.clone());
+            }
+        }
+
+        Decision::Keep
+    }
+}
+
+struct Shared {
+    path: Option<PathBuf>,
+    rules: Mutex<Arc<Rules>>,
+    generation: AtomicU64,
+    sighup: Arc<AtomicBool>,
+}
+
+/// Shared, reloadable rules. Clones refer to the same rules, so a handle kept
+/// by the caller can replace the rules of a running interpreter.
+#[derive(Clone)]
+pub struct RulesHandle {
+    shared: Arc<Shared>,
+}
+
+impl RulesHandle {
+    pub fn new(rules: Rules) -> Self {
+        Self::with_path(rules, None)
+    }
+
+    /// Loads rules from `path` and remembers it for later reloads.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let rules = Rules::load(&path)?;
+        Ok(Self::with_path(rules, Some(path.as_ref().to_path_buf())))
+    }
+
+    fn with_path(rules: Rules, path: Option<PathBuf>) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                path,
+                rules: Mutex::new(Arc::new(rules)),
+                generation: AtomicU64::new(0),
+                sighup: Arc::new(AtomicBool::new(false)),
+            }),
+        }
+    }
+
+    pub fn set(&self, rules: Rules) {
+        *self.shared.rules.lock().unwrap() = Arc::new(rules);
+        self.shared.generation.fetch_add(1, Ordering::Release);
+    }
+
+    /// Re-reads the rules file the handle was loaded from. On error the
+    /// current rules stay in effect.
+    pub fn reload(&self) -> Result<(), Error> {
+        let path = self.shared.path.as_ref().ok_or(Error::NoPath)?;
+        self.set(Rules::load(path)?);
+        Ok(())
+    }
+
+    /// Reloads the rules file whenever the process receives SIGHUP.
+    #[cfg(feature = "exec")]
+    pub fn reload_on_sighup(&self) -> io::Result<()> {
+        signal_hook::flag::register(signal_hook::consts::SIGHUP, self.shared.sighup.clone())?;
+        Ok(())
+    }
+
+    /// Incremented on every change of the rules.
+    pub fn generation(&self) -> u64 {
+        if self.shared.sighup.swap(false, Ordering::AcqRel) {
+            _ = self.reload();
+        }
+
+        self.shared.generation.load(Ordering::Acquire)
+    }
+
+    pub fn current(&self) -> Arc<Rules> {
+        self.shared.rules.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rules::{Decision, Rules, RulesHandle};
+
+    #[test]
+    fn test_parse_and_decide() {
+        let rules = Rules::parse(
+            "# rules\n\
+             suppress std::rt::lang_start\n\
+             category json serde_json::\n\
+             category net hyper::\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            rules.decide(["malloc", "serde_json::from_str", "main"]),
+            Decision::Category("json".into())
+        );
+        assert_eq!(
+            rules.decide(["malloc", "std::rt::lang_start"]),
+            Decision::Suppress
+        );
+        assert_eq!(rules.decide(["malloc", "main"]), Decision::Keep);
+
+        assert!(Rules::parse("ignore foo").is_err());
+    }
+
+    #[test]
+    fn test_handle_generation() {
+        let handle = RulesHandle::new(Rules::default());
+        assert_eq!(handle.generation(), 0);
+
+        handle.set(Rules::parse("suppress foo").unwrap());
+        assert_eq!(handle.generation(), 1);
+        assert_eq!(handle.current().suppress, ["foo"]);
+        assert!(handle.reload().is_err());
+    }
+}
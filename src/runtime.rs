@@ -0,0 +1,227 @@
+//! Location and cleanup of runtime artifacts such as the record fifos.
+//!
+//! Artifacts are named after the process that created them (`<pid>.pipe`,
+//! `<pid>-<suffix>.<ext>`), which lets [`RuntimeDirs::cleanup`] tell the
+//! leftovers of crashed runs from files still in use.
+
+use nix::errno::Errno;
+use nix::sys::signal::kill;
+use nix::unistd::Pid;
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+static FIFO_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// Overrides the directory chosen by [`RuntimeDirs::from_env`].
+pub const RUNTIME_DIR_ENV: &str = "MEMTRACK_RUNTIME_DIR";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeDirs {
+    pub runtime: PathBuf,
+    /// How long artifacts of finished or crashed runs are kept before
+    /// [`RuntimeDirs::cleanup`] removes them.
+    pub retention: Duration,
+}
+
+impl Default for RuntimeDirs {
+    fn default() -> Self {
+        Self::from_env()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CleanupReport {
+    pub removed: Vec<PathBuf>,
+    /// Artifacts of dead processes still within the retention period.
+    pub retained: usize,
+    /// Artifacts that could not be inspected or removed. The cleanup goes on
+    /// with the other ones.
+    pub failed: Vec<CleanupFailure>,
+}
+
+/// An artifact, or the runtime directory itself, the cleanup failed on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CleanupFailure {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+impl CleanupFailure {
+    pub fn new(path: impl AsRef<Path>, error: &io::Error) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            error: error.to_string(),
+        }
+    }
+}
+
+/// Pid encoded in an artifact name, or `None` for files not created by us.
+fn owner(name: &str) -> Option<i32> {
+    let end = name.find(['.', '-'])?;
+    name[..end].parse().ok()
+}
+
+fn is_alive(pid: i32) -> bool {
+    // EPERM means the process exists but belongs to someone else
+    !matches!(kill(Pid::from_raw(pid), None), Err(Errno::ESRCH))
+}
+
+impl RuntimeDirs {
+    pub fn new(runtime: impl AsRef<Path>) -> Self {
+        Self {
+            runtime: runtime.as_ref().to_path_buf(),
+            retention: Duration::ZERO,
+        }
+    }
+
+    pub fn with_retention(mut self, retention: Duration) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Picks the runtime directory from, in order, `MEMTRACK_RUNTIME_DIR`,
+    /// `$XDG_RUNTIME_DIR/memtrack` and `memtrack-<uid>` in the temporary
+    /// directory of [`env::temp_dir`], e.g. the per-user `$TMPDIR` of macOS
+    /// or the one of a sandbox.
+    pub fn from_env() -> Self {
+        let runtime = if let Some(dir) = env::var_os(RUNTIME_DIR_ENV).filter(|d| !d.is_empty()) {
+            PathBuf::from(dir)
+        } else if let Some(dir) = env::var_os("XDG_RUNTIME_DIR").filter(|d| !d.is_empty()) {
+            PathBuf::from(dir).join("memtrack")
+        } else {
+            env::temp_dir().join(format!("memtrack-{}", nix::unistd::getuid()))
+        };
+
+        Self::new(runtime)
+    }
+
+    /// Creates the runtime directory, accessible to the current user only.
+    pub fn create(&self) -> io::Result<()> {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&self.runtime)
+    }
+
+    /// Path of a new record fifo of the current process. Every call returns
+    /// a different path, so several programs can be traced at once.
+    pub fn unique_fifo_path(&self) -> PathBuf {
+        let seq = FIFO_SEQ.fetch_add(1, Ordering::Relaxed);
+        self.artifact(&seq.to_string(), "pipe")
+    }
+
+    /// Path of an artifact of the current process, e.g. `artifact("tee",
+    /// "bin")` for `<pid>-tee.bin`.
+    pub fn artifact(&self, suffix: &str, extension: &str) -> PathBuf {
+        self.runtime
+            .join(format!("{}-{}.{}", std::process::id(), suffix, extension))
+    }
+
+    /// Removes the artifacts of processes that are no longer running once
+    /// they are older than the retention period. Files that do not follow
+    /// the artifact naming and artifacts of live processes are left alone.
+    /// Fails only if the directory cannot be read, artifacts the cleanup
+    /// fails on are listed in [`CleanupReport::failed`].
+    pub fn cleanup(&self) -> io::Result<CleanupReport> {
+        let mut report = CleanupReport::default();
+
+        let entries = match fs::read_dir(&self.runtime) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(report),
+            Err(e) => return Err(e),
+        };
+
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    report.failed.push(CleanupFailure::new(&self.runtime, &e));
+                    continue;
+                }
+            };
+            let Some(pid) = entry.file_name().to_str().and_then(owner) else {
+                continue;
+            };
+            if pid <= 0 || is_alive(pid) {
+                continue;
+            }
+
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    report.failed.push(CleanupFailure::new(entry.path(), &e));
+                    continue;
+                }
+            };
+            if metadata.is_dir() {
+                continue;
+            }
+            let age = metadata
+                .modified()
+                .ok()
+                .and_then(|t| SystemTime::now().duration_since(t).ok())
+                .unwrap_or_default();
+            if age < self.retention {
+                report.retained += 1;
+                continue;
+            }
+
+            match fs::remove_file(entry.path()) {
+                Ok(()) => report.removed.push(entry.path()),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => report.failed.push(CleanupFailure::new(entry.path(), &e)),
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::{owner, RuntimeDirs};
+    use std::time::Duration;
+
+    #[test]
+    fn test_owner() {
+        assert_eq!(owner("123.pipe"), Some(123));
+        assert_eq!(owner("123-tee.bin"), Some(123));
+        assert_eq!(owner("notes.txt"), None);
+    }
+
+    #[test]
+    fn test_cleanup() {
+        let dirs = RuntimeDirs::new(
+            std::env::temp_dir().join(format!("memtrack-runtime-{}", std::process::id())),
+        );
+        dirs.create().unwrap();
+
+        // pid_max on Linux is at most 2^22
+        let dead = dirs.runtime.join("1073741823.pipe");
+        let live = dirs.unique_fifo_path();
+        let foreign = dirs.runtime.join("notes.txt");
+        for path in [&dead, &live, &foreign] {
+            std::fs::write(path, "").unwrap();
+        }
+
+        let retained = dirs
+            .clone()
+            .with_retention(Duration::from_secs(3600))
+            .cleanup()
+            .unwrap();
+        assert_eq!(retained.retained, 1);
+        assert!(retained.removed.is_empty());
+
+        let report = dirs.cleanup().unwrap();
+        let remaining = (live.exists(), foreign.exists());
+        _ = std::fs::remove_dir_all(&dirs.runtime);
+
+        assert_eq!(report.removed, [dead]);
+        assert_eq!(remaining, (true, true));
+    }
+}
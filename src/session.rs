@@ -0,0 +1,667 @@
+//! One-call facade running a program under the tracer and post-processing
+//! the produced trace.
+
+use crate::alerts::AlertRule;
+use crate::diff::{diff, SiteDiff};
+use crate::export::preview;
+use crate::export::preview::PreviewOptions;
+use crate::interpret::{
+    CaptureLimits, ChildStdio, ControlHandle, Interpreter, SharedSymbols, StackThreshold,
+};
+use crate::model::{Cost, Profile};
+#[cfg(feature = "download")]
+use crate::otlp::{OtlpBridge, OtlpOptions};
+use crate::parser::{AccumulatedData, Parser};
+use crate::redact::Redaction;
+use crate::report::{escape, verdict, Report, ReportOptions, Verdict};
+use crate::rules::RulesHandle;
+use crate::runtime::{CleanupFailure, CleanupReport, RuntimeDirs};
+use crate::soak::{SoakOptions, SoakSummaryWriter};
+use crate::{interpret, model, parser};
+use serde::Serialize;
+use std::ffi::OsStr;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("IO error")]
+    Io(#[from] io::Error),
+    #[error("Interpreter")]
+    Interpret(#[from] interpret::Error),
+    #[error("Parser")]
+    Parse(#[from] parser::Error),
+    #[error("Model")]
+    Model(#[from] model::Error),
+    /// The run failed, e.g. the program crashed. `data` holds what was
+    /// traced up to the failure, if it could be parsed.
+    #[error("Run failed")]
+    Run {
+        #[source]
+        source: interpret::Error,
+        data: Option<Box<AccumulatedData>>,
+    },
+    #[error("trace belongs to session {found:?}, not {expected:?}")]
+    SessionMismatch {
+        expected: String,
+        found: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Json,
+    Html,
+}
+
+/// Summary written from the parsed trace after a run.
+#[derive(Debug, Clone)]
+pub enum SummarySink {
+    Preview {
+        path: PathBuf,
+        options: PreviewOptions,
+    },
+    Report {
+        path: PathBuf,
+        format: ReportFormat,
+        options: ReportOptions,
+    },
+}
+
+pub struct Session {
+    lib_path: PathBuf,
+    output: PathBuf,
+    sinks: Vec<SummarySink>,
+    rules: Option<RulesHandle>,
+    demangle: bool,
+    alerts: Vec<AlertRule>,
+    #[cfg(feature = "download")]
+    otlp: Option<OtlpOptions>,
+    write_behind: Option<usize>,
+    soak: Option<SoakOptions>,
+    runtime_dirs: RuntimeDirs,
+    stdio: ChildStdio,
+    capture_limits: CaptureLimits,
+    stack_threshold: Option<StackThreshold>,
+    strict_frees: bool,
+    reconnect: bool,
+    redaction: Option<Redaction>,
+    symbols: Option<SharedSymbols>,
+    control: ControlHandle,
+    session_id: Option<String>,
+    resume: bool,
+    last_cleanup: Mutex<Option<CleanupReport>>,
+}
+
+impl Session {
+    pub fn new(lib_path: impl AsRef<Path>, output: impl AsRef<Path>) -> Self {
+        Self {
+            lib_path: lib_path.as_ref().to_path_buf(),
+            output: output.as_ref().to_path_buf(),
+            sinks: Vec::new(),
+            rules: None,
+            demangle: true,
+            alerts: Vec::new(),
+            #[cfg(feature = "download")]
+            otlp: None,
+            write_behind: None,
+            soak: None,
+            runtime_dirs: RuntimeDirs::default(),
+            stdio: ChildStdio::default(),
+            capture_limits: CaptureLimits::default(),
+            stack_threshold: None,
+            strict_frees: false,
+            reconnect: false,
+            redaction: None,
+            symbols: None,
+            control: ControlHandle::new(),
+            session_id: None,
+            resume: false,
+            last_cleanup: Mutex::new(None),
+        }
+    }
+
+    pub fn with_rules(mut self, rules: RulesHandle) -> Self {
+        self.rules = Some(rules);
+        self
+    }
+
+    /// See [`Interpreter::set_alerts`].
+    pub fn with_alerts(mut self, rules: Vec<AlertRule>) -> Self {
+        self.alerts = rules;
+        self
+    }
+
+    /// Publishes live statistics to an OTLP collector while tracing.
+    #[cfg(feature = "download")]
+    pub fn with_otlp(mut self, options: OtlpOptions) -> Self {
+        self.otlp = Some(options);
+        self
+    }
+
+    /// Rewrites a JSON summary of the run periodically while tracing, see
+    /// [`SoakSummaryWriter`].
+    pub fn with_soak_summary(mut self, options: SoakOptions) -> Self {
+        self.soak = Some(options);
+        self
+    }
+
+    /// See [`Interpreter::set_write_behind`].
+    pub fn with_write_behind(mut self, capacity: usize) -> Self {
+        self.write_behind = Some(capacity);
+        self
+    }
+
+    /// Directory for runtime artifacts. Stale artifacts of crashed runs
+    /// found there are cleaned up before each run.
+    pub fn with_runtime_dirs(mut self, dirs: RuntimeDirs) -> Self {
+        self.runtime_dirs = dirs;
+        self
+    }
+
+    /// See [`Interpreter::set_stdio`]. Output read with [`StdioMode::Pipe`]
+    /// is dropped; redirect it to a file to keep it.
+    ///
+    /// [`StdioMode::Pipe`]: crate::interpret::StdioMode::Pipe
+    pub fn with_stdio(mut self, stdio: ChildStdio) -> Self {
+        self.stdio = stdio;
+        self
+    }
+
+    /// Stops recording after `duration` of wall-clock time, leaving the
+    /// program running.
+    pub fn with_max_duration(mut self, duration: Duration) -> Self {
+        self.capture_limits.max_duration = Some(duration);
+        self
+    }
+
+    /// Stops recording after `events` allocations and frees, leaving the
+    /// program running.
+    pub fn with_max_events(mut self, events: u64) -> Self {
+        self.capture_limits.max_events = Some(events);
+        self
+    }
+
+    /// Captures stacks only for allocations of at least `threshold` bytes,
+    /// see [`StackThreshold`].
+    pub fn with_stack_threshold(mut self, threshold: StackThreshold) -> Self {
+        self.stack_threshold = Some(threshold);
+        self
+    }
+
+    /// See [`Interpreter::set_strict_frees`].
+    pub fn with_strict_frees(mut self, strict: bool) -> Self {
+        self.strict_frees = strict;
+        self
+    }
+
+    /// See [`Interpreter::set_reconnect`].
+    pub fn with_reconnect(mut self, reconnect: bool) -> Self {
+        self.reconnect = reconnect;
+        self
+    }
+
+    /// See [`Interpreter::set_redaction`].
+    pub fn with_redaction(mut self, redaction: Redaction) -> Self {
+        self.redaction = Some(redaction);
+        self
+    }
+
+    /// See [`Interpreter::set_shared_symbols`].
+    pub fn with_shared_symbols(mut self, symbols: SharedSymbols) -> Self {
+        self.symbols = Some(symbols);
+        self
+    }
+
+    pub fn has_shared_symbols(&self) -> bool {
+        self.symbols.is_some()
+    }
+
+    /// See [`Interpreter::set_demangle`].
+    pub fn with_demangle(mut self, demangle: bool) -> Self {
+        self.demangle = demangle;
+        self
+    }
+
+    /// Writes a `.memtrack.json` preview next to the trace after the run.
+    pub fn with_preview(self, options: PreviewOptions) -> Self {
+        let path = self.output.with_extension(preview::EXTENSION);
+        self.with_preview_at(path, options)
+    }
+
+    pub fn with_preview_at(self, path: impl AsRef<Path>, options: PreviewOptions) -> Self {
+        self.with_sink(SummarySink::Preview {
+            path: path.as_ref().to_path_buf(),
+            options,
+        })
+    }
+
+    /// Writes a report of the run to `path`, see [`Report`].
+    pub fn with_report(
+        self,
+        path: impl AsRef<Path>,
+        format: ReportFormat,
+        options: ReportOptions,
+    ) -> Self {
+        self.with_sink(SummarySink::Report {
+            path: path.as_ref().to_path_buf(),
+            format,
+            options,
+        })
+    }
+
+    /// Adds a summary written after the run. Summaries are built from the
+    /// trace parsed while it is written, so the file is never read back.
+    pub fn with_sink(mut self, sink: SummarySink) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    /// Names the trace, see [`Interpreter::set_session_id`]. A resumed
+    /// trace must have been started under the same name.
+    pub fn with_session_id(mut self, id: &str) -> Self {
+        self.session_id = Some(id.to_string());
+        self
+    }
+
+    /// Appends to an existing output instead of replacing it, see
+    /// [`Interpreter::resume`]. Starts a new trace if there is none yet.
+    pub fn with_resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// Handle to pause, resume or resample the traced program while
+    /// [`Session::run`] is in progress, see [`Interpreter::control`].
+    pub fn control(&self) -> ControlHandle {
+        self.control.clone()
+    }
+
+    pub fn output(&self) -> &Path {
+        &self.output
+    }
+
+    /// Cleanup of the runtime directory done at the start of the last
+    /// [`run`](Self::run). Its failures do not fail the run, they are only
+    /// reported here.
+    pub fn last_cleanup(&self) -> Option<CleanupReport> {
+        self.last_cleanup.lock().unwrap().clone()
+    }
+
+    /// Traces `program` into the output file and parses the result.
+    pub fn run<S, P>(
+        &self,
+        program: S,
+        args: impl IntoIterator<Item = S>,
+        cwd: P,
+    ) -> Result<AccumulatedData, Error>
+    where
+        S: AsRef<OsStr>,
+        P: AsRef<Path>,
+    {
+        // leftovers of earlier runs must not keep this one from running
+        let cleanup = self
+            .runtime_dirs
+            .cleanup()
+            .unwrap_or_else(|e| CleanupReport {
+                failed: vec![CleanupFailure::new(&self.runtime_dirs.runtime, &e)],
+                ..CleanupReport::default()
+            });
+        *self.last_cleanup.lock().unwrap() = Some(cleanup);
+
+        let resume = self.resume && self.output.exists();
+        let mut interpreter = if resume {
+            Interpreter::resume(&self.output)?
+        } else {
+            Interpreter::new(&self.output)?
+        };
+        if let Some(id) = &self.session_id {
+            if resume && interpreter.session_id() != Some(id.as_str()) {
+                return Err(Error::SessionMismatch {
+                    expected: id.clone(),
+                    found: interpreter.session_id().map(str::to_string),
+                });
+            }
+            interpreter.set_session_id(id);
+        }
+        interpreter.set_runtime_dirs(self.runtime_dirs.clone());
+        interpreter.set_stdio(self.stdio.clone());
+        if let Some(symbols) = &self.symbols {
+            interpreter.set_shared_symbols(symbols.clone());
+        }
+        interpreter.set_demangle(self.demangle);
+        interpreter.set_capture_limits(self.capture_limits);
+        interpreter.set_stack_threshold(self.stack_threshold.clone());
+        interpreter.set_strict_frees(self.strict_frees);
+        interpreter.set_reconnect(self.reconnect);
+        interpreter.set_redaction(self.redaction.clone());
+        interpreter.set_control(self.control.clone());
+        interpreter.set_alerts(self.alerts.clone());
+        if let Some(capacity) = self.write_behind {
+            interpreter.set_write_behind(capacity)?;
+        }
+        #[cfg(feature = "download")]
+        if let Some(options) = &self.otlp {
+            interpreter.add_observer(Box::new(OtlpBridge::new(options.clone())));
+        }
+        if let Some(options) = &self.soak {
+            interpreter.add_observer(Box::new(SoakSummaryWriter::new(options.clone())));
+        }
+        if let Some(rules) = &self.rules {
+            interpreter.set_rules(rules.clone());
+        }
+        // the summary only sees what this run writes, and is only parsed
+        // while recording for the sinks; otherwise the trace is parsed once
+        // after the run, off the recording path
+        if !resume && !self.sinks.is_empty() {
+            interpreter.set_summary()?;
+        }
+        if let Err(source) = interpreter.exec(program, args, cwd, &self.lib_path) {
+            let summary = interpreter.take_summary();
+            // flushes what was written before the failure
+            drop(interpreter);
+            let data = match summary {
+                Some(data) => data.ok(),
+                None => Parser::new().parse_file(&self.output).ok(),
+            };
+            return Err(Error::Run {
+                source,
+                data: data.map(Box::new),
+            });
+        }
+
+        let data = match interpreter.take_summary() {
+            Some(data) => data?,
+            None => Parser::new().parse_file(&self.output)?,
+        };
+
+        if !self.sinks.is_empty() {
+            let profile = Profile::new(&data)?;
+            for sink in &self.sinks {
+                write_sink(sink, &data, &profile)?;
+            }
+        }
+
+        Ok(data)
+    }
+}
+
+fn write_sink(sink: &SummarySink, data: &AccumulatedData, profile: &Profile) -> io::Result<()> {
+    match sink {
+        SummarySink::Preview { path, options } => preview::write(path, data, profile, options),
+        SummarySink::Report {
+            path,
+            format,
+            options,
+        } => {
+            let report = Report::new(data, profile, options);
+            let mut out = BufWriter::new(File::create(path)?);
+            match format {
+                ReportFormat::Json => report.write_json(&mut out)?,
+                ReportFormat::Html => report.write_html(&mut out)?,
+            }
+            out.flush()
+        }
+    }
+}
+
+/// A/B memory experiment: traces several labeled commands with identical
+/// settings and compares every run with the first one, the baseline.
+pub struct Ab {
+    lib_path: PathBuf,
+    dir: PathBuf,
+    settings: Option<Box<dyn Fn(Session) -> Session>>,
+    options: ReportOptions,
+    runs: Vec<(String, process::Command)>,
+}
+
+impl Ab {
+    /// Traces the runs into `<dir>/<label>.trace`.
+    pub fn new(lib_path: impl AsRef<Path>, dir: impl AsRef<Path>) -> Self {
+        Self {
+            lib_path: lib_path.as_ref().to_path_buf(),
+            dir: dir.as_ref().to_path_buf(),
+            settings: None,
+            options: ReportOptions::default(),
+            runs: Vec::new(),
+        }
+    }
+
+    /// Configures the [`Session`] of every run, e.g. with capture limits.
+    pub fn with_settings(mut self, settings: impl Fn(Session) -> Session + 'static) -> Self {
+        self.settings = Some(Box::new(settings));
+        self
+    }
+
+    /// Ranks and limits the compared sites by `options.metric` and
+    /// `options.top_sites`.
+    pub fn with_report_options(mut self, options: ReportOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Adds a run of `command`. Its program, arguments and working directory
+    /// are used; environment changes are not applied.
+    pub fn run(mut self, label: &str, command: process::Command) -> Self {
+        self.runs.push((label.to_string(), command));
+        self
+    }
+
+    /// Traces the runs in order and compares their traces.
+    pub fn report(self) -> Result<AbReport, Error> {
+        fs::create_dir_all(&self.dir)?;
+
+        let mut runs = Vec::with_capacity(self.runs.len());
+        for (label, command) in &self.runs {
+            let output = self.dir.join(format!("{}.trace", label));
+            let mut session = Session::new(&self.lib_path, output);
+            if let Some(settings) = &self.settings {
+                session = settings(session);
+            }
+            let cwd = command.get_current_dir().unwrap_or(Path::new("."));
+            let data = session.run(command.get_program(), command.get_args(), cwd)?;
+            runs.push((label.clone(), data));
+        }
+
+        Ok(AbReport::new(&runs, &self.options)?)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AbRun {
+    pub label: String,
+    /// Command line recorded in the trace.
+    pub command: Option<String>,
+    pub total: Cost,
+    pub peak_rss: u64,
+    pub duration_ms: u128,
+    pub verdict: Verdict,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AbComparison {
+    pub baseline: String,
+    pub candidate: String,
+    /// Sites by decreasing absolute change of the ranking metric.
+    pub sites: Vec<SiteDiff>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AbReport {
+    pub runs: Vec<AbRun>,
+    /// The baseline compared with every later run.
+    pub comparisons: Vec<AbComparison>,
+}
+
+impl AbReport {
+    pub fn new(
+        runs: &[(String, AccumulatedData)],
+        options: &ReportOptions,
+    ) -> Result<Self, model::Error> {
+        let change = |site: &SiteDiff| {
+            let get = |cost: Option<Cost>| cost.unwrap_or_default().get(options.metric) as i64;
+            (get(site.after) - get(site.before)).unsigned_abs()
+        };
+
+        let mut comparisons = Vec::new();
+        if let Some(((baseline, before), candidates)) = runs.split_first() {
+            for (candidate, after) in candidates {
+                let mut sites = diff(before, after)?.sites;
+                sites.sort_by_key(|site| std::cmp::Reverse(change(site)));
+                sites.truncate(options.top_sites);
+                comparisons.push(AbComparison {
+                    baseline: baseline.clone(),
+                    candidate: candidate.clone(),
+                    sites,
+                });
+            }
+        }
+
+        let runs = runs
+            .iter()
+            .map(|(label, data)| AbRun {
+                label: label.clone(),
+                command: data.command.clone(),
+                total: Cost::from(&data.total),
+                peak_rss: data.peak_rss,
+                duration_ms: data.duration.as_millis(),
+                verdict: verdict(data),
+            })
+            .collect();
+
+        Ok(Self { runs, comparisons })
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>, format: ReportFormat) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        match format {
+            ReportFormat::Json => self.write_json(&mut out)?,
+            ReportFormat::Html => self.write_html(&mut out)?,
+        }
+        out.flush()
+    }
+
+    pub fn write_json(&self, out: impl Write) -> io::Result<()> {
+        serde_json::to_writer_pretty(out, self)?;
+        Ok(())
+    }
+
+    pub fn write_html(&self, mut out: impl Write) -> io::Result<()> {
+        writeln!(out, "<!DOCTYPE html>")?;
+        writeln!(
+            out,
+            "<html><head><meta charset=\"utf-8\"><title>memtrack A/B report</title>"
+        )?;
+        writeln!(
+            out,
+            "<style>body{{font-family:sans-serif}}td,th{{padding:2px 8px}}</style></head><body>"
+        )?;
+
+        writeln!(out, "<h1>Runs</h1><table>")?;
+        writeln!(
+            out,
+            "<tr><th>run</th><th>allocations</th><th>leaked</th><th>peak</th>\
+             <th>peak RSS</th><th>duration</th><th>verdict</th></tr>"
+        )?;
+        for run in &self.runs {
+            writeln!(
+                out,
+                "<tr><td title=\"{}\">{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td>\
+                 <td>{} ms</td><td>{}</td></tr>",
+                escape(run.command.as_deref().unwrap_or_default()),
+                escape(&run.label),
+                run.total.allocations,
+                run.total.leaked,
+                run.total.peak,
+                run.peak_rss,
+                run.duration_ms,
+                escape(&run.verdict.to_string())
+            )?;
+        }
+        writeln!(out, "</table>")?;
+
+        for comparison in &self.comparisons {
+            writeln!(
+                out,
+                "<h1>{} vs {}</h1><table>",
+                escape(&comparison.candidate),
+                escape(&comparison.baseline)
+            )?;
+            writeln!(
+                out,
+                "<tr><th>site</th><th>allocations</th><th>leaked</th><th>peak</th></tr>"
+            )?;
+            for site in &comparison.sites {
+                let delta = site.delta();
+                writeln!(
+                    out,
+                    "<tr><td>{}</td><td>{:+}</td><td>{:+}</td><td>{:+}</td></tr>",
+                    escape(&site.stack.join(" ← ")),
+                    delta.allocations,
+                    delta.leaked,
+                    delta.peak
+                )?;
+            }
+            writeln!(out, "</table>")?;
+        }
+
+        writeln!(out, "</body></html>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::tests::{data, parse, TRACE};
+    use crate::report::ReportOptions;
+    use crate::runtime::RuntimeDirs;
+    use crate::session::{AbReport, Session};
+
+    #[test]
+    fn test_ab_report() {
+        let candidate = parse(&format!("{}+ 1\n+ 1\n", TRACE));
+        let runs = [
+            ("baseline".to_string(), data()),
+            ("candidate".to_string(), candidate),
+        ];
+        let report = AbReport::new(&runs, &ReportOptions::default()).unwrap();
+
+        assert_eq!(report.runs.len(), 2);
+        assert_eq!(report.runs[1].total.peak, 0x70);
+        assert_eq!(report.comparisons.len(), 1);
+        let sites = &report.comparisons[0].sites;
+        assert_eq!(sites[0].stack, ["b", "main"]);
+        assert_eq!(sites[0].delta().peak, 0x40);
+        assert_eq!(sites[1].delta().peak, 0);
+
+        let mut html = Vec::new();
+        report.write_html(&mut html).unwrap();
+        let html = String::from_utf8(html).unwrap();
+        assert!(html.contains("<h1>candidate vs baseline</h1>"));
+        assert!(html.contains("<td>b ← main</td><td>+2</td><td>+64</td><td>+64</td>"));
+    }
+
+    #[test]
+    fn test_cleanup_failure() {
+        let dir = std::env::temp_dir().join(format!("memtrack-session-{}", std::process::id()));
+        // a file in place of the runtime directory
+        std::fs::write(&dir, "").unwrap();
+
+        let session = Session::new("/nonexistent/lib", dir.join("out.trace"))
+            .with_runtime_dirs(RuntimeDirs::new(&dir));
+        assert!(session.last_cleanup().is_none());
+        let result = session.run("true", [], ".");
+        _ = std::fs::remove_file(&dir);
+
+        assert!(result.is_err());
+        let cleanup = session.last_cleanup().unwrap();
+        assert_eq!(cleanup.failed.len(), 1);
+        assert_eq!(cleanup.failed[0].path, dir);
+    }
+}
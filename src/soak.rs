@@ -0,0 +1,201 @@
+//! Rolling summary of a long-running trace for soak tests.
+//!
+//! [`SoakSummaryWriter`] is an [`Observer`] rewriting a JSON summary file
+//! every [`SoakOptions::interval`] of run time, so operators can follow the
+//! memory health of a multi-hour run without waiting for it to finish. The
+//! file is replaced atomically: readers see either the previous or the new
+//! summary, never a partial one.
+
+use crate::observer::{LiveStats, Observer};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct SoakOptions {
+    pub path: PathBuf,
+    /// Run time between two summaries.
+    pub interval: Duration,
+    pub top_sites: usize,
+}
+
+impl SoakOptions {
+    /// A summary every 5 minutes with the 10 top sites.
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            interval: Duration::from_secs(5 * 60),
+            top_sites: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SoakSite {
+    pub function: String,
+    /// Upper bound of the bytes allocated so far.
+    pub bytes: u64,
+    /// Bytes allocated per second since the previous summary.
+    pub rate: f64,
+}
+
+/// Rates are in bytes per second, over the whole run and since the previous
+/// summary.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SoakSummary {
+    pub elapsed_ms: u128,
+    /// Number of the summary, from 1.
+    pub sequence: u64,
+    pub allocations: u64,
+    pub temporary: u64,
+    pub heap: u64,
+    pub peak_heap: u64,
+    pub rss: u64,
+    pub heap_growth: f64,
+    pub heap_growth_recent: f64,
+    pub rss_growth: f64,
+    pub rss_growth_recent: f64,
+    pub top_sites: Vec<SoakSite>,
+}
+
+fn rate(now: u64, before: u64, elapsed: Duration) -> f64 {
+    if elapsed.is_zero() {
+        return 0.0;
+    }
+    (now as f64 - before as f64) / elapsed.as_secs_f64()
+}
+
+impl SoakSummary {
+    /// Summarizes `stats`, with the recent rates since `last`, the stats of
+    /// the previous summary.
+    pub fn new(stats: &LiveStats, last: Option<&LiveStats>, sequence: u64) -> Self {
+        let start = LiveStats::default();
+        let last = last.unwrap_or(&start);
+        let recent = stats.timestamp.saturating_sub(last.timestamp);
+        let last_sites: HashMap<&str, u64> = last
+            .top_allocating
+            .iter()
+            .map(|site| (site.function.as_str(), site.allocated))
+            .collect();
+
+        Self {
+            elapsed_ms: stats.timestamp.as_millis(),
+            sequence,
+            allocations: stats.allocations,
+            temporary: stats.temporary,
+            heap: stats.heap,
+            peak_heap: stats.peak_heap,
+            rss: stats.rss,
+            heap_growth: rate(stats.heap, 0, stats.timestamp),
+            heap_growth_recent: rate(stats.heap, last.heap, recent),
+            rss_growth: rate(stats.rss, 0, stats.timestamp),
+            rss_growth_recent: rate(stats.rss, last.rss, recent),
+            top_sites: stats
+                .top_allocating
+                .iter()
+                .map(|site| {
+                    let before = last_sites.get(site.function.as_str()).copied();
+                    SoakSite {
+                        function: site.function.clone(),
+                        bytes: site.allocated,
+                        rate: rate(site.allocated, before.unwrap_or(0), recent),
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Replaces the file at `path` by writing a sibling file and renaming
+    /// it.
+    pub fn write_atomic(&self, path: &Path) -> io::Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(&tmp, path)
+    }
+}
+
+pub struct SoakSummaryWriter {
+    options: SoakOptions,
+    /// Stats of the last summary written.
+    last: Option<LiveStats>,
+    written: u64,
+}
+
+impl SoakSummaryWriter {
+    pub fn new(options: SoakOptions) -> Self {
+        Self {
+            options,
+            last: None,
+            written: 0,
+        }
+    }
+}
+
+impl Observer for SoakSummaryWriter {
+    fn top_sites(&self) -> usize {
+        self.options.top_sites
+    }
+
+    fn on_stats(&mut self, stats: &LiveStats) {
+        let since = self.last.as_ref().map_or(Duration::ZERO, |l| l.timestamp);
+        if stats.timestamp.saturating_sub(since) < self.options.interval {
+            return;
+        }
+
+        let summary = SoakSummary::new(stats, self.last.as_ref(), self.written + 1);
+        // a failed write is retried on the next clock record
+        if summary.write_atomic(&self.options.path).is_ok() {
+            self.written += 1;
+            self.last = Some(stats.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::observer::{LiveStats, Observer, TopSite};
+    use crate::soak::{SoakOptions, SoakSummaryWriter};
+    use std::time::Duration;
+
+    #[test]
+    fn test_soak_summary() {
+        let path = std::env::temp_dir().join(format!("memtrack-soak-{}.json", std::process::id()));
+        let mut writer = SoakSummaryWriter::new(SoakOptions {
+            interval: Duration::from_secs(60),
+            ..SoakOptions::new(&path)
+        });
+
+        let stats = |secs, heap, site_bytes| LiveStats {
+            timestamp: Duration::from_secs(secs),
+            heap,
+            rss: heap * 2,
+            top_allocating: vec![TopSite {
+                function: "cache::insert".into(),
+                allocated: site_bytes,
+                error: 0,
+            }],
+            ..LiveStats::default()
+        };
+        writer.on_stats(&stats(30, 1000, 1000));
+        assert!(!path.exists());
+        writer.on_stats(&stats(60, 6000, 6000));
+        writer.on_stats(&stats(90, 9000, 9000));
+        writer.on_stats(&stats(120, 12000, 18000));
+
+        let summary = std::fs::read_to_string(&path);
+        _ = std::fs::remove_file(&path);
+        let summary: serde_json::Value = serde_json::from_str(&summary.unwrap()).unwrap();
+        assert_eq!(summary["sequence"], 2);
+        assert_eq!(summary["elapsed_ms"], 120_000);
+        assert_eq!(summary["heap_growth"], 100.0);
+        assert_eq!(summary["heap_growth_recent"], 100.0);
+        assert_eq!(summary["rss_growth"], 200.0);
+        assert_eq!(summary["top_sites"][0]["rate"], 200.0);
+    }
+}
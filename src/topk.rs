@@ -0,0 +1,119 @@
+//! Approximate heavy hitters of a weighted stream in bounded memory.
+//!
+//! [`SpaceSaving`] monitors at most `capacity` keys. A key not monitored
+//! replaces the one with the smallest count and inherits that count as its
+//! error, so counts are upper bounds overestimating by at most `error`. Every
+//! key whose true weight exceeds `total / capacity` is guaranteed to be
+//! monitored.
+
+use std::collections::{BTreeSet, HashMap};
+use std::hash::Hash;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Counter<K> {
+    pub key: K,
+    /// Upper bound of the key's weight.
+    pub count: u64,
+    /// Maximum overestimation of `count`.
+    pub error: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct SpaceSaving<K> {
+    capacity: usize,
+    counters: HashMap<K, (u64, u64)>,
+    /// Monitored keys ordered by count, to find the eviction candidate.
+    order: BTreeSet<(u64, K)>,
+    total: u64,
+}
+
+impl<K: Copy + Ord + Hash> SpaceSaving<K> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            counters: HashMap::with_capacity(capacity),
+            order: BTreeSet::new(),
+            total: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Sum of all weights added.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn add(&mut self, key: K, weight: u64) {
+        self.total += weight;
+
+        if let Some((count, _)) = self.counters.get_mut(&key) {
+            self.order.remove(&(*count, key));
+            *count += weight;
+            self.order.insert((*count, key));
+            return;
+        }
+
+        let (count, error) = if self.counters.len() < self.capacity {
+            (weight, 0)
+        } else {
+            let Some((min, evicted)) = self.order.pop_first() else {
+                return;
+            };
+            self.counters.remove(&evicted);
+            (min + weight, min)
+        };
+        self.counters.insert(key, (count, error));
+        self.order.insert((count, key));
+    }
+
+    /// The `n` keys with the highest counts, highest first.
+    pub fn top(&self, n: usize) -> Vec<Counter<K>> {
+        self.order
+            .iter()
+            .rev()
+            .take(n)
+            .map(|&(count, key)| Counter {
+                key,
+                count,
+                error: self.counters[&key].1,
+            })
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.counters.clear();
+        self.order.clear();
+        self.total = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::topk::SpaceSaving;
+
+    #[test]
+    fn test_space_saving() {
+        let mut top = SpaceSaving::new(3);
+        for _ in 0..10 {
+            top.add(1u64, 100);
+        }
+        for key in 2..50 {
+            top.add(key, 1);
+        }
+        top.add(7, 50);
+
+        let counters = top.top(2);
+        assert_eq!(counters[0].key, 1);
+        assert_eq!(counters[0].count, 1000);
+        assert_eq!(counters[0].error, 0);
+        // 7 was evicted before, its count is an upper bound
+        assert_eq!(counters[1].key, 7);
+        assert!(counters[1].count - counters[1].error <= 50);
+        assert!(counters[1].count >= 50);
+        assert_eq!(top.total(), 1000 + 48 + 50);
+        assert_eq!(top.top(10).len(), 3);
+    }
+}
@@ -0,0 +1,96 @@
+//! Hooks rewriting the records of the traced program before the
+//! [`Interpreter`](crate::interpret::Interpreter) aggregates and writes them.
+//!
+//! Transformers run in the order they were added. Each one sees the records
+//! left by the previous one, so a record replaced by several is passed on
+//! one by one.
+
+use crate::pipe_io::{Record, RecordRef};
+
+/// What happens to a record passed to a [`RecordTransformer`].
+#[derive(Debug)]
+pub enum Transform {
+    Keep,
+    Drop,
+    /// Handles the given records instead, which may include the original
+    /// one. An empty list is the same as [`Transform::Drop`].
+    Replace(Vec<Record>),
+}
+
+pub trait RecordTransformer {
+    fn transform(&mut self, record: &RecordRef<'_>) -> Transform;
+}
+
+impl<F: FnMut(&RecordRef<'_>) -> Transform> RecordTransformer for F {
+    fn transform(&mut self, record: &RecordRef<'_>) -> Transform {
+        self(record)
+    }
+}
+
+/// Rewrites the path prefix of loaded modules, e.g. to map the paths of a
+/// container image to the ones on the host the trace is analyzed on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModulePathRewrite {
+    pub from: String,
+    pub to: String,
+}
+
+impl ModulePathRewrite {
+    pub fn new(from: &str, to: &str) -> Self {
+        Self {
+            from: from.to_string(),
+            to: to.to_string(),
+        }
+    }
+}
+
+impl RecordTransformer for ModulePathRewrite {
+    fn transform(&mut self, record: &RecordRef<'_>) -> Transform {
+        let &RecordRef::Image {
+            name,
+            start_address,
+            size,
+        } = record
+        else {
+            return Transform::Keep;
+        };
+        let Some(rest) = name.strip_prefix(self.from.as_bytes()) else {
+            return Transform::Keep;
+        };
+
+        Transform::Replace(vec![Record::Image {
+            name: [self.to.as_bytes(), rest].concat(),
+            start_address,
+            size,
+        }])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pipe_io::{Record, RecordRef};
+    use crate::transform::{ModulePathRewrite, RecordTransformer, Transform};
+
+    #[test]
+    fn test_module_path_rewrite() {
+        let mut rewrite = ModulePathRewrite::new("/container/usr", "/srv/image/usr");
+        let image = |name: &'static str| RecordRef::Image {
+            name: name.as_bytes(),
+            start_address: 0x1000,
+            size: 0x100,
+        };
+
+        let Transform::Replace(records) = rewrite.transform(&image("/container/usr/lib/libc.so"))
+        else {
+            panic!("module not rewritten");
+        };
+        assert!(matches!(
+            &records[..],
+            [Record::Image { name, .. }] if name == b"/srv/image/usr/lib/libc.so"
+        ));
+        assert!(matches!(
+            rewrite.transform(&image("/usr/lib/libm.so")),
+            Transform::Keep
+        ));
+    }
+}